@@ -18,25 +18,6 @@ const SEED: u64 = 42;
 const MAX_N: usize = 1;
 const UNIVERSE: u32 = 1 << 20;
 
-const FILTER_CONFIGS: &[FilterConfig] = &[
-    FilterConfig {
-        length: false,
-        position: false,
-    },
-    FilterConfig {
-        length: true,
-        position: false,
-    },
-    FilterConfig {
-        length: false,
-        position: true,
-    },
-    FilterConfig {
-        length: true,
-        position: true,
-    },
-];
-
 fn database_txt() -> Vec<String> {
     DATABASE_TXT.lines().map(|s| s.to_owned()).collect()
 }
@@ -85,15 +66,15 @@ fn perform_range_search_linear_scan(
     let queries = make_queries(query_texts, &extractor);
 
     for r in [0.1, 0.2, 0.5] {
-        for &cfg in FILTER_CONFIGS {
+        for cfg in FilterConfig::PRESETS {
             let l = usize::from(cfg.length);
             let p = usize::from(cfg.position);
-            index = index.filter_config(cfg);
+            index = index.filter_config(cfg).unwrap();
             let group_id = format!("N={max_n}_n={n}_m={m}_r={r}/L={l}_P={p}");
             group.bench_function(group_id, |b| {
                 b.iter(|| {
                     for query in &queries {
-                        index.range_query(query, r);
+                        index.range_query(query, r).unwrap();
                     }
                 });
             });
@@ -123,6 +104,20 @@ fn perform_range_search_inverted_index(
                 }
             });
         });
+        let group_id = format!("N={max_n}_n={n}_m={m}_r={r}/batch");
+        group.bench_function(group_id, |b| {
+            b.iter(|| index.range_query_batch(&queries));
+        });
+        // Half of the postings are skipped as excluded.
+        let exclude = (0..n as u32).step_by(2).collect::<Vec<_>>();
+        let group_id = format!("N={max_n}_n={n}_m={m}_r={r}/excluding");
+        group.bench_function(group_id, |b| {
+            b.iter(|| {
+                for query in &queries {
+                    index.range_query_excluding(query, &exclude);
+                }
+            });
+        });
     }
 }
 
@@ -0,0 +1,70 @@
+// Searches the bundled sample of public-domain lines from Project Gutenberg end-to-end:
+//
+//   $ cargo run --example quickstart
+//
+// The queries are mostly lines of the database with a word dropped or replaced.
+
+use anyhow::Result;
+use set_search_experiment::text::FeatureExtractor;
+use set_search_experiment::{FilterConfig, InvertedIndex, LinearScan, OrderedSet, Record};
+
+const DATABASE: &str = include_str!("data/sample.db.txt");
+const QUERIES: &str = include_str!("data/sample.query.txt");
+
+const UNIVERSE: u32 = 1 << 16;
+const RADII: [f32; 2] = [0.3, 0.5];
+const TOPK: usize = 3;
+
+fn main() -> Result<()> {
+    // Word unigrams and bigrams hashed into the universe.
+    let extractor = FeatureExtractor::seeded(1..=2, UNIVERSE, 42)?;
+    let database = DATABASE.lines().collect::<Vec<_>>();
+    let queries = QUERIES.lines().collect::<Vec<_>>();
+    let extract = |text: &str| extractor.extract(&text.split_whitespace().collect::<Vec<_>>());
+
+    let records = database
+        .iter()
+        .enumerate()
+        .map(|(id, text)| Record {
+            id: id as u32,
+            set: extract(text),
+        })
+        .collect::<Vec<_>>();
+    let queries = queries
+        .iter()
+        .map(|text| (*text, extract(text)))
+        .collect::<Vec<(&str, OrderedSet<u32>)>>();
+    println!("{} records, {} queries", records.len(), queries.len());
+
+    let linear_scan =
+        LinearScan::from_records(&records, UNIVERSE)?.filter_config(FilterConfig::ALL)?;
+    for radius in RADII {
+        // The inverted index is built for a radius.
+        let inverted_index = InvertedIndex::from_records(&records, UNIVERSE, radius)?;
+        let mut n_founds = [0, 0];
+        for (_, query) in &queries {
+            n_founds[0] += linear_scan.range_query(query, radius)?.len();
+            n_founds[1] += inverted_index.range_query(query).len();
+        }
+        println!(
+            "radius={radius}: {} founds by the linear scan, {} by the inverted index",
+            n_founds[0], n_founds[1]
+        );
+    }
+
+    for (text, query) in queries.iter().take(5) {
+        println!("\nquery: {text}");
+        for ans in linear_scan.topk_query(query, TOPK)? {
+            println!("  {:.3} {}", ans.dist, database[ans.id as usize]);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_quickstart() {
+        super::main().unwrap();
+    }
+}
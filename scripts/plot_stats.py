@@ -65,7 +65,8 @@ def main():
     metadata = stats["metadata"]
     print(metadata)
 
-    lengths = stats["lengths"]
+    length_hist = stats["length_hist"]
+    lengths = np.repeat(np.arange(len(length_hist)), length_hist)
     plot_length_distribution(lengths, args.out_dir, metadata)
 
     elem_freqs = stats["elem_freqs"]
@@ -0,0 +1,355 @@
+use std::ops::{Range, RangeInclusive};
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use hashbrown::HashMap;
+
+use crate::metric::{Evaluation, FilterBounds, FilterConfig, Jaccard};
+use crate::{
+    check_radius, id_offsets, narrow_answers, Answer, FrozenRecord, Mapping, OrderedSet,
+    RangeSearch, Record,
+};
+
+const FILTER_CONFIG: FilterConfig = FilterConfig::new(true, true);
+
+const DEFAULT_MAX_LEVEL: usize = 4;
+
+// Record offset and the position of the element in the record.
+#[derive(Debug, Clone, Copy)]
+struct Posting {
+    offset: u32,
+    pos: u32,
+}
+
+// Inverted index of the ℓ-prefix scheme of AdaptJoin (Wang et al., SIGMOD 2012).
+// Two sets with the overlap `o >= ℓ` share at least ℓ elements in their `(|x| - o + ℓ)`-prefixes,
+// so the prefixes extended by `ℓ - 1` elements generate the candidates sharing ℓ elements.
+// Larger ℓ scans more postings but verifies fewer candidates, and ℓ is chosen per query
+// by the estimated costs, starting from the ordinary prefix filter of ℓ = 1.
+pub struct AdaptiveIndex {
+    mapping: Arc<Mapping>,
+    records: Vec<FrozenRecord>,
+    // Minimum overlap of each record with any set within the radius.
+    min_overlaps: Vec<u32>,
+    index: HashMap<u32, Vec<Posting>>,
+    radius: f32,
+    threshold: f32,
+    max_level: usize,
+}
+
+impl AdaptiveIndex {
+    pub fn from_records(records: &[Record<u32>], universe: u32, radius: f32) -> Result<Self> {
+        let mapping = Mapping::from_records(records, universe)?;
+        Self::from_records_with_mapping(records, Arc::new(mapping), radius)
+    }
+
+    pub fn from_records_with_mapping(
+        records: &[Record<u32>],
+        mapping: Arc<Mapping>,
+        radius: f32,
+    ) -> Result<Self> {
+        check_radius(radius)?;
+        if !mapping.covers(records) {
+            return Err(anyhow!("The mapping does not cover the records."));
+        }
+        id_offsets(records)?;
+        let mut records = records
+            .iter()
+            .map(|record| {
+                Record {
+                    id: record.id,
+                    set: mapping.apply(&record.set),
+                }
+                .freeze()
+            })
+            .collect::<Vec<_>>();
+        // Sorted by length as in `InvertedIndex`, so that the length window is a range of each list.
+        records.sort_by_key(|record| record.set.len());
+        let threshold = Jaccard::<u32>::threshold(radius);
+        let min_overlaps = records
+            .iter()
+            .map(|record| {
+                let len = record.set.len();
+                min_overlap(threshold, len + min_partner_len(threshold, len)) as u32
+            })
+            .collect();
+        let mut index = Self {
+            mapping,
+            records,
+            min_overlaps,
+            index: HashMap::new(),
+            radius,
+            threshold,
+            max_level: DEFAULT_MAX_LEVEL,
+        };
+        index.index = index.build_index();
+        Ok(index)
+    }
+
+    // Maximum ℓ to choose, indexing the prefixes extended by `max_level - 1` elements.
+    pub fn max_level(mut self, max_level: usize) -> Result<Self> {
+        if max_level == 0 {
+            return Err(anyhow!("max_level must be positive."));
+        }
+        self.max_level = max_level;
+        self.index = self.build_index();
+        Ok(self)
+    }
+
+    fn build_index(&self) -> HashMap<u32, Vec<Posting>> {
+        let mut index = HashMap::new();
+        for (i, record) in self.records.iter().enumerate() {
+            let pfx_len = prefix_len(record.set.len(), self.min_overlaps[i], self.max_level);
+            for (pos, &elem) in record.set.iter().take(pfx_len).enumerate() {
+                index.entry(elem).or_insert_with(Vec::new).push(Posting {
+                    offset: i as u32,
+                    pos: pos as u32,
+                });
+            }
+        }
+        index
+    }
+
+    pub fn mapping(&self) -> &Arc<Mapping> {
+        &self.mapping
+    }
+
+    pub fn n_postings(&self) -> usize {
+        self.index.values().map(|list| list.len()).sum()
+    }
+
+    pub fn range_query(&self, query: &OrderedSet<u32>) -> Vec<Answer> {
+        self.range_query_with_level(query).0
+    }
+
+    // Also returns the chosen ℓ.
+    pub fn range_query_with_level(&self, query: &OrderedSet<u32>) -> (Vec<Answer>, usize) {
+        let query = self.mapping.apply(query);
+        let jaccard = Jaccard::new(&query, self.radius, FILTER_CONFIG);
+        let window = self.length_window(jaccard.length_range());
+        let min_overlap = self.min_overlap(&query, &jaccard);
+        let max_level = self.max_level.min(min_overlap.max(1));
+
+        // Moves to ℓ + 1 while rescanning costs less than the verifications it saves,
+        // estimated by the candidates already sharing ℓ + 1 elements at ℓ.
+        let verify_cost = 2 * query.len();
+        let mut level = 1;
+        let mut counts = self.count(&query, min_overlap, &window, level);
+        while level < max_level {
+            let n_candidates = counts.values().filter(|&&c| c as usize >= level).count();
+            let n_next = counts.values().filter(|&&c| c as usize > level).count();
+            let scan_cost = self.scan_cost(&query, min_overlap, &window, level + 1);
+            if scan_cost + n_next * verify_cost >= n_candidates * verify_cost {
+                break;
+            }
+            level += 1;
+            counts = self.count(&query, min_overlap, &window, level);
+        }
+        (self.verify(&jaccard, &counts, level), level)
+    }
+
+    // Same as `range_query` but with the fixed ℓ = `level`, e.g., to compare with the choice.
+    pub fn range_query_at_level(
+        &self,
+        query: &OrderedSet<u32>,
+        level: usize,
+    ) -> Result<Vec<Answer>> {
+        if level == 0 || level > self.max_level {
+            return Err(anyhow!(
+                "level must be in [1, {}]: {}",
+                self.max_level,
+                level
+            ));
+        }
+        let query = self.mapping.apply(query);
+        let jaccard = Jaccard::new(&query, self.radius, FILTER_CONFIG);
+        let window = self.length_window(jaccard.length_range());
+        // Sets similar to the query can share fewer elements than the level.
+        let min_overlap = self.min_overlap(&query, &jaccard);
+        let level = level.min(min_overlap.max(1));
+        let counts = self.count(&query, min_overlap, &window, level);
+        Ok(self.verify(&jaccard, &counts, level))
+    }
+
+    // Minimum overlap of the query with any set within the radius, which bounds ℓ.
+    // The bounds are computed as `Jaccard` does, so that the borderline sets it accepts
+    // are not missed by rounding.
+    fn min_overlap(&self, query: &OrderedSet<u32>, jaccard: &Jaccard<u32>) -> usize {
+        min_overlap(
+            self.threshold,
+            query.len() + *jaccard.length_range().start(),
+        )
+    }
+
+    // Counts the shared elements in the prefixes of ℓ = `level` per record in the window.
+    fn count(
+        &self,
+        query: &OrderedSet<u32>,
+        min_overlap: usize,
+        window: &Range<u32>,
+        level: usize,
+    ) -> HashMap<u32, u32> {
+        let mut counts = HashMap::new();
+        for elem in query
+            .iter()
+            .take(prefix_len(query.len(), min_overlap as u32, level))
+        {
+            for p in self.postings(elem, window) {
+                let offset = p.offset as usize;
+                let record_len = self.records[offset].set.len();
+                if (p.pos as usize) < prefix_len(record_len, self.min_overlaps[offset], level) {
+                    *counts.entry(p.offset).or_insert(0) += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    // Number of the postings scanned by `count`.
+    fn scan_cost(
+        &self,
+        query: &OrderedSet<u32>,
+        min_overlap: usize,
+        window: &Range<u32>,
+        level: usize,
+    ) -> usize {
+        query
+            .iter()
+            .take(prefix_len(query.len(), min_overlap as u32, level))
+            .map(|elem| self.postings(elem, window).len())
+            .sum()
+    }
+
+    fn postings(&self, elem: &u32, window: &Range<u32>) -> &[Posting] {
+        let Some(list) = self.index.get(elem) else {
+            return &[];
+        };
+        let start = list.partition_point(|p| p.offset < window.start);
+        let end = list.partition_point(|p| p.offset < window.end);
+        &list[start..end]
+    }
+
+    fn verify(
+        &self,
+        jaccard: &Jaccard<u32>,
+        counts: &HashMap<u32, u32>,
+        level: usize,
+    ) -> Vec<Answer> {
+        let mut answers = vec![];
+        for (&offset, &count) in counts {
+            if (count as usize) < level {
+                continue;
+            }
+            let record = &self.records[offset as usize];
+            if let Evaluation::Accepted(dist) = jaccard.evaluate(&record.set) {
+                answers.push(Answer {
+                    id: record.id,
+                    dist,
+                });
+            }
+        }
+        answers.sort_unstable();
+        answers
+    }
+
+    // Returns the offsets of the records whose lengths are within the bounds.
+    fn length_window(&self, bounds: &RangeInclusive<usize>) -> Range<u32> {
+        let start = self
+            .records
+            .partition_point(|record| record.set.len() < *bounds.start());
+        let end = self
+            .records
+            .partition_point(|record| record.set.len() <= *bounds.end());
+        start as u32..end as u32
+    }
+}
+
+// Prefix of ℓ = `level`, i.e., `|x| - o + ℓ` for the minimum overlap `o`.
+fn prefix_len(set_len: usize, min_overlap: u32, level: usize) -> usize {
+    (set_len + level)
+        .saturating_sub(min_overlap as usize)
+        .min(set_len)
+}
+
+// Minimum overlap of two sets of `total_len` elements in total accepted by `Jaccard`.
+fn min_overlap(threshold: f32, total_len: usize) -> usize {
+    (Jaccard::<u32>::overlap_factor(threshold) * total_len as f32).ceil() as usize
+}
+
+// Minimum length of the sets whose length bounds contain `set_len`.
+fn min_partner_len(threshold: f32, set_len: usize) -> usize {
+    let mut len = ((set_len as f32 * threshold) as usize).saturating_sub(1);
+    while *Jaccard::<u32>::length_bounds(len, threshold).end() < set_len {
+        len += 1;
+    }
+    len
+}
+
+impl RangeSearch for AdaptiveIndex {
+    fn range_search(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Answer>> {
+        narrow_answers(self.range_query(query), radius, self.radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng};
+    use rand_xoshiro::Xoshiro256PlusPlus;
+
+    use crate::LinearScan;
+
+    #[test]
+    fn test_range_query() {
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(5);
+        // Frequent elements make the larger levels pay off.
+        let records = (0..500)
+            .map(|id| {
+                let n = rng.gen_range(1..15);
+                let set = (0..n)
+                    .map(|_| rng.gen_range(0..40u32).min(rng.gen_range(0..40)))
+                    .collect::<Vec<_>>();
+                Record {
+                    id,
+                    set: OrderedSet::from_unsorted(set),
+                }
+            })
+            .collect::<Vec<_>>();
+        let linear_scan = LinearScan::from_records(&records, 40).unwrap();
+
+        for radius in [0.0, 0.2, 0.4, 0.6] {
+            let index = AdaptiveIndex::from_records(&records, 40, radius).unwrap();
+            let mut levels = vec![];
+            for record in records.iter().take(100) {
+                let expected = linear_scan.range_query(&record.set, radius).unwrap();
+                let (answers, level) = index.range_query_with_level(&record.set);
+                assert_eq!(answers, expected, "radius={radius}");
+                levels.push(level);
+                for level in 1..=DEFAULT_MAX_LEVEL {
+                    assert_eq!(
+                        index.range_query_at_level(&record.set, level).unwrap(),
+                        expected,
+                        "radius={radius}, level={level}"
+                    );
+                }
+            }
+            assert!(levels.iter().all(|&level| level <= DEFAULT_MAX_LEVEL));
+            if radius > 0.0 {
+                assert!(levels.iter().any(|&level| level > 1), "radius={radius}");
+            }
+        }
+
+        let index = AdaptiveIndex::from_records(&records, 40, 0.2)
+            .unwrap()
+            .max_level(2)
+            .unwrap();
+        assert!(index.range_query_at_level(&records[0].set, 3).is_err());
+        assert!(index.range_query_at_level(&records[0].set, 0).is_err());
+        assert!(AdaptiveIndex::from_records(&records, 40, 0.2)
+            .unwrap()
+            .max_level(0)
+            .is_err());
+    }
+}
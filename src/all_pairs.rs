@@ -0,0 +1,108 @@
+use anyhow::Result;
+use hashbrown::HashMap;
+
+use crate::metric::{Evaluation, FilterBounds, FilterConfig, Jaccard};
+use crate::pairwise::SparseMatrix;
+use crate::{check_radius, Mapping, Record};
+
+const FILTER_CONFIG: FilterConfig = FilterConfig::new(true, true);
+
+// Posting list of the ranks of the records in the ascending order of length.
+#[derive(Default)]
+struct PostingList {
+    // Ranks before it are too short for the following probes.
+    start: usize,
+    ranks: Vec<u32>,
+}
+
+// Self-join of AllPairs (Bayardo et al., WWW 2007), returning all the pairs of the records
+// within the radius as `pairwise_distances_within` does, e.g., for corpus deduplication.
+// The records are probed and then indexed in the ascending order of length, so that every
+// pair is probed once from the longer record, against which the shorter one needs to index
+// the shorter prefix. The postings too short for the current record are trimmed for good.
+pub fn all_pairs(records: &[Record<u32>], universe: u32, radius: f32) -> Result<SparseMatrix> {
+    check_radius(radius)?;
+    let mapping = Mapping::from_records(records, universe)?;
+    let threshold = Jaccard::<u32>::threshold(radius);
+    let sets = records
+        .iter()
+        .map(|record| mapping.apply(&record.set))
+        .collect::<Vec<_>>();
+    let mut order = (0..records.len() as u32).collect::<Vec<_>>();
+    order.sort_by_key(|&offset| sets[offset as usize].len());
+    let set_of = |rank: u32| &sets[order[rank as usize] as usize];
+
+    let mut index: HashMap<u32, PostingList> = HashMap::new();
+    // Rank + 1 of the record that last visited each rank, to skip the duplicate candidates.
+    let mut visited_by = vec![0; records.len()];
+    let mut candidates = vec![];
+    let mut entries = vec![];
+    for (rank, &offset) in order.iter().enumerate() {
+        let set = &sets[offset as usize];
+        let jaccard = Jaccard::new(set, radius, FILTER_CONFIG);
+        let min_len = *jaccard.length_range().start();
+
+        candidates.clear();
+        let pfx_len = Jaccard::<u32>::query_prefix_len(set.len(), threshold);
+        for elem in set.iter().take(pfx_len) {
+            let Some(list) = index.get_mut(elem) else {
+                continue;
+            };
+            while list.start < list.ranks.len() && set_of(list.ranks[list.start]).len() < min_len {
+                list.start += 1;
+            }
+            for &other in &list.ranks[list.start..] {
+                if visited_by[other as usize] != rank + 1 {
+                    visited_by[other as usize] = rank + 1;
+                    candidates.push(other);
+                }
+            }
+        }
+        for &other in &candidates {
+            if let Evaluation::Accepted(dist) = jaccard.evaluate(set_of(other)) {
+                let other = order[other as usize];
+                entries.push((offset.min(other), offset.max(other), dist));
+            }
+        }
+
+        let pfx_len = Jaccard::<u32>::index_prefix_len(set.len(), threshold);
+        for &elem in set.iter().take(pfx_len) {
+            index.entry(elem).or_default().ranks.push(rank as u32);
+        }
+    }
+    entries.sort_unstable_by_key(|&(i, j, _)| (i, j));
+    Ok(SparseMatrix::new(records.len(), entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng};
+    use rand_xoshiro::Xoshiro256PlusPlus;
+
+    use crate::pairwise::pairwise_distances_within;
+    use crate::OrderedSet;
+
+    #[test]
+    fn test_all_pairs() {
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(11);
+        let records = (0..400)
+            .map(|id| {
+                let n = rng.gen_range(0..15);
+                let set = (0..n).map(|_| rng.gen_range(0..40)).collect::<Vec<_>>();
+                Record {
+                    id,
+                    set: OrderedSet::from_unsorted(set),
+                }
+            })
+            .collect::<Vec<_>>();
+        for radius in [0.0, 0.2, 0.4, 0.6] {
+            let joined = all_pairs(&records, 40, radius).unwrap();
+            let expected = pairwise_distances_within(&records, radius, None).unwrap();
+            assert_eq!(joined.n(), expected.n());
+            assert_eq!(joined.entries(), expected.entries(), "radius={radius}");
+        }
+        assert!(all_pairs(&records, 40, f32::NAN).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+// Limits on the work of consecutive queries, e.g., the probes of a request with a strict SLA.
+// Queries given the budget consume it, and return partial results once it is exhausted.
+#[derive(Debug, Clone)]
+pub struct Budget {
+    // Remaining numbers of candidates to verify and posting entries to scan.
+    verified: usize,
+    postings: usize,
+    exhausted: bool,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Budget {
+    // Unlimited until limited by the builders.
+    pub const fn new() -> Self {
+        Self {
+            verified: usize::MAX,
+            postings: usize::MAX,
+            exhausted: false,
+        }
+    }
+
+    pub fn max_verified(mut self, n: usize) -> Self {
+        self.verified = n;
+        self
+    }
+
+    pub fn max_postings(mut self, n: usize) -> Self {
+        self.postings = n;
+        self
+    }
+
+    pub const fn remaining_verified(&self) -> usize {
+        self.verified
+    }
+
+    pub const fn remaining_postings(&self) -> usize {
+        self.postings
+    }
+
+    // Whether a query has stopped early by the budget, i.e., returned partial results.
+    pub const fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    // Returns the number of verifications granted out of `n`.
+    pub(crate) fn take_verified(&mut self, n: usize) -> usize {
+        take(&mut self.verified, n, &mut self.exhausted)
+    }
+
+    // Returns the number of posting entries granted out of `n`.
+    pub(crate) fn take_postings(&mut self, n: usize) -> usize {
+        take(&mut self.postings, n, &mut self.exhausted)
+    }
+}
+
+fn take(remaining: &mut usize, n: usize, exhausted: &mut bool) -> usize {
+    if *remaining < n {
+        *exhausted = true;
+    }
+    let granted = n.min(*remaining);
+    *remaining -= granted;
+    granted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let mut budget = Budget::new().max_verified(2).max_postings(5);
+        assert_eq!(budget.take_postings(3), 3);
+        assert!(!budget.is_exhausted());
+        assert_eq!(budget.take_postings(3), 2);
+        assert!(budget.is_exhausted());
+        assert_eq!(budget.remaining_postings(), 0);
+
+        let mut budget = Budget::new().max_verified(2);
+        assert_eq!(budget.take_verified(2), 2);
+        assert!(!budget.is_exhausted());
+        assert_eq!(budget.take_verified(1), 0);
+        assert!(budget.is_exhausted());
+        assert_eq!(budget.remaining_postings(), usize::MAX);
+    }
+}
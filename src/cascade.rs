@@ -0,0 +1,141 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::{union_answers, Answer, OrderedSet, RangeSearch};
+
+struct Stage {
+    index: Box<dyn RangeSearch + Send + Sync>,
+    min_results: usize,
+    time_budget: Option<Duration>,
+}
+
+pub struct CascadeAnswers {
+    pub answers: Vec<Answer>,
+    // Number of the stages searched.
+    pub n_stages: usize,
+}
+
+// Chains indexes from the cheapest, e.g., ExactIndex, InvertedIndex and then LinearScan,
+// and stops after a stage once enough answers are found or its time budget runs out.
+// The answers of the searched stages are merged, keeping the minimum distance per id.
+#[derive(Default)]
+pub struct Cascade {
+    stages: Vec<Stage>,
+}
+
+impl Cascade {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Appends a stage after which the cascade returns if at least `min_results` answers
+    // are found so far, or if the elapsed time of the whole query exceeds `time_budget`.
+    pub fn stage<I>(mut self, index: I, min_results: usize, time_budget: Option<Duration>) -> Self
+    where
+        I: RangeSearch + Send + Sync + 'static,
+    {
+        self.stages.push(Stage {
+            index: Box::new(index),
+            min_results,
+            time_budget,
+        });
+        self
+    }
+
+    pub fn n_stages(&self) -> usize {
+        self.stages.len()
+    }
+
+    pub fn range_query(&self, query: &OrderedSet<u32>, radius: f32) -> Result<CascadeAnswers> {
+        let start = Instant::now();
+        let mut answers = vec![];
+        let mut n_stages = 0;
+        for stage in &self.stages {
+            answers.extend(stage.index.range_search(query, radius)?);
+            answers = union_answers(answers);
+            n_stages += 1;
+            let timed_out = stage
+                .time_budget
+                .is_some_and(|budget| start.elapsed() > budget);
+            if answers.len() >= stage.min_results || timed_out {
+                break;
+            }
+        }
+        Ok(CascadeAnswers { answers, n_stages })
+    }
+}
+
+impl RangeSearch for Cascade {
+    fn range_search(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Answer>> {
+        Ok(self.range_query(query, radius)?.answers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{ExactIndex, InvertedIndex, LinearScan, Record};
+
+    fn records() -> Vec<Record<u32>> {
+        (0..30)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_unsorted((id..id + 4).map(|e| e % 16)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_cascade() {
+        let records = records();
+        let cascade = Cascade::new()
+            .stage(ExactIndex::from_records(&records, 16).unwrap(), 1, None)
+            .stage(
+                InvertedIndex::from_records(&records, 16, 0.5).unwrap(),
+                3,
+                None,
+            )
+            .stage(LinearScan::from_records(&records, 16).unwrap(), 0, None);
+        assert_eq!(cascade.n_stages(), 3);
+        let linear_scan = LinearScan::from_records(&records, 16).unwrap();
+
+        // Found by the exact index.
+        let query = records[0].set.clone();
+        let result = cascade.range_query(&query, 0.5).unwrap();
+        assert_eq!(result.n_stages, 1);
+        assert_eq!(
+            result.answers,
+            linear_scan.range_query(&query, 0.0).unwrap()
+        );
+
+        // Found by the inverted index.
+        let query = OrderedSet::from_sorted([0, 1, 2]).unwrap();
+        let result = cascade.range_query(&query, 0.5).unwrap();
+        assert_eq!(result.n_stages, 2);
+        assert_eq!(
+            result.answers,
+            linear_scan.range_query(&query, 0.5).unwrap()
+        );
+
+        // Falls back to the linear scan, but the inverted index rejects the radius.
+        assert!(cascade.range_query(&query, 0.8).is_err());
+    }
+
+    #[test]
+    fn test_time_budget() {
+        let records = records();
+        let cascade = Cascade::new()
+            .stage(
+                ExactIndex::from_records(&records, 16).unwrap(),
+                1,
+                Some(Duration::ZERO),
+            )
+            .stage(LinearScan::from_records(&records, 16).unwrap(), 0, None);
+        let query = OrderedSet::from_sorted([0, 1, 2]).unwrap();
+        let result = cascade.range_query(&query, 0.5).unwrap();
+        assert_eq!(result.n_stages, 1);
+        assert!(result.answers.is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+use anyhow::anyhow;
+use anyhow::Result;
+use hashbrown::HashMap;
+
+use crate::{LinearScan, OrderedSet, Record};
+
+pub struct KnnClassifier<L> {
+    index: LinearScan,
+    labels: HashMap<u32, L>,
+}
+
+impl<L> KnnClassifier<L>
+where
+    L: Clone + Eq,
+{
+    // `labels[i]` is the label of `records[i]`.
+    pub fn from_records(records: &[Record<u32>], labels: &[L], universe: u32) -> Result<Self> {
+        if records.len() != labels.len() {
+            return Err(anyhow!(
+                "The numbers of records and labels must be the same."
+            ));
+        }
+        let index = LinearScan::from_records(records, universe)?;
+        let labels = records
+            .iter()
+            .zip(labels)
+            .map(|(record, label)| (record.id, label.clone()))
+            .collect();
+        Ok(Self { index, labels })
+    }
+
+    // Votes the labels of the k nearest neighbors weighted by their similarities.
+    // Ties are broken in favor of the label of the nearer neighbor.
+    // Returns `None` if no neighbors are found.
+    pub fn knn_classify(&self, query: &OrderedSet<u32>, k: usize) -> Result<Option<L>> {
+        let answers = self.index.topk_query(query, k)?;
+        let mut votes: Vec<(&L, f32)> = Vec::new();
+        for ans in &answers {
+            let label = &self.labels[&ans.id];
+            let weight = 1. - ans.dist;
+            match votes.iter_mut().find(|(l, _)| *l == label) {
+                Some((_, w)) => *w += weight,
+                None => votes.push((label, weight)),
+            }
+        }
+        let mut best: Option<(&L, f32)> = None;
+        for (label, weight) in votes {
+            if best.is_none_or(|(_, w)| weight > w) {
+                best = Some((label, weight));
+            }
+        }
+        Ok(best.map(|(label, _)| label.clone()))
+    }
+
+    pub fn index(&self) -> &LinearScan {
+        &self.index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_knn_classify() {
+        let records = vec![
+            Record {
+                id: 0,
+                set: OrderedSet::from_sorted([1, 2, 3]).unwrap(),
+            },
+            Record {
+                id: 1,
+                set: OrderedSet::from_sorted([1, 2, 4]).unwrap(),
+            },
+            Record {
+                id: 2,
+                set: OrderedSet::from_sorted([5, 6, 7]).unwrap(),
+            },
+            Record {
+                id: 3,
+                set: OrderedSet::from_sorted([5, 6, 8]).unwrap(),
+            },
+        ];
+        let labels = ["a", "a", "b", "b"];
+        let classifier = KnnClassifier::from_records(&records, &labels, 10).unwrap();
+
+        let query = OrderedSet::from_sorted([1, 2, 5]).unwrap();
+        assert_eq!(classifier.knn_classify(&query, 1).unwrap(), Some("a"));
+        assert_eq!(classifier.knn_classify(&query, 3).unwrap(), Some("a"));
+
+        let query = OrderedSet::from_unsorted([1, 5, 6, 7]);
+        assert_eq!(classifier.knn_classify(&query, 4).unwrap(), Some("b"));
+
+        assert!(classifier.knn_classify(&query, 0).is_err());
+    }
+
+    #[test]
+    fn test_invalid_labels() {
+        let records = vec![Record {
+            id: 0,
+            set: OrderedSet::from_sorted([1, 2, 3]).unwrap(),
+        }];
+        assert!(KnnClassifier::from_records(&records, &["a", "b"], 10).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use crate::pairwise::SparseMatrix;
+
+// A merge step in the format of `scipy.cluster.hierarchy.linkage`.
+// Clusters `0..n` are the records and the cluster formed by the k-th merge is `n + k`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merge {
+    pub left: u32,
+    pub right: u32,
+    pub dist: f32,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Dendrogram {
+    n: usize,
+    merges: Vec<Merge>,
+}
+
+impl Dendrogram {
+    pub const fn n(&self) -> usize {
+        self.n
+    }
+
+    // Merges are only made for the pairs in the sparse matrix,
+    // so the dendrogram is partial if the matrix is cut off by a radius.
+    pub fn merges(&self) -> &[Merge] {
+        &self.merges
+    }
+
+    // Labels the records with the clusters merged within `cutoff`,
+    // numbered in the order of the first record.
+    pub fn flat_clusters(&self, cutoff: f32) -> Vec<u32> {
+        let mut uf = UnionFind::new(self.n);
+        let mut members = (0..self.n).collect::<Vec<_>>();
+        for merge in self.merges.iter().take_while(|m| m.dist <= cutoff) {
+            let left = members[merge.left as usize];
+            let right = members[merge.right as usize];
+            members.push(uf.union(left, right));
+        }
+        let mut labels = vec![u32::MAX; self.n];
+        let mut root_labels = vec![u32::MAX; self.n];
+        let mut n_labels = 0;
+        for (i, label) in labels.iter_mut().enumerate() {
+            let root = uf.find(i);
+            if root_labels[root] == u32::MAX {
+                root_labels[root] = n_labels;
+                n_labels += 1;
+            }
+            *label = root_labels[root];
+        }
+        labels
+    }
+}
+
+pub fn single_linkage(matrix: &SparseMatrix) -> Dendrogram {
+    let n = matrix.n();
+    let mut edges = matrix.entries().to_vec();
+    edges.sort_unstable_by(|a, b| a.2.total_cmp(&b.2).then((a.0, a.1).cmp(&(b.0, b.1))));
+
+    let mut uf = UnionFind::new(n);
+    let mut clusters = (0..n as u32).collect::<Vec<_>>();
+    let mut sizes = vec![1; n];
+    let mut merges = Vec::new();
+    for (i, j, dist) in edges {
+        let a = uf.find(i as usize);
+        let b = uf.find(j as usize);
+        if a == b {
+            continue;
+        }
+        let (left, right) = (clusters[a].min(clusters[b]), clusters[a].max(clusters[b]));
+        let size = sizes[a] + sizes[b];
+        let root = uf.union(a, b);
+        clusters[root] = (n + merges.len()) as u32;
+        sizes[root] = size;
+        merges.push(Merge {
+            left,
+            right,
+            dist,
+            size,
+        });
+    }
+    Dendrogram { n, merges }
+}
+
+struct UnionFind {
+    parents: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(n: usize) -> Self {
+        Self {
+            parents: (0..n).collect(),
+        }
+    }
+
+    fn find(&mut self, mut x: usize) -> usize {
+        while self.parents[x] != x {
+            self.parents[x] = self.parents[self.parents[x]];
+            x = self.parents[x];
+        }
+        x
+    }
+
+    // Returns the new root.
+    fn union(&mut self, a: usize, b: usize) -> usize {
+        let a = self.find(a);
+        let b = self.find(b);
+        self.parents[b] = a;
+        a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pairwise::pairwise_distances_within;
+    use crate::{OrderedSet, Record};
+
+    fn records() -> Vec<Record<u32>> {
+        let sets = [
+            vec![1, 2, 3, 4],
+            vec![5, 6, 7, 8],
+            vec![1, 2, 3, 4, 5],
+            vec![5, 6, 7, 8, 9],
+            vec![1, 2, 3, 4, 5, 6],
+        ];
+        sets.into_iter()
+            .enumerate()
+            .map(|(id, set)| Record {
+                id: id as u32,
+                set: OrderedSet::from_sorted(set).unwrap(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_single_linkage() {
+        let matrix = pairwise_distances_within(&records(), 0.3, None).unwrap();
+        let dendrogram = single_linkage(&matrix);
+        assert_eq!(
+            dendrogram.merges(),
+            &[
+                Merge {
+                    left: 2,
+                    right: 4,
+                    dist: 1. - 5. / 6.,
+                    size: 2,
+                },
+                Merge {
+                    left: 0,
+                    right: 5,
+                    dist: 1. - 4. / 5.,
+                    size: 3,
+                },
+                Merge {
+                    left: 1,
+                    right: 3,
+                    dist: 1. - 4. / 5.,
+                    size: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flat_clusters() {
+        let matrix = pairwise_distances_within(&records(), 0.3, None).unwrap();
+        let dendrogram = single_linkage(&matrix);
+        assert_eq!(dendrogram.flat_clusters(0.0), vec![0, 1, 2, 3, 4]);
+        assert_eq!(dendrogram.flat_clusters(0.17), vec![0, 1, 2, 3, 2]);
+        assert_eq!(dendrogram.flat_clusters(0.2), vec![0, 1, 0, 1, 0]);
+    }
+}
@@ -0,0 +1,40 @@
+use std::collections::BinaryHeap;
+
+use hashbrown::HashSet;
+
+use crate::{Answer, Mapping, OrderedSet};
+
+// Buffers for query-time temporaries, reused across queries to avoid allocating them
+// per query. A context can be used with any index, but not by several threads at once.
+#[derive(Default)]
+pub struct QueryContext {
+    pub(crate) mapped: Vec<u32>,
+    pub(crate) visited: HashSet<u32>,
+    pub(crate) candidates: Vec<u32>,
+    pub(crate) heap: BinaryHeap<Answer>,
+    pub(crate) suffix_filtered: usize,
+}
+
+impl QueryContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Number of the candidates pruned by the suffix filter in the last query.
+    pub fn suffix_filtered(&self) -> usize {
+        self.suffix_filtered
+    }
+
+    // Maps the query into the buffer, which must be given back by `recycle`.
+    pub(crate) fn map_query(
+        &mut self,
+        mapping: &Mapping,
+        query: &OrderedSet<u32>,
+    ) -> OrderedSet<u32> {
+        mapping.apply_into(query, std::mem::take(&mut self.mapped))
+    }
+
+    pub(crate) fn recycle(&mut self, mapped: OrderedSet<u32>) {
+        self.mapped = mapped.into_vec();
+    }
+}
@@ -0,0 +1,426 @@
+use anyhow::anyhow;
+use anyhow::Result;
+use hashbrown::{HashMap, HashSet};
+
+use crate::metric::{Evaluation, FilterConfig, Jaccard};
+use crate::{narrow_answers, Answer, InvertedIndex, OrderedSet, RangeSearch, Record};
+
+const FILTER_CONFIG: FilterConfig = FilterConfig::new(true, true);
+
+#[derive(Debug, Clone)]
+enum Update {
+    Insert(Record<u32>),
+    Delete(u32),
+}
+
+// An inverted index accepting updates in real time. Updates go into a delta scanned
+// linearly by queries, and are merged into the inverted index by rebuilding it.
+//
+// For a single writer with multiple readers behind a `RwLock`, a merge can run in the
+// background: build it by `prepare_merge` under the read lock, and apply it by
+// `finish_merge` under the write lock. Updates made in between are kept in the delta.
+pub struct DeltaIndex {
+    universe: u32,
+    radius: f32,
+    records: Vec<Record<u32>>,
+    base: InvertedIndex,
+    // Records inserted into the delta.
+    inserted: HashMap<u32, OrderedSet<u32>>,
+    // Ids of the records deleted from the base.
+    deleted: HashSet<u32>,
+    // Updates since the last merge.
+    log: Vec<Update>,
+    version: u64,
+    // Deleted records no longer in the base or the delta, kept in the audit mode.
+    tombstones: Option<Vec<Record<u32>>>,
+}
+
+// An inverted index rebuilt with the first `n_applied` updates of the log.
+pub struct PendingMerge {
+    records: Vec<Record<u32>>,
+    base: InvertedIndex,
+    n_applied: usize,
+    // Records dropped from the base by the merge, kept as tombstones in the audit mode.
+    removed: Vec<Record<u32>>,
+}
+
+impl DeltaIndex {
+    pub fn from_records(records: &[Record<u32>], universe: u32, radius: f32) -> Result<Self> {
+        let base = InvertedIndex::from_records(records, universe, radius)?;
+        Ok(Self {
+            universe,
+            radius,
+            records: records.to_vec(),
+            base,
+            inserted: HashMap::new(),
+            deleted: HashSet::new(),
+            log: vec![],
+            version: 0,
+            tombstones: None,
+        })
+    }
+
+    // Keeps the sets of deleted records accessible as tombstones,
+    // so that `range_query_audit` shows what they would have matched, e.g., before a takedown.
+    pub fn audit(mut self, enabled: bool) -> Self {
+        self.tombstones = enabled.then(Vec::new);
+        self
+    }
+
+    // Forgets the tombstones kept in the audit mode.
+    pub fn purge_tombstones(&mut self) {
+        if let Some(tombstones) = &mut self.tombstones {
+            tombstones.clear();
+        }
+    }
+
+    pub fn contains_id(&self, id: u32) -> bool {
+        self.inserted.contains_key(&id)
+            || (self.base.contains_id(id) && !self.deleted.contains(&id))
+    }
+
+    // Incremented by every update changing the answers, e.g., to invalidate cached answers.
+    // Merges do not change it.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn insert(&mut self, record: Record<u32>) -> Result<()> {
+        self.insert_unversioned(record)?;
+        self.version += 1;
+        Ok(())
+    }
+
+    // Returns false if the id is not found.
+    pub fn delete(&mut self, id: u32) -> bool {
+        if !self.delete_unversioned(id) {
+            return false;
+        }
+        self.version += 1;
+        true
+    }
+
+    // Replaces the set of the id if it exists, or inserts the record otherwise.
+    // Queries never observe the record deleted but not inserted yet.
+    pub fn upsert(&mut self, record: Record<u32>) -> Result<()> {
+        self.check_universe(&record.set)?;
+        self.delete_unversioned(record.id);
+        self.insert_unversioned(record)?;
+        self.version += 1;
+        Ok(())
+    }
+
+    fn insert_unversioned(&mut self, record: Record<u32>) -> Result<()> {
+        if self.contains_id(record.id) {
+            return Err(anyhow!("Duplicate record id: {}", record.id));
+        }
+        self.check_universe(&record.set)?;
+        self.inserted.insert(record.id, record.set.clone());
+        self.log.push(Update::Insert(record));
+        Ok(())
+    }
+
+    // Records deleted from the base stay in it until the next merge.
+    fn delete_unversioned(&mut self, id: u32) -> bool {
+        let removed = if let Some(set) = self.inserted.remove(&id) {
+            if let Some(tombstones) = &mut self.tombstones {
+                tombstones.push(Record { id, set });
+            }
+            true
+        } else {
+            self.base.contains_id(id) && self.deleted.insert(id)
+        };
+        if removed {
+            self.log.push(Update::Delete(id));
+        }
+        removed
+    }
+
+    fn check_universe(&self, set: &OrderedSet<u32>) -> Result<()> {
+        if set.iter().any(|&elem| elem >= self.universe) {
+            return Err(anyhow!("The record is out of the universe."));
+        }
+        Ok(())
+    }
+
+    // Number of updates not merged yet, to decide when to merge.
+    pub fn delta_len(&self) -> usize {
+        self.log.len()
+    }
+
+    pub fn range_query(&self, query: &OrderedSet<u32>) -> Vec<Answer> {
+        let mut answers = self
+            .base
+            .range_query(query)
+            .into_iter()
+            .filter(|ans| !self.deleted.contains(&ans.id))
+            .collect::<Vec<_>>();
+        let jaccard = Jaccard::new(query, self.radius, FILTER_CONFIG);
+        for (&id, set) in &self.inserted {
+            if let Evaluation::Accepted(dist) = jaccard.evaluate(set) {
+                answers.push(Answer { id, dist });
+            }
+        }
+        answers.sort_unstable();
+        answers
+    }
+
+    // Also returns the deleted records within the radius separately from the live answers,
+    // which requires the audit mode. A deleted and re-inserted id can be in both.
+    pub fn range_query_audit(&self, query: &OrderedSet<u32>) -> Result<(Vec<Answer>, Vec<Answer>)> {
+        let Some(tombstones) = &self.tombstones else {
+            return Err(anyhow!("The audit mode is disabled."));
+        };
+        let (mut answers, mut deleted): (Vec<_>, Vec<_>) = self
+            .base
+            .range_query(query)
+            .into_iter()
+            .partition(|ans| !self.deleted.contains(&ans.id));
+        let jaccard = Jaccard::new(query, self.radius, FILTER_CONFIG);
+        for (&id, set) in &self.inserted {
+            if let Evaluation::Accepted(dist) = jaccard.evaluate(set) {
+                answers.push(Answer { id, dist });
+            }
+        }
+        for record in tombstones {
+            if let Evaluation::Accepted(dist) = jaccard.evaluate(&record.set) {
+                deleted.push(Answer {
+                    id: record.id,
+                    dist,
+                });
+            }
+        }
+        answers.sort_unstable();
+        deleted.sort_unstable();
+        Ok((answers, deleted))
+    }
+
+    pub fn prepare_merge(&self) -> Result<PendingMerge> {
+        let (mut records, removed): (Vec<_>, Vec<_>) = self
+            .records
+            .iter()
+            .cloned()
+            .partition(|record| !self.deleted.contains(&record.id));
+        let removed = if self.tombstones.is_some() {
+            removed
+        } else {
+            vec![]
+        };
+        records.extend(self.inserted.iter().map(|(&id, set)| Record {
+            id,
+            set: set.clone(),
+        }));
+        records.sort_by_key(|record| record.id);
+        let base = InvertedIndex::from_records(&records, self.universe, self.radius)?;
+        Ok(PendingMerge {
+            records,
+            base,
+            n_applied: self.log.len(),
+            removed,
+        })
+    }
+
+    // Replaces the inverted index and keeps the updates made after `prepare_merge`.
+    pub fn finish_merge(&mut self, pending: PendingMerge) {
+        let log = self.log.split_off(pending.n_applied);
+        self.records = pending.records;
+        self.base = pending.base;
+        self.inserted.clear();
+        self.deleted.clear();
+        self.log.clear();
+        // Replaying the updates would tombstone their deletions again.
+        let tombstones = self.tombstones.take();
+        for update in log {
+            match update {
+                Update::Insert(record) => self.insert_unversioned(record).unwrap(),
+                Update::Delete(id) => assert!(self.delete_unversioned(id)),
+            }
+        }
+        self.tombstones = tombstones.map(|mut tombstones| {
+            tombstones.extend(pending.removed);
+            tombstones
+        });
+    }
+
+    pub fn merge(&mut self) -> Result<()> {
+        let pending = self.prepare_merge()?;
+        self.finish_merge(pending);
+        Ok(())
+    }
+}
+
+impl RangeSearch for DeltaIndex {
+    fn range_search(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Answer>> {
+        narrow_answers(self.range_query(query), radius, self.radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, RwLock};
+
+    use crate::LinearScan;
+
+    fn records() -> Vec<Record<u32>> {
+        (0..30)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_unsorted((id..id + 4).map(|e| e % 16)),
+            })
+            .collect()
+    }
+
+    fn assert_same_as_linear_scan(index: &DeltaIndex, live: &[Record<u32>]) {
+        let linear_scan = LinearScan::from_records(live, 16).unwrap();
+        for record in records() {
+            assert_eq!(
+                index.range_query(&record.set),
+                linear_scan.range_query(&record.set, 0.5).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_updates() {
+        let records = records();
+        let mut index = DeltaIndex::from_records(&records[..20], 16, 0.5).unwrap();
+        for record in &records[20..] {
+            index.insert(record.clone()).unwrap();
+        }
+        assert!(index.insert(records[0].clone()).is_err());
+        assert!(index.delete(3));
+        assert!(index.delete(25));
+        assert!(!index.delete(25));
+        assert!(!index.delete(100));
+        assert_eq!(index.delta_len(), 12);
+
+        let live = records
+            .iter()
+            .filter(|record| record.id != 3 && record.id != 25)
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_same_as_linear_scan(&index, &live);
+
+        let pending = index.prepare_merge().unwrap();
+        // Updates during the merge are kept in the delta.
+        assert!(index.delete(4));
+        index.insert(records[3].clone()).unwrap();
+        index.finish_merge(pending);
+        assert_eq!(index.delta_len(), 2);
+
+        let live = records
+            .iter()
+            .filter(|record| record.id != 4 && record.id != 25)
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_same_as_linear_scan(&index, &live);
+
+        index.merge().unwrap();
+        assert_eq!(index.delta_len(), 0);
+        assert_same_as_linear_scan(&index, &live);
+    }
+
+    #[test]
+    fn test_upsert() {
+        let records = records();
+        let mut index = DeltaIndex::from_records(&records[..20], 16, 0.5).unwrap();
+        assert_eq!(index.version(), 0);
+
+        // Replaces record 0 with the set of record 25.
+        let upserted = Record {
+            id: 0,
+            set: records[25].set.clone(),
+        };
+        index.upsert(upserted.clone()).unwrap();
+        assert_eq!(index.version(), 1);
+        index.upsert(records[25].clone()).unwrap();
+        assert_eq!(index.version(), 2);
+        assert!(index
+            .upsert(Record {
+                id: 1,
+                set: OrderedSet::from_sorted([100]).unwrap(),
+            })
+            .is_err());
+        assert_eq!(index.version(), 2);
+
+        let mut live = records[1..20].to_vec();
+        live.push(upserted.clone());
+        live.push(records[25].clone());
+        assert_same_as_linear_scan(&index, &live);
+
+        index.merge().unwrap();
+        assert_eq!(index.version(), 2);
+        assert_same_as_linear_scan(&index, &live);
+        index.upsert(upserted).unwrap();
+        assert_eq!(index.version(), 3);
+        assert_same_as_linear_scan(&index, &live);
+    }
+
+    #[test]
+    fn test_range_query_audit() {
+        let records = records();
+        let index = DeltaIndex::from_records(&records[..20], 16, 0.5).unwrap();
+        assert!(index.range_query_audit(&records[3].set).is_err());
+
+        let mut index = DeltaIndex::from_records(&records[..20], 16, 0.5)
+            .unwrap()
+            .audit(true);
+        for record in &records[20..] {
+            index.insert(record.clone()).unwrap();
+        }
+        let answers = |query: &OrderedSet<u32>| {
+            let linear_scan = LinearScan::from_records(&records, 16).unwrap();
+            linear_scan.range_query(query, 0.5).unwrap()
+        };
+        let check = |index: &DeltaIndex, ids: &[u32]| {
+            for query in [&records[3].set, &records[4].set, &records[25].set] {
+                let (live, deleted) = index.range_query_audit(query).unwrap();
+                assert_eq!(live, index.range_query(query));
+                let (expected_deleted, expected_live): (Vec<_>, Vec<_>) = answers(query)
+                    .into_iter()
+                    .partition(|ans| ids.contains(&ans.id));
+                assert_eq!(live, expected_live);
+                assert_eq!(deleted, expected_deleted);
+            }
+        };
+        // Deleted from the base and the delta.
+        assert!(index.delete(3));
+        assert!(index.delete(25));
+        check(&index, &[3, 25]);
+
+        // Deleted during the merge.
+        let pending = index.prepare_merge().unwrap();
+        assert!(index.delete(4));
+        index.finish_merge(pending);
+        check(&index, &[3, 4, 25]);
+        index.merge().unwrap();
+        check(&index, &[3, 4, 25]);
+
+        index.purge_tombstones();
+        let (_, deleted) = index.range_query_audit(&records[3].set).unwrap();
+        assert!(deleted.is_empty());
+    }
+
+    #[test]
+    fn test_background_merge() {
+        let records = records();
+        let index = DeltaIndex::from_records(&records[..20], 16, 0.5).unwrap();
+        let index = Arc::new(RwLock::new(index));
+        for record in &records[20..] {
+            index.write().unwrap().insert(record.clone()).unwrap();
+        }
+        let merger = {
+            let index = index.clone();
+            std::thread::spawn(move || {
+                let pending = index.read().unwrap().prepare_merge().unwrap();
+                index.write().unwrap().finish_merge(pending);
+            })
+        };
+        let answers = index.read().unwrap().range_query(&records[25].set);
+        merger.join().unwrap();
+        assert_eq!(index.read().unwrap().delta_len(), 0);
+        assert_eq!(index.read().unwrap().range_query(&records[25].set), answers);
+    }
+}
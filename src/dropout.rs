@@ -0,0 +1,85 @@
+use anyhow::anyhow;
+use anyhow::Result;
+use rand::Rng;
+use rand::SeedableRng;
+use rand_xoshiro::Xoshiro256PlusPlus;
+
+use crate::OrderedSet;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Dropout {
+    n_probes: usize,
+    rate: f32,
+    seed: u64,
+}
+
+impl Dropout {
+    pub fn new(n_probes: usize, rate: f32, seed: u64) -> Result<Self> {
+        if !(0.0..1.0).contains(&rate) {
+            return Err(anyhow!("The dropout rate must be in [0, 1)."));
+        }
+        Ok(Self {
+            n_probes,
+            rate,
+            seed,
+        })
+    }
+
+    // Generates the same probes for the same query.
+    pub fn probes(&self, query: &OrderedSet<u32>) -> Vec<OrderedSet<u32>> {
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(self.seed);
+        let mut probes = Vec::with_capacity(self.n_probes);
+        for _ in 0..self.n_probes {
+            let elems = query
+                .iter()
+                .cloned()
+                .filter(|_| rng.gen::<f32>() >= self.rate)
+                .collect::<Vec<_>>();
+            probes.push(OrderedSet::from_sorted(elems).unwrap());
+        }
+        probes
+    }
+
+    pub const fn n_probes(&self) -> usize {
+        self.n_probes
+    }
+
+    pub const fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    pub const fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probes() {
+        let query = OrderedSet::from_sorted(0..100).unwrap();
+        let dropout = Dropout::new(3, 0.5, 334).unwrap();
+        let probes = dropout.probes(&query);
+        assert_eq!(probes.len(), 3);
+        for probe in &probes {
+            assert!(probe.len() < query.len());
+            assert!(probe.iter().all(|elem| *elem < 100));
+        }
+        assert_eq!(probes, dropout.probes(&query));
+    }
+
+    #[test]
+    fn test_no_dropout() {
+        let query = OrderedSet::from_sorted(0..100).unwrap();
+        let dropout = Dropout::new(2, 0.0, 334).unwrap();
+        assert_eq!(dropout.probes(&query), vec![query.clone(), query]);
+    }
+
+    #[test]
+    fn test_invalid_rate() {
+        assert!(Dropout::new(1, 1.0, 334).is_err());
+        assert!(Dropout::new(1, -0.1, 334).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use hashbrown::HashMap;
+
+use crate::{check_radius, id_offsets, Answer, Mapping, OrderedSet, RangeSearch, Record};
+
+// Searches records identical to the query by hashing the full mapped sets.
+// Any radius is answered as 0, i.e., the lower bound of the other indexes,
+// and it serves as a fast path before them.
+pub struct ExactIndex {
+    mapping: Arc<Mapping>,
+    index: HashMap<OrderedSet<u32>, Vec<u32>>,
+}
+
+impl ExactIndex {
+    pub fn from_records(records: &[Record<u32>], universe: u32) -> Result<Self> {
+        let mapping = Mapping::from_records(records, universe)?;
+        Self::from_records_with_mapping(records, Arc::new(mapping))
+    }
+
+    pub fn from_records_with_mapping(
+        records: &[Record<u32>],
+        mapping: Arc<Mapping>,
+    ) -> Result<Self> {
+        if !mapping.covers(records) {
+            return Err(anyhow!("The mapping does not cover the records."));
+        }
+        id_offsets(records)?;
+        let mut index = HashMap::new();
+        for record in records {
+            // Empty sets have undefined distances as in the other indexes.
+            if record.set.is_empty() {
+                continue;
+            }
+            index
+                .entry(mapping.apply(&record.set))
+                .or_insert_with(Vec::new)
+                .push(record.id);
+        }
+        for ids in index.values_mut() {
+            ids.sort_unstable();
+        }
+        Ok(Self { mapping, index })
+    }
+
+    pub fn mapping(&self) -> &Arc<Mapping> {
+        &self.mapping
+    }
+
+    pub fn range_query(&self, query: &OrderedSet<u32>) -> Vec<Answer> {
+        self.range_query_mapped(&self.mapping.apply(query))
+    }
+
+    // The query must be already mapped by `self.mapping()`.
+    pub fn range_query_mapped(&self, query: &OrderedSet<u32>) -> Vec<Answer> {
+        self.index.get(query).map_or_else(Vec::new, |ids| {
+            ids.iter().map(|&id| Answer { id, dist: 0.0 }).collect()
+        })
+    }
+
+    // Number of distinct sets.
+    pub fn n_keys(&self) -> usize {
+        self.index.len()
+    }
+}
+
+impl RangeSearch for ExactIndex {
+    fn range_search(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Answer>> {
+        check_radius(radius)?;
+        Ok(self.range_query(query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::LinearScan;
+
+    #[test]
+    fn test_exact_index() {
+        let records = (0..30)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_unsorted((0..id % 4).map(|e| e * 3 + id % 2)),
+            })
+            .collect::<Vec<_>>();
+        let index = ExactIndex::from_records(&records, 10).unwrap();
+        let linear_scan = LinearScan::from_records(&records, 10).unwrap();
+        assert_eq!(index.n_keys(), 3);
+        for record in &records {
+            assert_eq!(
+                index.range_search(&record.set, 0.5).unwrap(),
+                linear_scan.range_query(&record.set, 0.0).unwrap()
+            );
+        }
+        assert!(index.range_search(&records[1].set, -1.0).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use hashbrown::HashMap;
+
+use crate::lsh::MinHash;
+use crate::{union_answers, Answer, InvertedIndex, Mapping, OrderedSet, Record};
+
+// Routes queries to the MinHash-LSH buckets sharing band keys with them,
+// and searches exactly in each bucket with an inverted index.
+pub struct HybridIndex {
+    minhash: MinHash,
+    mapping: Arc<Mapping>,
+    buckets: Vec<HashMap<u64, InvertedIndex>>,
+}
+
+impl HybridIndex {
+    pub fn from_records(
+        records: &[Record<u32>],
+        universe: u32,
+        radius: f32,
+        minhash: MinHash,
+    ) -> Result<Self> {
+        let mapping = Arc::new(Mapping::from_records(records, universe)?);
+        let mut partitions = vec![HashMap::new(); minhash.n_bands()];
+        for record in records {
+            let keys = minhash.band_keys(&record.set);
+            for (partition, key) in partitions.iter_mut().zip(keys) {
+                partition
+                    .entry(key)
+                    .or_insert_with(Vec::new)
+                    .push(record.clone());
+            }
+        }
+        let mut buckets = Vec::with_capacity(partitions.len());
+        for partition in partitions {
+            let mut bucket = HashMap::with_capacity(partition.len());
+            for (key, records) in partition {
+                let index =
+                    InvertedIndex::from_records_with_mapping(&records, mapping.clone(), radius)?;
+                bucket.insert(key, index);
+            }
+            buckets.push(bucket);
+        }
+        Ok(Self {
+            minhash,
+            mapping,
+            buckets,
+        })
+    }
+
+    // Probes the buckets of the first `n_probes` bands;
+    // more probes give higher recall and latency.
+    pub fn range_query(&self, query: &OrderedSet<u32>, n_probes: usize) -> Result<Vec<Answer>> {
+        if n_probes == 0 || n_probes > self.buckets.len() {
+            return Err(anyhow!(
+                "The number of probes must be in 1..={}.",
+                self.buckets.len()
+            ));
+        }
+        let keys = self.minhash.band_keys(query);
+        let mapped = self.mapping.apply(query);
+        let mut answers = Vec::new();
+        for (bucket, key) in self.buckets.iter().zip(keys).take(n_probes) {
+            if let Some(index) = bucket.get(&key) {
+                answers.extend(index.range_query_unchecked(&mapped));
+            }
+        }
+        Ok(union_answers(answers))
+    }
+
+    pub fn mapping(&self) -> &Arc<Mapping> {
+        &self.mapping
+    }
+
+    pub fn n_buckets(&self) -> usize {
+        self.buckets.iter().map(|bucket| bucket.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_query() {
+        let a = OrderedSet::from_sorted([1, 2, 3, 4, 5, 6]).unwrap();
+        let b = OrderedSet::from_sorted([1, 2, 3, 4, 5, 6, 7]).unwrap();
+        let c = OrderedSet::from_sorted([10, 11, 12]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
+        let minhash = MinHash::new(16, 1, 334).unwrap();
+        let index = HybridIndex::from_records(&records, 20, 0.5, minhash).unwrap();
+
+        let query = OrderedSet::from_sorted([1, 2, 3, 4, 5, 6]).unwrap();
+        let answers = index.range_query(&query, 16).unwrap();
+        assert_eq!(
+            answers,
+            vec![
+                Answer {
+                    id: 0,
+                    dist: 1. - 6. / 6.
+                },
+                Answer {
+                    id: 1,
+                    dist: 1. - 6. / 7.
+                },
+            ]
+        );
+        assert!(!index.range_query(&query, 1).unwrap().is_empty());
+        assert!(index.range_query(&query, 0).is_err());
+        assert!(index.range_query(&query, 17).is_err());
+    }
+}
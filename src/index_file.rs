@@ -0,0 +1,468 @@
+use std::fmt;
+use std::io::{Read, Write};
+
+use anyhow::anyhow;
+use anyhow::Result;
+use hashbrown::HashMap;
+
+use crate::text::FeatureExtractor;
+use crate::{FrozenRecord, Mapping, OrderedSet, Record};
+
+// Layout of an index file, where every integer is little-endian and every length is
+// u64 regardless of the width of usize, so files are portable across platforms:
+//
+//   magic: [u8; 8]
+//   version: u32
+//   elem_width: u8, the bytes of an element, i.e., 4 for u32
+//   metric: u8
+//   kind: u8
+//   extractor_hash: u8 flag followed by u64 if set, since version 2
+//   body of the index kind
+//
+// Version 1 files have no extractor hash and are still readable.
+pub const MAGIC: [u8; 8] = *b"SSEINDEX";
+pub const FORMAT_VERSION: u32 = 2;
+const MIN_FORMAT_VERSION: u32 = 1;
+const ELEM_WIDTH: u8 = 4;
+
+// Bounds the preallocation by length prefixes, which can be corrupted.
+const MAX_PREALLOC: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexKind {
+    LinearScan,
+    InvertedIndex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Jaccard,
+    ElementWeightedJaccard,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    pub kind: IndexKind,
+    pub metric: Metric,
+    // `FeatureExtractor::config_hash` of the extractor of the records, if given on saving.
+    pub extractor_hash: Option<u64>,
+}
+
+// Errors of incompatible index files, which can be downcast from the errors of loading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    NotIndexFile,
+    UnsupportedVersion(u32),
+    UnsupportedElemWidth(u8),
+    UnknownMetric(u8),
+    UnknownKind(u8),
+    KindMismatch {
+        expected: IndexKind,
+        found: IndexKind,
+    },
+    MetricMismatch {
+        expected: Metric,
+        found: Metric,
+    },
+    ExtractorMismatch {
+        expected: u64,
+        found: u64,
+    },
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotIndexFile => write!(f, "Not an index file."),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "Unsupported format version: {}", version)
+            }
+            Self::UnsupportedElemWidth(width) => {
+                write!(f, "Unsupported element width: {}", width)
+            }
+            Self::UnknownMetric(metric) => write!(f, "Unknown metric: {}", metric),
+            Self::UnknownKind(kind) => write!(f, "Unknown index kind: {}", kind),
+            Self::KindMismatch { expected, found } => {
+                write!(f, "Expected {:?} but found {:?}.", expected, found)
+            }
+            Self::MetricMismatch { expected, found } => {
+                write!(f, "Expected {:?} but found {:?}.", expected, found)
+            }
+            Self::ExtractorMismatch { expected, found } => write!(
+                f,
+                "The index was built by another extractor: {:016x} != {:016x}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl IndexKind {
+    fn from_u8(kind: u8) -> Result<Self, FormatError> {
+        match kind {
+            0 => Ok(Self::LinearScan),
+            1 => Ok(Self::InvertedIndex),
+            _ => Err(FormatError::UnknownKind(kind)),
+        }
+    }
+}
+
+impl Metric {
+    fn from_u8(metric: u8) -> Result<Self, FormatError> {
+        match metric {
+            0 => Ok(Self::Jaccard),
+            1 => Ok(Self::ElementWeightedJaccard),
+            _ => Err(FormatError::UnknownMetric(metric)),
+        }
+    }
+}
+
+pub fn write_header<W>(writer: W, header: &Header) -> Result<()>
+where
+    W: Write,
+{
+    let mut enc = Encoder::new(writer);
+    enc.bytes(&MAGIC)?;
+    enc.u32(header.version)?;
+    enc.u8(ELEM_WIDTH)?;
+    enc.u8(header.metric as u8)?;
+    enc.u8(header.kind as u8)?;
+    if header.version >= 2 {
+        match header.extractor_hash {
+            Some(hash) => {
+                enc.u8(1)?;
+                enc.u64(hash)?;
+            }
+            None => enc.u8(0)?,
+        }
+    }
+    Ok(())
+}
+
+// Reads the header only, e.g., to inspect a file before loading it.
+pub fn read_header<R>(reader: R) -> Result<Header>
+where
+    R: Read,
+{
+    let mut dec = Decoder::new(reader);
+    let mut magic = [0; 8];
+    dec.bytes(&mut magic)?;
+    if magic != MAGIC {
+        return Err(FormatError::NotIndexFile.into());
+    }
+    let version = dec.u32()?;
+    if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&version) {
+        return Err(FormatError::UnsupportedVersion(version).into());
+    }
+    let elem_width = dec.u8()?;
+    if elem_width != ELEM_WIDTH {
+        return Err(FormatError::UnsupportedElemWidth(elem_width).into());
+    }
+    let metric = Metric::from_u8(dec.u8()?)?;
+    let kind = IndexKind::from_u8(dec.u8()?)?;
+    let extractor_hash = match version {
+        1 => None,
+        _ => match dec.u8()? {
+            0 => None,
+            _ => Some(dec.u64()?),
+        },
+    };
+    Ok(Header {
+        version,
+        kind,
+        metric,
+        extractor_hash,
+    })
+}
+
+// Checks the header against the index kind to load and the extractor of the queries.
+// Files without the extractor hash pass the extractor check.
+pub(crate) fn check_header(
+    header: &Header,
+    kind: IndexKind,
+    extractor: Option<&FeatureExtractor>,
+) -> Result<()> {
+    if header.kind != kind {
+        return Err(FormatError::KindMismatch {
+            expected: kind,
+            found: header.kind,
+        }
+        .into());
+    }
+    if let (Some(found), Some(extractor)) = (header.extractor_hash, extractor) {
+        let expected = extractor.config_hash();
+        if found != expected {
+            return Err(FormatError::ExtractorMismatch { expected, found }.into());
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn current_header(
+    kind: IndexKind,
+    metric: Metric,
+    extractor: Option<&FeatureExtractor>,
+) -> Header {
+    Header {
+        version: FORMAT_VERSION,
+        kind,
+        metric,
+        extractor_hash: extractor.map(|extractor| extractor.config_hash()),
+    }
+}
+
+// Writes the parts shared by the indexes. The offsets are derived from them on reading.
+pub(crate) fn write_records<W>(
+    enc: &mut Encoder<W>,
+    mapping: &Mapping,
+    records: &[FrozenRecord],
+    duplicates: &HashMap<u32, Vec<u32>>,
+) -> Result<()>
+where
+    W: Write,
+{
+    enc.u32s(mapping.as_slice())?;
+    enc.len(records.len())?;
+    for record in records {
+        enc.u32(record.id)?;
+        enc.u32s(record.set.as_slice())?;
+    }
+    // Sorted for the files to be reproducible.
+    let mut duplicates = duplicates.iter().collect::<Vec<_>>();
+    duplicates.sort_unstable_by_key(|&(&kept, _)| kept);
+    enc.len(duplicates.len())?;
+    for (&kept, ids) in duplicates {
+        enc.u32(kept)?;
+        enc.u32s(ids)?;
+    }
+    Ok(())
+}
+
+pub(crate) type SharedParts = (Mapping, Vec<FrozenRecord>, HashMap<u32, Vec<u32>>);
+
+pub(crate) fn read_records<R>(dec: &mut Decoder<R>) -> Result<SharedParts>
+where
+    R: Read,
+{
+    let mapping = Mapping::from_slice(&dec.u32s()?);
+    let n_records = dec.len()?;
+    let mut records = Vec::with_capacity(n_records.min(MAX_PREALLOC));
+    for _ in 0..n_records {
+        let id = dec.u32()?;
+        let set = OrderedSet::from_sorted(dec.u32s()?)?;
+        records.push(Record { id, set }.freeze());
+    }
+    let n_duplicates = dec.len()?;
+    let mut duplicates = HashMap::with_capacity(n_duplicates.min(MAX_PREALLOC));
+    for _ in 0..n_duplicates {
+        let kept = dec.u32()?;
+        duplicates.insert(kept, dec.u32s()?);
+    }
+    Ok((mapping, records, duplicates))
+}
+
+pub(crate) struct Encoder<W> {
+    writer: W,
+}
+
+impl<W> Encoder<W>
+where
+    W: Write,
+{
+    pub(crate) fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write_all(bytes)?;
+        Ok(())
+    }
+
+    pub(crate) fn u8(&mut self, v: u8) -> Result<()> {
+        self.bytes(&[v])
+    }
+
+    pub(crate) fn u32(&mut self, v: u32) -> Result<()> {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    pub(crate) fn u64(&mut self, v: u64) -> Result<()> {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    pub(crate) fn f32(&mut self, v: f32) -> Result<()> {
+        self.u32(v.to_bits())
+    }
+
+    // Lengths are u64 regardless of the width of usize.
+    pub(crate) fn len(&mut self, len: usize) -> Result<()> {
+        self.u64(len as u64)
+    }
+
+    pub(crate) fn u32s(&mut self, vs: &[u32]) -> Result<()> {
+        self.len(vs.len())?;
+        vs.iter().try_for_each(|&v| self.u32(v))
+    }
+
+    pub(crate) fn f32s(&mut self, vs: &[f32]) -> Result<()> {
+        self.len(vs.len())?;
+        vs.iter().try_for_each(|&v| self.f32(v))
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub(crate) struct Decoder<R> {
+    reader: R,
+}
+
+impl<R> Decoder<R>
+where
+    R: Read,
+{
+    pub(crate) fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    fn bytes(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.reader.read_exact(buf)?;
+        Ok(())
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        let mut buf = [0; 1];
+        self.bytes(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        let mut buf = [0; 4];
+        self.bytes(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        let mut buf = [0; 8];
+        self.bytes(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    pub(crate) fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    pub(crate) fn len(&mut self) -> Result<usize> {
+        let len = self.u64()?;
+        usize::try_from(len).map_err(|_| anyhow!("Too long for the platform: {}", len))
+    }
+
+    pub(crate) fn u32s(&mut self) -> Result<Vec<u32>> {
+        let len = self.len()?;
+        let mut vs = Vec::with_capacity(len.min(MAX_PREALLOC));
+        for _ in 0..len {
+            vs.push(self.u32()?);
+        }
+        Ok(vs)
+    }
+
+    pub(crate) fn f32s(&mut self) -> Result<Vec<f32>> {
+        let len = self.len()?;
+        let mut vs = Vec::with_capacity(len.min(MAX_PREALLOC));
+        for _ in 0..len {
+            vs.push(self.f32()?);
+        }
+        Ok(vs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let extractor = FeatureExtractor::seeded(1..=2, 100, 334).unwrap();
+        let header = current_header(IndexKind::InvertedIndex, Metric::Jaccard, Some(&extractor));
+        let mut buf = vec![];
+        write_header(&mut buf, &header).unwrap();
+        assert_eq!(buf.len(), 8 + 4 + 3 + 1 + 8);
+        assert_eq!(&buf[8..12], &[2, 0, 0, 0]);
+        assert_eq!(read_header(buf.as_slice()).unwrap(), header);
+
+        assert!(check_header(&header, IndexKind::InvertedIndex, Some(&extractor)).is_ok());
+        let other = FeatureExtractor::seeded(1..=2, 100, 335).unwrap();
+        let err = check_header(&header, IndexKind::InvertedIndex, Some(&other)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FormatError>(),
+            Some(FormatError::ExtractorMismatch { .. })
+        ));
+        let err = check_header(&header, IndexKind::LinearScan, None).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FormatError>(),
+            Some(FormatError::KindMismatch { .. })
+        ));
+
+        // Version 1 has no extractor hash.
+        let v1 = Header {
+            version: 1,
+            extractor_hash: None,
+            ..header
+        };
+        let mut buf = vec![];
+        write_header(&mut buf, &v1).unwrap();
+        assert_eq!(buf.len(), 8 + 4 + 3);
+        assert_eq!(read_header(buf.as_slice()).unwrap(), v1);
+        assert!(check_header(&v1, IndexKind::InvertedIndex, Some(&other)).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let header = current_header(IndexKind::LinearScan, Metric::Jaccard, None);
+        let mut buf = vec![];
+        write_header(&mut buf, &header).unwrap();
+
+        let read_err = |buf: &[u8]| {
+            read_header(buf)
+                .unwrap_err()
+                .downcast_ref::<FormatError>()
+                .cloned()
+        };
+        let mut broken = buf.clone();
+        broken[0] = b'X';
+        assert_eq!(read_err(&broken), Some(FormatError::NotIndexFile));
+        let mut broken = buf.clone();
+        broken[8] = 3;
+        assert_eq!(read_err(&broken), Some(FormatError::UnsupportedVersion(3)));
+        let mut broken = buf.clone();
+        broken[12] = 8;
+        assert_eq!(
+            read_err(&broken),
+            Some(FormatError::UnsupportedElemWidth(8))
+        );
+        let mut broken = buf.clone();
+        broken[14] = 7;
+        assert_eq!(read_err(&broken), Some(FormatError::UnknownKind(7)));
+        // Truncated.
+        assert_eq!(read_err(&buf[..10]), None);
+        assert!(read_header(&buf[..10]).is_err());
+    }
+
+    #[test]
+    fn test_long_len() {
+        let len = (u64::from(u32::MAX) + 1).to_le_bytes();
+        // Lengths beyond u32 overflow usize of 32-bit platforms.
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(Decoder::new(&len[..]).len().unwrap(), 1 << 32);
+        #[cfg(not(target_pointer_width = "64"))]
+        assert!(Decoder::new(&len[..]).len().is_err());
+        // The elements are missing, without allocating the length.
+        assert!(Decoder::new(&len[..]).u32s().is_err());
+        assert!(Decoder::new(&len[..]).f32s().is_err());
+    }
+}
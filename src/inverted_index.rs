@@ -1,98 +1,1702 @@
+use std::io::{Read, Write};
+use std::ops::{Range, RangeInclusive};
+use std::sync::Arc;
+
+use anyhow::anyhow;
 use anyhow::Result;
 use hashbrown::{HashMap, HashSet};
+use rayon::prelude::*;
+use rayon::ThreadPool;
+
+use crate::index_file::{
+    check_header, current_header, read_header, read_records, write_header, write_records, Decoder,
+    Encoder, IndexKind, Metric,
+};
+use crate::mapping::{config_hash, elem_freqs};
+use crate::metric::{
+    suffix_hamming_bound, Containment, ContainmentMode, Contrastive, ElementWeightedJaccard,
+    Evaluation, FilterBounds, FilterConfig, Jaccard, RationalThreshold,
+};
+use crate::overlap::{merge_lists, MergeStrategy};
+use crate::text::FeatureExtractor;
+use crate::{
+    admit_records, alias_offsets, check_contrastive_lambda, check_k, check_max_results,
+    check_radius, check_records, dedup_records, id_offsets, install, integrity_result,
+    narrow_answers, truncate_answers, union_answers, verify_parallel, Answer, Budget, Dropout,
+    FrozenRecord, FrozenSet, IntegrityReport, MappedQuery, Mapping, OrderedSet, QueryContext,
+    RangeSearch, Record,
+};
+
+const FILTER_CONFIG: FilterConfig = FilterConfig::new(true, true);
+
+pub struct InvertedIndex {
+    mapping: Arc<Mapping>,
+    // Never mutated after construction, thus stored without spare capacity.
+    records: Vec<FrozenRecord>,
+    offsets: HashMap<u32, u32>,
+    duplicates: HashMap<u32, Vec<u32>>,
+    index: HashMap<u32, Vec<u32>>,
+    radius: f32,
+    threshold: f32,
+    // Weights of the mapped elements for the weighted prefix filter.
+    weights: Option<Vec<f32>>,
+    // Threshold of the exact mode, which overrides `threshold` in the bounds.
+    rational: Option<RationalThreshold>,
+    // `FeatureExtractor::config_hash` of the extractor of the records, and the hash
+    // combined with the mapping to check `MappedQuery`.
+    extractor_hash: Option<u64>,
+    config_hash: Option<u64>,
+    max_results: Option<usize>,
+    config: FilterConfig,
+    // Pool of the parallel queries, or the current rayon pool if none.
+    pool: Option<Arc<ThreadPool>>,
+    merge: Option<MergeStrategy>,
+    // Posting lists of all the elements, for the merge strategy and the containment queries.
+    full_index: Option<HashMap<u32, Vec<u32>>>,
+}
+
+impl InvertedIndex {
+    pub fn from_records(records: &[Record<u32>], universe: u32, radius: f32) -> Result<Self> {
+        let mapping = Mapping::from_records(records, universe)?;
+        Self::from_records_with_mapping(records, Arc::new(mapping), radius)
+    }
+
+    // Indexes only the records with set lengths within `set_lens`,
+    // and also returns the ids of the skipped records.
+    pub fn from_records_within(
+        records: &[Record<u32>],
+        universe: u32,
+        radius: f32,
+        set_lens: RangeInclusive<usize>,
+    ) -> Result<(Self, Vec<u32>)> {
+        let (records, skipped) = admit_records(records, &set_lens);
+        Ok((Self::from_records(&records, universe, radius)?, skipped))
+    }
+
+    pub fn from_records_with_mapping(
+        records: &[Record<u32>],
+        mapping: Arc<Mapping>,
+        radius: f32,
+    ) -> Result<Self> {
+        check_radius(radius)?;
+        if !mapping.covers(records) {
+            return Err(anyhow!("The mapping does not cover the records."));
+        }
+        let threshold = Jaccard::<u32>::threshold(radius);
+        let mut records = records
+            .iter()
+            .map(|record| {
+                Record {
+                    id: record.id,
+                    set: mapping.apply(&record.set),
+                }
+                .freeze()
+            })
+            .collect::<Vec<_>>();
+        // Sorting records by length makes every posting list sorted by length,
+        // so that the length window of a query is a range of each list.
+        records.sort_by_key(|record| record.set.len());
+        let offsets = id_offsets(&records)?;
+        let index = Self::build_index(&records, threshold, None, None);
+        Ok(Self {
+            mapping,
+            records,
+            offsets,
+            duplicates: HashMap::new(),
+            index,
+            radius,
+            threshold,
+            weights: None,
+            rational: None,
+            extractor_hash: None,
+            config_hash: None,
+            max_results: None,
+            config: FILTER_CONFIG,
+            pool: None,
+            merge: None,
+            full_index: None,
+        })
+    }
+
+    // Searches records by the element-weighted Jaccard distance, e.g., with IDF weights,
+    // where `weights[e]` is the positive weight of (unmapped) element `e`.
+    // The indexed prefixes are chosen by the weight mass rather than the count.
+    pub fn from_records_weighted(
+        records: &[Record<u32>],
+        universe: u32,
+        radius: f32,
+        weights: &[f32],
+    ) -> Result<Self> {
+        if weights.len() != universe as usize {
+            return Err(anyhow!("The weights must be given for all the elements."));
+        }
+        if let Some(w) = weights.iter().find(|w| !w.is_finite() || **w <= 0.0) {
+            return Err(anyhow!("Invalid weight: {}", w));
+        }
+        let mut index = Self::from_records(records, universe, radius)?;
+        index.weights = Some(map_weights(&index.mapping, weights));
+        index.index = index.rebuild_index();
+        Ok(index)
+    }
+
+    // Decides the bounds and the acceptance exactly by the rational threshold, so that
+    // the answers agree with `LinearScan::range_query_rational` on the borderline pairs.
+    pub fn from_records_rational(
+        records: &[Record<u32>],
+        universe: u32,
+        threshold: RationalThreshold,
+    ) -> Result<Self> {
+        let mut index = Self::from_records(records, universe, threshold.radius())?;
+        index.rational = Some(threshold);
+        index.index = index.rebuild_index();
+        Ok(index)
+    }
+
+    fn rebuild_index(&self) -> HashMap<u32, Vec<u32>> {
+        Self::build_index(
+            &self.records,
+            self.threshold,
+            self.weights.as_deref(),
+            self.rational.as_ref(),
+        )
+    }
+
+    fn build_full_index(records: &[FrozenRecord]) -> HashMap<u32, Vec<u32>> {
+        let mut index = HashMap::new();
+        for (i, record) in records.iter().enumerate() {
+            for &elem in record.set.iter() {
+                index.entry(elem).or_insert_with(Vec::new).push(i as u32);
+            }
+        }
+        index
+    }
+
+    fn build_index(
+        records: &[FrozenRecord],
+        threshold: f32,
+        weights: Option<&[f32]>,
+        rational: Option<&RationalThreshold>,
+    ) -> HashMap<u32, Vec<u32>> {
+        let mut index = HashMap::new();
+        for (i, record) in records.iter().enumerate() {
+            let pfx_len = Self::index_prefix_len(&record.set, threshold, weights, rational);
+            for &elem in record.set.iter().take(pfx_len) {
+                index.entry(elem).or_insert_with(Vec::new).push(i as u32);
+            }
+        }
+        index
+    }
+
+    fn index_prefix_len(
+        set: &FrozenSet<u32>,
+        threshold: f32,
+        weights: Option<&[f32]>,
+        rational: Option<&RationalThreshold>,
+    ) -> usize {
+        match (weights, rational) {
+            (Some(weights), _) => ElementWeightedJaccard::prefix_len(set, weights, threshold),
+            (None, Some(rational)) => rational.index_prefix_len(set.len()),
+            (None, None) => Jaccard::<u32>::index_prefix_len(set.len(), threshold),
+        }
+    }
+
+    fn query_prefix_len(&self, query_len: usize) -> usize {
+        match &self.rational {
+            Some(rational) => rational.query_prefix_len(query_len),
+            None => Jaccard::<u32>::query_prefix_len(query_len, self.threshold),
+        }
+    }
+
+    fn jaccard<'a, S: AsRef<[u32]>>(&self, query: &'a OrderedSet<u32, S>) -> Jaccard<'a, u32> {
+        match self.rational {
+            Some(rational) => Jaccard::with_rational(query, rational, self.config.clone()),
+            None => Jaccard::new(query, 1. - self.threshold, self.config.clone()),
+        }
+    }
+
+    // Filters of the verification, with both the length and position filters by default.
+    // The suffix filter of PPJoin+ by `suffix_depth` prunes the candidates on collecting them,
+    // and `QueryContext::suffix_filtered` tells how many were pruned.
+    pub fn filter_config(mut self, config: FilterConfig) -> Result<Self> {
+        config.check_supported()?;
+        self.config = config;
+        Ok(self)
+    }
+
+    // Runs `range_query_parallel` in the pool, e.g., of `build_thread_pool`
+    // to control the parallelism per run. The answers do not depend on it.
+    pub fn thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    // Limits range queries to the nearest `limit` answers, e.g., against a mis-set radius
+    // accepting most of the database. `range_query_truncated` tells whether answers were dropped.
+    // The limit applies to `range_query` and its variants.
+    pub fn max_results(mut self, limit: usize) -> Result<Self> {
+        check_max_results(limit)?;
+        self.max_results = Some(limit);
+        Ok(self)
+    }
+
+    // Generates the candidates by merging the posting lists of all the query elements
+    // into the records in at least T of them, where T is the least overlap within the
+    // length window, instead of by the prefix filter, e.g., to measure which strategy
+    // is the fastest on a dataset. The lists of all the elements are built besides
+    // the prefix ones and are not saved. Weighted indexes ignore it.
+    pub fn merge_strategy(mut self, strategy: MergeStrategy) -> Self {
+        self.merge = Some(strategy);
+        self.containment_lists()
+    }
+
+    // Builds the posting lists of all the elements for `find_supersets` and `find_subsets`,
+    // which are not saved either.
+    pub fn containment_lists(mut self) -> Self {
+        if self.full_index.is_none() {
+            self.full_index = Some(Self::build_full_index(&self.records));
+        }
+        self
+    }
+
+    // Keeps one record per distinct set so that answers report the kept ids only.
+    // The other ids are given by `duplicates` and share the kept records,
+    // e.g., excluding one of them excludes the kept record.
+    pub fn deduplicate(mut self) -> Self {
+        let duplicates = dedup_records(&mut self.records);
+        self.offsets = id_offsets(&self.records).unwrap();
+        alias_offsets(&mut self.offsets, &duplicates);
+        self.duplicates = duplicates;
+        self.index = self.rebuild_index();
+        if let Some(full_index) = self.full_index.as_mut() {
+            *full_index = Self::build_full_index(&self.records);
+        }
+        self
+    }
+
+    // Returns the ids of the records removed as duplicates of the record.
+    pub fn duplicates(&self, id: u32) -> &[u32] {
+        self.duplicates.get(&id).map_or(&[], |ids| ids.as_slice())
+    }
+
+    pub fn mapping(&self) -> &Arc<Mapping> {
+        &self.mapping
+    }
+
+    // Records the extractor of the records, so that `MappedQuery` by another extractor
+    // or mapping fails instead of silently returning wrong answers.
+    pub fn extractor(self, extractor: &FeatureExtractor) -> Self {
+        self.with_extractor_hash(Some(extractor.config_hash()))
+    }
+
+    fn with_extractor_hash(mut self, extractor_hash: Option<u64>) -> Self {
+        self.extractor_hash = extractor_hash;
+        self.config_hash = extractor_hash.map(|hash| config_hash(hash, &self.mapping));
+        self
+    }
+
+    // Hash of the extractor and the mapping, if the extractor is known.
+    pub const fn config_hash(&self) -> Option<u64> {
+        self.config_hash
+    }
+
+    // Returns the mapped set of the record.
+    pub fn get_record(&self, id: u32) -> Option<&FrozenSet<u32>> {
+        self.offsets
+            .get(&id)
+            .map(|&i| &self.records[i as usize].set)
+    }
+
+    pub fn contains_id(&self, id: u32) -> bool {
+        self.offsets.contains_key(&id)
+    }
+
+    // Returns the posting list of the (unmapped) element, i.e., the offsets of the records
+    // whose indexed prefixes contain it. `record_id` gives their ids.
+    pub fn posting(&self, elem: u32) -> Option<&[u32]> {
+        let &mapped = self.mapping.as_slice().get(elem as usize)?;
+        self.index.get(&mapped).map(|list| list.as_slice())
+    }
+
+    // Iterates over the (unmapped) elements with posting lists and the lengths of the lists,
+    // e.g., to find the heaviest lists slowing down queries.
+    pub fn postings(&self) -> impl Iterator<Item = (u32, usize)> + '_ {
+        let inverse = self.mapping.inverse();
+        self.index
+            .iter()
+            .map(move |(&elem, list)| (inverse.as_slice()[elem as usize], list.len()))
+    }
+
+    pub fn record_id(&self, offset: u32) -> Option<u32> {
+        self.records.get(offset as usize).map(|record| record.id)
+    }
+
+    // Writes the index in the format of `index_file`, with the hash of the extractor
+    // of the records if given.
+    pub fn save<W>(&self, mut writer: W, extractor: Option<&FeatureExtractor>) -> Result<()>
+    where
+        W: Write,
+    {
+        // The format has no field for the threshold, which would be loaded as the float radius.
+        if self.rational.is_some() {
+            return Err(anyhow!(
+                "Indexes with a rational threshold cannot be saved."
+            ));
+        }
+        let metric = match self.weights {
+            Some(_) => Metric::ElementWeightedJaccard,
+            None => Metric::Jaccard,
+        };
+        let header = current_header(IndexKind::InvertedIndex, metric, extractor);
+        write_header(&mut writer, &header)?;
+        let mut enc = Encoder::new(writer);
+        write_records(&mut enc, &self.mapping, &self.records, &self.duplicates)?;
+        enc.f32(self.radius)?;
+        if let Some(weights) = &self.weights {
+            enc.f32s(weights)?;
+        }
+        let mut postings = self.index.iter().collect::<Vec<_>>();
+        postings.sort_unstable_by_key(|&(&elem, _)| elem);
+        enc.len(postings.len())?;
+        for (&elem, list) in postings {
+            enc.u32(elem)?;
+            enc.u32s(list)?;
+        }
+        Ok(())
+    }
+
+    // Reads an index written by `save` and validates it. Fails with `FormatError` if the file
+    // is incompatible, including when it was built by an extractor other than `extractor`.
+    pub fn load<R>(mut reader: R, extractor: Option<&FeatureExtractor>) -> Result<Self>
+    where
+        R: Read,
+    {
+        let header = read_header(&mut reader)?;
+        check_header(&header, IndexKind::InvertedIndex, extractor)?;
+        let mut dec = Decoder::new(reader);
+        let (mapping, records, duplicates) = read_records(&mut dec)?;
+        let radius = dec.f32()?;
+        check_radius(radius)?;
+        let weights = match header.metric {
+            Metric::Jaccard => None,
+            Metric::ElementWeightedJaccard => Some(dec.f32s()?),
+        };
+        let n_postings = dec.len()?;
+        let mut index = HashMap::new();
+        for _ in 0..n_postings {
+            let elem = dec.u32()?;
+            index.insert(elem, dec.u32s()?);
+        }
+        let mut offsets = id_offsets(&records)?;
+        alias_offsets(&mut offsets, &duplicates);
+        let index = Self {
+            mapping: Arc::new(mapping),
+            records,
+            offsets,
+            duplicates,
+            index,
+            radius,
+            threshold: Jaccard::<u32>::threshold(radius),
+            weights,
+            rational: None,
+            extractor_hash: None,
+            config_hash: None,
+            max_results: None,
+            config: FILTER_CONFIG,
+            pool: None,
+            merge: None,
+            full_index: None,
+        }
+        .with_extractor_hash(header.extractor_hash);
+        index.validate()?;
+        Ok(index)
+    }
+
+    // Checks the invariants of the index, e.g., after loading it from a file,
+    // and fails with all the violations found.
+    pub fn validate(&self) -> Result<IntegrityReport> {
+        let mut issues = vec![];
+        check_records(
+            &self.mapping,
+            &self.records,
+            &self.offsets,
+            &self.duplicates,
+            &mut issues,
+        );
+        if !self
+            .records
+            .windows(2)
+            .all(|w| w[0].set.len() <= w[1].set.len())
+        {
+            issues.push("The records are not sorted by length.".to_string());
+        }
+        if self.threshold != Jaccard::<u32>::threshold(self.radius) {
+            issues.push(format!(
+                "The threshold {} does not match the radius {}.",
+                self.threshold, self.radius
+            ));
+        }
+        if let Some(weights) = &self.weights {
+            if weights.len() != self.mapping.universe() as usize {
+                issues.push("The weights do not cover the universe.".to_string());
+            } else if weights.iter().any(|w| !w.is_finite() || *w <= 0.0) {
+                issues.push("The weights are not positive.".to_string());
+            }
+        }
+
+        let mut n_entries = 0;
+        for (&elem, list) in &self.index {
+            if elem >= self.mapping.universe() {
+                issues.push(format!("Element {} is out of the universe.", elem));
+            }
+            if !list.windows(2).all(|w| w[0] < w[1]) {
+                issues.push(format!("The posting list of {} is not sorted.", elem));
+            }
+            if list.iter().any(|&i| i as usize >= self.records.len()) {
+                issues.push(format!(
+                    "The posting list of {} has an offset out of range.",
+                    elem
+                ));
+            }
+            n_entries += list.len();
+        }
+
+        // The prefix lengths are computed only on consistent records and weights.
+        if issues.is_empty() {
+            let mut n_expected = 0;
+            for (i, record) in self.records.iter().enumerate() {
+                let pfx_len = Self::index_prefix_len(
+                    &record.set,
+                    self.threshold,
+                    self.weights.as_deref(),
+                    self.rational.as_ref(),
+                );
+                n_expected += pfx_len.min(record.set.len());
+                let indexed = record.set.iter().take(pfx_len).all(|elem| {
+                    self.index
+                        .get(elem)
+                        .is_some_and(|list| list.binary_search(&(i as u32)).is_ok())
+                });
+                if !indexed {
+                    issues.push(format!(
+                        "The prefix of record {} is not indexed.",
+                        record.id
+                    ));
+                }
+            }
+            if n_entries != n_expected {
+                issues.push(format!(
+                    "{} posting entries for prefixes of {} elements in total.",
+                    n_entries, n_expected
+                ));
+            }
+        }
+
+        let report = IntegrityReport {
+            n_records: self.records.len(),
+            n_duplicates: self.duplicates.values().map(|ids| ids.len()).sum(),
+            n_postings: self.index.len(),
+            n_entries,
+        };
+        integrity_result(report, issues)
+    }
+
+    // Returns the fraction of the posting volume touched by the indexed prefixes
+    // that `refresh_mapping` would save, in [0, 1].
+    // A fresh mapping gives 0.
+    pub fn mapping_staleness(&self) -> Result<f32> {
+        let universe = self.mapping.universe();
+        let refresh = Mapping::from_records(&self.records, universe)?;
+        let freqs = elem_freqs(&self.records, universe);
+        let mut refreshed_freqs = vec![0; freqs.len()];
+        for (src, &tgt) in refresh.as_slice().iter().enumerate() {
+            refreshed_freqs[tgt as usize] = freqs[src];
+        }
+
+        let refreshed_weights = self
+            .weights
+            .as_ref()
+            .map(|weights| map_weights(&refresh, weights));
+
+        let prefix_mass = |set: &FrozenSet<u32>, freqs: &[usize], weights: Option<&[f32]>| {
+            let pfx_len =
+                Self::index_prefix_len(set, self.threshold, weights, self.rational.as_ref());
+            set.iter()
+                .take(pfx_len)
+                .map(|&elem| freqs[elem as usize])
+                .sum::<usize>()
+        };
+        let mut current = 0;
+        let mut refreshed = 0;
+        for record in &self.records {
+            current += prefix_mass(&record.set, &freqs, self.weights.as_deref());
+            refreshed += prefix_mass(
+                &refresh.apply(&record.set).freeze(),
+                &refreshed_freqs,
+                refreshed_weights.as_deref(),
+            );
+        }
+        if current == 0 {
+            return Ok(0.0);
+        }
+        Ok(1.0 - refreshed as f32 / current as f32)
+    }
+
+    // Recomputes the mapping from the indexed records and rebuilds the postings.
+    // The index no longer shares the mapping with other indexes after this.
+    pub fn refresh_mapping(&mut self) -> Result<()> {
+        let refresh = Mapping::from_records(&self.records, self.mapping.universe())?;
+        for record in self.records.iter_mut() {
+            record.set = refresh.apply(&record.set).freeze();
+        }
+        if let Some(weights) = self.weights.as_mut() {
+            *weights = map_weights(&refresh, weights);
+        }
+        self.index = self.rebuild_index();
+        if let Some(full_index) = self.full_index.as_mut() {
+            *full_index = Self::build_full_index(&self.records);
+        }
+        self.mapping = Arc::new(self.mapping.then(&refresh));
+        self.config_hash = self
+            .extractor_hash
+            .map(|hash| config_hash(hash, &self.mapping));
+        Ok(())
+    }
+
+    // Maps the query once for the `*_prepared` queries.
+    pub fn prepare(&self, query: &OrderedSet<u32>) -> MappedQuery {
+        MappedQuery::with_config_hash(self.mapping.apply(query), self.config_hash)
+    }
+
+    pub fn range_query(&self, query: &OrderedSet<u32>) -> Vec<Answer> {
+        self.range_query_unchecked(&self.mapping.apply(query))
+    }
+
+    // The query must be already mapped by `self.mapping()`.
+    #[deprecated(note = "use `prepare` and `range_query_prepared`, which check the mapping")]
+    pub fn range_query_mapped(&self, query: &OrderedSet<u32>) -> Vec<Answer> {
+        self.range_query_unchecked(query)
+    }
+
+    // Fails if the query was produced by another configuration than `config_hash`.
+    pub fn range_query_prepared(&self, query: &MappedQuery) -> Result<Vec<Answer>> {
+        query.check(self.config_hash)?;
+        Ok(self.range_query_unchecked(query.set()))
+    }
+
+    // The query must be already mapped by `self.mapping()`.
+    pub(crate) fn range_query_unchecked(&self, query: &OrderedSet<u32>) -> Vec<Answer> {
+        self.range_query_inner(query, &[], &mut QueryContext::default())
+    }
+
+    // Reuses the buffers of `ctx` across queries.
+    pub fn range_query_with_context(
+        &self,
+        query: &OrderedSet<u32>,
+        ctx: &mut QueryContext,
+    ) -> Vec<Answer> {
+        let mapped = ctx.map_query(&self.mapping, query);
+        let answers = self.range_query_inner(&mapped, &[], ctx);
+        ctx.recycle(mapped);
+        answers
+    }
+
+    // Skips the records of the ids in `exclude` without verification.
+    pub fn range_query_excluding(&self, query: &OrderedSet<u32>, exclude: &[u32]) -> Vec<Answer> {
+        let excluded = exclude
+            .iter()
+            .filter_map(|id| self.offsets.get(id).cloned())
+            .collect::<Vec<_>>();
+        self.range_query_inner(
+            &self.mapping.apply(query),
+            &excluded,
+            &mut QueryContext::default(),
+        )
+    }
+
+    // Consumes `budget` by the posting entries scanned and the candidates verified,
+    // and returns partial results if it is exhausted, e.g., for a strict SLA across queries.
+    // `Budget::is_exhausted` tells whether the results are partial.
+    pub fn range_query_with_budget(
+        &self,
+        query: &OrderedSet<u32>,
+        budget: &mut Budget,
+    ) -> Vec<Answer> {
+        self.range_query_budgeted(
+            &self.mapping.apply(query),
+            &[],
+            &mut QueryContext::default(),
+            budget,
+        )
+        .0
+    }
+
+    // Also returns whether answers beyond `max_results` were dropped.
+    pub fn range_query_truncated(&self, query: &OrderedSet<u32>) -> (Vec<Answer>, bool) {
+        self.range_query_budgeted(
+            &self.mapping.apply(query),
+            &[],
+            &mut QueryContext::default(),
+            &mut Budget::new(),
+        )
+    }
+
+    fn range_query_inner<S: AsRef<[u32]>>(
+        &self,
+        query: &OrderedSet<u32, S>,
+        excluded: &[u32],
+        ctx: &mut QueryContext,
+    ) -> Vec<Answer> {
+        self.range_query_budgeted(query, excluded, ctx, &mut Budget::new())
+            .0
+    }
+
+    // Also returns whether answers were dropped by `max_results`.
+    fn range_query_budgeted<S: AsRef<[u32]>>(
+        &self,
+        query: &OrderedSet<u32, S>,
+        excluded: &[u32],
+        ctx: &mut QueryContext,
+        budget: &mut Budget,
+    ) -> (Vec<Answer>, bool) {
+        debug_assert!(self.is_mapped(query));
+        if let Some(weights) = &self.weights {
+            return self.range_query_weighted(query, excluded, ctx, weights, budget);
+        }
+        let jaccard = self.jaccard(query);
+        self.collect_candidates(query, &jaccard, excluded, ctx, budget);
+        let n_verified = budget.take_verified(ctx.candidates.len());
+        let mut answers = Vec::new();
+        let truncated = self.verify_limited(&jaccard, &ctx.candidates[..n_verified], &mut answers);
+        answers.sort_unstable();
+        (answers, truncated)
+    }
+
+    // Collects the offsets of the records sharing the prefix with the query into `ctx.candidates`.
+    fn collect_candidates<S: AsRef<[u32]>>(
+        &self,
+        query: &OrderedSet<u32, S>,
+        jaccard: &Jaccard<u32>,
+        excluded: &[u32],
+        ctx: &mut QueryContext,
+        budget: &mut Budget,
+    ) {
+        let pfx_len = self.query_prefix_len(query.len());
+
+        let deduplicator = &mut ctx.visited;
+        deduplicator.clear();
+
+        let window = self.length_window(jaccard.length_range());
+        // Excluded records are skipped by binary search in each posting list,
+        // so only those in the window matter.
+        let mut excluded = excluded
+            .iter()
+            .cloned()
+            .filter(|idx| window.contains(idx))
+            .collect::<Vec<_>>();
+        excluded.sort_unstable();
+
+        let candidates = &mut ctx.candidates;
+        candidates.clear();
+        ctx.suffix_filtered = 0;
+        if self.merge.is_some() {
+            self.merge_candidates(query, jaccard, &window, &excluded, candidates, budget);
+            return;
+        }
+        for (i, elem) in query.iter().take(pfx_len).enumerate() {
+            if let Some(list) = self.index.get(elem) {
+                let start = list.partition_point(|&idx| idx < window.start);
+                let end = list.partition_point(|&idx| idx < window.end);
+                let end = start + budget.take_postings(end - start);
+                for &idx in skip_excluded(&list[start..end], &excluded).flatten() {
+                    if !deduplicator.insert(idx) {
+                        continue;
+                    }
+                    // The first match relies on all the postings of the earlier elements,
+                    // which the budget may have skipped.
+                    if self.config.suffix_depth != 0
+                        && !budget.is_exhausted()
+                        && self.suffix_filtered(query, i, jaccard, idx)
+                    {
+                        ctx.suffix_filtered += 1;
+                        continue;
+                    }
+                    candidates.push(idx);
+                }
+            }
+        }
+    }
+
+    // Candidates sharing at least T elements with the query, where T is the overlap needed
+    // by the shortest records in the window, in the ascending order of the offsets.
+    fn merge_candidates<S: AsRef<[u32]>>(
+        &self,
+        query: &OrderedSet<u32, S>,
+        jaccard: &Jaccard<u32>,
+        window: &Range<u32>,
+        excluded: &[u32],
+        candidates: &mut Vec<u32>,
+        budget: &mut Budget,
+    ) {
+        let (Some(strategy), Some(full_index)) = (self.merge, &self.full_index) else {
+            return;
+        };
+        if window.is_empty() {
+            return;
+        }
+        let min_len = self.records[window.start as usize].set.len();
+        // One less than the float bound, which can demand one more element than needed
+        // at borderline thresholds.
+        let t = jaccard
+            .overlap_threshold(query.len() + min_len)
+            .saturating_sub(1)
+            .max(1);
+        let mut lists = query
+            .iter()
+            .map(|elem| {
+                let Some(list) = full_index.get(elem) else {
+                    return &[][..];
+                };
+                let start = list.partition_point(|&idx| idx < window.start);
+                let end = list.partition_point(|&idx| idx < window.end);
+                &list[start..start + budget.take_postings(end - start)]
+            })
+            .collect::<Vec<_>>();
+        lists.sort_by_key(|list| list.len());
+        candidates.extend(
+            merge_lists(&lists, t, strategy)
+                .into_iter()
+                .map(|(idx, _)| idx)
+                .filter(|idx| excluded.binary_search(idx).is_err()),
+        );
+        candidates.sort_unstable();
+    }
+
+    // Whether the record is pruned by the suffix filter, given that its first match with
+    // the query is the `i`-th element of the query. The prefixes before the match share
+    // no element, otherwise the record would have been found earlier, so the suffixes
+    // after the match must share `overlap - 1` elements, i.e., be within the Hamming
+    // distance of `|x| + |y| - 2 * overlap - i - j`.
+    fn suffix_filtered<S: AsRef<[u32]>>(
+        &self,
+        query: &OrderedSet<u32, S>,
+        i: usize,
+        jaccard: &Jaccard<u32>,
+        idx: u32,
+    ) -> bool {
+        let (x, y) = (query.as_slice(), self.records[idx as usize].set.as_slice());
+        let Ok(j) = y.binary_search(&x[i]) else {
+            return false;
+        };
+        let overlap = jaccard.overlap_threshold(x.len() + y.len());
+        let Some(max_hamming) = (x.len() + y.len()).checked_sub(2 * overlap + i + j) else {
+            return true;
+        };
+        let bound = suffix_hamming_bound(
+            &x[i + 1..],
+            &y[j + 1..],
+            max_hamming,
+            self.config.suffix_depth,
+        );
+        bound > max_hamming
+    }
+
+    // Verifies the candidates of a single query in up to `parallelism` threads,
+    // e.g., for interactive queries with tens of thousands of candidates at large radii.
+    // Few candidates are verified on the calling thread regardless of the hint.
+    pub fn range_query_parallel(&self, query: &OrderedSet<u32>, parallelism: usize) -> Vec<Answer> {
+        let query = self.mapping.apply(query);
+        if self.weights.is_some() {
+            return self.range_query_unchecked(&query);
+        }
+        let jaccard = self.jaccard(&query);
+        let mut ctx = QueryContext::default();
+        self.collect_candidates(&query, &jaccard, &[], &mut ctx, &mut Budget::new());
+        install(self.pool.as_deref(), || {
+            verify_parallel(
+                &ctx.candidates,
+                parallelism,
+                self.max_results,
+                |candidates, answers| {
+                    self.verify_limited(&jaccard, candidates, answers);
+                },
+            )
+        })
+    }
+
+    // Searches all the queries at once for offline batches. The prefix elements of the
+    // queries are grouped so that each posting list is looked up once for all the queries
+    // sharing the element, instead of once per query.
+    // Returns the answers of each query in the order of `queries`.
+    pub fn range_query_batch(&self, queries: &[OrderedSet<u32>]) -> Vec<Vec<Answer>> {
+        let queries = queries
+            .iter()
+            .map(|query| self.mapping.apply(query))
+            .collect::<Vec<_>>();
+        self.range_query_batch_mapped(&queries.iter().collect::<Vec<_>>())
+    }
+
+    // Maps the queries in parallel on the current rayon pool, e.g., the pool of the
+    // CPU-bound stage of a pipeline, for `query_prepared_batch` on another pool.
+    pub fn prepare_batch(&self, queries: &[OrderedSet<u32>]) -> Vec<MappedQuery> {
+        queries
+            .par_iter()
+            .map(|query| self.prepare(query))
+            .collect()
+    }
+
+    // Same as `range_query_batch` but for the prepared queries.
+    // Fails if any query was produced by another configuration than `config_hash`.
+    pub fn query_prepared_batch(&self, queries: &[MappedQuery]) -> Result<Vec<Vec<Answer>>> {
+        for query in queries {
+            query.check(self.config_hash)?;
+        }
+        let queries = queries.iter().map(|query| query.set()).collect::<Vec<_>>();
+        Ok(self.range_query_batch_mapped(&queries))
+    }
+
+    fn range_query_batch_mapped(&self, queries: &[&OrderedSet<u32>]) -> Vec<Vec<Answer>> {
+        if self.weights.is_some() || self.merge.is_some() {
+            return queries
+                .iter()
+                .map(|query| self.range_query_unchecked(query))
+                .collect();
+        }
+        let jaccards = queries
+            .iter()
+            .map(|query| self.jaccard(query))
+            .collect::<Vec<_>>();
+        let windows = jaccards
+            .iter()
+            .map(|jaccard| self.length_window(jaccard.length_range()))
+            .collect::<Vec<_>>();
+
+        // Pairs of a prefix element and the query offset, sorted to group the queries by element.
+        let mut probes = vec![];
+        for (q, query) in queries.iter().enumerate() {
+            let pfx_len = self.query_prefix_len(query.len());
+            probes.extend(query.iter().take(pfx_len).map(|&elem| (elem, q as u32)));
+        }
+        probes.sort_unstable();
+
+        let mut candidates = vec![vec![]; queries.len()];
+        for group in probes.chunk_by(|a, b| a.0 == b.0) {
+            let Some(list) = self.index.get(&group[0].0) else {
+                continue;
+            };
+            for &(_, q) in group {
+                let window = &windows[q as usize];
+                let start = list.partition_point(|&idx| idx < window.start);
+                let end = list.partition_point(|&idx| idx < window.end);
+                candidates[q as usize].extend_from_slice(&list[start..end]);
+            }
+        }
+
+        jaccards
+            .iter()
+            .zip(candidates)
+            .map(|(jaccard, mut candidates)| {
+                candidates.sort_unstable();
+                candidates.dedup();
+                let mut answers = Vec::new();
+                self.verify_limited(jaccard, &candidates, &mut answers);
+                answers.sort_unstable();
+                answers
+            })
+            .collect()
+    }
+
+    // Verifies the candidates in chunks of `max_results`, truncating the answers after each,
+    // and returns whether answers were dropped.
+    fn verify_limited(
+        &self,
+        jaccard: &Jaccard<u32>,
+        candidates: &[u32],
+        answers: &mut Vec<Answer>,
+    ) -> bool {
+        let Some(limit) = self.max_results else {
+            self.verify(jaccard, candidates, answers);
+            return false;
+        };
+        let mut truncated = false;
+        for chunk in candidates.chunks(limit) {
+            self.verify(jaccard, chunk, answers);
+            truncated |= truncate_answers(answers, limit);
+        }
+        truncated
+    }
+
+    fn verify(&self, jaccard: &Jaccard<u32>, candidates: &[u32], answers: &mut Vec<Answer>) {
+        #[cfg(feature = "batch-verify")]
+        self.verify_batched(jaccard, candidates, answers);
+        #[cfg(not(feature = "batch-verify"))]
+        for &idx in candidates {
+            let record = &self.records[idx as usize];
+            if let Evaluation::Accepted(dist) = jaccard.evaluate(&record.set) {
+                answers.push(Answer {
+                    id: record.id,
+                    dist,
+                });
+            }
+        }
+    }
+
+    // The count-based length filter does not hold for weights, so all the postings are scanned.
+    fn range_query_weighted<S: AsRef<[u32]>>(
+        &self,
+        query: &OrderedSet<u32, S>,
+        excluded: &[u32],
+        ctx: &mut QueryContext,
+        weights: &[f32],
+        budget: &mut Budget,
+    ) -> (Vec<Answer>, bool) {
+        let pfx_len = ElementWeightedJaccard::prefix_len(query, weights, self.threshold);
+        let deduplicator = &mut ctx.visited;
+        deduplicator.clear();
+        deduplicator.extend(excluded.iter().cloned());
+
+        let jaccard = ElementWeightedJaccard::new(query, weights);
+        let mut answers = Vec::new();
+        let mut truncated = false;
+        for elem in query.iter().take(pfx_len) {
+            let Some(list) = self.index.get(elem) else {
+                continue;
+            };
+            let list = &list[..budget.take_postings(list.len())];
+            for &idx in list {
+                if !deduplicator.insert(idx) || budget.take_verified(1) == 0 {
+                    continue;
+                }
+                let record = &self.records[idx as usize];
+                if let Some(dist) = jaccard.distance(&record.set) {
+                    if 1.0 - dist >= self.threshold {
+                        answers.push(Answer {
+                            id: record.id,
+                            dist,
+                        });
+                        if let Some(limit) = self.max_results {
+                            if answers.len() >= limit.saturating_mul(2) {
+                                truncated |= truncate_answers(&mut answers, limit);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(limit) = self.max_results {
+            truncated |= truncate_answers(&mut answers, limit);
+        }
+        answers.sort_unstable();
+        (answers, truncated)
+    }
+
+    // Keeps the answers whose records share at least one of `elems`, e.g., the features
+    // of the maximal n-gram order to reject answers overlapping only in short n-grams.
+    // The answers of unknown ids, e.g., of another index, are dropped.
+    pub fn retain_sharing(&self, answers: Vec<Answer>, elems: &OrderedSet<u32>) -> Vec<Answer> {
+        let elems = self.mapping.apply(elems);
+        answers
+            .into_iter()
+            .filter(|ans| {
+                let Some(&offset) = self.offsets.get(&ans.id) else {
+                    return false;
+                };
+                let set = &self.records[offset as usize].set;
+                elems
+                    .iter()
+                    .any(|elem| set.as_slice().binary_search(elem).is_ok())
+            })
+            .collect()
+    }
+
+    // Returns the records containing at least `min_containment` of the query,
+    // which needs the lists of `containment_lists`.
+    pub fn find_supersets(
+        &self,
+        query: &OrderedSet<u32>,
+        min_containment: f32,
+    ) -> Result<Vec<Answer>> {
+        self.containment_query(query, min_containment, ContainmentMode::Superset)
+    }
+
+    // Returns the records at least `min_containment` of which is contained in the query,
+    // which needs the lists of `containment_lists`.
+    pub fn find_subsets(
+        &self,
+        query: &OrderedSet<u32>,
+        min_containment: f32,
+    ) -> Result<Vec<Answer>> {
+        self.containment_query(query, min_containment, ContainmentMode::Subset)
+    }
+
+    fn containment_query(
+        &self,
+        query: &OrderedSet<u32>,
+        min_containment: f32,
+        mode: ContainmentMode,
+    ) -> Result<Vec<Answer>> {
+        let Some(full_index) = &self.full_index else {
+            return Err(anyhow!(
+                "The containment queries need the lists built by containment_lists."
+            ));
+        };
+        let query = self.mapping.apply(query);
+        let containment = Containment::new(&query, min_containment, mode, self.config.clone())?;
+        let mut candidates = Vec::new();
+        if min_containment == 0.0 {
+            // Any record contains the empty overlap.
+            candidates.extend(0..self.records.len() as u32);
+        } else if mode == ContainmentMode::Superset {
+            // The records shorter than the overlap are out of the window.
+            let min_overlap = Containment::<u32>::min_overlap(query.len(), min_containment);
+            let start = self
+                .records
+                .partition_point(|record| record.set.len() < min_overlap)
+                as u32;
+            let pfx_len = Containment::<u32>::prefix_len(query.len(), min_containment);
+            for elem in query.iter().take(pfx_len) {
+                if let Some(list) = full_index.get(elem) {
+                    let list = &list[list.partition_point(|&idx| idx < start)..];
+                    candidates.extend_from_slice(list);
+                }
+            }
+        } else {
+            // The prefix filter is applied to the records, since the overlap is normalized
+            // by their lengths.
+            for elem in query.iter() {
+                let Some(list) = full_index.get(elem) else {
+                    continue;
+                };
+                for &idx in list {
+                    let set = &self.records[idx as usize].set;
+                    if set.rank(elem) < Containment::<u32>::prefix_len(set.len(), min_containment) {
+                        candidates.push(idx);
+                    }
+                }
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        let mut answers = candidates
+            .into_iter()
+            .filter_map(|idx| {
+                let record = &self.records[idx as usize];
+                match containment.evaluate(&record.set) {
+                    Evaluation::Accepted(dist) => Some(Answer {
+                        id: record.id,
+                        dist,
+                    }),
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        answers.sort_unstable();
+        Ok(answers)
+    }
+
+    // Searches with the stored set of the record, excluding the record itself.
+    pub fn range_query_by_id(&self, id: u32) -> Option<Vec<Answer>> {
+        let &offset = self.offsets.get(&id)?;
+        let query = &self.records[offset as usize].set;
+        Some(self.range_query_inner(query, &[offset], &mut QueryContext::default()))
+    }
+
+    // Returns the records within the radius from any of the queries with the minimum distance.
+    pub fn range_query_any(&self, queries: &[OrderedSet<u32>]) -> Vec<Answer> {
+        let answers = queries
+            .iter()
+            .flat_map(|query| self.range_query(query))
+            .collect::<Vec<_>>();
+        union_answers(answers)
+    }
+
+    // Ranks the records within the radius from `positive`
+    // by `1 - (sim(positive) - lambda * sim(negative))`.
+    // Fails with `weights`, whose radius is not of the unweighted similarities.
+    pub fn topk_query_contrastive(
+        &self,
+        positive: &OrderedSet<u32>,
+        negative: &OrderedSet<u32>,
+        lambda: f32,
+        k: usize,
+    ) -> Result<Vec<Answer>> {
+        check_k(k)?;
+        check_contrastive_lambda(lambda)?;
+        if self.weights.is_some() {
+            return Err(anyhow!(
+                "Contrastive queries are not supported by weighted indexes."
+            ));
+        }
+        let positive = self.mapping.apply(positive);
+        let negative = self.mapping.apply(negative);
+        let contrastive = Contrastive::new(&positive, &negative, lambda);
+        let mut answers = self
+            .range_query_unchecked(&positive)
+            .into_iter()
+            .filter_map(|ans| {
+                let &offset = self.offsets.get(&ans.id)?;
+                Some(Answer {
+                    id: ans.id,
+                    dist: contrastive.distance(&self.records[offset as usize].set)?,
+                })
+            })
+            .collect::<Vec<_>>();
+        answers.sort_unstable();
+        answers.truncate(k);
+        Ok(answers)
+    }
+
+    // Also searches with the probes of `dropout`, whose answers are only the candidates
+    // verified against the whole query, so that the distances are those to the query.
+    pub fn range_query_dropout(&self, query: &OrderedSet<u32>, dropout: &Dropout) -> Vec<Answer> {
+        let query = self.mapping.apply(query);
+        let mut answers = self.range_query_unchecked(&query);
+        let mut visited = answers.iter().map(|ans| ans.id).collect::<HashSet<_>>();
+        let mut candidates = vec![];
+        for probe in dropout.probes(&query) {
+            for ans in self.range_query_unchecked(&probe) {
+                if visited.insert(ans.id) {
+                    candidates.push(self.offsets[&ans.id]);
+                }
+            }
+        }
+        match &self.weights {
+            Some(weights) => {
+                let jaccard = ElementWeightedJaccard::new(&query, weights);
+                for &idx in &candidates {
+                    let record = &self.records[idx as usize];
+                    if let Some(dist) = jaccard.distance(&record.set) {
+                        if 1.0 - dist >= self.threshold {
+                            answers.push(Answer {
+                                id: record.id,
+                                dist,
+                            });
+                        }
+                    }
+                }
+            }
+            None => self.verify(&self.jaccard(&query), &candidates, &mut answers),
+        }
+        if let Some(limit) = self.max_results {
+            truncate_answers(&mut answers, limit);
+        }
+        answers.sort_unstable();
+        answers
+    }
+
+    #[cfg(feature = "batch-verify")]
+    fn verify_batched(
+        &self,
+        jaccard: &Jaccard<u32>,
+        candidates: &[u32],
+        answers: &mut Vec<Answer>,
+    ) {
+        const BATCH_SIZE: usize = 4;
+        let mut chunks = candidates.chunks_exact(BATCH_SIZE);
+        for chunk in chunks.by_ref() {
+            let records: [&FrozenRecord; BATCH_SIZE] =
+                std::array::from_fn(|k| &self.records[chunk[k] as usize]);
+            let evals = jaccard.evaluate_batch(records.map(|record| &record.set));
+            for (record, eval) in records.iter().zip(evals) {
+                if let Evaluation::Accepted(dist) = eval {
+                    answers.push(Answer {
+                        id: record.id,
+                        dist,
+                    });
+                }
+            }
+        }
+        for &idx in chunks.remainder() {
+            let record = &self.records[idx as usize];
+            if let Evaluation::Accepted(dist) = jaccard.evaluate(&record.set) {
+                answers.push(Answer {
+                    id: record.id,
+                    dist,
+                });
+            }
+        }
+    }
+
+    // Returns the offsets of the records whose lengths are within the bounds.
+    fn length_window(&self, bounds: &RangeInclusive<usize>) -> Range<u32> {
+        let start = self
+            .records
+            .partition_point(|record| record.set.len() < *bounds.start());
+        let end = self
+            .records
+            .partition_point(|record| record.set.len() <= *bounds.end());
+        start as u32..end as u32
+    }
+
+    fn is_mapped<S: AsRef<[u32]>>(&self, query: &OrderedSet<u32, S>) -> bool {
+        query.is_strictly_sorted() && query.iter().all(|&e| e < self.mapping.universe())
+    }
+}
+
+impl RangeSearch for InvertedIndex {
+    fn range_search(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Answer>> {
+        narrow_answers(self.range_query(query), radius, self.radius)
+    }
+}
+
+// Moves the weight of each element to its mapped id.
+fn map_weights(mapping: &Mapping, weights: &[f32]) -> Vec<f32> {
+    let mut mapped = vec![0.0; weights.len()];
+    for (src, &tgt) in mapping.as_slice().iter().enumerate() {
+        mapped[tgt as usize] = weights[src];
+    }
+    mapped
+}
+
+// Splits the sorted posting list into the runs between the sorted excluded entries.
+fn skip_excluded<'a>(list: &'a [u32], excluded: &'a [u32]) -> impl Iterator<Item = &'a [u32]> {
+    let (mut list, mut excluded) = (list, excluded);
+    std::iter::from_fn(move || {
+        if list.is_empty() {
+            return None;
+        }
+        let Some((&first, rest)) = excluded.split_first() else {
+            return Some(std::mem::take(&mut list));
+        };
+        excluded = rest;
+        let pos = list.partition_point(|&idx| idx < first);
+        let (run, tail) = list.split_at(pos);
+        list = tail.strip_prefix(&[first]).unwrap_or(tail);
+        Some(run)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range_search() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([1, 2, 3, 4]).unwrap();
+        let c = OrderedSet::from_sorted([2, 3, 4]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
+
+        let index = InvertedIndex::from_records(&records, 10, 0.5).unwrap();
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let answers = index.range_query(&query);
+        assert_eq!(
+            answers,
+            vec![
+                Answer {
+                    id: 0,
+                    dist: 1. - 3. / 3.
+                },
+                Answer {
+                    id: 1,
+                    dist: 1. - 3. / 4.
+                },
+                Answer {
+                    id: 2,
+                    dist: 1. - 2. / 4.
+                },
+            ]
+        );
+
+        let index = InvertedIndex::from_records(&records, 10, 0.3).unwrap();
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let answers = index.range_query(&query);
+        assert_eq!(
+            answers,
+            vec![
+                Answer {
+                    id: 0,
+                    dist: 1. - 3. / 3.
+                },
+                Answer {
+                    id: 1,
+                    dist: 1. - 3. / 4.
+                },
+            ]
+        );
+
+        let index = InvertedIndex::from_records(&records, 10, 0.1).unwrap();
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let answers = index.range_query(&query);
+        assert_eq!(
+            answers,
+            vec![Answer {
+                id: 0,
+                dist: 1. - 3. / 3.
+            },]
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_range_search_mapped() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([1, 2, 3, 4]).unwrap();
+        let c = OrderedSet::from_sorted([2, 3, 4]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
+
+        let index = InvertedIndex::from_records(&records, 10, 0.5).unwrap();
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let mapped = index.mapping().apply(&query);
+        assert_eq!(index.range_query_mapped(&mapped), index.range_query(&query));
+    }
+
+    #[test]
+    fn test_refresh_mapping() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([1, 2, 3, 4]).unwrap();
+        let c = OrderedSet::from_sorted([2, 3, 4]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
+
+        // Sorts elements in descending order of frequency, the worst case.
+        let mapping = Arc::new(Mapping::from_slice(&[9, 5, 0, 1, 6, 2, 3, 4, 7, 8]));
+        let mut index = InvertedIndex::from_records_with_mapping(&records, mapping, 0.5).unwrap();
+        assert!(index.mapping_staleness().unwrap() > 0.0);
+
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let expected = index.range_query(&query);
+        index.refresh_mapping().unwrap();
+        assert_eq!(index.mapping_staleness().unwrap(), 0.0);
+        assert_eq!(index.range_query(&query), expected);
+    }
+
+    #[test]
+    fn test_postings() {
+        let records = vec![
+            Record {
+                id: 5,
+                set: OrderedSet::from_sorted([1, 2]).unwrap(),
+            },
+            Record {
+                id: 7,
+                set: OrderedSet::from_sorted([1, 3]).unwrap(),
+            },
+        ];
+        // Indexes all the elements.
+        let index = InvertedIndex::from_records(&records, 10, 1.0).unwrap();
+        let ids = index
+            .posting(1)
+            .unwrap()
+            .iter()
+            .map(|&offset| index.record_id(offset).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![5, 7]);
+        assert_eq!(index.posting(4), None);
+        assert_eq!(index.posting(100), None);
+
+        let mut postings = index.postings().collect::<Vec<_>>();
+        postings.sort_unstable();
+        assert_eq!(postings, vec![(1, 2), (2, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn test_retain_sharing() {
+        let records = vec![
+            Record {
+                id: 0,
+                set: OrderedSet::from_sorted([1, 2, 3]).unwrap(),
+            },
+            Record {
+                id: 1,
+                set: OrderedSet::from_sorted([1, 2, 4]).unwrap(),
+            },
+        ];
+        let index = InvertedIndex::from_records(&records, 10, 0.5).unwrap();
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let mut answers = index.range_query(&query);
+        assert_eq!(answers.len(), 2);
+        // Answers of another index.
+        answers.push(Answer { id: 7, dist: 0.0 });
+        let required = OrderedSet::from_sorted([3, 5]).unwrap();
+        assert_eq!(
+            index.retain_sharing(answers, &required),
+            vec![Answer { id: 0, dist: 0.0 }]
+        );
+    }
+
+    #[test]
+    fn test_get_record() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([2, 3, 4, 5]).unwrap();
+        let records = vec![Record { id: 3, set: a }, Record { id: 1, set: b }];
+        let index = InvertedIndex::from_records(&records, 10, 0.5).unwrap();
+        assert!(index.contains_id(3));
+        assert!(!index.contains_id(0));
+        assert_eq!(
+            index.get_record(1).map(|set| set.as_slice()),
+            Some(index.mapping().apply(&records[1].set).as_slice())
+        );
+        assert!(index.get_record(0).is_none());
+    }
+
+    #[test]
+    fn test_duplicate_ids() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([2, 3, 4, 5]).unwrap();
+        let records = vec![Record { id: 1, set: a }, Record { id: 1, set: b }];
+        assert!(InvertedIndex::from_records(&records, 10, 0.5).is_err());
+    }
+
+    #[test]
+    fn test_range_query_by_id() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([1, 2, 3, 4]).unwrap();
+        let c = OrderedSet::from_sorted([2, 3, 4]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
 
-use crate::metric::{Evaluation, FilterConfig, Jaccard};
-use crate::{Answer, Mapping, OrderedSet, Record};
+        let index = InvertedIndex::from_records(&records, 10, 0.3).unwrap();
+        assert_eq!(
+            index.range_query_by_id(0),
+            Some(vec![Answer {
+                id: 1,
+                dist: 1. - 3. / 4.
+            }])
+        );
+        assert_eq!(index.range_query_by_id(3), None);
+    }
 
-const FILTER_CONFIG: FilterConfig = FilterConfig {
-    length: true,
-    position: true,
-};
+    #[test]
+    fn test_range_query_excluding() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([1, 2, 3, 4]).unwrap();
+        let c = OrderedSet::from_sorted([2, 3, 4]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
 
-pub struct InvertedIndex {
-    mapping: Mapping,
-    records: Vec<Record<u32>>,
-    index: HashMap<u32, Vec<u32>>,
-    threshold: f32,
-}
+        let index = InvertedIndex::from_records(&records, 10, 0.5).unwrap();
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        assert_eq!(
+            index.range_query_excluding(&query, &[1, 9]),
+            vec![
+                Answer {
+                    id: 0,
+                    dist: 1. - 3. / 3.
+                },
+                Answer {
+                    id: 2,
+                    dist: 1. - 2. / 4.
+                },
+            ]
+        );
+    }
 
-impl InvertedIndex {
-    pub fn from_records(records: &[Record<u32>], universe: u32, radius: f32) -> Result<Self> {
-        let threshold = Self::threshold(radius);
-        let mapping = Mapping::from_records(records, universe)?;
-        let records = records
+    #[test]
+    fn test_skip_excluded() {
+        let list = [1, 3, 4, 7, 9];
+        let runs = |excluded: &[u32]| {
+            skip_excluded(&list, excluded)
+                .map(|run| run.to_vec())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(runs(&[]), vec![vec![1, 3, 4, 7, 9]]);
+        assert_eq!(runs(&[3, 5, 9]), vec![vec![1], vec![4], vec![7]]);
+        assert_eq!(runs(&[0, 1]), vec![vec![], vec![], vec![3, 4, 7, 9]]);
+        assert_eq!(runs(&[1, 3, 4, 7, 9]).concat(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_range_query_any() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([1, 2, 3, 4]).unwrap();
+        let c = OrderedSet::from_sorted([2, 3, 4]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
+
+        let index = InvertedIndex::from_records(&records, 10, 0.1).unwrap();
+        let queries = vec![
+            OrderedSet::from_sorted([1, 2, 3]).unwrap(),
+            OrderedSet::from_sorted([2, 3, 4]).unwrap(),
+        ];
+        assert_eq!(
+            index.range_query_any(&queries),
+            vec![
+                Answer {
+                    id: 0,
+                    dist: 1. - 3. / 3.
+                },
+                Answer {
+                    id: 2,
+                    dist: 1. - 3. / 3.
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_range_query_batch() {
+        let sets = [
+            vec![1, 2, 3],
+            vec![1, 2, 3, 4],
+            vec![2, 3, 4],
+            vec![4, 5, 6, 7],
+            vec![0, 5, 6],
+            vec![2, 6, 7, 8, 9],
+        ];
+        let records = sets
             .iter()
-            .map(|record| Record {
-                id: record.id,
-                set: mapping.apply(&record.set),
+            .enumerate()
+            .map(|(id, set)| Record {
+                id: id as u32,
+                set: OrderedSet::from_sorted(set.clone()).unwrap(),
             })
             .collect::<Vec<_>>();
-        let mut index = HashMap::new();
-        for (i, record) in records.iter().enumerate() {
-            let set_len = record.set.len() as f32;
-            let pfx_len = Self::index_prefix_len(set_len, threshold);
-            for &elem in record.set.iter().take(pfx_len) {
-                index.entry(elem).or_insert_with(Vec::new).push(i as u32);
-            }
+        let queries = [
+            vec![1, 2, 3],
+            vec![4, 5, 6],
+            vec![2, 6, 7, 8],
+            vec![],
+            vec![0, 9],
+        ]
+        .into_iter()
+        .map(|set| OrderedSet::from_sorted(set).unwrap())
+        .collect::<Vec<_>>();
+
+        for radius in [0.1, 0.3, 0.5, 0.8] {
+            let index = InvertedIndex::from_records(&records, 10, radius).unwrap();
+            let expected = queries
+                .iter()
+                .map(|query| index.range_query(query))
+                .collect::<Vec<_>>();
+            assert_eq!(index.range_query_batch(&queries), expected);
         }
-        Ok(Self {
-            mapping,
-            records,
-            index,
-            threshold,
-        })
+        let index = InvertedIndex::from_records(&records, 10, 0.5).unwrap();
+        assert!(index.range_query_batch(&[]).is_empty());
     }
 
-    pub fn range_query(&self, query: &OrderedSet<u32>) -> Vec<Answer> {
-        let query = self.mapping.apply(query);
-        let set_len = query.len() as f32;
-        let pfx_len = Self::query_prefix_len(set_len, self.threshold);
+    #[test]
+    fn test_query_prepared_batch() {
+        let records = (0..50)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_unsorted([id % 3, id % 5 + 3, id % 7 + 8, id % 11 + 15]),
+            })
+            .collect::<Vec<_>>();
+        let extractor = FeatureExtractor::seeded(1..=1, 26, 42).unwrap();
+        let index = InvertedIndex::from_records(&records, 26, 0.5)
+            .unwrap()
+            .extractor(&extractor);
+        let queries = records.iter().map(|r| r.set.clone()).collect::<Vec<_>>();
+        let prepared = index.prepare_batch(&queries);
+        assert_eq!(
+            index.query_prepared_batch(&prepared).unwrap(),
+            index.range_query_batch(&queries)
+        );
 
-        let mut answers = Vec::new();
-        let mut deduplicator = HashSet::new();
+        let mut foreign = prepared.clone();
+        foreign[3] = MappedQuery::with_config_hash(queries[3].clone(), Some(0));
+        assert!(index.query_prepared_batch(&foreign).is_err());
+    }
 
-        let jaccard = Jaccard::new(&query, 1. - self.threshold, FILTER_CONFIG);
+    #[test]
+    fn test_max_results() {
+        let records = (0..100u32)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_unsorted([0, 1, 2, id % 7 + 3, id % 11 + 10]),
+            })
+            .collect::<Vec<_>>();
+        let query = OrderedSet::from_sorted([0, 1, 2, 3, 10]).unwrap();
+        let unlimited = InvertedIndex::from_records(&records, 21, 0.9).unwrap();
+        let expected = unlimited.range_query(&query);
+        assert_eq!(expected.len(), 100);
 
-        for elem in query.iter().take(pfx_len) {
-            if let Some(list) = self.index.get(elem) {
-                for &idx in list {
-                    if !deduplicator.insert(idx) {
-                        continue;
-                    }
-                    let record = &self.records[idx as usize];
-                    if let Evaluation::Accepted(dist) = jaccard.evaluate(&record.set) {
-                        answers.push(Answer {
-                            id: record.id,
-                            dist,
-                        });
-                    }
-                }
-            }
-        }
+        let index = InvertedIndex::from_records(&records, 21, 0.9)
+            .unwrap()
+            .max_results(7)
+            .unwrap();
+        let (answers, truncated) = index.range_query_truncated(&query);
+        assert!(truncated);
+        assert_eq!(answers, expected[..7]);
+        assert_eq!(
+            index.range_query_batch(std::slice::from_ref(&query)),
+            vec![expected[..7].to_vec()]
+        );
 
-        answers.sort_unstable();
-        answers
+        let index = index.max_results(100).unwrap();
+        assert_eq!(index.range_query_truncated(&query), (expected, false));
     }
 
-    fn threshold(radius: f32) -> f32 {
-        1.0 - radius.max(0.0).min(1.0)
-    }
+    #[test]
+    fn test_range_query_parallel() {
+        let records = (0..5000u32)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_sorted([0, 1, id % 7 + 2, id % 11 + 10]).unwrap(),
+            })
+            .collect::<Vec<_>>();
+        let index = InvertedIndex::from_records(&records, 30, 0.8).unwrap();
+        let query = OrderedSet::from_sorted([0, 1, 2, 10]).unwrap();
+        let expected = index.range_query(&query);
+        assert_eq!(expected.len(), 5000);
+        for parallelism in [0, 1, 4] {
+            assert_eq!(index.range_query_parallel(&query, parallelism), expected);
+        }
 
-    fn index_prefix_len(set_len: f32, threshold: f32) -> usize {
-        (set_len * (1. - threshold) / (1. + threshold)).floor() as usize + 1
+        // Each chunk keeps the nearest answers.
+        let index = index.max_results(10).unwrap();
+        let expected = index.range_query(&query);
+        assert_eq!(expected.len(), 10);
+        for parallelism in [0, 1, 4] {
+            assert_eq!(index.range_query_parallel(&query, parallelism), expected);
+        }
     }
 
-    fn query_prefix_len(set_len: f32, threshold: f32) -> usize {
-        (set_len * (1. - threshold)).floor() as usize + 1
-    }
-}
+    #[test]
+    fn test_range_query_with_budget() {
+        let records = (0..100u32)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_sorted([0, 1, id % 7 + 2]).unwrap(),
+            })
+            .collect::<Vec<_>>();
+        let index = InvertedIndex::from_records(&records, 10, 0.5).unwrap();
+        let query = OrderedSet::from_sorted([0, 1, 2]).unwrap();
+        let expected = index.range_query(&query);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let mut budget = Budget::new();
+        assert_eq!(index.range_query_with_budget(&query, &mut budget), expected);
+        assert!(!budget.is_exhausted());
+
+        // The budget is shared by consecutive queries.
+        let mut budget = Budget::new().max_verified(150);
+        let answers = index.range_query_with_budget(&query, &mut budget);
+        assert_eq!(answers, expected);
+        assert!(!budget.is_exhausted());
+        let answers = index.range_query_with_budget(&query, &mut budget);
+        assert!(budget.is_exhausted());
+        assert_eq!(budget.remaining_verified(), 0);
+        assert!(answers.len() < expected.len());
+        assert!(answers.iter().all(|ans| expected.contains(ans)));
+
+        let mut budget = Budget::new().max_postings(10);
+        let answers = index.range_query_with_budget(&query, &mut budget);
+        assert!(budget.is_exhausted());
+        assert!(answers.len() <= 10);
+    }
 
     #[test]
-    fn test_range_search() {
+    fn test_topk_query_contrastive() {
         let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
         let b = OrderedSet::from_sorted([1, 2, 3, 4]).unwrap();
         let c = OrderedSet::from_sorted([2, 3, 4]).unwrap();
@@ -103,31 +1707,157 @@ mod tests {
         ];
 
         let index = InvertedIndex::from_records(&records, 10, 0.5).unwrap();
-        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
-        let answers = index.range_query(&query);
+        let positive = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let negative = OrderedSet::from_sorted([1]).unwrap();
         assert_eq!(
-            answers,
+            index
+                .topk_query_contrastive(&positive, &negative, 1.0, 2)
+                .unwrap(),
             vec![
                 Answer {
                     id: 0,
-                    dist: 1. - 3. / 3.
+                    dist: 1. - (3. / 3. - 1. / 3.)
                 },
                 Answer {
                     id: 1,
-                    dist: 1. - 3. / 4.
-                },
-                Answer {
-                    id: 2,
-                    dist: 1. - 2. / 4.
+                    dist: 1. - (3. / 4. - 1. / 4.)
                 },
             ]
         );
+        for lambda in [-1.0, f32::NAN, f32::INFINITY] {
+            assert!(index
+                .topk_query_contrastive(&positive, &negative, lambda, 2)
+                .is_err());
+        }
+        let weighted = InvertedIndex::from_records_weighted(&records, 10, 0.5, &[1.0; 10]).unwrap();
+        assert!(weighted
+            .topk_query_contrastive(&positive, &negative, 1.0, 2)
+            .is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let records = (0..30)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_unsorted((id..id + 1 + id % 5).map(|e| e % 16)),
+            })
+            .collect::<Vec<_>>();
+        let index = InvertedIndex::from_records(&records, 16, 0.5)
+            .unwrap()
+            .deduplicate();
+        let report = index.validate().unwrap();
+        assert_eq!(report.n_records + report.n_duplicates, 30);
+        assert_eq!(
+            report.n_entries,
+            index.postings().map(|(_, len)| len).sum::<usize>()
+        );
+
+        let mut broken = InvertedIndex::from_records(&records, 16, 0.5).unwrap();
+        let list = broken.index.values_mut().find(|list| list.len() > 1);
+        list.unwrap().reverse();
+        assert!(broken.validate().is_err());
+
+        let mut broken = InvertedIndex::from_records(&records, 16, 0.5).unwrap();
+        broken
+            .index
+            .values_mut()
+            .for_each(|list| list.retain(|&i| i != 3));
+        assert!(broken.validate().is_err());
+
+        let mut broken = InvertedIndex::from_records(&records, 16, 0.5).unwrap();
+        broken.threshold = 0.9;
+        assert!(broken.validate().is_err());
+
+        let mut broken = InvertedIndex::from_records(&records, 16, 0.5).unwrap();
+        broken.mapping = Arc::new(Mapping::from_slice(&[0; 16]));
+        assert!(broken.validate().is_err());
+
+        let mut broken = InvertedIndex::from_records(&records, 16, 0.5).unwrap();
+        broken.offsets.insert(100, 0);
+        assert!(broken.validate().is_err());
+    }
+
+    #[test]
+    fn test_save_load() {
+        use crate::index_file::FormatError;
+
+        let records = (0..30)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_unsorted((id..id + 1 + id % 5).map(|e| e % 16)),
+            })
+            .collect::<Vec<_>>();
+        let extractor = FeatureExtractor::seeded(1..=1, 16, 334).unwrap();
+        let weights = (0..16).map(|e| 1.0 + e as f32).collect::<Vec<_>>();
+        let indexes = [
+            InvertedIndex::from_records(&records, 16, 0.5)
+                .unwrap()
+                .deduplicate(),
+            InvertedIndex::from_records_weighted(&records, 16, 0.5, &weights).unwrap(),
+        ];
+        for index in indexes {
+            let mut buf = vec![];
+            index.save(&mut buf, Some(&extractor)).unwrap();
+            let loaded = InvertedIndex::load(buf.as_slice(), Some(&extractor)).unwrap();
+            assert_eq!(loaded.validate().unwrap(), index.validate().unwrap());
+            for record in &records {
+                assert_eq!(
+                    loaded.range_query(&record.set),
+                    index.range_query(&record.set)
+                );
+            }
+            // Reproducible.
+            let mut resaved = vec![];
+            loaded.save(&mut resaved, Some(&extractor)).unwrap();
+            assert_eq!(resaved, buf);
+
+            let other = FeatureExtractor::seeded(1..=1, 16, 335).unwrap();
+            let err = InvertedIndex::load(buf.as_slice(), Some(&other))
+                .err()
+                .unwrap();
+            assert!(matches!(
+                err.downcast_ref::<FormatError>(),
+                Some(FormatError::ExtractorMismatch { .. })
+            ));
+            assert!(InvertedIndex::load(&buf[..buf.len() - 1], None).is_err());
+
+            // The same body under the version 1 header without the extractor hash.
+            let mut v1 = buf[..15].to_vec();
+            v1[8] = 1;
+            v1.extend(&buf[24..]);
+            let loaded = InvertedIndex::load(v1.as_slice(), Some(&other)).unwrap();
+            assert_eq!(loaded.validate().unwrap(), index.validate().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_deduplicate() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([2, 3, 4]).unwrap();
+        let records = vec![
+            Record {
+                id: 0,
+                set: a.clone(),
+            },
+            Record { id: 1, set: b },
+            Record {
+                id: 2,
+                set: a.clone(),
+            },
+            Record { id: 3, set: a },
+        ];
+        let index = InvertedIndex::from_records(&records, 10, 0.5)
+            .unwrap()
+            .deduplicate();
+        assert_eq!(index.duplicates(0), &[2, 3]);
+        assert!(index.duplicates(1).is_empty());
+        assert!(index.contains_id(3));
+        assert_eq!(index.get_record(3), index.get_record(0));
 
-        let index = InvertedIndex::from_records(&records, 10, 0.3).unwrap();
         let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
-        let answers = index.range_query(&query);
         assert_eq!(
-            answers,
+            index.range_query(&query),
             vec![
                 Answer {
                     id: 0,
@@ -135,20 +1865,301 @@ mod tests {
                 },
                 Answer {
                     id: 1,
-                    dist: 1. - 3. / 4.
+                    dist: 1. - 2. / 4.
                 },
             ]
         );
+    }
 
-        let index = InvertedIndex::from_records(&records, 10, 0.1).unwrap();
-        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
-        let answers = index.range_query(&query);
-        assert_eq!(
-            answers,
-            vec![Answer {
+    #[test]
+    fn test_invalid_radius() {
+        let records = vec![Record {
+            id: 0,
+            set: OrderedSet::from_sorted([1, 2, 3]).unwrap(),
+        }];
+        assert!(InvertedIndex::from_records(&records, 10, f32::NAN).is_err());
+        assert!(InvertedIndex::from_records(&records, 10, -0.1).is_err());
+    }
+
+    #[test]
+    fn test_query_context() {
+        let records = (0..20)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_unsorted((id..id + 5).map(|e| e % 12)),
+            })
+            .collect::<Vec<_>>();
+        let index = InvertedIndex::from_records(&records, 12, 0.5).unwrap();
+        let mut ctx = QueryContext::new();
+        for record in &records {
+            assert_eq!(
+                index.range_query_with_context(&record.set, &mut ctx),
+                index.range_query(&record.set)
+            );
+        }
+    }
+
+    #[test]
+    fn test_suffix_filter() {
+        use rand::{Rng, SeedableRng};
+        use rand_xoshiro::Xoshiro256PlusPlus;
+
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(13);
+        let records = (0..300)
+            .map(|id| {
+                let n = rng.gen_range(5..30);
+                let set = (0..n).map(|_| rng.gen_range(0..60)).collect::<Vec<_>>();
+                Record {
+                    id,
+                    set: OrderedSet::from_unsorted(set),
+                }
+            })
+            .collect::<Vec<_>>();
+        for radius in [0.2, 0.5] {
+            let index = InvertedIndex::from_records(&records, 60, radius).unwrap();
+            let mut n_filtered = 0;
+            for depth in [1, 2, 4] {
+                let filtered = InvertedIndex::from_records(&records, 60, radius)
+                    .unwrap()
+                    .filter_config(FILTER_CONFIG.suffix_depth(depth))
+                    .unwrap();
+                let mut ctx = QueryContext::new();
+                for record in &records {
+                    assert_eq!(
+                        filtered.range_query_with_context(&record.set, &mut ctx),
+                        index.range_query(&record.set),
+                        "radius={radius}, depth={depth}"
+                    );
+                    n_filtered += ctx.suffix_filtered();
+                }
+            }
+            assert!(n_filtered > 0, "radius={radius}");
+        }
+    }
+
+    #[test]
+    fn test_containment_query() {
+        use rand::{Rng, SeedableRng};
+        use rand_xoshiro::Xoshiro256PlusPlus;
+
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(19);
+        let records = (0..300)
+            .map(|id| {
+                let n = rng.gen_range(0..15);
+                let set = (0..n).map(|_| rng.gen_range(0..30)).collect::<Vec<_>>();
+                Record {
+                    id,
+                    set: OrderedSet::from_unsorted(set),
+                }
+            })
+            .collect::<Vec<_>>();
+        let linear_scan = crate::LinearScan::from_records(&records, 30).unwrap();
+        let index = InvertedIndex::from_records(&records, 30, 0.5).unwrap();
+        let query = &records[0].set;
+        assert!(index.find_supersets(query, 0.5).is_err());
+
+        let mut index = index.containment_lists();
+        for refresh in [false, true] {
+            if refresh {
+                index.refresh_mapping().unwrap();
+            }
+            for query in records.iter().take(50).map(|record| &record.set) {
+                for min_containment in [0.0, 0.3, 0.5, 0.8, 1.0] {
+                    assert_eq!(
+                        index.find_supersets(query, min_containment).unwrap(),
+                        linear_scan.find_supersets(query, min_containment).unwrap(),
+                        "min_containment={min_containment}"
+                    );
+                    assert_eq!(
+                        index.find_subsets(query, min_containment).unwrap(),
+                        linear_scan.find_subsets(query, min_containment).unwrap(),
+                        "min_containment={min_containment}"
+                    );
+                }
+            }
+        }
+        assert!(index.find_supersets(query, 1.5).is_err());
+        assert!(index.find_subsets(query, f32::NAN).is_err());
+    }
+
+    #[test]
+    fn test_merge_strategy() {
+        use rand::{Rng, SeedableRng};
+        use rand_xoshiro::Xoshiro256PlusPlus;
+
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(17);
+        let records = (0..300)
+            .map(|id| {
+                let n = rng.gen_range(1..20);
+                let set = (0..n).map(|_| rng.gen_range(0..40)).collect::<Vec<_>>();
+                Record {
+                    id,
+                    set: OrderedSet::from_unsorted(set),
+                }
+            })
+            .collect::<Vec<_>>();
+        for radius in [0.0, 0.3, 0.6] {
+            for strategy in [
+                MergeStrategy::ScanCount,
+                MergeStrategy::MergeOpt,
+                MergeStrategy::MergeSkip,
+                MergeStrategy::DivideSkip,
+            ] {
+                let mut index = InvertedIndex::from_records(&records, 40, radius)
+                    .unwrap()
+                    .merge_strategy(strategy);
+                for refresh in [false, true] {
+                    if refresh {
+                        index.refresh_mapping().unwrap();
+                    }
+                    for query in records.iter().take(50).map(|record| &record.set) {
+                        // Unlike the prefix filter, the merge finds all the records sharing
+                        // an element within the radius, including those longer than the query.
+                        let jaccard = Jaccard::new(query, radius, FilterConfig::NONE);
+                        let mut expected = records
+                            .iter()
+                            .filter_map(|record| {
+                                let dist = jaccard.distance(&record.set)?;
+                                (dist <= radius).then_some(Answer {
+                                    id: record.id,
+                                    dist,
+                                })
+                            })
+                            .collect::<Vec<_>>();
+                        expected.sort_unstable();
+                        assert_eq!(
+                            index.range_query(query),
+                            expected,
+                            "radius={radius}, strategy={strategy:?}"
+                        );
+                        expected.retain(|ans| ans.id > 2);
+                        assert_eq!(index.range_query_excluding(query, &[0, 1, 2]), expected);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_records_within() {
+        let records = vec![
+            Record {
                 id: 0,
-                dist: 1. - 3. / 3.
-            },]
-        );
+                set: OrderedSet::from_sorted([1]).unwrap(),
+            },
+            Record {
+                id: 1,
+                set: OrderedSet::from_sorted([1, 2]).unwrap(),
+            },
+        ];
+        let (index, skipped) =
+            InvertedIndex::from_records_within(&records, 10, 0.5, 2..=usize::MAX).unwrap();
+        assert_eq!(skipped, vec![0]);
+        let query = OrderedSet::from_sorted([1]).unwrap();
+        assert_eq!(index.range_query(&query), vec![Answer { id: 1, dist: 0.5 }]);
+    }
+
+    #[test]
+    fn test_from_records_rational() {
+        use rand::{Rng, SeedableRng};
+        use rand_xoshiro::Xoshiro256PlusPlus;
+
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(7);
+        let random_set = |rng: &mut Xoshiro256PlusPlus| {
+            let n = rng.gen_range(1..10);
+            OrderedSet::from_unsorted((0..n).map(|_| rng.gen_range(0..12)).collect::<Vec<_>>())
+        };
+        let records = (0..200)
+            .map(|id| Record {
+                id,
+                set: random_set(&mut rng),
+            })
+            .collect::<Vec<_>>();
+        let linear_scan = crate::LinearScan::from_records(&records, 12).unwrap();
+
+        // Radii not exact in floats, where many pairs are on the boundaries.
+        for (p, q) in [(1, 3), (1, 2), (5, 9), (2, 3), (4, 5)] {
+            let threshold = RationalThreshold::from_radius(p, q).unwrap();
+            let index = InvertedIndex::from_records_rational(&records, 12, threshold).unwrap();
+            index.validate().unwrap();
+            for record in records.iter().take(50) {
+                let expected = records
+                    .iter()
+                    .filter(|other| {
+                        let a = record.set.as_slice();
+                        let b = other.set.as_slice();
+                        let inter = b.iter().filter(|e| a.contains(e)).count();
+                        let union = a.len() + b.len() - inter;
+                        // inter / union >= (q - p) / q
+                        inter as u32 * q >= (q - p) * union as u32
+                    })
+                    .map(|other| other.id)
+                    .collect::<Vec<_>>();
+                let answers = index.range_query(&record.set);
+                assert_eq!(
+                    answers,
+                    linear_scan.range_query_rational(&record.set, threshold)
+                );
+                let mut ids = answers.iter().map(|a| a.id).collect::<Vec<_>>();
+                ids.sort_unstable();
+                assert_eq!(ids, expected);
+            }
+            assert!(index.save(&mut vec![], None).is_err());
+        }
+    }
+
+    #[test]
+    fn test_from_records_weighted() {
+        use rand::{Rng, SeedableRng};
+        use rand_xoshiro::Xoshiro256PlusPlus;
+
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(7);
+        let random_set = |rng: &mut Xoshiro256PlusPlus| {
+            let n = rng.gen_range(1..8);
+            OrderedSet::from_unsorted((0..n).map(|_| rng.gen_range(0..20)).collect::<Vec<_>>())
+        };
+        let records = (0..100)
+            .map(|id| Record {
+                id,
+                set: random_set(&mut rng),
+            })
+            .collect::<Vec<_>>();
+        // IDF weights.
+        let freqs = elem_freqs(&records, 20);
+        let weights = freqs
+            .iter()
+            .map(|&freq| (records.len() as f32 / (freq + 1) as f32).ln() + 1.0)
+            .collect::<Vec<_>>();
+
+        for radius in [0.0, 0.3, 0.6] {
+            let mut index =
+                InvertedIndex::from_records_weighted(&records, 20, radius, &weights).unwrap();
+            for refresh in [false, true] {
+                if refresh {
+                    index.refresh_mapping().unwrap();
+                }
+                for _ in 0..20 {
+                    let query = random_set(&mut rng);
+                    let jaccard = ElementWeightedJaccard::new(&query, &weights);
+                    let mut expected = records
+                        .iter()
+                        .filter_map(|record| {
+                            let dist = jaccard.distance(&record.set)?;
+                            (1.0 - dist >= 1.0 - radius).then_some(Answer {
+                                id: record.id,
+                                dist,
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    expected.sort_unstable();
+                    assert_eq!(index.range_query(&query), expected);
+                }
+            }
+        }
+
+        assert!(InvertedIndex::from_records_weighted(&records, 20, 0.5, &weights[1..]).is_err());
+        let mut invalid = weights.clone();
+        invalid[3] = 0.0;
+        assert!(InvertedIndex::from_records_weighted(&records, 20, 0.5, &invalid).is_err());
     }
 }
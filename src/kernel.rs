@@ -0,0 +1,155 @@
+use std::cmp::Ordering;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::OnceLock;
+
+// Instruction sets of the intersection kernels, detected at runtime
+// so that a single binary runs the best kernel of each machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kernel {
+    Scalar,
+    Avx2,
+}
+
+static FORCE_SCALAR: AtomicBool = AtomicBool::new(false);
+
+// Makes every dispatch use the scalar kernel, e.g., to compare kernels in benchmarks.
+pub fn force_scalar(enabled: bool) {
+    FORCE_SCALAR.store(enabled, atomic::Ordering::Relaxed);
+}
+
+// Returns the best instruction set of the machine, which is detected once.
+pub fn detected() -> Kernel {
+    static DETECTED: OnceLock<Kernel> = OnceLock::new();
+    *DETECTED.get_or_init(detect)
+}
+
+// Returns the instruction set used by the dispatch.
+pub fn active() -> Kernel {
+    if FORCE_SCALAR.load(atomic::Ordering::Relaxed) {
+        Kernel::Scalar
+    } else {
+        detected()
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn detect() -> Kernel {
+    if is_x86_feature_detected!("avx2") {
+        Kernel::Avx2
+    } else {
+        Kernel::Scalar
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn detect() -> Kernel {
+    Kernel::Scalar
+}
+
+// Counts the common elements of two strictly sorted slices.
+pub fn intersection_len(a: &[u32], b: &[u32]) -> usize {
+    match active() {
+        #[cfg(target_arch = "x86_64")]
+        // Safety: `Avx2` is only detected on the machines with AVX2.
+        Kernel::Avx2 => unsafe { intersection_len_avx2(a, b) },
+        _ => intersection_len_scalar(a, b),
+    }
+}
+
+pub fn intersection_len_scalar(a: &[u32], b: &[u32]) -> usize {
+    let mut i = 0;
+    let mut j = 0;
+    let mut intersection = 0;
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Equal => {
+                intersection += 1;
+                i += 1;
+                j += 1;
+            }
+            Ordering::Less => {
+                i += 1;
+            }
+            Ordering::Greater => {
+                j += 1;
+            }
+        }
+    }
+    intersection
+}
+
+// Compares blocks of eight elements all-to-all by rotating one of them,
+// and advances the block with the smaller maximum. Since the elements are unique,
+// each common element is found in exactly one pair of the compared blocks.
+// The rest after either side runs out of blocks is merged by the scalar kernel.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn intersection_len_avx2(a: &[u32], b: &[u32]) -> usize {
+    use std::arch::x86_64::*;
+
+    const LANES: usize = 8;
+    let rotate = _mm256_setr_epi32(1, 2, 3, 4, 5, 6, 7, 0);
+    let mut i = 0;
+    let mut j = 0;
+    let mut intersection = 0;
+    while i + LANES <= a.len() && j + LANES <= b.len() {
+        let va = _mm256_loadu_si256(a.as_ptr().add(i) as *const __m256i);
+        let mut vb = _mm256_loadu_si256(b.as_ptr().add(j) as *const __m256i);
+        let mut matched = _mm256_cmpeq_epi32(va, vb);
+        for _ in 1..LANES {
+            vb = _mm256_permutevar8x32_epi32(vb, rotate);
+            matched = _mm256_or_si256(matched, _mm256_cmpeq_epi32(va, vb));
+        }
+        intersection += _mm256_movemask_ps(_mm256_castsi256_ps(matched)).count_ones() as usize;
+        let a_max = a[i + LANES - 1];
+        let b_max = b[j + LANES - 1];
+        if a_max <= b_max {
+            i += LANES;
+        }
+        if b_max <= a_max {
+            j += LANES;
+        }
+    }
+    intersection + intersection_len_scalar(&a[i..], &b[j..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng};
+    use rand_xoshiro::Xoshiro256PlusPlus;
+
+    fn random_set(rng: &mut impl Rng, len: usize, universe: u32) -> Vec<u32> {
+        let mut set = (0..len)
+            .map(|_| rng.gen_range(0..universe))
+            .collect::<Vec<_>>();
+        set.sort_unstable();
+        set.dedup();
+        set
+    }
+
+    #[test]
+    fn test_intersection_len() {
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(7);
+        for _ in 0..200 {
+            let (a_len, b_len) = (rng.gen_range(0..100), rng.gen_range(0..100));
+            let a = random_set(&mut rng, a_len, 200);
+            let b = random_set(&mut rng, b_len, 200);
+            let expected = intersection_len_scalar(&a, &b);
+            assert_eq!(intersection_len(&a, &b), expected);
+            assert_eq!(intersection_len(&b, &a), expected);
+        }
+        let a = [0, u32::MAX - 1, u32::MAX];
+        let b = [u32::MAX];
+        assert_eq!(intersection_len(&a, &b), 1);
+    }
+
+    #[test]
+    fn test_force_scalar() {
+        force_scalar(true);
+        assert_eq!(active(), Kernel::Scalar);
+        force_scalar(false);
+        assert_eq!(active(), detected());
+    }
+}
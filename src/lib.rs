@@ -1,8 +1,28 @@
+pub mod adaptive;
+pub mod all_pairs;
+pub mod budget;
+pub mod cascade;
+pub mod classify;
+pub mod cluster;
+pub mod context;
+pub mod delta;
+pub mod dropout;
+pub mod exact;
+pub mod hybrid;
+pub mod index_file;
 pub mod inverted_index;
+pub mod kernel;
 pub mod linear_scan;
+pub mod lsh;
 pub mod mapping;
 pub mod metric;
+pub mod overlap;
+pub mod pairwise;
+pub mod positional;
+pub mod query_log;
+pub mod registry;
 pub mod set;
+pub mod stats;
 pub mod text;
 
 use std::cmp::Eq;
@@ -10,32 +30,88 @@ use std::cmp::Ord;
 use std::cmp::Ordering;
 use std::cmp::PartialEq;
 use std::cmp::PartialOrd;
+use std::hash::Hash;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
 
-use approx::abs_diff_eq;
+use anyhow::anyhow;
+use anyhow::Result;
+use approx::relative_eq;
+use hashbrown::HashMap;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
 
+pub use adaptive::AdaptiveIndex;
+pub use all_pairs::all_pairs;
+pub use budget::Budget;
+pub use cascade::Cascade;
+pub use context::QueryContext;
+pub use delta::DeltaIndex;
+pub use dropout::Dropout;
+pub use exact::ExactIndex;
+pub use hybrid::HybridIndex;
 pub use inverted_index::InvertedIndex;
 pub use linear_scan::LinearScan;
-pub use mapping::Mapping;
-pub use metric::FilterConfig;
-pub use set::OrderedSet;
+pub use lsh::MinHashIndex;
+pub use mapping::{MappedQuery, Mapping};
+pub use metric::{FilterConfig, RationalThreshold};
+pub use overlap::{MergeStrategy, OverlapIndex};
+pub use positional::PositionalIndex;
+pub use registry::Registry;
+pub use set::{FrozenSet, OrderedSet, WeightedSet};
 
-#[derive(Debug, Clone)]
+// Tolerance with which `Answer` and `Evaluation` compare distances. It is fixed,
+// so that the ordering of answers does not change while they are sorted or heaped.
+// Comparisons with another tolerance, e.g., of the results of methods accumulating
+// rounding errors differently, pass it to `dist_eq_with`.
+pub const DIST_EPSILON: f32 = f32::EPSILON;
+
+pub fn dist_eq(a: f32, b: f32) -> bool {
+    dist_eq_with(a, b, DIST_EPSILON)
+}
+
+// Equal within `epsilon` either absolutely or relatively to the larger magnitude.
+// Distances of large unions are close to 1 and their rounding errors scale with them.
+pub fn dist_eq_with(a: f32, b: f32, epsilon: f32) -> bool {
+    relative_eq!(a, b, epsilon = epsilon, max_relative = epsilon)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Answer {
     pub id: u32,
     pub dist: f32,
 }
 
+impl Answer {
+    pub fn similarity(&self) -> f32 {
+        1.0 - self.dist
+    }
+}
+
+impl From<Answer> for (u32, f32) {
+    fn from(ans: Answer) -> Self {
+        (ans.id, ans.dist)
+    }
+}
+
+impl From<(u32, f32)> for Answer {
+    fn from((id, dist): (u32, f32)) -> Self {
+        Self { id, dist }
+    }
+}
+
 impl Eq for Answer {}
 
 impl PartialEq for Answer {
     fn eq(&self, other: &Self) -> bool {
-        self.id == other.id && abs_diff_eq!(self.dist, other.dist)
+        self.id == other.id && dist_eq(self.dist, other.dist)
     }
 }
 
 impl Ord for Answer {
     fn cmp(&self, other: &Self) -> Ordering {
-        if abs_diff_eq!(self.dist, other.dist) {
+        if dist_eq(self.dist, other.dist) {
             self.id.cmp(&other.id)
         } else {
             self.dist.partial_cmp(&other.dist).unwrap()
@@ -49,8 +125,405 @@ impl PartialOrd for Answer {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct Record<T> {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "T: Serialize, S: AsRef<[T]>",
+    deserialize = "T: Deserialize<'de> + Ord + Copy, S: From<Vec<T>>"
+))]
+pub struct Record<T, S = Vec<T>> {
     pub id: u32,
-    pub set: OrderedSet<T>,
+    pub set: OrderedSet<T, S>,
+}
+
+// Record stored in the indexes, whose set has no capacity.
+pub(crate) type FrozenRecord = Record<u32, Box<[u32]>>;
+
+impl<T> Record<T>
+where
+    T: Ord + Copy,
+{
+    pub fn freeze(self) -> Record<T, Box<[T]>> {
+        Record {
+            id: self.id,
+            set: self.set.freeze(),
+        }
+    }
+}
+
+// Summary of an index that passed validation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrityReport {
+    pub n_records: usize,
+    // Number of the ids removed as duplicates.
+    pub n_duplicates: usize,
+    // Number of the posting lists and their total length, 0 for indexes without postings.
+    pub n_postings: usize,
+    pub n_entries: usize,
+}
+
+// Checks the mapping, the mapped records, and the offsets of the ids, appending the
+// violations to `issues`.
+pub(crate) fn check_records<S>(
+    mapping: &Mapping,
+    records: &[Record<u32, S>],
+    offsets: &HashMap<u32, u32>,
+    duplicates: &HashMap<u32, Vec<u32>>,
+    issues: &mut Vec<String>,
+) where
+    S: AsRef<[u32]>,
+{
+    if !mapping.is_permutation() {
+        issues.push("The mapping is not a permutation.".to_string());
+    }
+    for record in records {
+        if !record.set.is_strictly_sorted() {
+            issues.push(format!("The set of record {} is not sorted.", record.id));
+        }
+        if record.set.iter().any(|&elem| elem >= mapping.universe()) {
+            issues.push(format!(
+                "The set of record {} is out of the universe.",
+                record.id
+            ));
+        }
+    }
+    for (i, record) in records.iter().enumerate() {
+        if offsets.get(&record.id) != Some(&(i as u32)) {
+            issues.push(format!("The offset of record {} is wrong.", record.id));
+        }
+    }
+    for (kept, ids) in duplicates {
+        let kept_offset = offsets.get(kept);
+        for id in ids {
+            if kept_offset.is_none() || offsets.get(id) != kept_offset {
+                issues.push(format!("Duplicate {} does not alias record {}.", id, kept));
+            }
+        }
+    }
+    let n_ids = records.len() + duplicates.values().map(|ids| ids.len()).sum::<usize>();
+    if offsets.len() != n_ids {
+        issues.push(format!(
+            "{} ids have offsets for {} records and duplicates.",
+            offsets.len(),
+            n_ids
+        ));
+    }
+}
+
+// Fails with the issues if any.
+pub(crate) fn integrity_result(
+    report: IntegrityReport,
+    issues: Vec<String>,
+) -> Result<IntegrityReport> {
+    const MAX_ISSUES: usize = 10;
+    if issues.is_empty() {
+        return Ok(report);
+    }
+    let mut message = format!("{} integrity issues: ", issues.len());
+    message += &issues[..issues.len().min(MAX_ISSUES)].join(" ");
+    if issues.len() > MAX_ISSUES {
+        message += " ...";
+    }
+    Err(anyhow!(message))
+}
+
+// Common interface of the indexes answering range queries, e.g., to compare or chain them.
+// Indexes built for a fixed radius answer smaller radii by filtering their answers
+// and reject larger ones.
+pub trait RangeSearch {
+    fn range_search(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Answer>>;
+}
+
+// Narrows the answers of an index built for `built_radius` to `radius`.
+pub(crate) fn narrow_answers(
+    mut answers: Vec<Answer>,
+    radius: f32,
+    built_radius: f32,
+) -> Result<Vec<Answer>> {
+    check_radius(radius)?;
+    if radius > built_radius {
+        return Err(anyhow!(
+            "The radius exceeds that of the index: {} > {}",
+            radius,
+            built_radius
+        ));
+    }
+    if radius < built_radius {
+        answers.retain(|ans| ans.dist <= radius);
+    }
+    Ok(answers)
+}
+
+// Merges answers to several queries, keeping the minimum distance per id.
+pub(crate) fn union_answers<I>(answers: I) -> Vec<Answer>
+where
+    I: IntoIterator<Item = Answer>,
+{
+    let mut min_dists = HashMap::new();
+    for ans in answers {
+        min_dists
+            .entry(ans.id)
+            .and_modify(|dist: &mut f32| *dist = dist.min(ans.dist))
+            .or_insert(ans.dist);
+    }
+    let mut answers = min_dists
+        .into_iter()
+        .map(|(id, dist)| Answer { id, dist })
+        .collect::<Vec<_>>();
+    answers.sort_unstable();
+    answers
+}
+
+// Keeps the nearest `limit` answers in any order, and returns whether any answer was dropped.
+// Callers truncate whenever the answers reach twice the limit, so that the memory of a query
+// is bounded by the limit regardless of the number of accepted records.
+pub(crate) fn truncate_answers(answers: &mut Vec<Answer>, limit: usize) -> bool {
+    if answers.len() <= limit {
+        return false;
+    }
+    if limit != 0 {
+        answers.select_nth_unstable(limit - 1);
+    }
+    answers.truncate(limit);
+    true
+}
+
+// Rejects the limit of no answers.
+pub(crate) fn check_max_results(limit: usize) -> Result<()> {
+    if limit == 0 {
+        return Err(anyhow!("max_results must be positive."));
+    }
+    Ok(())
+}
+
+// Rejects NaN and negative radii. Radii greater than 1 are accepted and behave as 1.
+pub(crate) fn check_radius(radius: f32) -> Result<()> {
+    if radius.is_nan() || radius < 0.0 {
+        return Err(anyhow!("Invalid radius: {}", radius));
+    }
+    Ok(())
+}
+
+pub(crate) fn check_k(k: usize) -> Result<()> {
+    if k == 0 {
+        return Err(anyhow!("k must be positive."));
+    }
+    Ok(())
+}
+
+// Rejects NaN and containments out of [0, 1].
+pub(crate) fn check_containment(min_containment: f32) -> Result<()> {
+    if !(0.0..=1.0).contains(&min_containment) {
+        return Err(anyhow!("Invalid containment: {}", min_containment));
+    }
+    Ok(())
+}
+
+// Rejects NaN, infinite and negative weights of the similarity to the negative query.
+pub(crate) fn check_contrastive_lambda(lambda: f32) -> Result<()> {
+    if !lambda.is_finite() || lambda < 0.0 {
+        return Err(anyhow!(
+            "lambda must be finite and non-negative: {}",
+            lambda
+        ));
+    }
+    Ok(())
+}
+
+// Splits the records into those with set lengths within `set_lens` and the ids of the others.
+pub(crate) fn admit_records(
+    records: &[Record<u32>],
+    set_lens: &RangeInclusive<usize>,
+) -> (Vec<Record<u32>>, Vec<u32>) {
+    let mut admitted = Vec::with_capacity(records.len());
+    let mut skipped = Vec::new();
+    for record in records {
+        if set_lens.contains(&record.set.len()) {
+            admitted.push(record.clone());
+        } else {
+            skipped.push(record.id);
+        }
+    }
+    (admitted, skipped)
+}
+
+// Maps record ids to their offsets, rejecting duplicate ids.
+pub(crate) fn id_offsets<S>(records: &[Record<u32, S>]) -> Result<HashMap<u32, u32>> {
+    let mut offsets = HashMap::with_capacity(records.len());
+    for (i, record) in records.iter().enumerate() {
+        if offsets.insert(record.id, i as u32).is_some() {
+            return Err(anyhow!("Duplicate record id: {}", record.id));
+        }
+    }
+    Ok(offsets)
+}
+
+// Keeps the first record of each distinct set and returns the ids of the removed
+// records per kept id.
+pub(crate) fn dedup_records<S>(records: &mut Vec<Record<u32, S>>) -> HashMap<u32, Vec<u32>>
+where
+    S: Hash + Eq,
+{
+    let mut keeps = Vec::with_capacity(records.len());
+    let mut duplicates = HashMap::new();
+    {
+        let mut firsts = HashMap::new();
+        for record in records.iter() {
+            let &mut first = firsts.entry(&record.set).or_insert(record.id);
+            keeps.push(first == record.id);
+            if first != record.id {
+                duplicates
+                    .entry(first)
+                    .or_insert_with(Vec::new)
+                    .push(record.id);
+            }
+        }
+    }
+    let mut keeps = keeps.into_iter();
+    records.retain(|_| keeps.next().unwrap());
+    duplicates
+}
+
+// Maps the ids of the removed duplicates to the offsets of their kept records.
+pub(crate) fn alias_offsets(offsets: &mut HashMap<u32, u32>, duplicates: &HashMap<u32, Vec<u32>>) {
+    for (kept, ids) in duplicates {
+        let offset = offsets[kept];
+        for &id in ids {
+            offsets.insert(id, offset);
+        }
+    }
+}
+
+// Fewer candidates are verified on the calling thread, since spawning costs more.
+const MIN_PARALLEL_CHUNK: usize = 1024;
+
+// Builds a pool of exactly `n_threads` threads regardless of `RAYON_NUM_THREADS`,
+// e.g., for the indexes by `thread_pool` to compare parallelism levels in one process.
+pub fn build_thread_pool(n_threads: usize) -> Result<Arc<ThreadPool>> {
+    if n_threads == 0 {
+        return Err(anyhow!("The number of threads must be positive."));
+    }
+    let pool = ThreadPoolBuilder::new().num_threads(n_threads).build()?;
+    Ok(Arc::new(pool))
+}
+
+// Runs `op` in the pool if given, or in the current rayon pool otherwise.
+pub(crate) fn install<R, F>(pool: Option<&ThreadPool>, op: F) -> R
+where
+    R: Send,
+    F: FnOnce() -> R + Send,
+{
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
+
+// Splits the candidates into up to `parallelism` chunks verified by `verify` in parallel,
+// and merges their answers. The answers are sorted as in the sequential search.
+// The chunks are merged in their order, so that the answers are the same
+// for any parallelism and pool even if their distances tie within the epsilon.
+// With `limit`, each chunk keeps its nearest answers before the merge.
+pub(crate) fn verify_parallel<C, F>(
+    candidates: &[C],
+    parallelism: usize,
+    limit: Option<usize>,
+    verify: F,
+) -> Vec<Answer>
+where
+    C: Sync,
+    F: Fn(&[C], &mut Vec<Answer>) + Sync,
+{
+    let n_chunks = parallelism.min(candidates.len() / MIN_PARALLEL_CHUNK);
+    let verify_chunk = |chunk: &[C]| {
+        let mut answers = Vec::new();
+        verify(chunk, &mut answers);
+        if let Some(limit) = limit {
+            truncate_answers(&mut answers, limit);
+        }
+        answers
+    };
+    let mut answers = if n_chunks <= 1 {
+        verify_chunk(candidates)
+    } else {
+        let chunk_len = candidates.len().div_ceil(n_chunks);
+        let chunks = candidates
+            .par_chunks(chunk_len)
+            .map(verify_chunk)
+            .collect::<Vec<_>>();
+        let mut answers = chunks.concat();
+        if let Some(limit) = limit {
+            truncate_answers(&mut answers, limit);
+        }
+        answers
+    };
+    answers.sort_unstable();
+    answers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dist_eq() {
+        assert!(dist_eq(0.5, 0.5 + f32::EPSILON / 2.));
+        assert!(!dist_eq(0.5, 0.5001));
+
+        assert!(dist_eq_with(0.5, 0.5001, 1e-3));
+        // Relative to the magnitude.
+        assert!(dist_eq_with(100.0, 100.05, 1e-3));
+        assert!(!dist_eq_with(0.001, 0.0021, 1e-3));
+    }
+
+    #[test]
+    fn test_answer_conversions() {
+        let ans = Answer { id: 3, dist: 0.25 };
+        assert_eq!(ans.similarity(), 0.75);
+        assert_eq!(<(u32, f32)>::from(ans.clone()), (3, 0.25));
+        assert_eq!(Answer::from((3, 0.25)), ans);
+    }
+
+    #[test]
+    fn test_serde() {
+        let record = Record {
+            id: 1,
+            set: OrderedSet::from_sorted([2, 5]).unwrap(),
+        };
+        let j = serde_json::to_string(&record).unwrap();
+        assert_eq!(j, r#"{"id":1,"set":[2,5]}"#);
+        let record: Record<u32> = serde_json::from_str(&j).unwrap();
+        assert_eq!(record.set, OrderedSet::from_sorted([2, 5]).unwrap());
+        assert!(serde_json::from_str::<Record<u32>>(r#"{"id":1,"set":[5,2]}"#).is_err());
+
+        let ans: Answer = serde_json::from_str(r#"{"id":1,"dist":0.5}"#).unwrap();
+        assert_eq!(ans, Answer { id: 1, dist: 0.5 });
+        let eval = serde_json::to_string(&metric::Evaluation::Accepted(0.5)).unwrap();
+        assert_eq!(eval, r#"{"Accepted":0.5}"#);
+        let config: FilterConfig =
+            serde_json::from_str(r#"{"length":true,"position":false}"#).unwrap();
+        assert!(config.length && !config.position);
+    }
+
+    #[test]
+    fn test_frozen_record() {
+        // The stored records save the capacity word of `Vec`.
+        assert!(std::mem::size_of::<FrozenRecord>() < std::mem::size_of::<Record<u32>>());
+
+        let mut set = OrderedSet::with_capacity(16);
+        set.insert(3);
+        set.insert(1);
+        let record = Record { id: 1, set }.freeze();
+        assert_eq!(record.set.as_slice(), &[1, 3]);
+        assert_eq!(
+            record.set.to_set(),
+            OrderedSet::from_sorted([1, 3]).unwrap()
+        );
+
+        // Serialized as the records of `Vec`.
+        let j = serde_json::to_string(&record).unwrap();
+        assert_eq!(j, r#"{"id":1,"set":[1,3]}"#);
+        let frozen: FrozenRecord = serde_json::from_str(&j).unwrap();
+        assert_eq!((frozen.id, frozen.set), (record.id, record.set));
+        assert!(serde_json::from_str::<FrozenRecord>(r#"{"id":1,"set":[3,1]}"#).is_err());
+    }
 }
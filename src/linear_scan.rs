@@ -1,86 +1,1088 @@
 use std::collections::BinaryHeap;
+use std::io::{Read, Write};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
 
+use anyhow::anyhow;
 use anyhow::Result;
+use hashbrown::{HashMap, HashSet};
+use rayon::prelude::*;
+use rayon::ThreadPool;
 
-use crate::metric::{Evaluation, FilterConfig, Jaccard};
-use crate::{Answer, Mapping, OrderedSet, Record};
+use crate::index_file::{
+    check_header, current_header, read_header, read_records, write_header, write_records, Decoder,
+    Encoder, FormatError, IndexKind, Metric,
+};
+use crate::mapping::config_hash;
+use crate::metric::{
+    Containment, ContainmentMode, Contrastive, Evaluation, EvaluationSweep, FilterConfig,
+    FilterPolicy, Jaccard, RationalThreshold, SmoothedJaccard, WeightedJaccard,
+};
+use crate::text::FeatureExtractor;
+use crate::{
+    admit_records, alias_offsets, check_contrastive_lambda, check_k, check_max_results,
+    check_radius, check_records, dedup_records, dist_eq, id_offsets, install, integrity_result,
+    truncate_answers, verify_parallel, Answer, Budget, Dropout, FrozenRecord, FrozenSet,
+    IntegrityReport, MappedQuery, Mapping, OrderedSet, QueryContext, RangeSearch, Record,
+    WeightedSet, DIST_EPSILON,
+};
+
+// Why a record matches a query, or not.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairExplanation {
+    // Shared (unmapped) elements in ascending order.
+    pub shared: Vec<u32>,
+    pub intersection: usize,
+    pub union: usize,
+    pub dist: Option<f32>,
+    // Whether the pair passed each filter alone, and was accepted by the radius.
+    pub length_passed: bool,
+    pub position_passed: bool,
+    pub accepted: bool,
+}
+
+impl PairExplanation {
+    // Returns the n-grams of the shared elements recorded by `FeatureExtractor::record_ngrams`,
+    // or `None` if the extractor is not recording.
+    pub fn ngrams(&self, extractor: &FeatureExtractor) -> Option<Vec<Vec<String>>> {
+        self.shared
+            .iter()
+            .map(|&elem| extractor.recorded_ngrams(elem))
+            .collect()
+    }
+}
 
 pub struct LinearScan {
-    mapping: Mapping,
-    records: Vec<Record<u32>>,
+    mapping: Arc<Mapping>,
+    // Never mutated after construction, thus stored without spare capacity.
+    records: Vec<FrozenRecord>,
+    // Offsets of the records sorted by length, to scan top-k queries from the nearest lengths.
+    by_length: Vec<u32>,
+    offsets: HashMap<u32, u32>,
+    duplicates: HashMap<u32, Vec<u32>>,
     config: FilterConfig,
+    policy: Option<FilterPolicy>,
+    scorer: Option<Scorer>,
+    priors: Option<Priors>,
+    mmr: Option<Mmr>,
+    // `FeatureExtractor::config_hash` of the extractor of the records, and the hash
+    // combined with the mapping to check `MappedQuery`.
+    extractor_hash: Option<u64>,
+    config_hash: Option<u64>,
+    max_results: Option<usize>,
+    // Pool of the parallel queries, or the current rayon pool if none.
+    pool: Option<Arc<ThreadPool>>,
+}
+
+// Minimum enlargement of the radius in `range_query_progressive`, which otherwise doubles it.
+const PROGRESSIVE_STEP: f32 = 0.05;
+
+// Adjusts the distance of an accepted record, or vetoes it by `None`, e.g., by business rules.
+// The elements of the set are sorted and mapped by `LinearScan::mapping`.
+pub type Scorer = Arc<dyn Fn(&Answer, &[u32]) -> Option<f32> + Send + Sync>;
+
+// Combines the distance with the prior of a record in [0, 1], e.g., the document importance,
+// for the top-k ranking. Prior 1 keeps the distance, and lower priors only increase it,
+// so that the top-k search can still narrow the radius by the k-th combined distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PriorFormula {
+    // `1 - (1 - dist) * prior^exponent`, i.e., scales the similarity.
+    Scale { exponent: f32 },
+    // `dist + weight * (1 - prior)`.
+    Penalty { weight: f32 },
+}
+
+impl PriorFormula {
+    pub fn combine(&self, dist: f32, prior: f32) -> f32 {
+        match *self {
+            Self::Scale { exponent } => 1.0 - (1.0 - dist) * prior.powf(exponent),
+            Self::Penalty { weight } => dist + weight * (1.0 - prior),
+        }
+    }
+}
+
+// Maximal Marginal Relevance re-ranking of the `k * pool_factor` nearest records.
+#[derive(Debug, Clone, Copy)]
+struct Mmr {
+    lambda: f32,
+    pool_factor: usize,
+}
+
+struct Priors {
+    // Records without priors have prior 1.
+    by_id: HashMap<u32, f32>,
+    formula: PriorFormula,
+}
+
+// Statistics of a range query, with the filters decided for it.
+#[derive(Debug, Clone, Default)]
+pub struct QueryStats {
+    pub config: FilterConfig,
+    pub length_filtered: usize,
+    pub position_filtered: usize,
+    pub verified: usize,
+    pub accepted: usize,
+    // Whether answers were dropped by `LinearScan::max_results`.
+    pub truncated: bool,
 }
 
 impl LinearScan {
     pub fn from_records(records: &[Record<u32>], universe: u32) -> Result<Self> {
         let mapping = Mapping::from_records(records, universe)?;
+        Self::from_records_with_mapping(records, Arc::new(mapping))
+    }
+
+    // Indexes only the records with set lengths within `set_lens`,
+    // and also returns the ids of the skipped records.
+    pub fn from_records_within(
+        records: &[Record<u32>],
+        universe: u32,
+        set_lens: RangeInclusive<usize>,
+    ) -> Result<(Self, Vec<u32>)> {
+        let (records, skipped) = admit_records(records, &set_lens);
+        Ok((Self::from_records(&records, universe)?, skipped))
+    }
+
+    pub fn from_records_with_mapping(
+        records: &[Record<u32>],
+        mapping: Arc<Mapping>,
+    ) -> Result<Self> {
+        if !mapping.covers(records) {
+            return Err(anyhow!("The mapping does not cover the records."));
+        }
+        let offsets = id_offsets(records)?;
         let records = records
             .iter()
-            .map(|record| Record {
-                id: record.id,
-                set: mapping.apply(&record.set),
+            .map(|record| {
+                Record {
+                    id: record.id,
+                    set: mapping.apply(&record.set),
+                }
+                .freeze()
             })
             .collect::<Vec<_>>();
+        let by_length = length_order(&records);
         Ok(Self {
             mapping,
             records,
+            by_length,
+            offsets,
+            duplicates: HashMap::new(),
             config: FilterConfig::default(),
+            policy: None,
+            scorer: None,
+            priors: None,
+            mmr: None,
+            extractor_hash: None,
+            config_hash: None,
+            max_results: None,
+            pool: None,
         })
     }
 
-    pub fn filter_config(mut self, config: FilterConfig) -> Self {
+    pub fn filter_config(mut self, config: FilterConfig) -> Result<Self> {
+        config.check_supported()?;
         self.config = config;
+        Ok(self)
+    }
+
+    // Overrides `filter_config` in range queries by the decision of the policy per query.
+    pub fn filter_policy(mut self, policy: FilterPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    // Rescores the records accepted in range and top-k queries during the verification.
+    // Since the scores may be boosted below the Jaccard distances, top-k queries
+    // no longer narrow the radius by the k-th score and verify all the records.
+    pub fn scorer<F>(mut self, scorer: F) -> Self
+    where
+        F: Fn(&Answer, &[u32]) -> Option<f32> + Send + Sync + 'static,
+    {
+        self.scorer = Some(Arc::new(scorer));
+        self
+    }
+
+    // Ranks top-k queries by the distances combined with the priors of the records by `formula`.
+    // Range queries are not affected.
+    pub fn priors(mut self, priors: &[(u32, f32)], formula: PriorFormula) -> Result<Self> {
+        let param = match formula {
+            PriorFormula::Scale { exponent } => exponent,
+            PriorFormula::Penalty { weight } => weight,
+        };
+        if !param.is_finite() || param < 0.0 {
+            return Err(anyhow!("Invalid parameter of the prior formula: {}", param));
+        }
+        let mut by_id = HashMap::with_capacity(priors.len());
+        for &(id, prior) in priors {
+            if !self.contains_id(id) {
+                return Err(anyhow!("Unknown record id: {}", id));
+            }
+            if !(0.0..=1.0).contains(&prior) {
+                return Err(anyhow!("The prior must be in [0, 1]: {}", prior));
+            }
+            by_id.insert(id, prior);
+        }
+        self.priors = Some(Priors { by_id, formula });
+        Ok(self)
+    }
+
+    // Diversifies top-k answers by Maximal Marginal Relevance. From the `k * pool_factor`
+    // nearest records, it greedily selects the record maximizing
+    // `lambda * sim(query) - (1 - lambda) * max sim(selected)` by the Jaccard similarity,
+    // e.g., to avoid k copies of the same document. Answers are in the selected order.
+    pub fn mmr(mut self, lambda: f32, pool_factor: usize) -> Result<Self> {
+        if !(0.0..=1.0).contains(&lambda) {
+            return Err(anyhow!("lambda must be in [0, 1]: {}", lambda));
+        }
+        if pool_factor == 0 {
+            return Err(anyhow!("pool_factor must be positive."));
+        }
+        self.mmr = Some(Mmr {
+            lambda,
+            pool_factor,
+        });
+        Ok(self)
+    }
+
+    // Limits range queries to the nearest `limit` answers, e.g., against a mis-set radius
+    // accepting most of the database. `range_query_truncated` tells whether answers were dropped.
+    // The limit applies to the range queries sharing the verification of `range_query`,
+    // e.g., `range_query_excluding`, `range_query_with_budget`, `range_query_parallel`,
+    // and `query_prepared_batch`.
+    pub fn max_results(mut self, limit: usize) -> Result<Self> {
+        check_max_results(limit)?;
+        self.max_results = Some(limit);
+        Ok(self)
+    }
+
+    // Runs `range_query_parallel` and `query_prepared_batch` in the pool, e.g., of
+    // `build_thread_pool` to control the parallelism per run. The answers do not depend on it.
+    pub fn thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.pool = Some(pool);
         self
     }
 
-    pub fn range_query(&self, query: &OrderedSet<u32>, radius: f32) -> Vec<Answer> {
+    // Keeps one record per distinct set so that answers report the kept ids only.
+    // The other ids are given by `duplicates` and share the kept records,
+    // e.g., excluding one of them excludes the kept record.
+    pub fn deduplicate(mut self) -> Self {
+        let duplicates = dedup_records(&mut self.records);
+        self.by_length = length_order(&self.records);
+        self.offsets = id_offsets(&self.records).unwrap();
+        alias_offsets(&mut self.offsets, &duplicates);
+        self.duplicates = duplicates;
+        self
+    }
+
+    // Returns the ids of the records removed as duplicates of the record.
+    pub fn duplicates(&self, id: u32) -> &[u32] {
+        self.duplicates.get(&id).map_or(&[], |ids| ids.as_slice())
+    }
+
+    pub fn mapping(&self) -> &Arc<Mapping> {
+        &self.mapping
+    }
+
+    // Records the extractor of the records, so that `MappedQuery` by another extractor
+    // or mapping fails instead of silently returning wrong answers.
+    pub fn extractor(self, extractor: &FeatureExtractor) -> Self {
+        self.with_extractor_hash(Some(extractor.config_hash()))
+    }
+
+    fn with_extractor_hash(mut self, extractor_hash: Option<u64>) -> Self {
+        self.extractor_hash = extractor_hash;
+        self.config_hash = extractor_hash.map(|hash| config_hash(hash, &self.mapping));
+        self
+    }
+
+    // Hash of the extractor and the mapping, if the extractor is known.
+    pub const fn config_hash(&self) -> Option<u64> {
+        self.config_hash
+    }
+
+    // Returns the mapped set of the record.
+    pub fn get_record(&self, id: u32) -> Option<&FrozenSet<u32>> {
+        self.offsets
+            .get(&id)
+            .map(|&i| &self.records[i as usize].set)
+    }
+
+    pub fn contains_id(&self, id: u32) -> bool {
+        self.offsets.contains_key(&id)
+    }
+
+    // Maps the query once for the `*_prepared` queries, e.g., with several radii.
+    pub fn prepare(&self, query: &OrderedSet<u32>) -> MappedQuery {
+        MappedQuery::with_config_hash(self.mapping.apply(query), self.config_hash)
+    }
+
+    pub fn range_query(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Answer>> {
+        self.range_query_unchecked(&self.mapping.apply(query), radius)
+    }
+
+    // The query must be already mapped by `self.mapping()`.
+    #[deprecated(note = "use `prepare` and `range_query_prepared`, which check the mapping")]
+    pub fn range_query_mapped(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Answer>> {
+        self.range_query_unchecked(query, radius)
+    }
+
+    // Fails if the query was produced by another configuration than `config_hash`.
+    pub fn range_query_prepared(&self, query: &MappedQuery, radius: f32) -> Result<Vec<Answer>> {
+        query.check(self.config_hash)?;
+        self.range_query_unchecked(query.set(), radius)
+    }
+
+    // The query must be already mapped by `self.mapping()`.
+    fn range_query_unchecked(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Answer>> {
+        check_radius(radius)?;
+        Ok(self.range_query_inner(query, radius, &[], &mut QueryStats::default()))
+    }
+
+    // Decides the acceptance exactly by the rational threshold, ignoring the filter policy.
+    pub fn range_query_rational(
+        &self,
+        query: &OrderedSet<u32>,
+        threshold: RationalThreshold,
+    ) -> Vec<Answer> {
+        let query = self.mapping.apply(query);
+        let jaccard = Jaccard::with_rational(&query, threshold, self.config.clone());
+        let mut answers = self
+            .records
+            .iter()
+            .filter_map(|record| match jaccard.evaluate(&record.set) {
+                Evaluation::Accepted(dist) => self.rescore(record, dist),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        answers.sort_unstable();
+        answers
+    }
+
+    // Enlarges the radius from `radius` until at least `min_results` records are found or the
+    // radius reaches 1, e.g., to always show something. Returns the answers within the final
+    // radius, which is also returned. Records accepted at a radius are not evaluated again.
+    pub fn range_query_progressive(
+        &self,
+        query: &OrderedSet<u32>,
+        radius: f32,
+        min_results: usize,
+    ) -> Result<(Vec<Answer>, f32)> {
+        check_radius(radius)?;
         let query = self.mapping.apply(query);
-        let jaccard = Jaccard::new(&query, radius, self.config);
+        let mut jaccard = Jaccard::new(&query, radius, self.config.clone());
+        let mut radius = radius;
         let mut answers = Vec::new();
-        for record in &self.records {
-            if let Evaluation::Accepted(dist) = jaccard.evaluate(&record.set) {
-                answers.push(Answer {
-                    id: record.id,
-                    dist,
-                });
+        let mut pending = (0..self.records.len()).collect::<Vec<_>>();
+        loop {
+            pending.retain(|&i| {
+                let record = &self.records[i];
+                match jaccard.evaluate(&record.set) {
+                    Evaluation::Accepted(dist) => {
+                        answers.extend(self.rescore(record, dist));
+                        false
+                    }
+                    Evaluation::Undefined => false,
+                    _ => true,
+                }
+            });
+            if answers.len() >= min_results || radius >= 1.0 || pending.is_empty() {
+                break;
+            }
+            radius = (radius * 2.0).max(radius + PROGRESSIVE_STEP).min(1.0);
+            jaccard.update_radius(radius);
+        }
+        answers.sort_unstable();
+        Ok((answers, radius))
+    }
+
+    // Also returns whether answers beyond `max_results` were dropped.
+    pub fn range_query_truncated(
+        &self,
+        query: &OrderedSet<u32>,
+        radius: f32,
+    ) -> Result<(Vec<Answer>, bool)> {
+        let (answers, stats) = self.range_query_with_stats(query, radius)?;
+        Ok((answers, stats.truncated))
+    }
+
+    pub fn range_query_with_stats(
+        &self,
+        query: &OrderedSet<u32>,
+        radius: f32,
+    ) -> Result<(Vec<Answer>, QueryStats)> {
+        check_radius(radius)?;
+        let mut stats = QueryStats::default();
+        let answers = self.range_query_inner(&self.mapping.apply(query), radius, &[], &mut stats);
+        Ok((answers, stats))
+    }
+
+    // Also returns the records within `radius + margin` but not within `radius` as near misses,
+    // e.g., for tuning the radius. Candidates are verified against the relaxed radius once,
+    // and only the accepted ones are labeled by the strict radius.
+    pub fn range_query_with_near_misses(
+        &self,
+        query: &OrderedSet<u32>,
+        radius: f32,
+        margin: f32,
+    ) -> Result<(Vec<Answer>, Vec<Answer>)> {
+        check_radius(radius)?;
+        if margin.is_nan() || margin < 0.0 {
+            return Err(anyhow!("Invalid margin: {}", margin));
+        }
+        let query = self.mapping.apply(query);
+        let relaxed =
+            self.range_query_inner(&query, radius + margin, &[], &mut QueryStats::default());
+        let strict = Jaccard::new(&query, radius, self.config.clone());
+        let (answers, near_misses) = relaxed.into_iter().partition(|ans| {
+            self.offsets.get(&ans.id).is_some_and(|&offset| {
+                let set = &self.records[offset as usize].set;
+                matches!(strict.evaluate(set), Evaluation::Accepted(_))
+            })
+        });
+        Ok((answers, near_misses))
+    }
+
+    // Skips the records of the ids in `exclude` without verification.
+    pub fn range_query_excluding(
+        &self,
+        query: &OrderedSet<u32>,
+        radius: f32,
+        exclude: &[u32],
+    ) -> Result<Vec<Answer>> {
+        check_radius(radius)?;
+        let excluded = self.sorted_offsets(exclude);
+        Ok(self.range_query_inner(
+            &self.mapping.apply(query),
+            radius,
+            &excluded,
+            &mut QueryStats::default(),
+        ))
+    }
+
+    // Consumes `budget` by the records verified and returns partial results if it is exhausted.
+    // `Budget::is_exhausted` tells whether the results are partial.
+    pub fn range_query_with_budget(
+        &self,
+        query: &OrderedSet<u32>,
+        radius: f32,
+        budget: &mut Budget,
+    ) -> Result<Vec<Answer>> {
+        check_radius(radius)?;
+        Ok(self.range_query_budgeted(
+            &self.mapping.apply(query),
+            radius,
+            &[],
+            &mut QueryStats::default(),
+            budget,
+        ))
+    }
+
+    fn range_query_inner<S: AsRef<[u32]>>(
+        &self,
+        query: &OrderedSet<u32, S>,
+        radius: f32,
+        excluded: &[u32],
+        stats: &mut QueryStats,
+    ) -> Vec<Answer> {
+        self.range_query_budgeted(query, radius, excluded, stats, &mut Budget::new())
+    }
+
+    fn range_query_budgeted<S: AsRef<[u32]>>(
+        &self,
+        query: &OrderedSet<u32, S>,
+        radius: f32,
+        excluded: &[u32],
+        stats: &mut QueryStats,
+        budget: &mut Budget,
+    ) -> Vec<Answer> {
+        debug_assert!(self.is_mapped(query));
+        stats.config = self.policy.map_or(self.config.clone(), |policy| {
+            policy.decide(query.len(), radius)
+        });
+        let jaccard = Jaccard::new(query, radius, stats.config.clone());
+        let evaluate = jaccard.evaluator();
+        let mut answers = Vec::new();
+        let n_verified = budget.take_verified(self.records.len().saturating_sub(excluded.len()));
+        let records = self
+            .records
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| excluded.binary_search(&(*i as u32)).is_err())
+            .take(n_verified);
+        for (_, record) in records {
+            match evaluate(&jaccard, &record.set) {
+                Evaluation::LengthFiltered => stats.length_filtered += 1,
+                Evaluation::PositionFiltered => stats.position_filtered += 1,
+                Evaluation::Verified | Evaluation::Undefined => stats.verified += 1,
+                Evaluation::Accepted(dist) => {
+                    stats.accepted += 1;
+                    answers.extend(self.rescore(record, dist));
+                    if let Some(limit) = self.max_results {
+                        if answers.len() >= limit.saturating_mul(2) {
+                            stats.truncated |= truncate_answers(&mut answers, limit);
+                        }
+                    }
+                }
             }
         }
+        if let Some(limit) = self.max_results {
+            stats.truncated |= truncate_answers(&mut answers, limit);
+        }
         answers.sort_unstable();
         answers
     }
 
-    pub fn topk_query(&self, query: &OrderedSet<u32>, k: usize) -> Vec<Answer> {
+    fn rescore(&self, record: &FrozenRecord, dist: f32) -> Option<Answer> {
+        let ans = Answer {
+            id: record.id,
+            dist,
+        };
+        match &self.scorer {
+            Some(scorer) => {
+                scorer(&ans, record.set.as_slice()).map(|dist| Answer { id: ans.id, dist })
+            }
+            None => Some(ans),
+        }
+    }
+
+    // Verifies the records for a single query in up to `parallelism` threads,
+    // e.g., for interactive queries over a large database.
+    pub fn range_query_parallel(
+        &self,
+        query: &OrderedSet<u32>,
+        radius: f32,
+        parallelism: usize,
+    ) -> Result<Vec<Answer>> {
+        check_radius(radius)?;
         let query = self.mapping.apply(query);
-        let mut jaccard = Jaccard::new(&query, 1.0, self.config);
-        let mut heap = BinaryHeap::with_capacity(k);
-        for record in &self.records {
-            if let Evaluation::Accepted(dist) = jaccard.evaluate(&record.set) {
-                if heap.len() < k {
-                    heap.push(Answer {
-                        id: record.id,
-                        dist,
-                    });
-                    if heap.len() == k {
-                        let max_radius = heap.peek().unwrap().dist;
-                        jaccard.update_radius(max_radius);
+        let config = self.policy.map_or(self.config.clone(), |policy| {
+            policy.decide(query.len(), radius)
+        });
+        let jaccard = Jaccard::new(&query, radius, config);
+        Ok(install(self.pool.as_deref(), || {
+            verify_parallel(
+                &self.records,
+                parallelism,
+                self.max_results,
+                |records, answers| {
+                    for record in records {
+                        if let Evaluation::Accepted(dist) = jaccard.evaluate(&record.set) {
+                            answers.extend(self.rescore(record, dist));
+                        }
                     }
-                } else if heap.peek().unwrap().dist > dist {
+                },
+            )
+        }))
+    }
+
+    // Returns the records within `radius` from any of the queries with the minimum distance.
+    pub fn range_query_any(&self, queries: &[OrderedSet<u32>], radius: f32) -> Result<Vec<Answer>> {
+        check_radius(radius)?;
+        let queries = queries
+            .iter()
+            .map(|query| self.mapping.apply(query))
+            .collect::<Vec<_>>();
+        let jaccards = queries
+            .iter()
+            .map(|query| Jaccard::new(query, radius, self.config.clone()))
+            .collect::<Vec<_>>();
+        let mut answers = Vec::new();
+        for record in &self.records {
+            let dist = jaccards
+                .iter()
+                .filter_map(|jaccard| match jaccard.evaluate(&record.set) {
+                    Evaluation::Accepted(dist) => Some(dist),
+                    _ => None,
+                })
+                .min_by(f32::total_cmp);
+            if let Some(dist) = dist {
+                answers.extend(self.rescore(record, dist));
+            }
+        }
+        answers.sort_unstable();
+        Ok(answers)
+    }
+
+    // Searches with the union of the queries weighted by the maximum weight per element
+    // under the weighted Jaccard distance. The filters are not applied.
+    pub fn range_query_weighted(
+        &self,
+        queries: &[(OrderedSet<u32>, f32)],
+        radius: f32,
+    ) -> Result<Vec<Answer>> {
+        check_radius(radius)?;
+        let query = self.weighted_union(queries)?;
+        let jaccard = WeightedJaccard::new(&query);
+        let mut answers = Vec::new();
+        for record in &self.records {
+            if let Some(dist) = jaccard.distance(&record.set) {
+                if dist <= radius {
+                    answers.extend(self.rescore(record, dist));
+                }
+            }
+        }
+        answers.sort_unstable();
+        Ok(answers)
+    }
+
+    pub fn topk_query_weighted(
+        &self,
+        queries: &[(OrderedSet<u32>, f32)],
+        k: usize,
+    ) -> Result<Vec<Answer>> {
+        check_k(k)?;
+        let query = self.weighted_union(queries)?;
+        let jaccard = WeightedJaccard::new(&query);
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        for record in &self.records {
+            if let Some(dist) = jaccard.distance(&record.set) {
+                heap.extend(self.rescore(record, dist));
+                if heap.len() > k {
+                    heap.pop();
+                }
+            }
+        }
+        Ok(heap.into_sorted_vec())
+    }
+
+    // Ranks the records by `1 - (sim(positive) - lambda * sim(negative))`.
+    pub fn topk_query_contrastive(
+        &self,
+        positive: &OrderedSet<u32>,
+        negative: &OrderedSet<u32>,
+        lambda: f32,
+        k: usize,
+    ) -> Result<Vec<Answer>> {
+        check_k(k)?;
+        check_contrastive_lambda(lambda)?;
+        let positive = self.mapping.apply(positive);
+        let negative = self.mapping.apply(negative);
+        let contrastive = Contrastive::new(&positive, &negative, lambda);
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        for record in &self.records {
+            if let Some(dist) = contrastive.distance(&record.set) {
+                heap.extend(self.rescore(record, dist));
+                if heap.len() > k {
                     heap.pop();
-                    heap.push(Answer {
-                        id: record.id,
-                        dist,
-                    });
+                }
+            }
+        }
+        Ok(heap.into_sorted_vec())
+    }
+
+    fn weighted_union(&self, queries: &[(OrderedSet<u32>, f32)]) -> Result<WeightedSet<u32>> {
+        let queries = queries
+            .iter()
+            .map(|(query, weight)| (self.mapping.apply(query), *weight))
+            .collect::<Vec<_>>();
+        WeightedSet::from_weighted_sets(queries.iter().map(|(query, weight)| (query, *weight)))
+    }
+
+    // Also searches with the probes of `dropout`, whose answers are only the candidates
+    // verified against the whole query, so that the distances are those to the query.
+    pub fn range_query_dropout(
+        &self,
+        query: &OrderedSet<u32>,
+        radius: f32,
+        dropout: &Dropout,
+    ) -> Result<Vec<Answer>> {
+        let query = self.mapping.apply(query);
+        let mut answers = self.range_query_unchecked(&query, radius)?;
+        let mut visited = answers.iter().map(|ans| ans.id).collect::<HashSet<_>>();
+        let jaccard = Jaccard::new(&query, radius, self.config.clone());
+        for probe in dropout.probes(&query) {
+            for ans in self.range_query_unchecked(&probe, radius)? {
+                if !visited.insert(ans.id) {
+                    continue;
+                }
+                let record = &self.records[self.offsets[&ans.id] as usize];
+                if let Evaluation::Accepted(dist) = jaccard.evaluate(&record.set) {
+                    answers.extend(self.rescore(record, dist));
+                }
+            }
+        }
+        if let Some(limit) = self.max_results {
+            truncate_answers(&mut answers, limit);
+        }
+        answers.sort_unstable();
+        Ok(answers)
+    }
+
+    pub fn topk_query(&self, query: &OrderedSet<u32>, k: usize) -> Result<Vec<Answer>> {
+        self.topk_query_unchecked(&self.mapping.apply(query), k)
+    }
+
+    // The query must be already mapped by `self.mapping()`.
+    #[deprecated(note = "use `prepare` and `topk_query_prepared`, which check the mapping")]
+    pub fn topk_query_mapped(&self, query: &OrderedSet<u32>, k: usize) -> Result<Vec<Answer>> {
+        self.topk_query_unchecked(query, k)
+    }
+
+    // Fails if the query was produced by another configuration than `config_hash`.
+    pub fn topk_query_prepared(&self, query: &MappedQuery, k: usize) -> Result<Vec<Answer>> {
+        query.check(self.config_hash)?;
+        self.topk_query_unchecked(query.set(), k)
+    }
+
+    // The query must be already mapped by `self.mapping()`.
+    fn topk_query_unchecked(&self, query: &OrderedSet<u32>, k: usize) -> Result<Vec<Answer>> {
+        check_k(k)?;
+        Ok(self.topk_query_inner(query, k, &[], &mut BinaryHeap::new()))
+    }
+
+    // Maps the queries in parallel on the current rayon pool, e.g., the pool of the
+    // CPU-bound stage of a pipeline, for `query_prepared_batch` on another pool.
+    pub fn prepare_batch(&self, queries: &[OrderedSet<u32>]) -> Vec<MappedQuery> {
+        queries
+            .par_iter()
+            .map(|query| self.prepare(query))
+            .collect()
+    }
+
+    // Searches the prepared queries in parallel on the pool of `thread_pool`, or the current
+    // rayon pool if none. The answers are in the order of `queries`.
+    // Fails if any query was produced by another configuration than `config_hash`.
+    pub fn query_prepared_batch(
+        &self,
+        queries: &[MappedQuery],
+        radius: f32,
+    ) -> Result<Vec<Vec<Answer>>> {
+        check_radius(radius)?;
+        for query in queries {
+            query.check(self.config_hash)?;
+        }
+        Ok(install(self.pool.as_deref(), || {
+            queries
+                .par_iter()
+                .map(|query| {
+                    self.range_query_inner(query.set(), radius, &[], &mut QueryStats::default())
+                })
+                .collect()
+        }))
+    }
+
+    // Skips the records of the ids in `exclude` without verification.
+    pub fn topk_query_excluding(
+        &self,
+        query: &OrderedSet<u32>,
+        k: usize,
+        exclude: &[u32],
+    ) -> Result<Vec<Answer>> {
+        check_k(k)?;
+        let excluded = self.sorted_offsets(exclude);
+        Ok(self.topk_query_inner(
+            &self.mapping.apply(query),
+            k,
+            &excluded,
+            &mut BinaryHeap::new(),
+        ))
+    }
+
+    // Reuses the buffers of `ctx` across queries.
+    pub fn range_query_with_context(
+        &self,
+        query: &OrderedSet<u32>,
+        radius: f32,
+        ctx: &mut QueryContext,
+    ) -> Result<Vec<Answer>> {
+        check_radius(radius)?;
+        let mapped = ctx.map_query(&self.mapping, query);
+        let answers = self.range_query_inner(&mapped, radius, &[], &mut QueryStats::default());
+        ctx.recycle(mapped);
+        Ok(answers)
+    }
+
+    // Reuses the buffers of `ctx` across queries.
+    pub fn topk_query_with_context(
+        &self,
+        query: &OrderedSet<u32>,
+        k: usize,
+        ctx: &mut QueryContext,
+    ) -> Result<Vec<Answer>> {
+        check_k(k)?;
+        let mapped = ctx.map_query(&self.mapping, query);
+        let answers = self.topk_query_inner(&mapped, k, &[], &mut ctx.heap);
+        ctx.recycle(mapped);
+        Ok(answers)
+    }
+
+    fn topk_query_inner<S: AsRef<[u32]>>(
+        &self,
+        query: &OrderedSet<u32, S>,
+        k: usize,
+        excluded: &[u32],
+        heap: &mut BinaryHeap<Answer>,
+    ) -> Vec<Answer> {
+        debug_assert!(self.is_mapped(query));
+        let n_pool = self.mmr.map_or(k, |mmr| k.saturating_mul(mmr.pool_factor));
+        let mut jaccard = Jaccard::new(query, 1.0, self.config.clone());
+        let narrows = self.scorer.is_none();
+        heap.clear();
+        heap.reserve(n_pool);
+        // Records are scanned from the lengths nearest to the query, and the scan stops once
+        // the distance bound by the lengths exceeds the k-th distance. Scores can lower the
+        // distances, so all the records are scanned with a scorer.
+        let order = LengthOrder::new(&self.by_length, &self.records, query.len());
+        for (i, bound) in order {
+            if narrows && heap.len() == n_pool {
+                let kth = heap.peek().unwrap().dist;
+                if bound > kth && !dist_eq(bound, kth) {
+                    break;
+                }
+            }
+            if excluded.binary_search(&i).is_ok() {
+                continue;
+            }
+            let record = &self.records[i as usize];
+            let Evaluation::Accepted(dist) = jaccard.evaluate(&record.set) else {
+                continue;
+            };
+            let Some(mut ans) = self.rescore(record, dist) else {
+                continue;
+            };
+            if let Some(priors) = &self.priors {
+                let prior = priors.by_id.get(&ans.id).copied().unwrap_or(1.0);
+                ans.dist = priors.formula.combine(ans.dist, prior);
+            }
+            if heap.len() < n_pool {
+                heap.push(ans);
+                if heap.len() == n_pool && narrows {
+                    let max_radius = heap.peek().unwrap().dist;
+                    jaccard.update_radius(max_radius + DIST_EPSILON);
+                }
+            } else if *heap.peek().unwrap() > ans {
+                // Ties are broken by the ids independently of the scan order,
+                // so the narrowed radius keeps the ties within the epsilon.
+                heap.pop();
+                heap.push(ans);
+                if narrows {
                     let max_radius = heap.peek().unwrap().dist;
-                    jaccard.update_radius(max_radius);
+                    jaccard.update_radius(max_radius + DIST_EPSILON);
+                }
+            }
+        }
+        let mut answers = heap.drain().collect::<Vec<_>>();
+        answers.sort_unstable();
+        match self.mmr {
+            Some(mmr) => self.diversify(answers, k, mmr.lambda),
+            None => answers,
+        }
+    }
+
+    // Selects `k` of the sorted candidates by Maximal Marginal Relevance.
+    fn diversify(&self, candidates: Vec<Answer>, k: usize, lambda: f32) -> Vec<Answer> {
+        let sets = candidates
+            .iter()
+            .map(|ans| &self.records[self.offsets[&ans.id] as usize].set)
+            .collect::<Vec<_>>();
+        // Maximum similarities of the candidates to the selected ones.
+        let mut max_sims = vec![0.0f32; candidates.len()];
+        let mut selected = vec![false; candidates.len()];
+        let mut answers = Vec::with_capacity(k);
+        while answers.len() < k.min(candidates.len()) {
+            let mut best = None;
+            for (i, ans) in candidates.iter().enumerate() {
+                if selected[i] {
+                    continue;
+                }
+                let score = lambda * (1.0 - ans.dist) - (1.0 - lambda) * max_sims[i];
+                // Ties are broken by the candidate order.
+                if best.is_none_or(|(_, best_score)| score > best_score) {
+                    best = Some((i, score));
+                }
+            }
+            let (best, _) = best.unwrap();
+            selected[best] = true;
+            answers.push(candidates[best].clone());
+            let jaccard = Jaccard::new(sets[best], 1.0, FilterConfig::default());
+            for (i, set) in sets.iter().enumerate() {
+                if !selected[i] {
+                    let sim = 1.0 - jaccard.distance(set).unwrap_or(1.0);
+                    max_sims[i] = max_sims[i].max(sim);
+                }
+            }
+        }
+        answers
+    }
+
+    // Searches with `SmoothedJaccard` of `alpha`, which discounts the similarities of short sets.
+    pub fn range_query_smoothed(
+        &self,
+        query: &OrderedSet<u32>,
+        radius: f32,
+        alpha: f32,
+    ) -> Result<Vec<Answer>> {
+        check_radius(radius)?;
+        check_alpha(alpha)?;
+        let query = self.mapping.apply(query);
+        let smoothed = SmoothedJaccard::new(&query, radius, alpha, self.config.clone());
+        let mut answers = Vec::new();
+        for record in &self.records {
+            if let Evaluation::Accepted(dist) = smoothed.evaluate(&record.set) {
+                answers.extend(self.rescore(record, dist));
+            }
+        }
+        answers.sort_unstable();
+        Ok(answers)
+    }
+
+    pub fn topk_query_smoothed(
+        &self,
+        query: &OrderedSet<u32>,
+        k: usize,
+        alpha: f32,
+    ) -> Result<Vec<Answer>> {
+        check_k(k)?;
+        check_alpha(alpha)?;
+        let query = self.mapping.apply(query);
+        let smoothed = SmoothedJaccard::new(&query, 1.0, alpha, self.config.clone());
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        for record in &self.records {
+            if let Some(dist) = smoothed.distance(&record.set) {
+                heap.extend(self.rescore(record, dist));
+                if heap.len() > k {
+                    heap.pop();
                 }
             }
         }
-        heap.into_sorted_vec()
+        Ok(heap.into_sorted_vec())
+    }
+
+    // Keeps the answers whose records share at least one of `elems`, e.g., the features
+    // of the maximal n-gram order to reject answers overlapping only in short n-grams.
+    // The answers of unknown ids, e.g., of another index, are dropped.
+    pub fn retain_sharing(&self, answers: Vec<Answer>, elems: &OrderedSet<u32>) -> Vec<Answer> {
+        let elems = self.mapping.apply(elems);
+        answers
+            .into_iter()
+            .filter(|ans| {
+                let Some(&offset) = self.offsets.get(&ans.id) else {
+                    return false;
+                };
+                let set = &self.records[offset as usize].set;
+                elems
+                    .iter()
+                    .any(|elem| set.as_slice().binary_search(elem).is_ok())
+            })
+            .collect()
+    }
+
+    // Returns the records containing at least `min_containment` of the query.
+    pub fn find_supersets(
+        &self,
+        query: &OrderedSet<u32>,
+        min_containment: f32,
+    ) -> Result<Vec<Answer>> {
+        self.containment_query(query, min_containment, ContainmentMode::Superset)
+    }
+
+    // Returns the records at least `min_containment` of which is contained in the query.
+    pub fn find_subsets(
+        &self,
+        query: &OrderedSet<u32>,
+        min_containment: f32,
+    ) -> Result<Vec<Answer>> {
+        self.containment_query(query, min_containment, ContainmentMode::Subset)
+    }
+
+    fn containment_query(
+        &self,
+        query: &OrderedSet<u32>,
+        min_containment: f32,
+        mode: ContainmentMode,
+    ) -> Result<Vec<Answer>> {
+        let query = self.mapping.apply(query);
+        let containment = Containment::new(&query, min_containment, mode, self.config.clone())?;
+        let mut answers = Vec::new();
+        for record in &self.records {
+            if let Evaluation::Accepted(dist) = containment.evaluate(&record.set) {
+                answers.extend(self.rescore(record, dist));
+            }
+        }
+        answers.sort_unstable();
+        Ok(answers)
+    }
+
+    // Explains the match of the query and the record of the id at the radius,
+    // or returns `None` if the id is not found.
+    pub fn explain_pair(
+        &self,
+        query: &OrderedSet<u32>,
+        id: u32,
+        radius: f32,
+    ) -> Result<Option<PairExplanation>> {
+        check_radius(radius)?;
+        let Some(&offset) = self.offsets.get(&id) else {
+            return Ok(None);
+        };
+        let set = &self.records[offset as usize].set;
+        let mapping = self.mapping.as_slice();
+        let shared = query
+            .iter()
+            .filter(|&&elem| {
+                set.as_slice()
+                    .binary_search(&mapping[elem as usize])
+                    .is_ok()
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mapped = self.mapping.apply(query);
+        let jaccard = Jaccard::new(&mapped, radius, self.config.clone());
+        let sweep = jaccard.evaluate_sweep(set);
+        let passed = |length, position, filtered| {
+            sweep.get(&FilterConfig::new(length, position)) != filtered
+        };
+        Ok(Some(PairExplanation {
+            intersection: shared.len(),
+            union: query.len() + set.len() - shared.len(),
+            shared,
+            dist: jaccard.distance(set),
+            length_passed: passed(true, false, Evaluation::LengthFiltered),
+            position_passed: passed(false, true, Evaluation::PositionFiltered),
+            accepted: matches!(
+                sweep.get(&FilterConfig::new(false, false)),
+                Evaluation::Accepted(_)
+            ),
+        }))
+    }
+
+    // Searches with the stored set of the record, excluding the record itself.
+    // Returns `None` if the id is not found.
+    pub fn range_query_by_id(&self, id: u32, radius: f32) -> Result<Option<Vec<Answer>>> {
+        check_radius(radius)?;
+        let Some(&offset) = self.offsets.get(&id) else {
+            return Ok(None);
+        };
+        let query = &self.records[offset as usize].set;
+        Ok(Some(self.range_query_inner(
+            query,
+            radius,
+            &[offset],
+            &mut QueryStats::default(),
+        )))
+    }
+
+    // Searches with the stored set of the record, excluding the record itself.
+    // Returns `None` if the id is not found.
+    pub fn topk_query_by_id(&self, id: u32, k: usize) -> Result<Option<Vec<Answer>>> {
+        check_k(k)?;
+        let Some(&offset) = self.offsets.get(&id) else {
+            return Ok(None);
+        };
+        let query = &self.records[offset as usize].set;
+        Ok(Some(self.topk_query_inner(
+            query,
+            k,
+            &[offset],
+            &mut BinaryHeap::new(),
+        )))
     }
 
     pub fn all_distances(&self, query: &OrderedSet<u32>) -> Vec<Answer> {
         let query = self.mapping.apply(query);
-        let jaccard = Jaccard::new(&query, 1.0, self.config);
+        let jaccard = Jaccard::new(&query, 1.0, self.config.clone());
         let mut answers = Vec::new();
         for record in &self.records {
             let dist = jaccard.distance(&record.set).unwrap_or(f32::INFINITY);
@@ -92,20 +1094,223 @@ impl LinearScan {
         answers
     }
 
-    pub fn evaluate(&self, query: &OrderedSet<u32>, radius: f32) -> Vec<Evaluation> {
+    pub fn evaluate(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Evaluation>> {
+        check_radius(radius)?;
         let query = self.mapping.apply(query);
-        let jaccard: Jaccard<'_, u32> = Jaccard::new(&query, radius, self.config);
+        let jaccard: Jaccard<'_, u32> = Jaccard::new(&query, radius, self.config.clone());
         let mut evaluations = Vec::new();
         for record in &self.records {
             evaluations.push(jaccard.evaluate(&record.set));
         }
-        evaluations
+        Ok(evaluations)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    // Returns the answers accepted under `config` instead of `self.config`,
+    // to compare the accepted sets and distances between configurations.
+    pub fn evaluate_accepted(
+        &self,
+        query: &OrderedSet<u32>,
+        radius: f32,
+        config: FilterConfig,
+    ) -> Result<Vec<Answer>> {
+        check_radius(radius)?;
+        config.check_supported()?;
+        let query = self.mapping.apply(query);
+        let jaccard = Jaccard::new(&query, radius, config);
+        let mut answers = Vec::new();
+        for record in &self.records {
+            if let Evaluation::Accepted(dist) = jaccard.evaluate(&record.set) {
+                answers.push(Answer {
+                    id: record.id,
+                    dist,
+                });
+            }
+        }
+        answers.sort_unstable();
+        Ok(answers)
+    }
+
+    pub fn evaluate_sweep(
+        &self,
+        query: &OrderedSet<u32>,
+        radius: f32,
+    ) -> Result<Vec<EvaluationSweep>> {
+        check_radius(radius)?;
+        let query = self.mapping.apply(query);
+        let jaccard = Jaccard::new(&query, radius, self.config.clone());
+        let mut sweeps = Vec::with_capacity(self.records.len());
+        for record in &self.records {
+            sweeps.push(jaccard.evaluate_sweep(&record.set));
+        }
+        Ok(sweeps)
+    }
+
+    fn sorted_offsets(&self, ids: &[u32]) -> Vec<u32> {
+        let mut offsets = ids
+            .iter()
+            .filter_map(|id| self.offsets.get(id).cloned())
+            .collect::<Vec<_>>();
+        offsets.sort_unstable();
+        offsets
+    }
+
+    fn is_mapped<S: AsRef<[u32]>>(&self, query: &OrderedSet<u32, S>) -> bool {
+        query.is_strictly_sorted() && query.iter().all(|&e| e < self.mapping.universe())
+    }
+
+    // Writes the records in the format of `index_file`, with the hash of their extractor
+    // if given. The filter config and policy are not saved.
+    pub fn save<W>(&self, mut writer: W, extractor: Option<&FeatureExtractor>) -> Result<()>
+    where
+        W: Write,
+    {
+        let header = current_header(IndexKind::LinearScan, Metric::Jaccard, extractor);
+        write_header(&mut writer, &header)?;
+        let mut enc = Encoder::new(writer);
+        write_records(&mut enc, &self.mapping, &self.records, &self.duplicates)
+    }
+
+    // Reads an index written by `save` and validates it. Fails with `FormatError` if the file
+    // is incompatible, including when it was built by an extractor other than `extractor`.
+    pub fn load<R>(mut reader: R, extractor: Option<&FeatureExtractor>) -> Result<Self>
+    where
+        R: Read,
+    {
+        let header = read_header(&mut reader)?;
+        check_header(&header, IndexKind::LinearScan, extractor)?;
+        if header.metric != Metric::Jaccard {
+            return Err(FormatError::MetricMismatch {
+                expected: Metric::Jaccard,
+                found: header.metric,
+            }
+            .into());
+        }
+        let (mapping, records, duplicates) = read_records(&mut Decoder::new(reader))?;
+        let mut offsets = id_offsets(&records)?;
+        alias_offsets(&mut offsets, &duplicates);
+        let index = Self {
+            mapping: Arc::new(mapping),
+            by_length: length_order(&records),
+            records,
+            offsets,
+            duplicates,
+            config: FilterConfig::default(),
+            policy: None,
+            scorer: None,
+            priors: None,
+            mmr: None,
+            extractor_hash: None,
+            config_hash: None,
+            max_results: None,
+            pool: None,
+        }
+        .with_extractor_hash(header.extractor_hash);
+        index.validate()?;
+        Ok(index)
+    }
+
+    // Checks the invariants of the index, e.g., after loading it from a file,
+    // and fails with all the violations found.
+    pub fn validate(&self) -> Result<IntegrityReport> {
+        let mut issues = vec![];
+        check_records(
+            &self.mapping,
+            &self.records,
+            &self.offsets,
+            &self.duplicates,
+            &mut issues,
+        );
+        let report = IntegrityReport {
+            n_records: self.records.len(),
+            n_duplicates: self.duplicates.values().map(|ids| ids.len()).sum(),
+            n_postings: 0,
+            n_entries: 0,
+        };
+        integrity_result(report, issues)
+    }
+}
+
+impl RangeSearch for LinearScan {
+    fn range_search(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Answer>> {
+        self.range_query(query, radius)
+    }
+}
+
+fn length_order(records: &[FrozenRecord]) -> Vec<u32> {
+    let mut order = (0..records.len() as u32).collect::<Vec<_>>();
+    order.sort_by_key(|&i| records[i as usize].set.len());
+    order
+}
+
+// Lower bound of the Jaccard distance between sets of the lengths.
+fn length_bound(a_len: usize, b_len: usize) -> f32 {
+    if a_len == 0 && b_len == 0 {
+        return 0.0;
+    }
+    1.0 - a_len.min(b_len) as f32 / a_len.max(b_len) as f32
+}
+
+// Yields the offsets of the records with their length bounds in ascending order of the bounds,
+// expanding from the query length in both directions of `by_length`.
+struct LengthOrder<'a> {
+    by_length: &'a [u32],
+    records: &'a [FrozenRecord],
+    query_len: usize,
+    lo: usize,
+    hi: usize,
+}
+
+impl<'a> LengthOrder<'a> {
+    fn new(by_length: &'a [u32], records: &'a [FrozenRecord], query_len: usize) -> Self {
+        let mid = by_length.partition_point(|&i| records[i as usize].set.len() < query_len);
+        Self {
+            by_length,
+            records,
+            query_len,
+            lo: mid,
+            hi: mid,
+        }
+    }
+
+    fn bound(&self, i: u32) -> f32 {
+        length_bound(self.query_len, self.records[i as usize].set.len())
+    }
+}
+
+impl Iterator for LengthOrder<'_> {
+    type Item = (u32, f32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let below = (self.lo > 0).then(|| self.by_length[self.lo - 1]);
+        let above = self.by_length.get(self.hi).copied();
+        match (below, above) {
+            (Some(b), Some(a)) if self.bound(b) <= self.bound(a) => {
+                self.lo -= 1;
+                Some((b, self.bound(b)))
+            }
+            (_, Some(a)) => {
+                self.hi += 1;
+                Some((a, self.bound(a)))
+            }
+            (Some(b), None) => {
+                self.lo -= 1;
+                Some((b, self.bound(b)))
+            }
+            (None, None) => None,
+        }
+    }
+}
+
+fn check_alpha(alpha: f32) -> Result<()> {
+    if !alpha.is_finite() || alpha < 0.0 {
+        return Err(anyhow!("Invalid alpha: {}", alpha));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
     #[test]
     fn test_all_distances() {
@@ -159,4 +1364,1093 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_shared_mapping() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([2, 3, 4, 5]).unwrap();
+        let c = OrderedSet::from_sorted([3, 4, 5, 6, 7]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
+        let mapping = Arc::new(Mapping::from_records(&records, 10).unwrap());
+        let shard0 = LinearScan::from_records_with_mapping(&records[..1], mapping.clone()).unwrap();
+        let shard1 = LinearScan::from_records_with_mapping(&records[1..], mapping.clone()).unwrap();
+        assert!(Arc::ptr_eq(shard0.mapping(), shard1.mapping()));
+
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let mut answers = shard0.range_query(&query, 0.7).unwrap();
+        answers.extend(shard1.range_query(&query, 0.7).unwrap());
+        assert_eq!(
+            answers,
+            vec![
+                Answer {
+                    id: 0,
+                    dist: 1. - 3. / 3.
+                },
+                Answer {
+                    id: 1,
+                    dist: 1. - 2. / 5.
+                },
+            ]
+        );
+
+        let small = Arc::new(Mapping::from_slice(&[0, 1, 2, 3]));
+        assert!(LinearScan::from_records_with_mapping(&records, small).is_err());
+    }
+
+    #[test]
+    fn test_prepare() {
+        let extractor = FeatureExtractor::seeded(1..=1, 16, 42).unwrap();
+        let records = ["a b c", "a b d", "a e f g", "h"]
+            .iter()
+            .enumerate()
+            .map(|(id, text)| Record {
+                id: id as u32,
+                set: extractor.extract(&text.split(' ').collect::<Vec<_>>()),
+            })
+            .collect::<Vec<_>>();
+        let index = LinearScan::from_records(&records, 16)
+            .unwrap()
+            .extractor(&extractor);
+        let query = records[0].set.clone();
+        let prepared = index.prepare(&query);
+        assert_eq!(prepared.config_hash(), index.config_hash());
+        for radius in [0.0, 0.5, 0.9] {
+            assert_eq!(
+                index.range_query_prepared(&prepared, radius).unwrap(),
+                index.range_query(&query, radius).unwrap()
+            );
+        }
+        assert_eq!(
+            index.topk_query_prepared(&prepared, 2).unwrap(),
+            index.topk_query(&query, 2).unwrap()
+        );
+
+        // A query prepared by an index with another mapping is rejected.
+        let other = LinearScan::from_records(&records[2..], 16)
+            .unwrap()
+            .extractor(&extractor);
+        assert_ne!(other.mapping(), index.mapping());
+        assert!(other.range_query_prepared(&prepared, 0.5).is_err());
+        assert!(other.topk_query_prepared(&prepared, 2).is_err());
+    }
+
+    #[test]
+    fn test_query_prepared_batch() {
+        let records = (0..20)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_unsorted([id % 3, id % 5 + 3, id % 7 + 8]),
+            })
+            .collect::<Vec<_>>();
+        let index = LinearScan::from_records(&records, 16).unwrap();
+        let queries = records.iter().map(|r| r.set.clone()).collect::<Vec<_>>();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let prepared = pool.install(|| index.prepare_batch(&queries));
+        let answers = index.query_prepared_batch(&prepared, 0.6).unwrap();
+        for (query, answers) in queries.iter().zip(answers) {
+            assert_eq!(answers, index.range_query(query, 0.6).unwrap());
+        }
+        assert!(index.query_prepared_batch(&prepared, -1.0).is_err());
+    }
+
+    #[test]
+    fn test_topk_query_by_lengths() {
+        use rand::{Rng, SeedableRng};
+        use rand_xoshiro::Xoshiro256PlusPlus;
+
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(3);
+        let mut random_set = |max_len: usize| {
+            let n = rng.gen_range(0..max_len);
+            OrderedSet::from_unsorted((0..n).map(|_| rng.gen_range(0..30)).collect::<Vec<_>>())
+        };
+        let records = (0..300)
+            .map(|id| Record {
+                id,
+                set: random_set(20),
+            })
+            .collect::<Vec<_>>();
+        let index = LinearScan::from_records(&records, 30).unwrap();
+        for _ in 0..50 {
+            let query = random_set(25);
+            for k in [1, 5, 40] {
+                let mut expected = index
+                    .all_distances(&query)
+                    .into_iter()
+                    .filter(|ans| ans.dist.is_finite())
+                    .collect::<Vec<_>>();
+                expected.sort_unstable();
+                expected.truncate(k);
+                assert_eq!(index.topk_query(&query, k).unwrap(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_range_query_progressive() {
+        let records = [
+            vec![1, 2, 3, 4],
+            vec![1, 2, 3, 5],
+            vec![1, 2, 6, 7],
+            vec![8, 9],
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(id, set)| Record {
+            id: id as u32,
+            set: OrderedSet::from_sorted(set).unwrap(),
+        })
+        .collect::<Vec<_>>();
+        let index = LinearScan::from_records(&records, 10).unwrap();
+        let query = OrderedSet::from_sorted([1, 2, 3, 4]).unwrap();
+
+        // 0.0 -> 0.05 -> 0.1 -> 0.2 -> 0.4 finds the second record at 0.4.
+        let (answers, radius) = index.range_query_progressive(&query, 0.0, 2).unwrap();
+        assert_eq!(radius, 0.4);
+        assert_eq!(answers, index.range_query(&query, radius).unwrap());
+        assert_eq!(answers.len(), 2);
+
+        let (answers, radius) = index.range_query_progressive(&query, 0.5, 1).unwrap();
+        assert_eq!((answers.len(), radius), (2, 0.5));
+
+        // The radius stops at 1 even with fewer records.
+        let (answers, radius) = index.range_query_progressive(&query, 0.1, 10).unwrap();
+        assert_eq!((answers.len(), radius), (4, 1.0));
+        assert!(index.range_query_progressive(&query, -0.1, 1).is_err());
+    }
+
+    #[test]
+    fn test_max_results() {
+        let records = (0..100u32)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_unsorted([0, 1, 2, id % 7 + 3, id % 11 + 10]),
+            })
+            .collect::<Vec<_>>();
+        let query = OrderedSet::from_sorted([0, 1, 2, 3, 10]).unwrap();
+        let unlimited = LinearScan::from_records(&records, 21).unwrap();
+        let expected = unlimited.range_query(&query, 0.9).unwrap();
+        assert_eq!(expected.len(), 100);
+
+        // The nearest answers are kept across the compactions at twice the limit.
+        let index = LinearScan::from_records(&records, 21)
+            .unwrap()
+            .max_results(7)
+            .unwrap();
+        let (answers, truncated) = index.range_query_truncated(&query, 0.9).unwrap();
+        assert!(truncated);
+        assert_eq!(answers, expected[..7]);
+        assert_eq!(index.range_query(&query, 0.9).unwrap(), expected[..7]);
+
+        let (answers, truncated) = index.range_query_truncated(&query, 0.0).unwrap();
+        assert!(!truncated);
+        assert_eq!(answers, unlimited.range_query(&query, 0.0).unwrap());
+        assert!(LinearScan::from_records(&records, 21)
+            .unwrap()
+            .max_results(0)
+            .is_err());
+    }
+
+    #[test]
+    fn test_range_query_dropout() {
+        let a = OrderedSet::from_sorted([1, 2]).unwrap();
+        let b = OrderedSet::from_sorted([1, 2, 3, 4, 5, 6]).unwrap();
+        let c = OrderedSet::from_sorted([7, 8, 9]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
+        let index = LinearScan::from_records(&records, 10).unwrap();
+        let query = OrderedSet::from_sorted([1, 2, 3, 4, 5, 6]).unwrap();
+
+        let dropout = Dropout::new(4, 0.0, 334).unwrap();
+        assert_eq!(
+            index.range_query_dropout(&query, 0.5, &dropout).unwrap(),
+            index.range_query(&query, 0.5).unwrap()
+        );
+
+        let dropout = Dropout::new(16, 0.6, 334).unwrap();
+        let answers = index.range_query_dropout(&query, 0.5, &dropout).unwrap();
+        assert_eq!(
+            answers[0],
+            Answer {
+                id: 1,
+                dist: 1. - 6. / 6.
+            }
+        );
+        assert!(answers.iter().all(|ans| ans.id != 2));
+        // Probes close to `a` do not make it closer to the query.
+        assert_eq!(answers, index.range_query(&query, 0.5).unwrap());
+    }
+
+    #[test]
+    fn test_get_record() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([2, 3, 4, 5]).unwrap();
+        let records = vec![Record { id: 3, set: a }, Record { id: 1, set: b }];
+        let index = LinearScan::from_records(&records, 10).unwrap();
+        assert!(index.contains_id(3));
+        assert!(!index.contains_id(0));
+        assert_eq!(
+            index.get_record(1).map(|set| set.as_slice()),
+            Some(index.mapping().apply(&records[1].set).as_slice())
+        );
+        assert!(index.get_record(0).is_none());
+    }
+
+    #[test]
+    fn test_duplicate_ids() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([2, 3, 4, 5]).unwrap();
+        let records = vec![Record { id: 1, set: a }, Record { id: 1, set: b }];
+        assert!(LinearScan::from_records(&records, 10).is_err());
+    }
+
+    #[test]
+    fn test_query_by_id() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([2, 3, 4, 5]).unwrap();
+        let c = OrderedSet::from_sorted([3, 4, 5, 6, 7]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
+        let index = LinearScan::from_records(&records, 10).unwrap();
+
+        assert_eq!(
+            index.range_query_by_id(1, 0.6).unwrap(),
+            Some(vec![
+                Answer {
+                    id: 2,
+                    dist: 1. - 3. / 6.
+                },
+                Answer {
+                    id: 0,
+                    dist: 1. - 2. / 5.
+                },
+            ])
+        );
+        assert_eq!(
+            index.topk_query_by_id(1, 1).unwrap(),
+            Some(vec![Answer {
+                id: 2,
+                dist: 1. - 3. / 6.
+            }])
+        );
+        assert_eq!(index.range_query_by_id(3, 0.6).unwrap(), None);
+        assert_eq!(index.topk_query_by_id(3, 1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_query_excluding() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([2, 3, 4, 5]).unwrap();
+        let c = OrderedSet::from_sorted([3, 4, 5, 6, 7]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
+        let index = LinearScan::from_records(&records, 10).unwrap();
+
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        assert_eq!(
+            index
+                .range_query_excluding(&query, 1.0, &[0, 2, 9])
+                .unwrap(),
+            vec![Answer {
+                id: 1,
+                dist: 1. - 2. / 5.
+            }]
+        );
+        assert_eq!(
+            index.topk_query_excluding(&query, 2, &[0]).unwrap(),
+            vec![
+                Answer {
+                    id: 1,
+                    dist: 1. - 2. / 5.
+                },
+                Answer {
+                    id: 2,
+                    dist: 1. - 1. / 7.
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_range_query_with_near_misses() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([2, 3, 4, 5]).unwrap();
+        let c = OrderedSet::from_sorted([3, 4, 5, 6, 7]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
+        let index = LinearScan::from_records(&records, 10).unwrap();
+
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let (answers, near_misses) = index
+            .range_query_with_near_misses(&query, 0.5, 0.2)
+            .unwrap();
+        assert_eq!(answers, vec![Answer { id: 0, dist: 0.0 }]);
+        assert_eq!(
+            near_misses,
+            vec![Answer {
+                id: 1,
+                dist: 1. - 2. / 5.
+            }]
+        );
+        assert_eq!(answers, index.range_query(&query, 0.5).unwrap());
+        assert!(index
+            .range_query_with_near_misses(&query, 0.5, -0.1)
+            .is_err());
+    }
+
+    #[test]
+    fn test_explain_pair() {
+        let records = vec![
+            Record {
+                id: 0,
+                set: OrderedSet::from_sorted([1, 2, 3]).unwrap(),
+            },
+            Record {
+                id: 1,
+                set: OrderedSet::from_sorted([3, 4, 5, 6, 7, 8, 9]).unwrap(),
+            },
+        ];
+        let index = LinearScan::from_records(&records, 10).unwrap();
+        let query = OrderedSet::from_sorted([2, 3, 4]).unwrap();
+
+        let explanation = index.explain_pair(&query, 0, 0.5).unwrap().unwrap();
+        assert_eq!(explanation.shared, vec![2, 3]);
+        assert_eq!((explanation.intersection, explanation.union), (2, 4));
+        assert_eq!(explanation.dist, Some(0.5));
+        assert!(explanation.length_passed && explanation.position_passed);
+        assert!(explanation.accepted);
+
+        let explanation = index.explain_pair(&query, 1, 0.5).unwrap().unwrap();
+        assert_eq!(explanation.shared, vec![3, 4]);
+        assert_eq!((explanation.intersection, explanation.union), (2, 8));
+        assert!(!explanation.length_passed);
+        assert!(!explanation.accepted);
+
+        assert_eq!(index.explain_pair(&query, 2, 0.5).unwrap(), None);
+        let extractor = FeatureExtractor::seeded(1..=1, 10, 334).unwrap();
+        assert_eq!(explanation.ngrams(&extractor), None);
+    }
+
+    #[test]
+    fn test_range_query_any() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([2, 3, 4, 5]).unwrap();
+        let c = OrderedSet::from_sorted([3, 4, 5, 6, 7]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
+        let index = LinearScan::from_records(&records, 10).unwrap();
+
+        let queries = vec![
+            OrderedSet::from_sorted([1, 2, 3]).unwrap(),
+            OrderedSet::from_sorted([3, 4, 5]).unwrap(),
+        ];
+        assert_eq!(
+            index.range_query_any(&queries, 0.5).unwrap(),
+            vec![
+                Answer {
+                    id: 0,
+                    dist: 1. - 3. / 3.
+                },
+                Answer {
+                    id: 1,
+                    dist: 1. - 3. / 4.
+                },
+                Answer {
+                    id: 2,
+                    dist: 1. - 3. / 5.
+                },
+            ]
+        );
+        assert!(index.range_query_any(&[], 0.5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_range_query_parallel() {
+        let records = (0..5000u32)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_sorted([id % 3, id % 7 + 3, id % 11 + 10]).unwrap(),
+            })
+            .collect::<Vec<_>>();
+        let index = LinearScan::from_records(&records, 30)
+            .unwrap()
+            .filter_config(FilterConfig::ALL)
+            .unwrap();
+        let query = OrderedSet::from_sorted([0, 3, 10]).unwrap();
+        let expected = index.range_query(&query, 0.6).unwrap();
+        assert!(!expected.is_empty());
+        for parallelism in [0, 1, 4] {
+            assert_eq!(
+                index
+                    .range_query_parallel(&query, 0.6, parallelism)
+                    .unwrap(),
+                expected
+            );
+        }
+        assert!(index.range_query_parallel(&query, -1.0, 4).is_err());
+
+        // Each chunk keeps the nearest answers.
+        let index = index.max_results(10).unwrap();
+        let expected = index.range_query(&query, 0.6).unwrap();
+        assert_eq!(expected.len(), 10);
+        for parallelism in [0, 1, 4] {
+            assert_eq!(
+                index
+                    .range_query_parallel(&query, 0.6, parallelism)
+                    .unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_thread_pool() {
+        let records = (0..5000u32)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_sorted([id % 3, id % 7 + 3, id % 11 + 10]).unwrap(),
+            })
+            .collect::<Vec<_>>();
+        let query = OrderedSet::from_sorted([0, 3, 10]).unwrap();
+        let index = LinearScan::from_records(&records, 30).unwrap();
+        let queries = records[..50]
+            .iter()
+            .map(|record| index.prepare(&record.set))
+            .collect::<Vec<_>>();
+        let expected = index.range_query(&query, 0.6).unwrap();
+        let expected_batch = index.query_prepared_batch(&queries, 0.6).unwrap();
+
+        // The same answers in the same order for any pool and parallelism.
+        for n_threads in [1, 2, 4] {
+            let pool = crate::build_thread_pool(n_threads).unwrap();
+            assert_eq!(pool.current_num_threads(), n_threads);
+            let index = LinearScan::from_records(&records, 30)
+                .unwrap()
+                .thread_pool(pool);
+            for parallelism in [1, 3, 8] {
+                let answers = index
+                    .range_query_parallel(&query, 0.6, parallelism)
+                    .unwrap();
+                assert_eq!(answers, expected);
+                assert!(answers.iter().zip(&expected).all(|(a, b)| a.id == b.id));
+            }
+            assert_eq!(
+                index.query_prepared_batch(&queries, 0.6).unwrap(),
+                expected_batch
+            );
+        }
+        assert!(crate::build_thread_pool(0).is_err());
+    }
+
+    #[test]
+    fn test_scorer() {
+        let sets = [vec![1, 2, 3], vec![1, 2, 3, 4], vec![2, 3, 4], vec![5, 6]];
+        let records = sets
+            .iter()
+            .enumerate()
+            .map(|(id, set)| Record {
+                id: id as u32,
+                set: OrderedSet::from_sorted(set.clone()).unwrap(),
+            })
+            .collect::<Vec<_>>();
+        // Vetoes record 0 and boosts record 2 above record 1.
+        let index = LinearScan::from_records(&records, 10)
+            .unwrap()
+            .filter_config(FilterConfig::ALL)
+            .unwrap()
+            .scorer(|ans, _: &[u32]| match ans.id {
+                0 => None,
+                2 => Some(0.1),
+                _ => Some(ans.dist),
+            });
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        assert_eq!(
+            index.range_query(&query, 0.5).unwrap(),
+            vec![Answer { id: 2, dist: 0.1 }, Answer { id: 1, dist: 0.25 }]
+        );
+        // Record 2 is farther than record 1 by Jaccard but is still found.
+        assert_eq!(
+            index.topk_query(&query, 1).unwrap(),
+            vec![Answer { id: 2, dist: 0.1 }]
+        );
+        assert_eq!(
+            index.range_query_parallel(&query, 0.5, 4).unwrap(),
+            index.range_query(&query, 0.5).unwrap()
+        );
+        // The other searches also go through the scorer.
+        let other = OrderedSet::from_sorted([5, 6]).unwrap();
+        assert_eq!(
+            index
+                .range_query_any(&[query.clone(), other.clone()], 0.5)
+                .unwrap(),
+            vec![
+                Answer { id: 3, dist: 0.0 },
+                Answer { id: 2, dist: 0.1 },
+                Answer { id: 1, dist: 0.25 }
+            ]
+        );
+        assert_eq!(
+            index
+                .topk_query_weighted(&[(query.clone(), 1.0)], 1)
+                .unwrap(),
+            vec![Answer { id: 2, dist: 0.1 }]
+        );
+        assert_eq!(
+            index
+                .topk_query_contrastive(&query, &other, 1.0, 1)
+                .unwrap(),
+            vec![Answer { id: 2, dist: 0.1 }]
+        );
+        assert!(index
+            .find_supersets(&query, 1.0)
+            .unwrap()
+            .iter()
+            .all(|ans| ans.id != 0));
+    }
+
+    #[test]
+    fn test_priors() {
+        let sets = [vec![1, 2, 3], vec![1, 2, 3, 4], vec![2, 3, 4], vec![5, 6]];
+        let records = sets
+            .iter()
+            .enumerate()
+            .map(|(id, set)| Record {
+                id: id as u32,
+                set: OrderedSet::from_sorted(set.clone()).unwrap(),
+            })
+            .collect::<Vec<_>>();
+        let index = || {
+            LinearScan::from_records(&records, 10)
+                .unwrap()
+                .filter_config(FilterConfig::ALL)
+                .unwrap()
+        };
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+
+        let scaled = index()
+            .priors(&[(0, 0.5)], PriorFormula::Scale { exponent: 1.0 })
+            .unwrap();
+        assert_eq!(
+            scaled.topk_query(&query, 2).unwrap(),
+            vec![Answer { id: 1, dist: 0.25 }, Answer { id: 0, dist: 0.5 }]
+        );
+        let penalized = index()
+            .priors(&[(0, 0.5), (1, 0.0)], PriorFormula::Penalty { weight: 0.2 })
+            .unwrap();
+        assert_eq!(
+            penalized.topk_query(&query, 2).unwrap(),
+            vec![Answer { id: 0, dist: 0.1 }, Answer { id: 1, dist: 0.45 }]
+        );
+        // Range queries keep the distances.
+        assert_eq!(
+            penalized.range_query(&query, 0.3).unwrap(),
+            index().range_query(&query, 0.3).unwrap()
+        );
+
+        assert!(index()
+            .priors(&[(0, 1.5)], PriorFormula::Scale { exponent: 1.0 })
+            .is_err());
+        assert!(index()
+            .priors(&[(9, 0.5)], PriorFormula::Scale { exponent: 1.0 })
+            .is_err());
+        assert!(index()
+            .priors(&[], PriorFormula::Penalty { weight: -1.0 })
+            .is_err());
+    }
+
+    #[test]
+    fn test_mmr() {
+        // Records 0 to 2 are near-duplicates.
+        let sets = [
+            vec![1, 2, 3, 4],
+            vec![1, 2, 3, 4, 5],
+            vec![1, 2, 3, 4, 6],
+            vec![3, 4, 7, 8],
+        ];
+        let records = sets
+            .iter()
+            .enumerate()
+            .map(|(id, set)| Record {
+                id: id as u32,
+                set: OrderedSet::from_sorted(set.clone()).unwrap(),
+            })
+            .collect::<Vec<_>>();
+        let index = || LinearScan::from_records(&records, 10).unwrap();
+        let query = OrderedSet::from_sorted([1, 2, 3, 4, 7]).unwrap();
+        let ids = |answers: Vec<Answer>| answers.iter().map(|ans| ans.id).collect::<Vec<_>>();
+
+        assert_eq!(ids(index().topk_query(&query, 2).unwrap()), vec![0, 1]);
+        let diverse = index().mmr(0.5, 4).unwrap();
+        assert_eq!(ids(diverse.topk_query(&query, 2).unwrap()), vec![0, 3]);
+        // Only relevance with lambda 1.
+        let relevant = index().mmr(1.0, 4).unwrap();
+        assert_eq!(
+            relevant.topk_query(&query, 2).unwrap(),
+            index().topk_query(&query, 2).unwrap()
+        );
+        assert!(index().mmr(1.5, 4).is_err());
+        assert!(index().mmr(0.5, 0).is_err());
+    }
+
+    #[test]
+    fn test_range_query_with_budget() {
+        let records = (0..100u32)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_sorted([0, 1, id % 7 + 2]).unwrap(),
+            })
+            .collect::<Vec<_>>();
+        let index = LinearScan::from_records(&records, 10).unwrap();
+        let query = OrderedSet::from_sorted([0, 1, 2]).unwrap();
+        let expected = index.range_query(&query, 0.5).unwrap();
+
+        let mut budget = Budget::new().max_verified(150);
+        let answers = index.range_query_with_budget(&query, 0.5, &mut budget);
+        assert_eq!(answers.unwrap(), expected);
+        assert!(!budget.is_exhausted());
+        let answers = index
+            .range_query_with_budget(&query, 0.5, &mut budget)
+            .unwrap();
+        assert!(budget.is_exhausted());
+        assert!(answers.len() < expected.len());
+        assert!(answers.iter().all(|ans| expected.contains(ans)));
+    }
+
+    #[test]
+    fn test_weighted_query() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([2, 3, 4, 5]).unwrap();
+        let c = OrderedSet::from_sorted([3, 4, 5, 6, 7]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
+        let index = LinearScan::from_records(&records, 10).unwrap();
+
+        let queries = vec![
+            (OrderedSet::from_sorted([1, 2, 3]).unwrap(), 1.0),
+            (OrderedSet::from_sorted([3, 4, 5]).unwrap(), 0.5),
+        ];
+        let expected = vec![
+            Answer {
+                id: 0,
+                dist: 1. - 3. / 4.,
+            },
+            Answer {
+                id: 1,
+                dist: 1. - 3. / 5.,
+            },
+        ];
+        assert_eq!(index.range_query_weighted(&queries, 0.5).unwrap(), expected);
+        assert_eq!(index.topk_query_weighted(&queries, 2).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_topk_query_contrastive() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([2, 3, 4, 5]).unwrap();
+        let c = OrderedSet::from_sorted([3, 4, 5, 6, 7]).unwrap();
+        let records = vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ];
+        let index = LinearScan::from_records(&records, 10).unwrap();
+
+        let positive = OrderedSet::from_sorted([2, 3, 4]).unwrap();
+        let negative = OrderedSet::from_sorted([4, 5]).unwrap();
+        assert_eq!(
+            index
+                .topk_query_contrastive(&positive, &negative, 0.0, 1)
+                .unwrap(),
+            vec![Answer {
+                id: 1,
+                dist: 1. - 3. / 4.
+            }]
+        );
+        assert_eq!(
+            index
+                .topk_query_contrastive(&positive, &negative, 1.0, 1)
+                .unwrap(),
+            vec![Answer {
+                id: 0,
+                dist: 1. - 2. / 4.
+            }]
+        );
+        for lambda in [-1.0, f32::NAN, f32::INFINITY] {
+            assert!(index
+                .topk_query_contrastive(&positive, &negative, lambda, 1)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let records = (0..10)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_unsorted([id % 3, id % 4 + 3]),
+            })
+            .collect::<Vec<_>>();
+        let index = LinearScan::from_records(&records, 8).unwrap().deduplicate();
+        let report = index.validate().unwrap();
+        assert_eq!(report.n_records + report.n_duplicates, 10);
+
+        let mut broken = LinearScan::from_records(&records, 8).unwrap();
+        broken.records[0].set = OrderedSet::from_sorted([0, 8]).unwrap().freeze();
+        assert!(broken.validate().is_err());
+
+        let mut broken = LinearScan::from_records(&records, 8).unwrap();
+        broken.offsets.insert(0, 1);
+        assert!(broken.validate().is_err());
+    }
+
+    #[test]
+    fn test_save_load() {
+        let records = (0..10)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_unsorted([id % 3, id % 4 + 3]),
+            })
+            .collect::<Vec<_>>();
+        let index = LinearScan::from_records(&records, 8).unwrap().deduplicate();
+        let mut buf = vec![];
+        index.save(&mut buf, None).unwrap();
+        let loaded = LinearScan::load(buf.as_slice(), None).unwrap();
+        for record in &records {
+            assert_eq!(
+                loaded.range_query(&record.set, 0.5).unwrap(),
+                index.range_query(&record.set, 0.5).unwrap()
+            );
+        }
+
+        let inverted_index = crate::InvertedIndex::from_records(&records, 8, 0.5).unwrap();
+        let mut buf = vec![];
+        inverted_index.save(&mut buf, None).unwrap();
+        let err = LinearScan::load(buf.as_slice(), None).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<FormatError>(),
+            Some(FormatError::KindMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_deduplicate() {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([2, 3, 4]).unwrap();
+        let records = vec![
+            Record {
+                id: 0,
+                set: a.clone(),
+            },
+            Record { id: 1, set: b },
+            Record {
+                id: 2,
+                set: a.clone(),
+            },
+            Record { id: 3, set: a },
+        ];
+        let index = LinearScan::from_records(&records, 10)
+            .unwrap()
+            .deduplicate();
+        assert_eq!(index.duplicates(0), &[2, 3]);
+        assert!(index.duplicates(1).is_empty());
+        assert!(index.contains_id(3));
+        assert_eq!(index.get_record(3), index.get_record(0));
+
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        assert_eq!(
+            index.range_query(&query, 0.5).unwrap(),
+            vec![
+                Answer {
+                    id: 0,
+                    dist: 1. - 3. / 3.
+                },
+                Answer {
+                    id: 1,
+                    dist: 1. - 2. / 4.
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        let records = vec![Record {
+            id: 0,
+            set: OrderedSet::from_sorted([1, 2, 3]).unwrap(),
+        }];
+        let index = LinearScan::from_records(&records, 10).unwrap();
+        let query = OrderedSet::from_sorted([1, 2]).unwrap();
+        assert!(index.range_query(&query, f32::NAN).is_err());
+        assert!(index.range_query(&query, -0.1).is_err());
+        assert!(index.range_query_by_id(0, f32::NAN).is_err());
+        assert!(index.evaluate(&query, f32::NAN).is_err());
+        assert!(index.topk_query(&query, 0).is_err());
+        assert!(index.topk_query_by_id(0, 0).is_err());
+        // Radii greater than 1 behave as 1.
+        assert_eq!(
+            index.range_query(&query, 1.5).unwrap(),
+            index.range_query(&query, 1.0).unwrap()
+        );
+        // The unimplemented filters are rejected.
+        let config = FilterConfig::ALL.count_threshold(2);
+        assert!(index
+            .evaluate_accepted(&query, 0.5, config.clone())
+            .is_err());
+        assert!(index.filter_config(config).is_err());
+        let config = FilterConfig::ALL.prefix_extension(1);
+        let index = LinearScan::from_records(&records, 10).unwrap();
+        assert!(index.filter_config(config).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_accepted() {
+        let records = vec![
+            Record {
+                id: 0,
+                set: OrderedSet::from_sorted([1, 2, 3]).unwrap(),
+            },
+            Record {
+                id: 1,
+                set: OrderedSet::from_sorted([1, 2, 3, 4, 5, 6]).unwrap(),
+            },
+            Record {
+                id: 2,
+                set: OrderedSet::from_sorted([2, 3, 4]).unwrap(),
+            },
+        ];
+        let index = LinearScan::from_records(&records, 10).unwrap();
+        let query = OrderedSet::from_sorted([1, 2, 3, 4]).unwrap();
+        let expected = index.range_query(&query, 0.5).unwrap();
+        assert_eq!(expected.len(), 3);
+        for length in [false, true] {
+            for position in [false, true] {
+                let config = FilterConfig::new(length, position);
+                assert_eq!(
+                    index.evaluate_accepted(&query, 0.5, config).unwrap(),
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_containment_query() {
+        let records = vec![
+            Record {
+                id: 0,
+                set: OrderedSet::from_sorted([1, 2, 3, 4, 5, 6]).unwrap(),
+            },
+            Record {
+                id: 1,
+                set: OrderedSet::from_sorted([1, 2]).unwrap(),
+            },
+            Record {
+                id: 2,
+                set: OrderedSet::from_sorted([2, 3, 7]).unwrap(),
+            },
+        ];
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        for length in [false, true] {
+            for position in [false, true] {
+                let index = LinearScan::from_records(&records, 10)
+                    .unwrap()
+                    .filter_config(FilterConfig::new(length, position))
+                    .unwrap();
+                assert_eq!(
+                    index.find_supersets(&query, 0.6).unwrap(),
+                    vec![
+                        Answer { id: 0, dist: 0.0 },
+                        Answer {
+                            id: 1,
+                            dist: 1.0 - 2. / 3.
+                        },
+                        Answer {
+                            id: 2,
+                            dist: 1.0 - 2. / 3.
+                        },
+                    ]
+                );
+                assert_eq!(
+                    index.find_subsets(&query, 0.6).unwrap(),
+                    vec![
+                        Answer { id: 1, dist: 0.0 },
+                        Answer {
+                            id: 2,
+                            dist: 1.0 - 2. / 3.
+                        },
+                    ]
+                );
+                assert!(index.find_subsets(&query, f32::NAN).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_smoothed_query() {
+        let records = vec![
+            Record {
+                id: 0,
+                set: OrderedSet::from_sorted([1]).unwrap(),
+            },
+            Record {
+                id: 1,
+                set: OrderedSet::from_sorted([1, 2, 3]).unwrap(),
+            },
+            Record {
+                id: 2,
+                set: OrderedSet::from_sorted([2]).unwrap(),
+            },
+        ];
+        let index = LinearScan::from_records(&records, 10).unwrap();
+        let query = OrderedSet::from_sorted([1]).unwrap();
+        assert_eq!(
+            index.range_query_smoothed(&query, 0.5, 1.0).unwrap(),
+            vec![Answer { id: 0, dist: 0.5 }]
+        );
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        assert_eq!(
+            index.topk_query_smoothed(&query, 2, 1.0).unwrap(),
+            vec![Answer { id: 1, dist: 0.25 }, Answer { id: 0, dist: 0.75 },]
+        );
+        assert!(index.range_query_smoothed(&query, 0.5, -1.0).is_err());
+    }
+
+    #[test]
+    fn test_filter_policy() {
+        let records = vec![
+            Record {
+                id: 0,
+                set: OrderedSet::from_sorted([1]).unwrap(),
+            },
+            Record {
+                id: 1,
+                set: OrderedSet::from_sorted([1, 2, 3, 4, 5, 6]).unwrap(),
+            },
+        ];
+        let policy = FilterPolicy {
+            length_min_len: 2,
+            position_min_len: 2,
+            max_radius: 1.0,
+        };
+        let index = LinearScan::from_records(&records, 10)
+            .unwrap()
+            .filter_policy(policy);
+
+        let query = OrderedSet::from_sorted([1]).unwrap();
+        let (answers, stats) = index.range_query_with_stats(&query, 0.2).unwrap();
+        assert_eq!(answers, vec![Answer { id: 0, dist: 0.0 }]);
+        assert!(!stats.config.length && !stats.config.position);
+        assert_eq!(stats.verified, 1);
+
+        let query = OrderedSet::from_sorted([1, 2, 3, 4, 5]).unwrap();
+        let (answers, stats) = index.range_query_with_stats(&query, 0.2).unwrap();
+        assert_eq!(
+            answers,
+            vec![Answer {
+                id: 1,
+                dist: 1. - 5. / 6.
+            }]
+        );
+        assert!(stats.config.length && stats.config.position);
+        assert_eq!(stats.length_filtered, 1);
+        assert_eq!(stats.accepted, 1);
+    }
+
+    #[test]
+    fn test_query_context() {
+        let records = (0..20)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_unsorted((id..id + 5).map(|e| e % 12)),
+            })
+            .collect::<Vec<_>>();
+        let index = LinearScan::from_records(&records, 12).unwrap();
+        let mut ctx = QueryContext::new();
+        for record in &records {
+            assert_eq!(
+                index
+                    .range_query_with_context(&record.set, 0.5, &mut ctx)
+                    .unwrap(),
+                index.range_query(&record.set, 0.5).unwrap()
+            );
+            assert_eq!(
+                index
+                    .topk_query_with_context(&record.set, 3, &mut ctx)
+                    .unwrap(),
+                index.topk_query(&record.set, 3).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_retain_sharing() {
+        let records = vec![
+            Record {
+                id: 0,
+                set: OrderedSet::from_sorted([1, 2, 3]).unwrap(),
+            },
+            Record {
+                id: 1,
+                set: OrderedSet::from_sorted([1, 2, 4]).unwrap(),
+            },
+        ];
+        let index = LinearScan::from_records(&records, 10).unwrap();
+        let query = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let mut answers = index.range_query(&query, 0.5).unwrap();
+        assert_eq!(answers.len(), 2);
+        // Answers of another index.
+        answers.push(Answer { id: 7, dist: 0.0 });
+        let required = OrderedSet::from_sorted([3, 5]).unwrap();
+        assert_eq!(
+            index.retain_sharing(answers, &required),
+            vec![Answer { id: 0, dist: 0.0 }]
+        );
+    }
+
+    #[test]
+    fn test_from_records_within() {
+        let records = vec![
+            Record {
+                id: 0,
+                set: OrderedSet::new(),
+            },
+            Record {
+                id: 1,
+                set: OrderedSet::from_sorted([1, 2]).unwrap(),
+            },
+            Record {
+                id: 2,
+                set: OrderedSet::from_sorted([1, 2, 3, 4]).unwrap(),
+            },
+        ];
+        let (index, skipped) = LinearScan::from_records_within(&records, 10, 1..=3).unwrap();
+        assert_eq!(skipped, vec![0, 2]);
+        assert!(index.contains_id(1));
+        assert!(!index.contains_id(0));
+        assert!(!index.contains_id(2));
+    }
 }
@@ -0,0 +1,240 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use hashbrown::{HashMap, HashSet};
+use rand::RngCore;
+use rand::SeedableRng;
+use rand_xoshiro::SplitMix64;
+
+use crate::metric::{Evaluation, FilterConfig, Jaccard};
+use crate::{
+    check_radius, id_offsets, narrow_answers, Answer, FrozenRecord, Mapping, OrderedSet,
+    RangeSearch, Record,
+};
+
+// MinHash signatures split into bands of `band_size` hash values.
+#[derive(Clone, Debug)]
+pub struct MinHash {
+    seeds: Vec<u64>,
+    n_bands: usize,
+    band_size: usize,
+}
+
+impl MinHash {
+    pub fn new(n_bands: usize, band_size: usize, seed: u64) -> Result<Self> {
+        if n_bands == 0 || band_size == 0 {
+            return Err(anyhow!("The numbers of bands and rows must be positive."));
+        }
+        let mut seeder = SplitMix64::seed_from_u64(seed);
+        let seeds = (0..n_bands * band_size)
+            .map(|_| seeder.next_u64())
+            .collect();
+        Ok(Self {
+            seeds,
+            n_bands,
+            band_size,
+        })
+    }
+
+    // Returns `u64::MAX`s for the empty set.
+    pub fn signature(&self, set: &OrderedSet<u32>) -> Vec<u64> {
+        self.seeds
+            .iter()
+            .map(|&seed| {
+                set.iter()
+                    .map(|&elem| mix(u64::from(elem) ^ seed))
+                    .min()
+                    .unwrap_or(u64::MAX)
+            })
+            .collect()
+    }
+
+    // Returns a key per band; sets sharing a key are candidates.
+    pub fn band_keys(&self, set: &OrderedSet<u32>) -> Vec<u64> {
+        self.signature(set)
+            .chunks(self.band_size)
+            .map(|band| band.iter().fold(0, |key, &h| mix(key ^ h)))
+            .collect()
+    }
+
+    pub const fn n_bands(&self) -> usize {
+        self.n_bands
+    }
+
+    pub const fn band_size(&self) -> usize {
+        self.band_size
+    }
+}
+
+// Approximate range search with the records sharing a band key with the query as candidates,
+// which are verified by `Jaccard`. Records within the radius are missed with the probability
+// of no common band, e.g., to compare recall and latency with the exact indexes.
+pub struct MinHashIndex {
+    minhash: MinHash,
+    mapping: Arc<Mapping>,
+    records: Vec<FrozenRecord>,
+    // Offsets of the records per band key, for each band.
+    buckets: Vec<HashMap<u64, Vec<u32>>>,
+    radius: f32,
+}
+
+impl MinHashIndex {
+    pub fn from_records(
+        records: &[Record<u32>],
+        universe: u32,
+        radius: f32,
+        minhash: MinHash,
+    ) -> Result<Self> {
+        check_radius(radius)?;
+        let mapping = Mapping::from_records(records, universe)?;
+        id_offsets(records)?;
+        let mut buckets = vec![HashMap::new(); minhash.n_bands()];
+        for (i, record) in records.iter().enumerate() {
+            // Empty sets have undefined distances as in the other indexes.
+            if record.set.is_empty() {
+                continue;
+            }
+            let keys = minhash.band_keys(&record.set);
+            for (bucket, key) in buckets.iter_mut().zip(keys) {
+                bucket.entry(key).or_insert_with(Vec::new).push(i as u32);
+            }
+        }
+        let records = records
+            .iter()
+            .map(|record| {
+                Record {
+                    id: record.id,
+                    set: mapping.apply(&record.set),
+                }
+                .freeze()
+            })
+            .collect();
+        Ok(Self {
+            minhash,
+            mapping: Arc::new(mapping),
+            records,
+            buckets,
+            radius,
+        })
+    }
+
+    pub fn mapping(&self) -> &Arc<Mapping> {
+        &self.mapping
+    }
+
+    pub fn range_query(&self, query: &OrderedSet<u32>) -> Vec<Answer> {
+        self.range_query_with_candidates(query).0
+    }
+
+    // Also returns the number of the candidates verified.
+    pub fn range_query_with_candidates(&self, query: &OrderedSet<u32>) -> (Vec<Answer>, usize) {
+        let mut visited = HashSet::new();
+        let mut candidates = vec![];
+        for (bucket, key) in self.buckets.iter().zip(self.minhash.band_keys(query)) {
+            for &offset in bucket.get(&key).map_or(&[][..], |list| list.as_slice()) {
+                if visited.insert(offset) {
+                    candidates.push(offset);
+                }
+            }
+        }
+        let mapped = self.mapping.apply(query);
+        let jaccard = Jaccard::new(&mapped, self.radius, FilterConfig::ALL);
+        let mut answers = vec![];
+        for &offset in &candidates {
+            let record = &self.records[offset as usize];
+            if let Evaluation::Accepted(dist) = jaccard.evaluate(&record.set) {
+                answers.push(Answer {
+                    id: record.id,
+                    dist,
+                });
+            }
+        }
+        answers.sort_unstable();
+        (answers, candidates.len())
+    }
+
+    pub fn n_buckets(&self) -> usize {
+        self.buckets.iter().map(|bucket| bucket.len()).sum()
+    }
+}
+
+impl RangeSearch for MinHashIndex {
+    fn range_search(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Answer>> {
+        narrow_answers(self.range_query(query), radius, self.radius)
+    }
+}
+
+// The finalizer of SplitMix64.
+const fn mix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_band_keys() {
+        let minhash = MinHash::new(4, 2, 334).unwrap();
+        let a = OrderedSet::from_sorted([1, 2, 3, 4]).unwrap();
+        let b = OrderedSet::from_sorted([1, 2, 3, 4]).unwrap();
+        let c = OrderedSet::from_sorted([5, 6, 7, 8]).unwrap();
+        assert_eq!(minhash.signature(&a).len(), 8);
+        assert_eq!(minhash.band_keys(&a).len(), 4);
+        assert_eq!(minhash.band_keys(&a), minhash.band_keys(&b));
+        assert_ne!(minhash.band_keys(&a), minhash.band_keys(&c));
+    }
+
+    #[test]
+    fn test_minhash_index() {
+        use rand::Rng;
+        use rand_xoshiro::Xoshiro256PlusPlus;
+
+        use crate::LinearScan;
+
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(7);
+        let base = (0..40).collect::<Vec<u32>>();
+        // Perturbed copies of a few bases, so that many pairs are similar.
+        let records = (0..200)
+            .map(|id| {
+                let offset = rng.gen_range(0..4) * 100;
+                let set = base
+                    .iter()
+                    .filter(|_| rng.gen_bool(0.9))
+                    .map(|&e| e + offset)
+                    .collect::<Vec<_>>();
+                Record {
+                    id,
+                    set: OrderedSet::from_sorted(set).unwrap(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let linear_scan = LinearScan::from_records(&records, 400).unwrap();
+        let minhash = MinHash::new(20, 2, 334).unwrap();
+        let index = MinHashIndex::from_records(&records, 400, 0.3, minhash).unwrap();
+
+        let mut n_expected = 0;
+        let mut n_found = 0;
+        for record in records.iter().take(20) {
+            let expected = linear_scan.range_query(&record.set, 0.3).unwrap();
+            let (answers, n_candidates) = index.range_query_with_candidates(&record.set);
+            // No false positives, as candidates are verified.
+            assert!(answers.iter().all(|ans| expected.contains(ans)));
+            assert!(n_candidates >= answers.len());
+            n_expected += expected.len();
+            n_found += answers.len();
+        }
+        // Pairs within 0.3 share a band with the probability over 0.999.
+        assert!(n_found as f64 >= 0.99 * n_expected as f64);
+        assert!(index.range_search(&records[0].set, 0.5).is_err());
+    }
+
+    #[test]
+    fn test_invalid_bands() {
+        assert!(MinHash::new(0, 2, 334).is_err());
+        assert!(MinHash::new(2, 0, 334).is_err());
+    }
+}
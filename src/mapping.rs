@@ -1,8 +1,11 @@
 use anyhow::anyhow;
 use anyhow::Result;
 
+use crate::stats::CorpusStats;
+use crate::text::{fnv1a_64, FeatureExtractor};
 use crate::{OrderedSet, Record};
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Mapping {
     mapping: Vec<u32>,
 }
@@ -14,28 +17,35 @@ impl Mapping {
         }
     }
 
-    pub fn from_records(records: &[Record<u32>], universe: u32) -> Result<Self> {
+    pub fn from_records<S: AsRef<[u32]>>(
+        records: &[Record<u32, S>],
+        universe: u32,
+    ) -> Result<Self> {
         if universe == 0 {
             return Err(anyhow!("Invalid universe."));
         }
-        let mut freqs = vec![0usize; universe as usize];
-        for record in records {
-            for &elem in record.set.iter() {
-                freqs[elem as usize] += 1;
-            }
-        }
+        Ok(Self::from_freqs(elem_freqs(records, universe)))
+    }
+
+    // Same as `from_records` but with the frequencies accumulated in one pass,
+    // e.g., to build indexes with `from_records_with_mapping` over a stream.
+    pub fn from_stats(stats: &CorpusStats) -> Self {
+        Self::from_freqs(stats.dfs().to_vec())
+    }
 
+    fn from_freqs(freqs: Vec<usize>) -> Self {
+        let universe = freqs.len();
         let mut elem_freq = freqs.into_iter().enumerate().collect::<Vec<_>>();
-        elem_freq.sort_unstable_by(|&(_, a), &(_, b)| a.cmp(&b));
+        elem_freq.sort_unstable_by_key(|&(_, freq)| freq);
 
-        let mut mapping = vec![0u32; universe as usize];
+        let mut mapping = vec![0u32; universe];
         for (tgt, (src, _)) in elem_freq.into_iter().enumerate() {
             mapping[src] = tgt as u32;
         }
-        Ok(Self { mapping })
+        Self { mapping }
     }
 
-    pub fn apply(&self, set: &OrderedSet<u32>) -> OrderedSet<u32> {
+    pub fn apply<S: AsRef<[u32]>>(&self, set: &OrderedSet<u32, S>) -> OrderedSet<u32> {
         let set = set
             .iter()
             .map(|&elem| self.mapping[elem as usize])
@@ -43,13 +53,134 @@ impl Mapping {
         OrderedSet::from_unsorted(set)
     }
 
+    // Same as `apply` but reuses the buffer.
+    pub(crate) fn apply_into(&self, set: &OrderedSet<u32>, mut buf: Vec<u32>) -> OrderedSet<u32> {
+        buf.clear();
+        buf.extend(set.iter().map(|&elem| self.mapping[elem as usize]));
+        OrderedSet::from_unsorted_vec(buf)
+    }
+
+    // Returns the mapping that applies `self` and then `next`.
+    pub fn then(&self, next: &Mapping) -> Self {
+        let mapping = self
+            .mapping
+            .iter()
+            .map(|&elem| next.mapping[elem as usize])
+            .collect();
+        Self { mapping }
+    }
+
+    // Returns the mapping back to the original elements.
+    pub fn inverse(&self) -> Self {
+        let mut mapping = vec![0u32; self.mapping.len()];
+        for (src, &tgt) in self.mapping.iter().enumerate() {
+            mapping[tgt as usize] = src as u32;
+        }
+        Self { mapping }
+    }
+
+    pub fn covers<S: AsRef<[u32]>>(&self, records: &[Record<u32, S>]) -> bool {
+        let universe = self.universe();
+        records
+            .iter()
+            .all(|record| record.set.iter().all(|&elem| elem < universe))
+    }
+
     pub fn universe(&self) -> u32 {
         self.mapping.len() as u32
     }
 
+    // Whether every element is mapped to a distinct element in the universe.
+    pub fn is_permutation(&self) -> bool {
+        let mut seen = vec![false; self.mapping.len()];
+        for &tgt in &self.mapping {
+            match seen.get_mut(tgt as usize) {
+                Some(seen) if !*seen => *seen = true,
+                _ => return false,
+            }
+        }
+        true
+    }
+
     pub fn as_slice(&self) -> &[u32] {
         &self.mapping
     }
+
+    // Hash of the mapping, stable across platforms and releases.
+    pub fn fingerprint(&self) -> u64 {
+        let bytes = self
+            .mapping
+            .iter()
+            .flat_map(|elem| elem.to_le_bytes())
+            .collect::<Vec<_>>();
+        fnv1a_64(&bytes)
+    }
+}
+
+// Hash of the extractor and the mapping producing the mapped sets, which indexes
+// and `MappedQuery` carry so that sets produced in different ways are not mixed.
+pub fn config_hash(extractor_hash: u64, mapping: &Mapping) -> u64 {
+    let mut bytes = extractor_hash.to_le_bytes().to_vec();
+    bytes.extend(mapping.fingerprint().to_le_bytes());
+    fnv1a_64(&bytes)
+}
+
+// A query mapped by the mapping of an index, tagged with the configuration producing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappedQuery {
+    set: OrderedSet<u32>,
+    config_hash: Option<u64>,
+}
+
+impl MappedQuery {
+    // Extracts and maps the tokens. Computes the hash of the mapping, so mapped queries
+    // should be produced by `prepare` of the index or `Registry::extract_mapped` in loops.
+    pub fn new<S>(extractor: &FeatureExtractor, mapping: &Mapping, tokens: &[S]) -> Self
+    where
+        S: AsRef<str>,
+    {
+        let hash = config_hash(extractor.config_hash(), mapping);
+        Self::with_config_hash(mapping.apply(&extractor.extract(tokens)), Some(hash))
+    }
+
+    // The set must be already mapped. Queries without the hash are not checked.
+    pub(crate) fn with_config_hash(set: OrderedSet<u32>, config_hash: Option<u64>) -> Self {
+        Self { set, config_hash }
+    }
+
+    pub fn set(&self) -> &OrderedSet<u32> {
+        &self.set
+    }
+
+    pub fn into_set(self) -> OrderedSet<u32> {
+        self.set
+    }
+
+    pub const fn config_hash(&self) -> Option<u64> {
+        self.config_hash
+    }
+
+    // Fails if both the query and the index have hashes and they differ.
+    pub(crate) fn check(&self, index_hash: Option<u64>) -> Result<()> {
+        match (self.config_hash, index_hash) {
+            (Some(found), Some(expected)) if found != expected => Err(anyhow!(
+                "The query was produced by another configuration: {:016x} != {:016x}",
+                found,
+                expected
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub(crate) fn elem_freqs<S: AsRef<[u32]>>(records: &[Record<u32, S>], universe: u32) -> Vec<usize> {
+    let mut freqs = vec![0usize; universe as usize];
+    for record in records {
+        for &elem in record.set.iter() {
+            freqs[elem as usize] += 1;
+        }
+    }
+    freqs
 }
 
 #[cfg(test)]
@@ -73,5 +204,88 @@ mod tests {
 
         let mapped = mapping.apply(&OrderedSet::from_sorted([0, 1]).unwrap());
         assert_eq!(mapped, OrderedSet::from_sorted([1, 2]).unwrap());
+
+        assert!(mapping.is_permutation());
+        assert!(!Mapping::from_slice(&[0, 1, 1]).is_permutation());
+        assert!(!Mapping::from_slice(&[0, 3, 1]).is_permutation());
+    }
+
+    #[test]
+    fn test_from_stats() {
+        let records = (0..20)
+            .map(|id| Record {
+                id,
+                set: OrderedSet::from_unsorted([id % 3, id % 7 + 3]),
+            })
+            .collect::<Vec<_>>();
+        let mut stats = CorpusStats::new(10, 0, 0).unwrap();
+        for record in &records {
+            stats.push(&record.set).unwrap();
+        }
+        assert_eq!(
+            Mapping::from_stats(&stats),
+            Mapping::from_records(&records, 10).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_then() {
+        let first = Mapping::from_slice(&[2, 0, 1]);
+        let second = Mapping::from_slice(&[1, 2, 0]);
+        assert_eq!(first.then(&second).as_slice(), &[0, 1, 2]);
+        assert_eq!(first.inverse(), second);
+    }
+
+    #[test]
+    fn test_covers() {
+        let records = vec![
+            Record {
+                id: 0,
+                set: OrderedSet::from_sorted([0, 1, 3]).unwrap(),
+            },
+            Record {
+                id: 1,
+                set: OrderedSet::from_sorted([2, 4]).unwrap(),
+            },
+        ];
+        assert!(Mapping::from_slice(&[0, 1, 2, 3, 4]).covers(&records));
+        assert!(!Mapping::from_slice(&[0, 1, 2, 3]).covers(&records));
+    }
+
+    #[test]
+    fn test_mapped_query() {
+        let extractor = FeatureExtractor::seeded(1..=1, 16, 42).unwrap();
+        let records = ["a b c", "a b d", "e f"]
+            .iter()
+            .enumerate()
+            .map(|(id, text)| Record {
+                id: id as u32,
+                set: extractor.extract(&text.split(' ').collect::<Vec<_>>()),
+            })
+            .collect::<Vec<_>>();
+        let index = crate::LinearScan::from_records(&records, 16)
+            .unwrap()
+            .extractor(&extractor);
+        let mapping = index.mapping().clone();
+
+        let query = MappedQuery::new(&extractor, &mapping, &["a", "b", "c"]);
+        assert_eq!(query.config_hash(), index.config_hash());
+        let answers = index.range_query_prepared(&query, 0.5).unwrap();
+        assert_eq!(answers.len(), 2);
+
+        // Another seed or mapping hashes elements differently.
+        let other = FeatureExtractor::seeded(1..=1, 16, 7).unwrap();
+        let query = MappedQuery::new(&other, &mapping, &["a", "b", "c"]);
+        assert!(index.range_query_prepared(&query, 0.5).is_err());
+        let reversed = Mapping::from_slice(&(0..16).rev().collect::<Vec<_>>());
+        let query = MappedQuery::new(&extractor, &reversed, &["a", "b", "c"]);
+        assert!(index.range_query_prepared(&query, 0.5).is_err());
+
+        // Queries and indexes without the hash are not checked.
+        let query = MappedQuery::with_config_hash(query.into_set(), None);
+        assert!(index.range_query_prepared(&query, 0.5).is_ok());
+        let index = crate::LinearScan::from_records(&records, 16).unwrap();
+        let query = MappedQuery::new(&other, &mapping, &["a", "b", "c"]);
+        assert!(index.range_query_prepared(&query, 0.5).is_ok());
     }
 }
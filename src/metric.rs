@@ -1,17 +1,161 @@
 use std::cmp::Ordering;
+use std::fmt;
 use std::ops::RangeInclusive;
+use std::str::FromStr;
 
-use approx::abs_diff_eq;
+use anyhow::anyhow;
+use anyhow::Result;
 
-use crate::set::OrderedSet;
+use serde::{Deserialize, Serialize};
 
-#[derive(Default, Debug, Clone, Copy)]
+use crate::kernel;
+use crate::set::{OrderedSet, WeightedSet};
+use crate::{check_containment, dist_eq};
+
+// Filters and their parameters. The parameters are of the other filters and
+// do not change the length and position filters.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FilterConfig {
     pub length: bool,
     pub position: bool,
+    // Recursion depth of the suffix filter of `InvertedIndex`, disabled if 0.
+    #[serde(default)]
+    pub suffix_depth: usize,
+    // Number of the elements probed beyond the prefix to tighten the overlap bounds.
+    // Not implemented yet, so the indexes reject any other than 0.
+    #[serde(default)]
+    pub prefix_extension: usize,
+    // Minimum number of the shared prefix elements to verify a candidate, 1 if none.
+    // Not implemented yet, so the indexes reject any other than `None`.
+    #[serde(default)]
+    pub count_threshold: Option<usize>,
+    // Computes the overlap needed per pair exactly instead of by the float ceil,
+    // which can demand one more element than needed at borderline thresholds.
+    #[serde(default)]
+    pub exact_overlap: bool,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl FilterConfig {
+    pub const NONE: Self = Self::new(false, false);
+    pub const LENGTH_ONLY: Self = Self::new(true, false);
+    pub const POSITION_ONLY: Self = Self::new(false, true);
+    pub const ALL: Self = Self::new(true, true);
+    // Every combination of the filters, e.g., to compare them.
+    pub const PRESETS: [Self; 4] = [
+        Self::NONE,
+        Self::LENGTH_ONLY,
+        Self::POSITION_ONLY,
+        Self::ALL,
+    ];
+
+    pub const fn new(length: bool, position: bool) -> Self {
+        Self {
+            length,
+            position,
+            suffix_depth: 0,
+            prefix_extension: 0,
+            count_threshold: None,
+            exact_overlap: false,
+        }
+    }
+
+    pub fn length(mut self, enabled: bool) -> Self {
+        self.length = enabled;
+        self
+    }
+
+    pub fn position(mut self, enabled: bool) -> Self {
+        self.position = enabled;
+        self
+    }
+
+    pub fn suffix_depth(mut self, depth: usize) -> Self {
+        self.suffix_depth = depth;
+        self
+    }
+
+    pub fn prefix_extension(mut self, n_elems: usize) -> Self {
+        self.prefix_extension = n_elems;
+        self
+    }
+
+    pub fn count_threshold(mut self, n_elems: usize) -> Self {
+        self.count_threshold = Some(n_elems);
+        self
+    }
+
+    pub fn exact_overlap(mut self, enabled: bool) -> Self {
+        self.exact_overlap = enabled;
+        self
+    }
+
+    // Fails if the parameters of the unimplemented filters are set.
+    pub(crate) fn check_supported(&self) -> Result<()> {
+        if self.prefix_extension != 0 {
+            return Err(anyhow!("The prefix extension is not supported yet."));
+        }
+        if self.count_threshold.is_some() {
+            return Err(anyhow!("The count threshold is not supported yet."));
+        }
+        Ok(())
+    }
+}
+
+// Parses "none", "all", or comma-separated filter names, e.g., "length,position".
+impl FromStr for FilterConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "none" => return Ok(Self::NONE),
+            "all" => return Ok(Self::ALL),
+            _ => {}
+        }
+        let mut config = Self::NONE;
+        for name in s.split(',') {
+            match name.trim() {
+                "length" => config.length = true,
+                "position" => config.position = true,
+                name => return Err(anyhow!("Unknown filter: {}", name)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl fmt::Display for FilterConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.length, self.position) {
+            (false, false) => write!(f, "none"),
+            (true, false) => write!(f, "length"),
+            (false, true) => write!(f, "position"),
+            (true, true) => write!(f, "all"),
+        }
+    }
+}
+
+// Decides the filters per query, since they cost more than they prune
+// on short queries and at large radii.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterPolicy {
+    // Minimum query lengths to enable the filters.
+    pub length_min_len: usize,
+    pub position_min_len: usize,
+    // Maximum radius to enable the filters.
+    pub max_radius: f32,
+}
+
+impl FilterPolicy {
+    pub fn decide(&self, query_len: usize, radius: f32) -> FilterConfig {
+        let enabled = radius <= self.max_radius;
+        FilterConfig::new(
+            enabled && query_len >= self.length_min_len,
+            enabled && query_len >= self.position_min_len,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Evaluation {
     LengthFiltered,
     PositionFiltered,
@@ -29,79 +173,795 @@ impl PartialEq for Evaluation {
             (Self::PositionFiltered, Self::PositionFiltered) => true,
             (Self::Verified, Self::Verified) => true,
             (Self::Undefined, Self::Undefined) => true,
-            (Self::Accepted(a), Self::Accepted(b)) => abs_diff_eq!(a, b),
+            (Self::Accepted(a), Self::Accepted(b)) => dist_eq(*a, *b),
             _ => false,
         }
     }
 }
 
-pub struct Jaccard<'a, T> {
-    base: &'a OrderedSet<T>,
-    overlap_factor: f32,
-    length_bounds: RangeInclusive<usize>,
-    config: FilterConfig,
+// Formulas of the filter bounds for a metric, in terms of the similarity threshold.
+pub trait FilterBounds {
+    // Similarity threshold corresponding to the radius.
+    fn threshold(radius: f32) -> f32;
+
+    // Ratio of the minimum overlap to |a| + |b|.
+    fn overlap_factor(threshold: f32) -> f32;
+
+    // Lengths of the sets that can be within the threshold from a set of `base_len`.
+    fn length_bounds(base_len: usize, threshold: f32) -> RangeInclusive<usize>;
+
+    // Prefix length to be indexed for a set of `set_len`.
+    fn index_prefix_len(set_len: usize, threshold: f32) -> usize;
+
+    // Prefix length to be probed for a query of `set_len`.
+    fn query_prefix_len(set_len: usize, threshold: f32) -> usize;
+}
+
+pub struct Jaccard<'a, T> {
+    base: &'a [T],
+    threshold: f32,
+    overlap_factor: f32,
+    length_bounds: RangeInclusive<usize>,
+    config: FilterConfig,
+    rational: Option<RationalThreshold>,
+}
+
+impl<'a, T> Jaccard<'a, T>
+where
+    T: Element,
+{
+    pub fn new<S: AsRef<[T]>>(
+        base: &'a OrderedSet<T, S>,
+        radius: f32,
+        config: FilterConfig,
+    ) -> Self {
+        let threshold = Self::threshold(radius);
+        let overlap_factor = Self::overlap_factor(threshold);
+        let length_bounds = Self::length_bounds(base.len(), threshold);
+        Self {
+            base: base.as_slice(),
+            threshold,
+            overlap_factor,
+            length_bounds,
+            config,
+            rational: None,
+        }
+    }
+
+    // Decides the bounds and the acceptance exactly by the rational threshold,
+    // while the distances of the answers are still in floats.
+    pub fn with_rational<S: AsRef<[T]>>(
+        base: &'a OrderedSet<T, S>,
+        threshold: RationalThreshold,
+        config: FilterConfig,
+    ) -> Self {
+        let mut jaccard = Self::new(base, threshold.radius(), config);
+        jaccard.overlap_factor = threshold.num as f32 / (threshold.num + threshold.den) as f32;
+        jaccard.length_bounds = threshold.length_bounds(base.len());
+        jaccard.rational = Some(threshold);
+        jaccard
+    }
+
+    // Leaves the rational threshold if any.
+    pub fn update_radius(&mut self, radius: f32) {
+        let threshold = Self::threshold(radius);
+        self.rational = None;
+        self.threshold = threshold;
+        self.overlap_factor = Self::overlap_factor(threshold);
+        self.length_bounds = Self::length_bounds(self.base.len(), threshold);
+    }
+
+    pub fn length_range(&self) -> &RangeInclusive<usize> {
+        &self.length_bounds
+    }
+
+    pub fn distance<S: AsRef<[T]>>(&self, other: &OrderedSet<T, S>) -> Option<f32> {
+        let a = self.base;
+        let b = other;
+
+        if a.is_empty() && b.is_empty() {
+            return None;
+        }
+        if a.is_empty() || b.is_empty() {
+            return Some(1.0);
+        }
+
+        let intersection = intersection_len(a, b);
+        let union = a.len() + b.len() - intersection;
+        Some(1.0 - (intersection as f32) / (union as f32))
+    }
+
+    // Returns the overlap threshold if the merge is needed, or the evaluation otherwise.
+    fn precheck<S: AsRef<[T]>>(&self, other: &OrderedSet<T, S>) -> Result<usize, Evaluation> {
+        if self.config.length {
+            self.precheck_with::<S, true>(other)
+        } else {
+            self.precheck_with::<S, false>(other)
+        }
+    }
+
+    fn precheck_with<S: AsRef<[T]>, const LENGTH: bool>(
+        &self,
+        other: &OrderedSet<T, S>,
+    ) -> Result<usize, Evaluation> {
+        let a = self.base;
+        let b = other;
+
+        if a.is_empty() && b.is_empty() {
+            return Err(Evaluation::Undefined);
+        }
+
+        // radius = 1.0
+        if self.overlap_factor == 0.0 {
+            let dist = self.distance(b).unwrap();
+            return Err(Evaluation::Accepted(dist));
+        }
+
+        if a.is_empty() || b.is_empty() {
+            return Err(Evaluation::Verified);
+        }
+
+        // 1) Length filter
+        // dbg!(&self.length_bounds, b.len());
+        if LENGTH && !self.length_bounds.contains(&b.len()) {
+            return Err(Evaluation::LengthFiltered);
+        }
+
+        let overlap_threshold = self.overlap_threshold(a.len() + b.len());
+        // dbg!(self.overlap_factor, overlap_threshold);
+        Ok(overlap_threshold)
+    }
+
+    pub(crate) fn overlap_threshold(&self, total_len: usize) -> usize {
+        if let Some(rational) = &self.rational {
+            rational.overlap_threshold(total_len)
+        } else if self.config.exact_overlap {
+            exact_overlap_threshold(self.threshold, total_len)
+        } else {
+            (self.overlap_factor * total_len as f32).ceil() as usize
+        }
+    }
+
+    pub fn evaluate<S: AsRef<[T]>>(&self, other: &OrderedSet<T, S>) -> Evaluation {
+        match (self.config.length, self.config.position) {
+            (false, false) => self.evaluate_with::<S, false, false>(other),
+            (true, false) => self.evaluate_with::<S, true, false>(other),
+            (false, true) => self.evaluate_with::<S, false, true>(other),
+            (true, true) => self.evaluate_with::<S, true, true>(other),
+        }
+    }
+
+    // Returns `evaluate_with` specialized for `self.config`, e.g., to decide the filters
+    // once per query rather than per set, so that every configuration is compared
+    // with no branch of the disabled filters.
+    pub fn evaluator<S: AsRef<[T]>>(&self) -> fn(&Self, &OrderedSet<T, S>) -> Evaluation {
+        match (self.config.length, self.config.position) {
+            (false, false) => Self::evaluate_with::<S, false, false>,
+            (true, false) => Self::evaluate_with::<S, true, false>,
+            (false, true) => Self::evaluate_with::<S, false, true>,
+            (true, true) => Self::evaluate_with::<S, true, true>,
+        }
+    }
+
+    // Same as `evaluate` with the length and position filters given at compile time,
+    // ignoring those of `self.config`.
+    pub fn evaluate_with<S: AsRef<[T]>, const LENGTH: bool, const POSITION: bool>(
+        &self,
+        other: &OrderedSet<T, S>,
+    ) -> Evaluation {
+        let a = self.base;
+        let b = other;
+
+        let overlap_threshold = match self.precheck_with::<S, LENGTH>(b) {
+            Ok(overlap_threshold) => overlap_threshold,
+            Err(eval) => return eval,
+        };
+
+        // Without the position filter, the merge needs no early termination
+        // and is left to the kernel of the machine.
+        if !POSITION {
+            let intersection = intersection_len(a, b);
+            if intersection < overlap_threshold {
+                return Evaluation::Verified;
+            }
+            let union = a.len() + b.len() - intersection;
+            return Evaluation::Accepted(1.0 - (intersection as f32) / (union as f32));
+        }
+
+        let mut i = 0;
+        let mut j = 0;
+        let mut intersection = 0;
+
+        while i < a.len() && j < b.len() {
+            let a_i = a.get(i).unwrap();
+            let b_j = b.get(j).unwrap();
+            match a_i.cmp(b_j) {
+                Ordering::Equal => {
+                    intersection += 1;
+                    i += 1;
+                    j += 1;
+                }
+                Ordering::Less => {
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    j += 1;
+                }
+            }
+            // 2) Position filter
+            let a_sfx_len = a.len() - i;
+            let b_sfx_len = b.len() - j;
+            // dbg!(intersection, a_sfx_len, b_sfx_len);
+            if intersection + a_sfx_len.min(b_sfx_len) < overlap_threshold {
+                return Evaluation::PositionFiltered;
+            }
+        }
+
+        if intersection < overlap_threshold {
+            return Evaluation::Verified;
+        }
+
+        let union = a.len() + b.len() - intersection;
+        let dist = 1.0 - (intersection as f32) / (union as f32);
+        Evaluation::Accepted(dist)
+    }
+
+    // Same as `evaluate` but also returns the state of the merge when it stopped,
+    // e.g., to study how tight the filters are. The merge is never left to the kernel.
+    pub fn evaluate_detailed<S: AsRef<[T]>>(&self, other: &OrderedSet<T, S>) -> EvaluationDetail {
+        let a = self.base;
+        let b = other;
+
+        let overlap_threshold = match self.precheck(b) {
+            Ok(overlap_threshold) => overlap_threshold,
+            Err(evaluation) => {
+                return EvaluationDetail {
+                    evaluation,
+                    base_pos: 0,
+                    other_pos: 0,
+                    intersection: 0,
+                    overlap_threshold: None,
+                }
+            }
+        };
+        let detail = |evaluation, i, j, intersection| EvaluationDetail {
+            evaluation,
+            base_pos: i,
+            other_pos: j,
+            intersection,
+            overlap_threshold: Some(overlap_threshold),
+        };
+
+        let mut i = 0;
+        let mut j = 0;
+        let mut intersection = 0;
+
+        while i < a.len() && j < b.len() {
+            let a_i = a.get(i).unwrap();
+            let b_j = b.get(j).unwrap();
+            match a_i.cmp(b_j) {
+                Ordering::Equal => {
+                    intersection += 1;
+                    i += 1;
+                    j += 1;
+                }
+                Ordering::Less => {
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    j += 1;
+                }
+            }
+            // 2) Position filter
+            let a_sfx_len = a.len() - i;
+            let b_sfx_len = b.len() - j;
+            if self.config.position && intersection + a_sfx_len.min(b_sfx_len) < overlap_threshold {
+                return detail(Evaluation::PositionFiltered, i, j, intersection);
+            }
+        }
+
+        if intersection < overlap_threshold {
+            return detail(Evaluation::Verified, i, j, intersection);
+        }
+        let union = a.len() + b.len() - intersection;
+        let dist = 1.0 - (intersection as f32) / (union as f32);
+        detail(Evaluation::Accepted(dist), i, j, intersection)
+    }
+
+    // Evaluates N sets at once, advancing their merges in an interleaved manner
+    // to exploit instruction-level parallelism. The results are the same as `evaluate`.
+    pub fn evaluate_batch<S, const N: usize>(
+        &self,
+        others: [&OrderedSet<T, S>; N],
+    ) -> [Evaluation; N]
+    where
+        S: AsRef<[T]>,
+    {
+        // The position filter is decided once per batch, so that the merges without it
+        // have no branch of it in their inner loop.
+        if self.config.position {
+            self.evaluate_batch_with::<S, N, true>(others)
+        } else {
+            self.evaluate_batch_with::<S, N, false>(others)
+        }
+    }
+
+    fn evaluate_batch_with<S, const N: usize, const POSITION: bool>(
+        &self,
+        others: [&OrderedSet<T, S>; N],
+    ) -> [Evaluation; N]
+    where
+        S: AsRef<[T]>,
+    {
+        let a = self.base;
+
+        let mut evals = [Evaluation::Undefined; N];
+        let mut active = [false; N];
+        let mut thresholds = [0; N];
+        for k in 0..N {
+            match self.precheck(others[k]) {
+                Ok(overlap_threshold) => {
+                    active[k] = true;
+                    thresholds[k] = overlap_threshold;
+                }
+                Err(eval) => evals[k] = eval,
+            }
+        }
+
+        let mut i = [0; N];
+        let mut j = [0; N];
+        let mut intersection = [0; N];
+        let mut n_active = active.iter().filter(|&&x| x).count();
+
+        while n_active != 0 {
+            for k in 0..N {
+                if !active[k] {
+                    continue;
+                }
+                let b = others[k];
+                if i[k] < a.len() && j[k] < b.len() {
+                    let a_i = a.get(i[k]).unwrap();
+                    let b_j = b.get(j[k]).unwrap();
+                    match a_i.cmp(b_j) {
+                        Ordering::Equal => {
+                            intersection[k] += 1;
+                            i[k] += 1;
+                            j[k] += 1;
+                        }
+                        Ordering::Less => {
+                            i[k] += 1;
+                        }
+                        Ordering::Greater => {
+                            j[k] += 1;
+                        }
+                    }
+                    // 2) Position filter
+                    if POSITION {
+                        let a_sfx_len = a.len() - i[k];
+                        let b_sfx_len = b.len() - j[k];
+                        if intersection[k] + a_sfx_len.min(b_sfx_len) < thresholds[k] {
+                            evals[k] = Evaluation::PositionFiltered;
+                            active[k] = false;
+                            n_active -= 1;
+                        }
+                    }
+                    continue;
+                }
+                evals[k] = if intersection[k] < thresholds[k] {
+                    Evaluation::Verified
+                } else {
+                    let union = a.len() + b.len() - intersection[k];
+                    Evaluation::Accepted(1.0 - (intersection[k] as f32) / (union as f32))
+                };
+                active[k] = false;
+                n_active -= 1;
+            }
+        }
+        evals
+    }
+
+    // Evaluates all the filter configurations at once, ignoring `self.config`.
+    pub fn evaluate_sweep<S: AsRef<[T]>>(&self, other: &OrderedSet<T, S>) -> EvaluationSweep {
+        let a = self.base;
+        let b = other;
+
+        if a.is_empty() && b.is_empty() {
+            return EvaluationSweep::uniform(Evaluation::Undefined);
+        }
+
+        // radius = 1.0
+        if self.overlap_factor == 0.0 {
+            let dist = self.distance(b).unwrap();
+            return EvaluationSweep::uniform(Evaluation::Accepted(dist));
+        }
+
+        if a.is_empty() || b.is_empty() {
+            return EvaluationSweep::uniform(Evaluation::Verified);
+        }
+
+        // 1) Length filter
+        let length_filtered = !self.length_bounds.contains(&b.len());
+
+        let overlap_threshold = self.overlap_threshold(a.len() + b.len());
+
+        let mut i = 0;
+        let mut j = 0;
+        let mut intersection = 0;
+        let mut position_filtered = false;
+
+        while i < a.len() && j < b.len() {
+            let a_i = a.get(i).unwrap();
+            let b_j = b.get(j).unwrap();
+            match a_i.cmp(b_j) {
+                Ordering::Equal => {
+                    intersection += 1;
+                    i += 1;
+                    j += 1;
+                }
+                Ordering::Less => {
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    j += 1;
+                }
+            }
+            // 2) Position filter
+            let a_sfx_len = a.len() - i;
+            let b_sfx_len = b.len() - j;
+            if intersection + a_sfx_len.min(b_sfx_len) < overlap_threshold {
+                position_filtered = true;
+            }
+        }
+
+        let merged = if intersection < overlap_threshold {
+            Evaluation::Verified
+        } else {
+            let union = a.len() + b.len() - intersection;
+            Evaluation::Accepted(1.0 - (intersection as f32) / (union as f32))
+        };
+
+        let mut evaluations = [merged; 4];
+        for (k, eval) in evaluations.iter_mut().enumerate() {
+            let cfg = EvaluationSweep::config(k);
+            if cfg.length && length_filtered {
+                *eval = Evaluation::LengthFiltered;
+            } else if cfg.position && position_filtered {
+                *eval = Evaluation::PositionFiltered;
+            }
+        }
+        EvaluationSweep { evaluations }
+    }
+}
+
+impl<T> FilterBounds for Jaccard<'_, T> {
+    fn threshold(radius: f32) -> f32 {
+        1.0 - radius.clamp(0.0, 1.0)
+    }
+
+    fn overlap_factor(threshold: f32) -> f32 {
+        threshold / (1. + threshold)
+    }
+
+    fn length_bounds(base_len: usize, threshold: f32) -> RangeInclusive<usize> {
+        if threshold == 0.0 {
+            0..=usize::MAX
+        } else {
+            let base_len = base_len as f32;
+            let length_lower = (base_len * threshold).ceil() as usize;
+            let length_upper = (base_len / threshold).floor() as usize;
+            length_lower..=length_upper
+        }
+    }
+
+    fn index_prefix_len(set_len: usize, threshold: f32) -> usize {
+        (set_len as f32 * (1. - threshold) / (1. + threshold)).floor() as usize + 1
+    }
+
+    fn query_prefix_len(set_len: usize, threshold: f32) -> usize {
+        (set_len as f32 * (1. - threshold)).floor() as usize + 1
+    }
+}
+
+// Similarity threshold `num / den` for the exact mode, where the bounds and the acceptance
+// are computed in integers so that the indexes agree on the borderline pairs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RationalThreshold {
+    num: u32,
+    den: u32,
+}
+
+impl RationalThreshold {
+    // Radius `p / q`, i.e., the similarity threshold `(q - p) / q`.
+    pub fn from_radius(p: u32, q: u32) -> anyhow::Result<Self> {
+        if q == 0 || p > q {
+            return Err(anyhow!("Invalid radius: {}/{}", p, q));
+        }
+        Ok(Self { num: q - p, den: q })
+    }
+
+    pub fn radius(&self) -> f32 {
+        (self.den - self.num) as f32 / self.den as f32
+    }
+
+    pub fn length_bounds(&self, base_len: usize) -> RangeInclusive<usize> {
+        if self.num == 0 {
+            return 0..=usize::MAX;
+        }
+        let (n, num, den) = (base_len as u128, self.num as u128, self.den as u128);
+        let lower = (n * num).div_ceil(den);
+        let upper = n * den / num;
+        lower as usize..=upper.min(usize::MAX as u128) as usize
+    }
+
+    // Minimum overlap `⌈t / (1 + t) * total_len⌉`, which is exactly
+    // `intersection / union >= t` for the pairs of `total_len`.
+    pub fn overlap_threshold(&self, total_len: usize) -> usize {
+        let (n, num, den) = (total_len as u128, self.num as u128, self.den as u128);
+        (n * num).div_ceil(num + den) as usize
+    }
+
+    // Same as `query_prefix_len`, since the shorter prefix of `FilterBounds::index_prefix_len`
+    // misses the records longer than the query.
+    pub fn index_prefix_len(&self, set_len: usize) -> usize {
+        self.query_prefix_len(set_len)
+    }
+
+    // `|x| - ⌈t|x|⌉ + 1`, sharing an element with that of any set within the threshold.
+    pub fn query_prefix_len(&self, set_len: usize) -> usize {
+        let (n, num, den) = (set_len as u128, self.num as u128, self.den as u128);
+        (n * (den - num) / den) as usize + 1
+    }
+}
+
+// Minimum overlap `o = ⌈t / (1 + t) * total_len⌉`, i.e., `o >= t * (total_len - o)`,
+// decided exactly since the product of an f32 and an integer below 2^29 is exact in f64.
+pub(crate) fn exact_overlap_threshold(threshold: f32, total_len: usize) -> usize {
+    let t = threshold as f64;
+    let holds = |o: usize| o as f64 >= t * (total_len - o) as f64;
+    let mut o = ((t / (1. + t)) * total_len as f64).ceil() as usize;
+    o = o.min(total_len);
+    while o > 0 && holds(o - 1) {
+        o -= 1;
+    }
+    while !holds(o) {
+        o += 1;
+    }
+    o
+}
+
+// Suffix filter of PPJoin+, returning a lower bound of the Hamming distance `|x Δ y|`.
+// `y` is split by its middle element, which is binary-searched in `x`. The halves are
+// disjoint in the elements, so the bound is the sum of those of the halves,
+// which are split recursively up to `depth` while the bound is within `max_hamming`.
+pub(crate) fn suffix_hamming_bound<T>(x: &[T], y: &[T], max_hamming: usize, depth: usize) -> usize
+where
+    T: Ord,
+{
+    let len_diff = x.len().abs_diff(y.len());
+    if depth == 0 || x.is_empty() || y.is_empty() {
+        return len_diff;
+    }
+    let mid = y.len() / 2;
+    let (y_l, y_r) = (&y[..mid], &y[mid + 1..]);
+    let pos = x.partition_point(|elem| elem < &y[mid]);
+    let found = x.get(pos) == Some(&y[mid]);
+    let (x_l, x_r) = (&x[..pos], &x[pos + found as usize..]);
+    let diff = !found as usize;
+
+    let r_diff = x_r.len().abs_diff(y_r.len());
+    let bound = x_l.len().abs_diff(y_l.len()) + r_diff + diff;
+    if bound > max_hamming {
+        return bound;
+    }
+    let l_bound = suffix_hamming_bound(x_l, y_l, max_hamming - r_diff - diff, depth - 1);
+    let bound = l_bound + r_diff + diff;
+    if bound > max_hamming {
+        return bound;
+    }
+    let r_bound = suffix_hamming_bound(x_r, y_r, max_hamming - l_bound - diff, depth - 1);
+    l_bound + r_bound + diff
+}
+
+// Result of `Jaccard::evaluate_detailed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct EvaluationDetail {
+    pub evaluation: Evaluation,
+    // Numbers of the elements of the base and the other set consumed by the merge,
+    // which are zero if decided before the merge.
+    pub base_pos: usize,
+    pub other_pos: usize,
+    // Intersection found by the merge until it stopped.
+    pub intersection: usize,
+    // Overlap needed to be within the radius, or none if decided before the merge.
+    pub overlap_threshold: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvaluationSweep {
+    evaluations: [Evaluation; 4],
+}
+
+impl EvaluationSweep {
+    const fn uniform(eval: Evaluation) -> Self {
+        Self {
+            evaluations: [eval; 4],
+        }
+    }
+
+    const fn config(k: usize) -> FilterConfig {
+        FilterConfig::new(k & 1 != 0, k & 2 != 0)
+    }
+
+    pub const fn get(&self, config: &FilterConfig) -> Evaluation {
+        let k = (config.length as usize) | (config.position as usize) << 1;
+        self.evaluations[k]
+    }
+}
+
+// Distance of `1 - (sim(positive) - lambda * sim(negative))` in [0, 1 + lambda],
+// where sim is the Jaccard similarity.
+pub struct Contrastive<'a, T> {
+    positive: Jaccard<'a, T>,
+    negative: Jaccard<'a, T>,
+    lambda: f32,
+}
+
+impl<'a, T> Contrastive<'a, T>
+where
+    T: Element,
+{
+    pub fn new<P, N>(
+        positive: &'a OrderedSet<T, P>,
+        negative: &'a OrderedSet<T, N>,
+        lambda: f32,
+    ) -> Self
+    where
+        P: AsRef<[T]>,
+        N: AsRef<[T]>,
+    {
+        Self {
+            positive: Jaccard::new(positive, 1.0, FilterConfig::default()),
+            negative: Jaccard::new(negative, 1.0, FilterConfig::default()),
+            lambda,
+        }
+    }
+
+    pub fn distance<S: AsRef<[T]>>(&self, other: &OrderedSet<T, S>) -> Option<f32> {
+        let pos_dist = self.positive.distance(other)?;
+        let neg_dist = self.negative.distance(other).unwrap_or(1.0);
+        Some(pos_dist + self.lambda * (1.0 - neg_dist))
+    }
+}
+
+// Weighted Jaccard distance between a weighted set and unweighted sets,
+// whose elements are regarded as having weight 1.
+pub struct WeightedJaccard<'a, T> {
+    base: &'a WeightedSet<T>,
+    total_weight: f32,
 }
 
-impl<'a, T> Jaccard<'a, T>
+impl<'a, T> WeightedJaccard<'a, T>
 where
     T: Ord + Copy,
 {
-    pub fn new(base: &'a OrderedSet<T>, radius: f32, config: FilterConfig) -> Self {
-        let threshold = Self::threshold(radius);
-        let overlap_factor = Self::overlap_factor(threshold);
-        let length_bounds = Self::length_bounds(base.len(), threshold);
+    pub fn new(base: &'a WeightedSet<T>) -> Self {
         Self {
             base,
-            overlap_factor,
-            length_bounds,
-            config,
+            total_weight: base.total_weight(),
         }
     }
 
-    pub fn update_radius(&mut self, radius: f32) {
-        let threshold = Self::threshold(radius);
-        self.overlap_factor = Self::overlap_factor(threshold);
-        self.length_bounds = Self::length_bounds(self.base.len(), threshold);
-    }
+    pub fn distance<S: AsRef<[T]>>(&self, other: &OrderedSet<T, S>) -> Option<f32> {
+        let a = self.base;
+        let b = other;
 
-    fn threshold(radius: f32) -> f32 {
-        1.0 - radius.max(0.0).min(1.0)
-    }
+        let mut i = 0;
+        let mut j = 0;
+        let mut common_weight = 0.0;
 
-    fn overlap_factor(threshold: f32) -> f32 {
-        threshold / (1. + threshold)
+        while i < a.len() && j < b.len() {
+            let (a_i, w_i) = a.get(i).unwrap();
+            let b_j = b.get(j).unwrap();
+            match a_i.cmp(b_j) {
+                Ordering::Equal => {
+                    // Weights can exceed the weight 1 of the other side.
+                    common_weight += w_i.min(1.0);
+                    i += 1;
+                    j += 1;
+                }
+                Ordering::Less => {
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    j += 1;
+                }
+            }
+        }
+
+        // sum of max(w_a, w_b) = |b| + sum of w_a - sum of min(w_a, w_b),
+        // as max(w_a, 1) = w_a + 1 - min(w_a, 1)
+        let union_weight = b.len() as f32 + self.total_weight - common_weight;
+        if union_weight == 0.0 {
+            return None;
+        }
+        Some(1.0 - common_weight / union_weight)
     }
+}
 
-    fn length_bounds(base_len: usize, threshold: f32) -> RangeInclusive<usize> {
-        if threshold == 0.0 {
-            0..=usize::MAX
-        } else {
-            let base_len = base_len as f32;
-            let length_lower = (base_len * threshold).ceil() as usize;
-            let length_upper = (base_len / threshold).floor() as usize;
-            length_lower..=length_upper
+// Jaccard discounted for short sets, i.e., `1 - |a ∩ b| / (|a ∪ b| + alpha)`,
+// so that sets with few elements get more distinct and larger distances.
+// It is never less than the Jaccard distance, so the filters of Jaccard remain valid.
+pub struct SmoothedJaccard<'a, T> {
+    jaccard: Jaccard<'a, T>,
+    alpha: f32,
+    radius: f32,
+}
+
+impl<'a, T> SmoothedJaccard<'a, T>
+where
+    T: Element,
+{
+    pub fn new<S: AsRef<[T]>>(
+        base: &'a OrderedSet<T, S>,
+        radius: f32,
+        alpha: f32,
+        config: FilterConfig,
+    ) -> Self {
+        Self {
+            jaccard: Jaccard::new(base, radius, config),
+            alpha,
+            radius,
         }
     }
 
-    pub fn distance(&self, other: &OrderedSet<T>) -> Option<f32> {
-        let a = self.base;
+    pub fn distance<S: AsRef<[T]>>(&self, other: &OrderedSet<T, S>) -> Option<f32> {
+        let a = self.jaccard.base;
         let b = other;
-
         if a.is_empty() && b.is_empty() {
             return None;
         }
-        if a.is_empty() || b.is_empty() {
-            return Some(1.0);
+        let intersection = intersection_len(a, b);
+        let union = a.len() + b.len() - intersection;
+        Some(1.0 - intersection as f32 / (union as f32 + self.alpha))
+    }
+
+    pub fn evaluate<S: AsRef<[T]>>(&self, other: &OrderedSet<T, S>) -> Evaluation {
+        match self.jaccard.evaluate(other) {
+            Evaluation::Accepted(_) => {
+                let dist = self.distance(other).unwrap();
+                if dist <= self.radius {
+                    Evaluation::Accepted(dist)
+                } else {
+                    Evaluation::Verified
+                }
+            }
+            eval => eval,
         }
+    }
+}
+
+fn intersection_len<T, S>(a: &[T], b: &OrderedSet<T, S>) -> usize
+where
+    T: Element,
+    S: AsRef<[T]>,
+{
+    T::intersection_len(a, b.as_slice())
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
 
+// Elements of the sets of the metrics, whose intersections of u32 are dispatched
+// to the kernel of the machine. Sealed so that the specialization stays ours.
+pub trait Element: Ord + Copy + sealed::Sealed {
+    // Counts the common elements of two strictly sorted slices.
+    fn intersection_len(a: &[Self], b: &[Self]) -> usize {
         let mut i = 0;
         let mut j = 0;
         let mut intersection = 0;
-
         while i < a.len() && j < b.len() {
-            let a_i = a.get(i).unwrap();
-            let b_j = b.get(j).unwrap();
-            match a_i.cmp(b_j) {
+            match a[i].cmp(&b[j]) {
                 Ordering::Equal => {
                     intersection += 1;
                     i += 1;
@@ -115,41 +975,180 @@ where
                 }
             }
         }
+        intersection
+    }
+}
 
-        let union = a.len() + b.len() - intersection;
-        Some(1.0 - (intersection as f32) / (union as f32))
+impl sealed::Sealed for u32 {}
+
+impl Element for u32 {
+    fn intersection_len(a: &[Self], b: &[Self]) -> usize {
+        kernel::intersection_len(a, b)
+    }
+}
+
+macro_rules! impl_element {
+    ($($t:ty),*) => {
+        $(
+            impl sealed::Sealed for $t {}
+            impl Element for $t {}
+        )*
+    };
+}
+
+impl_element!(u8, u16, u64, usize, i8, i16, i32, i64, isize, char);
+
+// Jaccard over elements with global weights, e.g., IDF, i.e., `1 - w(a ∩ b) / w(a ∪ b)`.
+// `weights[e]` is the weight of element `e`, which must be positive.
+pub struct ElementWeightedJaccard<'a> {
+    base: &'a [u32],
+    weights: &'a [f32],
+    base_weight: f32,
+}
+
+impl<'a> ElementWeightedJaccard<'a> {
+    pub fn new<S: AsRef<[u32]>>(base: &'a OrderedSet<u32, S>, weights: &'a [f32]) -> Self {
+        let base_weight = base.iter().map(|&elem| weights[elem as usize]).sum();
+        Self {
+            base: base.as_slice(),
+            weights,
+            base_weight,
+        }
     }
 
-    pub fn evaluate(&self, other: &OrderedSet<T>) -> Evaluation {
+    pub fn distance<S: AsRef<[u32]>>(&self, other: &OrderedSet<u32, S>) -> Option<f32> {
         let a = self.base;
         let b = other;
 
-        if a.is_empty() && b.is_empty() {
-            return Evaluation::Undefined;
+        let mut i = 0;
+        let mut j = 0;
+        let mut common_weight = 0.0;
+        let mut other_weight = 0.0;
+
+        while i < a.len() && j < b.len() {
+            let a_i = *a.get(i).unwrap();
+            let b_j = *b.get(j).unwrap();
+            match a_i.cmp(&b_j) {
+                Ordering::Equal => {
+                    common_weight += self.weights[a_i as usize];
+                    other_weight += self.weights[b_j as usize];
+                    i += 1;
+                    j += 1;
+                }
+                Ordering::Less => {
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    other_weight += self.weights[b_j as usize];
+                    j += 1;
+                }
+            }
         }
+        other_weight += b.as_slice()[j..]
+            .iter()
+            .map(|&elem| self.weights[elem as usize])
+            .sum::<f32>();
 
-        // radius = 1.0
-        if self.overlap_factor == 0.0 {
-            let dist = self.distance(b).unwrap();
-            return Evaluation::Accepted(dist);
+        let union_weight = self.base_weight + other_weight - common_weight;
+        if union_weight <= 0.0 {
+            return None;
         }
+        Some(1.0 - common_weight / union_weight)
+    }
 
-        if a.is_empty() || b.is_empty() {
-            return Evaluation::Verified;
+    // Shortest prefix such that the weight of the rest is less than `threshold * w(set)`,
+    // which is a lower bound of `w(a ∩ b)` for any set similar to `set` by `threshold`.
+    // Two similar sets share an element in their prefixes under any global order.
+    pub fn prefix_len<S: AsRef<[u32]>>(
+        set: &OrderedSet<u32, S>,
+        weights: &[f32],
+        threshold: f32,
+    ) -> usize {
+        let total = set.iter().map(|&elem| weights[elem as usize]).sum::<f32>();
+        let min_overlap = threshold * total;
+        // Sums the suffix from the end to avoid cancellation.
+        let mut suffix = 0.0;
+        let mut pfx_len = set.len();
+        for (i, &elem) in set.as_slice().iter().enumerate().rev() {
+            suffix += weights[elem as usize];
+            if suffix >= min_overlap {
+                break;
+            }
+            pfx_len = i;
+        }
+        pfx_len
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainmentMode {
+    // Records containing the query, i.e., |q ∩ r| / |q| >= c.
+    Superset,
+    // Records contained in the query, i.e., |q ∩ r| / |r| >= c.
+    Subset,
+}
+
+// Asymmetric containment of the query in records or vice versa,
+// with the distance of `1 - containment`.
+pub struct Containment<'a, T> {
+    base: &'a OrderedSet<T>,
+    min_containment: f32,
+    mode: ContainmentMode,
+    config: FilterConfig,
+}
+
+impl<'a, T> Containment<'a, T>
+where
+    T: Ord + Copy,
+{
+    pub fn new(
+        base: &'a OrderedSet<T>,
+        min_containment: f32,
+        mode: ContainmentMode,
+        config: FilterConfig,
+    ) -> Result<Self> {
+        check_containment(min_containment)?;
+        Ok(Self {
+            base,
+            min_containment,
+            mode,
+            config,
+        })
+    }
+
+    // Minimum overlap for a set of `set_len` to be contained by `min_containment`.
+    // It is consistent with the acceptance test `overlap / set_len >= min_containment`.
+    pub fn min_overlap(set_len: usize, min_containment: f32) -> usize {
+        let mut overlap = (set_len as f32 * min_containment).ceil() as usize;
+        while overlap > 0 && (overlap - 1) as f32 / set_len as f32 >= min_containment {
+            overlap -= 1;
         }
+        overlap
+    }
+
+    // Prefix length of a set of `set_len` that must share an element with
+    // any set containing it by `min_containment`, which is zero above 1.
+    pub fn prefix_len(set_len: usize, min_containment: f32) -> usize {
+        (set_len + 1).saturating_sub(Self::min_overlap(set_len, min_containment).max(1))
+    }
 
-        let cfg = self.config;
+    pub fn evaluate<S: AsRef<[T]>>(&self, other: &OrderedSet<T, S>) -> Evaluation {
+        let (a, b) = (self.base, other);
+        // The set whose length normalizes the overlap.
+        let normalizer = match self.mode {
+            ContainmentMode::Superset => a.len(),
+            ContainmentMode::Subset => b.len(),
+        };
+        if normalizer == 0 {
+            return Evaluation::Undefined;
+        }
+        let overlap_threshold = Self::min_overlap(normalizer, self.min_containment);
 
         // 1) Length filter
-        // dbg!(&self.length_bounds, b.len());
-        if cfg.length && !self.length_bounds.contains(&b.len()) {
+        if self.config.length && a.len().min(b.len()) < overlap_threshold {
             return Evaluation::LengthFiltered;
         }
 
-        let total_len = (a.len() + b.len()) as f32;
-        let overlap_threshold = (self.overlap_factor * total_len).ceil() as usize;
-        // dbg!(self.overlap_factor, overlap_threshold);
-
         let mut i = 0;
         let mut j = 0;
         let mut intersection = 0;
@@ -170,11 +1169,10 @@ where
                     j += 1;
                 }
             }
-            // 2) Position filter
-            if cfg.position {
+            // 2) Position filter, equivalent to the prefix bound of `prefix_len`
+            if self.config.position {
                 let a_sfx_len = a.len() - i;
                 let b_sfx_len = b.len() - j;
-                // dbg!(intersection, a_sfx_len, b_sfx_len);
                 if intersection + a_sfx_len.min(b_sfx_len) < overlap_threshold {
                     return Evaluation::PositionFiltered;
                 }
@@ -184,10 +1182,8 @@ where
         if intersection < overlap_threshold {
             return Evaluation::Verified;
         }
-
-        let union = a.len() + b.len() - intersection;
-        let dist = 1.0 - (intersection as f32) / (union as f32);
-        Evaluation::Accepted(dist)
+        let containment = intersection as f32 / normalizer as f32;
+        Evaluation::Accepted(1.0 - containment)
     }
 }
 
@@ -197,20 +1193,195 @@ mod tests {
 
     use approx::assert_abs_diff_eq;
 
+    #[test]
+    fn test_filter_config_from_str() {
+        for config in FilterConfig::PRESETS {
+            let parsed = config.to_string().parse::<FilterConfig>().unwrap();
+            assert_eq!(
+                (parsed.length, parsed.position),
+                (config.length, config.position)
+            );
+        }
+        let config = "position, length".parse::<FilterConfig>().unwrap();
+        assert!(config.length && config.position);
+        assert!("suffix".parse::<FilterConfig>().is_err());
+        assert!("".parse::<FilterConfig>().is_err());
+    }
+
+    #[test]
+    fn test_filter_config_builder() {
+        let config = FilterConfig::NONE
+            .length(true)
+            .suffix_depth(2)
+            .count_threshold(3);
+        assert!(config.length && !config.position);
+        assert_eq!(config.suffix_depth, 2);
+        assert_eq!(config.prefix_extension, 0);
+        assert_eq!(config.count_threshold, Some(3));
+        assert_eq!(FilterConfig::default(), FilterConfig::NONE);
+
+        // The parameters can be omitted.
+        let config: FilterConfig =
+            serde_json::from_str(r#"{"length":true,"position":true}"#).unwrap();
+        assert_eq!(config, FilterConfig::ALL);
+    }
+
     #[test]
     fn test_jaccard() {
         let a = OrderedSet::<u32>::from_unsorted([1, 2, 3, 4, 5]);
         let b = OrderedSet::<u32>::from_unsorted([3, 4, 5, 6, 7]);
         let jaccard = Jaccard::new(&a, 1.0, FilterConfig::default());
         assert_abs_diff_eq!(jaccard.distance(&b).unwrap(), 1. - 3. / 7.);
+
+        // Elements other than u32 take the scalar merge, also in a frozen storage.
+        let a = OrderedSet::<u64>::from_unsorted([1, 2, 3, 4, 5]);
+        let b = OrderedSet::<u64>::from_unsorted([3, 4, 5, 6, 7]).freeze();
+        let jaccard = Jaccard::new(&a, 1.0, FilterConfig::default());
+        assert_abs_diff_eq!(jaccard.distance(&b).unwrap(), 1. - 3. / 7.);
     }
 
     #[test]
-    fn test_length_filter_1() {
-        let cfg = FilterConfig {
-            length: true,
-            position: false,
+    fn test_contrastive() {
+        let pos = OrderedSet::<u32>::from_unsorted([1, 2, 3, 4]);
+        let neg = OrderedSet::<u32>::from_unsorted([3, 4, 5]);
+        let other = OrderedSet::<u32>::from_unsorted([2, 3, 4]);
+        let contrastive = Contrastive::new(&pos, &neg, 0.5);
+        assert_abs_diff_eq!(
+            contrastive.distance(&other).unwrap(),
+            1. - (3. / 4. - 0.5 * 2. / 4.)
+        );
+        let empty = OrderedSet::<u32>::new();
+        let contrastive = Contrastive::new(&empty, &empty, 0.5);
+        assert_eq!(contrastive.distance(&empty), None);
+    }
+
+    #[test]
+    fn test_weighted_jaccard() {
+        let a = OrderedSet::<u32>::from_unsorted([1, 2, 3]);
+        let b = OrderedSet::<u32>::from_unsorted([3, 4]);
+        let c = OrderedSet::<u32>::from_unsorted([2, 3, 5]);
+        let query = WeightedSet::from_weighted_sets([(&a, 0.5), (&b, 1.0)]).unwrap();
+        let jaccard = WeightedJaccard::new(&query);
+        // min: 0.5 (2) + 1.0 (3), max: 0.5 (1) + 1.0 (2) + 1.0 (3) + 1.0 (4) + 1.0 (5)
+        assert_abs_diff_eq!(jaccard.distance(&c).unwrap(), 1. - 1.5 / 4.5);
+        assert_abs_diff_eq!(jaccard.distance(&OrderedSet::new()).unwrap(), 1.);
+
+        // min: 1.0 (3), max: 1.0 (2) + 2.0 (3) + 2.0 (4) + 1.0 (5)
+        let heavy = WeightedSet::from_weighted_sets([(&b, 2.0)]).unwrap();
+        let jaccard = WeightedJaccard::new(&heavy);
+        assert_abs_diff_eq!(jaccard.distance(&c).unwrap(), 1. - 1. / 6.);
+        assert_abs_diff_eq!(jaccard.distance(&b).unwrap(), 1. - 2. / 4.);
+
+        let empty = WeightedSet::<u32>::from_weighted_sets([]).unwrap();
+        let jaccard = WeightedJaccard::new(&empty);
+        assert_eq!(jaccard.distance(&OrderedSet::new()), None);
+    }
+
+    #[test]
+    fn test_filter_bounds() {
+        type J<'a> = Jaccard<'a, u32>;
+        assert_eq!(J::threshold(0.25), 0.75);
+        assert_eq!(J::threshold(-1.0), 1.0);
+        assert_eq!(J::overlap_factor(1.0), 0.5);
+        assert_eq!(J::length_bounds(4, 0.5), 2..=8);
+        assert_eq!(J::length_bounds(4, 0.0), 0..=usize::MAX);
+        // 5 * 0.5 / 1.5 = 1.67
+        assert_eq!(J::index_prefix_len(5, 0.5), 2);
+        // 5 * 0.5 = 2.5
+        assert_eq!(J::query_prefix_len(5, 0.5), 3);
+        assert_eq!(J::query_prefix_len(5, 1.0), 1);
+    }
+
+    #[test]
+    fn test_containment() {
+        let cfg = FilterConfig::new(true, true);
+        let q = OrderedSet::from_sorted([1, 2, 3, 4, 5]).unwrap();
+        let sup = Containment::new(&q, 0.6, ContainmentMode::Superset, cfg.clone()).unwrap();
+        let b = OrderedSet::from_sorted([1, 2, 3, 6, 7, 8, 9]).unwrap();
+        assert_eq!(sup.evaluate(&b), Evaluation::Accepted(1.0 - 3. / 5.));
+        let b = OrderedSet::from_sorted([1, 2, 6, 7, 8, 9]).unwrap();
+        assert_ne!(sup.evaluate(&b), Evaluation::Accepted(1.0 - 2. / 5.));
+        let b = OrderedSet::from_sorted([1, 2]).unwrap();
+        assert_eq!(sup.evaluate(&b), Evaluation::LengthFiltered);
+
+        let sub = Containment::new(&q, 0.6, ContainmentMode::Subset, cfg.clone()).unwrap();
+        let b = OrderedSet::from_sorted([1, 2]).unwrap();
+        assert_eq!(sub.evaluate(&b), Evaluation::Accepted(0.0));
+        let b = OrderedSet::from_sorted([1, 2, 6]).unwrap();
+        assert_eq!(sub.evaluate(&b), Evaluation::Accepted(1.0 - 2. / 3.));
+        let b = OrderedSet::from_sorted([1, 6, 7]).unwrap();
+        assert_ne!(sub.evaluate(&b), Evaluation::Accepted(1.0 - 1. / 3.));
+        assert_eq!(sub.evaluate(&OrderedSet::new()), Evaluation::Undefined);
+
+        // 0.6 * 5 = 3.0000002 in f32
+        assert_eq!(Containment::<u32>::min_overlap(5, 0.6), 3);
+        assert_eq!(Containment::<u32>::prefix_len(5, 0.6), 3);
+        assert_eq!(Containment::<u32>::prefix_len(5, 0.0), 5);
+        assert_eq!(Containment::<u32>::prefix_len(5, 1.5), 0);
+        assert!(Containment::new(&q, 1.5, ContainmentMode::Superset, cfg.clone()).is_err());
+        assert!(Containment::new(&q, f32::NAN, ContainmentMode::Subset, cfg).is_err());
+    }
+
+    #[test]
+    fn test_smoothed_jaccard() {
+        let cfg = FilterConfig::new(true, true);
+        let a = OrderedSet::from_sorted([1]).unwrap();
+        let b = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let c = OrderedSet::from_sorted([1, 2, 4]).unwrap();
+        let smoothed = SmoothedJaccard::new(&a, 1.0, 1.0, cfg.clone());
+        assert_eq!(smoothed.distance(&a), Some(1.0 - 1. / 2.));
+        let smoothed = SmoothedJaccard::new(&b, 1.0, 1.0, cfg.clone());
+        assert_eq!(smoothed.distance(&b), Some(1.0 - 3. / 4.));
+        assert_eq!(smoothed.distance(&c), Some(1.0 - 2. / 5.));
+
+        // Accepted by Jaccard (0.5) but not by the smoothed distance (0.6).
+        let smoothed = SmoothedJaccard::new(&b, 0.5, 1.0, cfg.clone());
+        assert_eq!(smoothed.evaluate(&c), Evaluation::Verified);
+        let smoothed = SmoothedJaccard::new(&b, 0.6, 1.0, cfg.clone());
+        assert_eq!(smoothed.evaluate(&c), Evaluation::Accepted(0.6));
+        // alpha = 0 is Jaccard.
+        let smoothed = SmoothedJaccard::new(&b, 0.5, 0.0, cfg.clone());
+        assert_eq!(smoothed.evaluate(&c), Evaluation::Accepted(0.5));
+    }
+
+    #[test]
+    fn test_filter_policy() {
+        let policy = FilterPolicy {
+            length_min_len: 2,
+            position_min_len: 4,
+            max_radius: 0.5,
         };
+        let cfg = policy.decide(1, 0.1);
+        assert!(!cfg.length && !cfg.position);
+        let cfg = policy.decide(3, 0.1);
+        assert!(cfg.length && !cfg.position);
+        let cfg = policy.decide(4, 0.5);
+        assert!(cfg.length && cfg.position);
+        let cfg = policy.decide(4, 0.6);
+        assert!(!cfg.length && !cfg.position);
+    }
+
+    #[test]
+    fn test_element_weighted_jaccard() {
+        let weights = [1.0, 2.0, 3.0, 4.0];
+        let a = OrderedSet::from_sorted([0, 1, 2]).unwrap();
+        let b = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let jaccard = ElementWeightedJaccard::new(&a, &weights);
+        assert_eq!(jaccard.distance(&b), Some(1.0 - 5. / 10.));
+        assert_eq!(jaccard.distance(&a), Some(0.0));
+        let empty = OrderedSet::new();
+        let jaccard = ElementWeightedJaccard::new(&empty, &weights);
+        assert_eq!(jaccard.distance(&empty), None);
+
+        // The suffix [3] weighs 4 < 0.5 * 10, but [2, 3] weighs 7.
+        assert_eq!(ElementWeightedJaccard::prefix_len(&b, &weights, 0.5), 2);
+        assert_eq!(ElementWeightedJaccard::prefix_len(&b, &weights, 1.0), 1);
+        assert_eq!(ElementWeightedJaccard::prefix_len(&b, &weights, 0.0), 3);
+    }
+
+    #[test]
+    fn test_length_filter_1() {
+        let cfg = FilterConfig::new(true, false);
 
         // J(a,b) = 1 - 4/6 = 0.333...
         let a = OrderedSet::<u32>::from_unsorted([1, 2, 3, 4, 5]);
@@ -218,23 +1389,20 @@ mod tests {
 
         // length_bounds = 4..=7
         assert_eq!(
-            Jaccard::new(&a, 0.33, cfg).evaluate(&b),
+            Jaccard::new(&a, 0.33, cfg.clone()).evaluate(&b),
             Evaluation::Verified
         );
 
         // length_bounds = 4..=7
         assert_eq!(
-            Jaccard::new(&a, 0.34, cfg).evaluate(&b),
+            Jaccard::new(&a, 0.34, cfg.clone()).evaluate(&b),
             Evaluation::Accepted(1. / 3.)
         );
     }
 
     #[test]
     fn test_length_filter_2() {
-        let cfg = FilterConfig {
-            length: true,
-            position: false,
-        };
+        let cfg = FilterConfig::new(true, false);
 
         // J(a,b) = 1 - 2/3 = 0.333...
         let a = OrderedSet::<u32>::from_unsorted([1, 2]);
@@ -242,23 +1410,20 @@ mod tests {
 
         // length_bounds = 2..=2
         assert_eq!(
-            Jaccard::new(&a, 0.33, cfg).evaluate(&b),
+            Jaccard::new(&a, 0.33, cfg.clone()).evaluate(&b),
             Evaluation::LengthFiltered
         );
 
         // length_bounds = 2..=3
         assert_eq!(
-            Jaccard::new(&a, 0.34, cfg).evaluate(&b),
+            Jaccard::new(&a, 0.34, cfg.clone()).evaluate(&b),
             Evaluation::Accepted(1. / 3.)
         );
     }
 
     #[test]
     fn test_position_filter_1() {
-        let cfg = FilterConfig {
-            length: false,
-            position: true,
-        };
+        let cfg = FilterConfig::new(false, true);
 
         // J(a,b) = 1 - 4/6 = 0.333...
         let a = OrderedSet::<u32>::from_unsorted([1, 2, 3, 4, 5]);
@@ -269,7 +1434,7 @@ mod tests {
         // a_sfx_len = 4
         // b_sfx_len = 5
         assert_eq!(
-            Jaccard::new(&a, 0.33, cfg).evaluate(&b),
+            Jaccard::new(&a, 0.33, cfg.clone()).evaluate(&b),
             Evaluation::PositionFiltered
         );
 
@@ -278,17 +1443,14 @@ mod tests {
         // a_sfx_len = 1
         // b_sfx_len = 2
         assert_eq!(
-            Jaccard::new(&a, 0.34, cfg).evaluate(&b),
+            Jaccard::new(&a, 0.34, cfg.clone()).evaluate(&b),
             Evaluation::Accepted(1. / 3.)
         );
     }
 
     #[test]
     fn test_position_filter_2() {
-        let cfg = FilterConfig {
-            length: false,
-            position: true,
-        };
+        let cfg = FilterConfig::new(false, true);
 
         // J(a,b) = 1 - 4/6 = 0.333...
         let a = OrderedSet::<u32>::from_unsorted([2, 3, 4, 5, 6]);
@@ -299,7 +1461,7 @@ mod tests {
         // a_sfx_len = 0
         // b_sfx_len = 1
         assert_eq!(
-            Jaccard::new(&a, 0.33, cfg).evaluate(&b),
+            Jaccard::new(&a, 0.33, cfg.clone()).evaluate(&b),
             Evaluation::PositionFiltered
         );
 
@@ -308,17 +1470,14 @@ mod tests {
         // a_sfx_len = 1
         // b_sfx_len = 1
         assert_eq!(
-            Jaccard::new(&a, 0.34, cfg).evaluate(&b),
+            Jaccard::new(&a, 0.34, cfg.clone()).evaluate(&b),
             Evaluation::Accepted(1. / 3.)
         );
     }
 
     #[test]
     fn test_position_filter_3() {
-        let cfg = FilterConfig {
-            length: false,
-            position: true,
-        };
+        let cfg = FilterConfig::new(false, true);
 
         // J(a,b) = 1 - 1/3 = 0.666...
         let a = OrderedSet::<u32>::from_unsorted([1]);
@@ -329,7 +1488,7 @@ mod tests {
         // a_sfx_len = 1
         // b_sfx_len = 3
         assert_eq!(
-            Jaccard::new(&a, 0.66, cfg).evaluate(&b),
+            Jaccard::new(&a, 0.66, cfg.clone()).evaluate(&b),
             Evaluation::PositionFiltered
         );
 
@@ -338,67 +1497,297 @@ mod tests {
         // a_sfx_len = 1
         // b_sfx_len = 3
         assert_eq!(
-            Jaccard::new(&a, 0.67, cfg).evaluate(&b),
+            Jaccard::new(&a, 0.67, cfg.clone()).evaluate(&b),
             Evaluation::Accepted(2. / 3.)
         );
     }
 
     #[test]
     fn test_identical() {
-        let cfg = FilterConfig {
-            length: true,
-            position: true,
-        };
+        let cfg = FilterConfig::new(true, true);
 
         let a = OrderedSet::<u32>::from_unsorted([1, 2, 3, 4, 5]);
         let b = OrderedSet::<u32>::from_unsorted([1, 2, 3, 4, 5]);
 
         assert_eq!(
-            Jaccard::new(&a, 0.00, cfg).evaluate(&b),
+            Jaccard::new(&a, 0.00, cfg.clone()).evaluate(&b),
             Evaluation::Accepted(0.00)
         );
         assert_eq!(
-            Jaccard::new(&a, 1.00, cfg).evaluate(&b),
+            Jaccard::new(&a, 1.00, cfg.clone()).evaluate(&b),
             Evaluation::Accepted(0.00)
         );
     }
 
     #[test]
     fn test_one_side_empty() {
-        let cfg = FilterConfig {
-            length: true,
-            position: true,
-        };
+        let cfg = FilterConfig::new(true, true);
 
         let a = OrderedSet::<u32>::from_unsorted([1, 2, 3, 4, 5]);
         let b = OrderedSet::<u32>::from_unsorted([]);
 
         assert_eq!(
-            Jaccard::new(&a, 0.00, cfg).evaluate(&b),
+            Jaccard::new(&a, 0.00, cfg.clone()).evaluate(&b),
             Evaluation::Verified
         );
         assert_eq!(
-            Jaccard::new(&a, 1.00, cfg).evaluate(&b),
+            Jaccard::new(&a, 1.00, cfg.clone()).evaluate(&b),
             Evaluation::Accepted(1.00)
         );
     }
 
+    #[test]
+    fn test_evaluate_sweep() {
+        let sets = [
+            OrderedSet::<u32>::from_unsorted([]),
+            OrderedSet::<u32>::from_unsorted([1]),
+            OrderedSet::<u32>::from_unsorted([1, 2]),
+            OrderedSet::<u32>::from_unsorted([1, 2, 3]),
+            OrderedSet::<u32>::from_unsorted([1, 2, 3, 4, 5]),
+            OrderedSet::<u32>::from_unsorted([2, 3, 4, 5, 6]),
+            OrderedSet::<u32>::from_unsorted([2, 3, 4, 5, 7]),
+        ];
+        let configs = [
+            FilterConfig::new(false, false),
+            FilterConfig::new(true, false),
+            FilterConfig::new(false, true),
+            FilterConfig::new(true, true),
+        ];
+        for radius in [0.0, 0.33, 0.34, 0.5, 0.66, 0.67, 1.0] {
+            for a in &sets {
+                for b in &sets {
+                    let sweep = Jaccard::new(a, radius, FilterConfig::default()).evaluate_sweep(b);
+                    for cfg in &configs {
+                        assert_eq!(
+                            sweep.get(cfg),
+                            Jaccard::new(a, radius, cfg.clone()).evaluate(b)
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_evaluate_batch() {
+        let sets = [
+            OrderedSet::<u32>::from_unsorted([]),
+            OrderedSet::<u32>::from_unsorted([1]),
+            OrderedSet::<u32>::from_unsorted([1, 2, 3]),
+            OrderedSet::<u32>::from_unsorted([1, 2, 3, 4, 5]),
+            OrderedSet::<u32>::from_unsorted([2, 3, 4, 5, 6]),
+            OrderedSet::<u32>::from_unsorted([2, 3, 4, 5, 7]),
+            OrderedSet::<u32>::from_unsorted([1, 3, 5, 7, 9, 11]),
+            OrderedSet::<u32>::from_unsorted([6, 7, 8]),
+        ];
+        // Each preset takes its own merge, with or without the position filter.
+        for (cfg, radius) in FilterConfig::PRESETS
+            .iter()
+            .flat_map(|cfg| [0.0, 0.33, 0.34, 0.5, 0.67, 1.0].map(|radius| (cfg, radius)))
+        {
+            for a in &sets {
+                let jaccard = Jaccard::new(a, radius, cfg.clone());
+                let batch = [&sets[2], &sets[3], &sets[4], &sets[5]];
+                let evals = jaccard.evaluate_batch(batch);
+                for (b, eval) in batch.iter().zip(evals) {
+                    assert_eq!(eval, jaccard.evaluate(b));
+                }
+                let batch = [&sets[0], &sets[1], &sets[6], &sets[7]];
+                let evals = jaccard.evaluate_batch(batch);
+                for (b, eval) in batch.iter().zip(evals) {
+                    assert_eq!(eval, jaccard.evaluate(b));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_evaluate_with() {
+        let sets = [
+            OrderedSet::<u32>::from_unsorted([]),
+            OrderedSet::<u32>::from_unsorted([1]),
+            OrderedSet::<u32>::from_unsorted([1, 2, 3]),
+            OrderedSet::<u32>::from_unsorted([1, 2, 3, 4, 5]),
+            OrderedSet::<u32>::from_unsorted([2, 3, 4, 5, 7]),
+            OrderedSet::<u32>::from_unsorted([1, 3, 5, 7, 9, 11]),
+            OrderedSet::<u32>::from_unsorted([6, 7, 8]),
+        ];
+        for radius in [0.0, 0.33, 0.34, 0.5, 0.67, 1.0] {
+            for a in &sets {
+                for b in &sets {
+                    let expected =
+                        |cfg| Jaccard::new(a, radius, cfg).evaluate_detailed(b).evaluation;
+                    // The specialization ignores the filters of the config.
+                    let jaccard = Jaccard::new(a, radius, FilterConfig::NONE);
+                    assert_eq!(
+                        jaccard.evaluate_with::<_, false, false>(b),
+                        expected(FilterConfig::NONE)
+                    );
+                    assert_eq!(
+                        jaccard.evaluate_with::<_, true, false>(b),
+                        expected(FilterConfig::LENGTH_ONLY)
+                    );
+                    assert_eq!(
+                        jaccard.evaluate_with::<_, false, true>(b),
+                        expected(FilterConfig::POSITION_ONLY)
+                    );
+                    assert_eq!(
+                        jaccard.evaluate_with::<_, true, true>(b),
+                        expected(FilterConfig::ALL)
+                    );
+                    for cfg in FilterConfig::PRESETS {
+                        let jaccard = Jaccard::new(a, radius, cfg.clone());
+                        assert_eq!(jaccard.evaluator()(&jaccard, b), expected(cfg));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_exact_overlap_threshold() {
+        // Thresholds p/q exact in f32, compared with the integer ceil of p * n / (p + q).
+        for (p, q) in [(1usize, 2usize), (1, 4), (3, 4), (9, 16), (1, 1), (0, 1)] {
+            let threshold = p as f32 / q as f32;
+            for n in 0..1000 {
+                let expected = (p * n).div_ceil(p + q);
+                assert_eq!(
+                    exact_overlap_threshold(threshold, n),
+                    expected,
+                    "{p}/{q}, {n}"
+                );
+            }
+        }
+
+        // |a| + |b| = 75 needs 27 = 0.36 * 75 at t = 9/16, while the float ceil demands 28.
+        let a = OrderedSet::from_unsorted(0..37);
+        let b = OrderedSet::from_unsorted(10..48);
+        let radius = 1. - 9. / 16.;
+        for cfg in FilterConfig::PRESETS {
+            let jaccard = Jaccard::new(&a, radius, cfg.clone());
+            assert!(!matches!(jaccard.evaluate(&b), Evaluation::Accepted(_)));
+            let jaccard = Jaccard::new(&a, radius, cfg.exact_overlap(true));
+            assert_eq!(jaccard.evaluate(&b), Evaluation::Accepted(radius));
+            assert_eq!(
+                jaccard.evaluate_sweep(&b).get(&FilterConfig::ALL),
+                Evaluation::Accepted(radius)
+            );
+        }
+    }
+
+    #[test]
+    fn test_suffix_hamming_bound() {
+        let subset = |mask: u32| (0..8).filter(|i| mask >> i & 1 == 1).collect::<Vec<u32>>();
+        for x_mask in 0..256u32 {
+            for y_mask in 0..256u32 {
+                let (x, y) = (subset(x_mask), subset(y_mask));
+                let hamming = (x_mask ^ y_mask).count_ones() as usize;
+                for depth in 0..3 {
+                    for max_hamming in [0, 2, 4] {
+                        assert!(suffix_hamming_bound(&x, &y, max_hamming, depth) <= hamming);
+                    }
+                }
+                // Deep enough to split down to the empty sets.
+                assert_eq!(suffix_hamming_bound(&x, &y, 16, 8), hamming);
+            }
+        }
+        // The sets of the same length are told apart by the split.
+        assert_eq!(suffix_hamming_bound(&[1, 2, 3, 4], &[5, 6, 7, 8], 2, 1), 4);
+    }
+
+    #[test]
+    fn test_rational_threshold() {
+        assert!(RationalThreshold::from_radius(1, 0).is_err());
+        assert!(RationalThreshold::from_radius(3, 2).is_err());
+
+        // Radius 5/9, i.e., the similarity threshold 4/9, which is not exact in floats.
+        let rational = RationalThreshold::from_radius(5, 9).unwrap();
+        for n in 0..200usize {
+            let bounds = rational.length_bounds(n);
+            for m in 0..500usize {
+                // Lengths m are within the threshold iff min/max >= 4/9.
+                let within = 9 * n.min(m) >= 4 * n.max(m);
+                assert_eq!(bounds.contains(&m), within, "{n}, {m}");
+            }
+            let o = rational.overlap_threshold(n);
+            assert!(9 * o >= 4 * (n - o));
+            assert!(o == 0 || 9 * (o - 1) < 4 * (n - o + 1));
+        }
+
+        // {0..9} and {0..4} meet the threshold 4/9 exactly.
+        let a = OrderedSet::from_unsorted(0..9u32);
+        let b = OrderedSet::from_unsorted(0..4u32);
+        for cfg in FilterConfig::PRESETS {
+            let jaccard = Jaccard::with_rational(&a, rational, cfg.clone());
+            assert!(matches!(jaccard.evaluate(&b), Evaluation::Accepted(_)));
+            let rational = RationalThreshold::from_radius(4, 9).unwrap();
+            let jaccard = Jaccard::with_rational(&a, rational, cfg);
+            assert!(!matches!(jaccard.evaluate(&b), Evaluation::Accepted(_)));
+        }
+        let all = RationalThreshold::from_radius(1, 1).unwrap();
+        let jaccard = Jaccard::with_rational(&a, all, FilterConfig::ALL);
+        assert_eq!(
+            jaccard.evaluate(&OrderedSet::from_unsorted([20])),
+            Evaluation::Accepted(1.0)
+        );
+    }
+
+    #[test]
+    fn test_evaluate_detailed() {
+        let sets = [
+            OrderedSet::<u32>::from_unsorted([]),
+            OrderedSet::<u32>::from_unsorted([1]),
+            OrderedSet::<u32>::from_unsorted([1, 2, 3]),
+            OrderedSet::<u32>::from_unsorted([1, 2, 3, 4, 5]),
+            OrderedSet::<u32>::from_unsorted([2, 3, 4, 5, 6]),
+            OrderedSet::<u32>::from_unsorted([1, 3, 5, 7, 9, 11]),
+            OrderedSet::<u32>::from_unsorted([6, 7, 8]),
+        ];
+        for cfg in FilterConfig::PRESETS {
+            for radius in [0.0, 0.33, 0.5, 0.67, 1.0] {
+                for a in &sets {
+                    let jaccard = Jaccard::new(a, radius, cfg.clone());
+                    for b in &sets {
+                        let detail = jaccard.evaluate_detailed(b);
+                        assert_eq!(detail.evaluation, jaccard.evaluate(b));
+                        assert!(detail.intersection <= intersection_len(a.as_slice(), b));
+                    }
+                }
+            }
+        }
+
+        // {1, 2, 3} vs {6, 7, 8} needs an overlap of 2, which fails after one step.
+        let jaccard = Jaccard::new(&sets[2], 0.5, FilterConfig::ALL);
+        let detail = jaccard.evaluate_detailed(&sets[6]);
+        assert_eq!(
+            detail,
+            EvaluationDetail {
+                evaluation: Evaluation::PositionFiltered,
+                base_pos: 2,
+                other_pos: 0,
+                intersection: 0,
+                overlap_threshold: Some(2),
+            }
+        );
+        let jaccard = Jaccard::new(&sets[2], 0.5, FilterConfig::LENGTH_ONLY);
+        let detail = jaccard.evaluate_detailed(&sets[6]);
+        assert_eq!(detail.evaluation, Evaluation::Verified);
+        assert_eq!((detail.base_pos, detail.other_pos), (3, 0));
+    }
+
     #[test]
     fn test_undifined() {
-        let cfg = FilterConfig {
-            length: true,
-            position: true,
-        };
+        let cfg = FilterConfig::new(true, true);
 
         let a = OrderedSet::<u32>::from_unsorted([]);
         let b = OrderedSet::<u32>::from_unsorted([]);
 
         assert_eq!(
-            Jaccard::new(&a, 0.00, cfg).evaluate(&b),
+            Jaccard::new(&a, 0.00, cfg.clone()).evaluate(&b),
             Evaluation::Undefined
         );
         assert_eq!(
-            Jaccard::new(&a, 1.00, cfg).evaluate(&b),
+            Jaccard::new(&a, 1.00, cfg.clone()).evaluate(&b),
             Evaluation::Undefined
         );
     }
@@ -0,0 +1,430 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use hashbrown::HashMap;
+
+use crate::{id_offsets, Mapping, OrderedSet, Record};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverlapAnswer {
+    pub id: u32,
+    pub overlap: usize,
+}
+
+// Merges the posting lists of the query elements into the records in at least T of them
+// (Li et al., ICDE 2008), e.g., to measure which is the fastest on a dataset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergeStrategy {
+    // Counts the postings of the rarest |q| - T + 1 elements in a hash map,
+    // and then the rest only for the counted records.
+    #[default]
+    ScanCount,
+    // Merges the lists but the T - 1 longest ones in a heap,
+    // and binary-searches the merged records in the longest ones.
+    MergeOpt,
+    // Pops the T - 1 smallest heads of the heap at once if they are not enough
+    // for the T-th, skipping their lists to it by binary searches.
+    MergeSkip,
+    // MergeOpt with the fewer longest lists tuned by their length, and MergeSkip on the rest.
+    DivideSkip,
+}
+
+// Coefficient of DivideSkip deciding the number of the longest lists, as in the paper.
+const DIVIDE_SKIP_MU: f64 = 0.0085;
+
+// Searches records sharing at least T elements with the query, indexing all the elements.
+pub struct OverlapIndex {
+    mapping: Arc<Mapping>,
+    records: Vec<Record<u32>>,
+    // Offsets of the records per element, in the ascending order.
+    index: HashMap<u32, Vec<u32>>,
+    strategy: MergeStrategy,
+}
+
+impl OverlapIndex {
+    pub fn from_records(records: &[Record<u32>], universe: u32) -> Result<Self> {
+        let mapping = Mapping::from_records(records, universe)?;
+        let records = records
+            .iter()
+            .map(|record| Record {
+                id: record.id,
+                set: mapping.apply(&record.set),
+            })
+            .collect::<Vec<_>>();
+        id_offsets(&records)?;
+        let mut index = HashMap::new();
+        for (i, record) in records.iter().enumerate() {
+            for &elem in record.set.iter() {
+                index.entry(elem).or_insert_with(Vec::new).push(i as u32);
+            }
+        }
+        Ok(Self {
+            mapping: Arc::new(mapping),
+            records,
+            index,
+            strategy: MergeStrategy::default(),
+        })
+    }
+
+    pub fn merge_strategy(mut self, strategy: MergeStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn mapping(&self) -> &Arc<Mapping> {
+        &self.mapping
+    }
+
+    // Returns the records sharing at least `t` elements with the query,
+    // in the descending order of the overlap.
+    pub fn overlap_query(&self, query: &OrderedSet<u32>, t: usize) -> Result<Vec<OverlapAnswer>> {
+        if t == 0 {
+            return Err(anyhow!("The overlap threshold must be positive."));
+        }
+        let query = self.mapping.apply(query);
+        if query.len() < t {
+            return Ok(vec![]);
+        }
+        let counts = match self.strategy {
+            MergeStrategy::ScanCount => self.scan_count(&query, t),
+            strategy => merge_lists(&self.sorted_lists(&query), t, strategy),
+        };
+        Ok(self.answers(counts, t))
+    }
+
+    fn scan_count(&self, query: &OrderedSet<u32>, t: usize) -> Vec<(u32, usize)> {
+        // Any answer contains one of the first |q| - t + 1 (rarest) elements,
+        // so only they generate candidates and the rest only count them.
+        let pfx_len = query.len() - t + 1;
+        let mut counts = HashMap::new();
+        for (i, elem) in query.iter().enumerate() {
+            let Some(list) = self.index.get(elem) else {
+                continue;
+            };
+            for &idx in list {
+                if i < pfx_len {
+                    // Count filter: shorter records cannot share t elements.
+                    if self.records[idx as usize].set.len() >= t {
+                        *counts.entry(idx).or_insert(0) += 1;
+                    }
+                } else if let Some(count) = counts.get_mut(&idx) {
+                    *count += 1;
+                }
+            }
+        }
+        counts.into_iter().collect()
+    }
+
+    // Returns the counts reaching `t` in the descending order of the overlap.
+    fn answers(&self, counts: Vec<(u32, usize)>, t: usize) -> Vec<OverlapAnswer> {
+        let mut answers = counts
+            .into_iter()
+            .filter(|&(_, count)| count >= t)
+            .map(|(idx, overlap)| OverlapAnswer {
+                id: self.records[idx as usize].id,
+                overlap,
+            })
+            .collect::<Vec<_>>();
+        answers.sort_unstable_by(|a, b| b.overlap.cmp(&a.overlap).then(a.id.cmp(&b.id)));
+        answers
+    }
+
+    // Same as `overlap_query` but with CPMerge (Okazaki and Tsujii, COLING 2010),
+    // which does not depend on the global frequencies of the mapping.
+    pub fn overlap_query_cpmerge(
+        &self,
+        query: &OrderedSet<u32>,
+        t: usize,
+    ) -> Result<Vec<OverlapAnswer>> {
+        if t == 0 {
+            return Err(anyhow!("The overlap threshold must be positive."));
+        }
+        let lists = self.sorted_lists(&self.mapping.apply(query));
+        Ok(self.cpmerge(&lists, t))
+    }
+
+    // Returns the `k` records sharing the most elements with the query, ties broken by id.
+    // CPMerge is repeated with the threshold lowered from |q| until `k` records reach it,
+    // which are exactly the top-k since every record with a larger overlap is also found.
+    pub fn topk_query(&self, query: &OrderedSet<u32>, k: usize) -> Result<Vec<OverlapAnswer>> {
+        if k == 0 {
+            return Err(anyhow!("k must be positive."));
+        }
+        let lists = self.sorted_lists(&self.mapping.apply(query));
+        let mut answers = vec![];
+        for t in (1..=lists.len()).rev() {
+            answers = self.cpmerge(&lists, t);
+            if answers.len() >= k {
+                break;
+            }
+        }
+        answers.truncate(k);
+        Ok(answers)
+    }
+
+    // Posting lists of the query elements from the shortest.
+    fn sorted_lists(&self, query: &OrderedSet<u32>) -> Vec<&[u32]> {
+        let mut lists = query
+            .iter()
+            .map(|elem| self.index.get(elem).map_or(&[][..], |list| list.as_slice()))
+            .collect::<Vec<_>>();
+        lists.sort_by_key(|list| list.len());
+        lists
+    }
+
+    // Any answer appears in one of the |q| - t + 1 shortest lists, so only they are merged
+    // into the candidates, whose counts are completed by binary searches on the longer lists.
+    // Candidates are dropped as soon as the remaining lists cannot make up the count.
+    fn cpmerge(&self, lists: &[&[u32]], t: usize) -> Vec<OverlapAnswer> {
+        if lists.len() < t {
+            return vec![];
+        }
+        let n_merged = lists.len() - t + 1;
+        let mut counts = HashMap::new();
+        for list in &lists[..n_merged] {
+            for &idx in list.iter() {
+                // Count filter: shorter records cannot share t elements.
+                if self.records[idx as usize].set.len() >= t {
+                    *counts.entry(idx).or_insert(0) += 1;
+                }
+            }
+        }
+        let mut candidates = counts.into_iter().collect::<Vec<_>>();
+        candidates.sort_unstable_by_key(|&(idx, _)| idx);
+
+        for (i, list) in lists.iter().enumerate().skip(n_merged) {
+            // Both are sorted by offset, so the search resumes from the last position.
+            let mut pos = 0;
+            let n_rest = lists.len() - i - 1;
+            candidates.retain_mut(|(idx, count)| {
+                pos += list[pos..].partition_point(|&other| other < *idx);
+                if list.get(pos) == Some(idx) {
+                    *count += 1;
+                }
+                *count + n_rest >= t
+            });
+        }
+
+        self.answers(candidates, t)
+    }
+}
+
+// Merges the posting lists from the shortest into the records in at least `t` of them
+// with their counts by `strategy`, e.g., for the candidates of `InvertedIndex`.
+pub(crate) fn merge_lists(
+    lists: &[&[u32]],
+    t: usize,
+    strategy: MergeStrategy,
+) -> Vec<(u32, usize)> {
+    let mut counts = match strategy {
+        MergeStrategy::ScanCount => scan_count(lists, t),
+        MergeStrategy::MergeOpt => divide_skip(lists, t, t - 1),
+        MergeStrategy::MergeSkip => merge_skip(lists, t),
+        MergeStrategy::DivideSkip => {
+            let longest = lists.last().map_or(0, |list| list.len());
+            let n_long = t as f64 / (DIVIDE_SKIP_MU * (longest as f64).log2().max(0.0) + 1.0);
+            divide_skip(lists, t, (n_long as usize).min(t - 1))
+        }
+    };
+    counts.retain(|&(_, count)| count >= t);
+    counts
+}
+
+// Same as `OverlapIndex::scan_count` but without the count filter by the record lengths.
+fn scan_count(lists: &[&[u32]], t: usize) -> Vec<(u32, usize)> {
+    if lists.len() < t {
+        return vec![];
+    }
+    let pfx_len = lists.len() - t + 1;
+    let mut counts = HashMap::new();
+    for (i, list) in lists.iter().enumerate() {
+        for &idx in list.iter() {
+            if i < pfx_len {
+                *counts.entry(idx).or_insert(0) += 1;
+            } else if let Some(count) = counts.get_mut(&idx) {
+                *count += 1;
+            }
+        }
+    }
+    counts.into_iter().collect()
+}
+
+// Returns the offsets in at least `t` of the lists with their counts, in the ascending order.
+fn merge_skip(lists: &[&[u32]], t: usize) -> Vec<(u32, usize)> {
+    let mut counts = vec![];
+    let mut pos = vec![0; lists.len()];
+    let mut heap = lists
+        .iter()
+        .enumerate()
+        .filter_map(|(k, list)| list.first().map(|&idx| Reverse((idx, k))))
+        .collect::<BinaryHeap<_>>();
+    let mut popped = vec![];
+    while let Some(&Reverse((top, _))) = heap.peek() {
+        popped.clear();
+        while let Some(&Reverse((idx, k))) = heap.peek() {
+            if idx != top {
+                break;
+            }
+            heap.pop();
+            popped.push(k);
+        }
+        if popped.len() >= t {
+            counts.push((top, popped.len()));
+            for &k in &popped {
+                pos[k] += 1;
+            }
+        } else {
+            // The offsets before the t-th smallest head are in at most t - 1 lists.
+            while popped.len() < t - 1 {
+                let Some(Reverse((_, k))) = heap.pop() else {
+                    break;
+                };
+                popped.push(k);
+            }
+            let Some(&Reverse((next, _))) = heap.peek() else {
+                // Fewer than t lists remain.
+                break;
+            };
+            for &k in &popped {
+                pos[k] += lists[k][pos[k]..].partition_point(|&idx| idx < next);
+            }
+        }
+        for &k in &popped {
+            if let Some(&idx) = lists[k].get(pos[k]) {
+                heap.push(Reverse((idx, k)));
+            }
+        }
+    }
+    counts
+}
+
+// Merges the lists but the `n_long` longest ones by `merge_skip` with the threshold lowered
+// by `n_long`, and completes the counts by binary searches on the longest ones.
+// The lists are sorted by length, and `n_long` must be less than `t`.
+fn divide_skip(lists: &[&[u32]], t: usize, n_long: usize) -> Vec<(u32, usize)> {
+    if lists.len() < t {
+        return vec![];
+    }
+    let (short, long) = lists.split_at(lists.len() - n_long);
+    let mut counts = merge_skip(short, t - n_long);
+    for list in long {
+        // Both are sorted by offset, so the search resumes from the last position.
+        let mut pos = 0;
+        for (idx, count) in counts.iter_mut() {
+            pos += list[pos..].partition_point(|&other| other < *idx);
+            if list.get(pos) == Some(idx) {
+                *count += 1;
+            }
+        }
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng};
+    use rand_xoshiro::Xoshiro256PlusPlus;
+
+    #[test]
+    fn test_overlap_query() {
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(5);
+        let records = (0..100)
+            .map(|id| {
+                let n = rng.gen_range(0..10);
+                let set = (0..n).map(|_| rng.gen_range(0..30)).collect::<Vec<_>>();
+                Record {
+                    id,
+                    set: OrderedSet::from_unsorted(set),
+                }
+            })
+            .collect::<Vec<_>>();
+        let index = OverlapIndex::from_records(&records, 30).unwrap();
+        let merged = [
+            MergeStrategy::MergeOpt,
+            MergeStrategy::MergeSkip,
+            MergeStrategy::DivideSkip,
+        ]
+        .map(|strategy| {
+            let index = OverlapIndex::from_records(&records, 30).unwrap();
+            (strategy, index.merge_strategy(strategy))
+        });
+        for _ in 0..20 {
+            let query = (0..8).map(|_| rng.gen_range(0..30)).collect::<Vec<_>>();
+            let query = OrderedSet::from_unsorted(query);
+            for t in 1..=4 {
+                let mut expected = records
+                    .iter()
+                    .map(|record| OverlapAnswer {
+                        id: record.id,
+                        overlap: record
+                            .set
+                            .iter()
+                            .filter(|e| query.as_slice().binary_search(e).is_ok())
+                            .count(),
+                    })
+                    .filter(|ans| ans.overlap >= t)
+                    .collect::<Vec<_>>();
+                expected.sort_unstable_by(|a, b| b.overlap.cmp(&a.overlap).then(a.id.cmp(&b.id)));
+                assert_eq!(index.overlap_query(&query, t).unwrap(), expected);
+                assert_eq!(index.overlap_query_cpmerge(&query, t).unwrap(), expected);
+                for (strategy, index) in &merged {
+                    assert_eq!(
+                        index.overlap_query(&query, t).unwrap(),
+                        expected,
+                        "{strategy:?}, t={t}"
+                    );
+                }
+            }
+        }
+        let query = OrderedSet::from_sorted([1]).unwrap();
+        assert!(index.overlap_query(&query, 0).is_err());
+        assert!(index.overlap_query_cpmerge(&query, 0).is_err());
+    }
+
+    #[test]
+    fn test_topk_query() {
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(7);
+        // A few frequent elements make the longest lists.
+        let records = (0..200)
+            .map(|id| {
+                let n = rng.gen_range(0..12);
+                let set = (0..n)
+                    .map(|_| rng.gen_range(0..50u32).min(rng.gen_range(0..50)))
+                    .collect::<Vec<_>>();
+                Record {
+                    id,
+                    set: OrderedSet::from_unsorted(set),
+                }
+            })
+            .collect::<Vec<_>>();
+        let index = OverlapIndex::from_records(&records, 50).unwrap();
+        for _ in 0..20 {
+            let query = (0..10).map(|_| rng.gen_range(0..50)).collect::<Vec<_>>();
+            let query = OrderedSet::from_unsorted(query);
+            let mut expected = records
+                .iter()
+                .map(|record| OverlapAnswer {
+                    id: record.id,
+                    overlap: record
+                        .set
+                        .iter()
+                        .filter(|e| query.as_slice().binary_search(e).is_ok())
+                        .count(),
+                })
+                .filter(|ans| ans.overlap > 0)
+                .collect::<Vec<_>>();
+            expected.sort_unstable_by(|a, b| b.overlap.cmp(&a.overlap).then(a.id.cmp(&b.id)));
+            for k in [1, 3, 10, 1000] {
+                let expected = &expected[..k.min(expected.len())];
+                assert_eq!(index.topk_query(&query, k).unwrap(), expected, "k={k}");
+            }
+        }
+        let query = OrderedSet::from_sorted([1]).unwrap();
+        assert!(index.topk_query(&query, 0).is_err());
+    }
+}
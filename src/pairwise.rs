@@ -0,0 +1,160 @@
+use std::cmp::Ordering;
+
+use anyhow::Result;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+
+use crate::metric::{Evaluation, FilterConfig, Jaccard};
+use crate::{check_radius, install, Record};
+
+const FILTER_CONFIG: FilterConfig = FilterConfig::new(true, true);
+
+// Upper triangle of a distance matrix in the row-major order of
+// `scipy.spatial.distance.squareform`.
+#[derive(Debug, Clone)]
+pub struct CondensedMatrix {
+    n: usize,
+    dists: Vec<f32>,
+}
+
+impl CondensedMatrix {
+    // `i` and `j` are offsets of the records.
+    pub fn get(&self, i: usize, j: usize) -> f32 {
+        match i.cmp(&j) {
+            Ordering::Less => self.dists[self.offset(i, j)],
+            Ordering::Greater => self.dists[self.offset(j, i)],
+            Ordering::Equal => 0.0,
+        }
+    }
+
+    fn offset(&self, i: usize, j: usize) -> usize {
+        self.n * i - i * (i + 1) / 2 + (j - i - 1)
+    }
+
+    pub const fn n(&self) -> usize {
+        self.n
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        &self.dists
+    }
+}
+
+// Pairs within a radius, in the ascending order of `(i, j)` with `i < j`.
+#[derive(Debug, Clone)]
+pub struct SparseMatrix {
+    n: usize,
+    entries: Vec<(u32, u32, f32)>,
+}
+
+impl SparseMatrix {
+    pub(crate) const fn new(n: usize, entries: Vec<(u32, u32, f32)>) -> Self {
+        Self { n, entries }
+    }
+
+    pub const fn n(&self) -> usize {
+        self.n
+    }
+
+    pub fn entries(&self) -> &[(u32, u32, f32)] {
+        &self.entries
+    }
+}
+
+// The distance between two empty sets is `f32::INFINITY` as in `LinearScan::all_distances`.
+// The rows are computed in `pool` if given, e.g., of `build_thread_pool`,
+// or in the current rayon pool otherwise.
+pub fn pairwise_distances(records: &[Record<u32>], pool: Option<&ThreadPool>) -> CondensedMatrix {
+    let n = records.len();
+    let rows = install(pool, || {
+        (0..n)
+            .into_par_iter()
+            .map(|i| {
+                let jaccard = Jaccard::new(&records[i].set, 1.0, FilterConfig::default());
+                records[i + 1..]
+                    .iter()
+                    .map(|record| jaccard.distance(&record.set).unwrap_or(f32::INFINITY))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    });
+    let dists = rows.into_iter().flatten().collect();
+    CondensedMatrix { n, dists }
+}
+
+pub fn pairwise_distances_within(
+    records: &[Record<u32>],
+    radius: f32,
+    pool: Option<&ThreadPool>,
+) -> Result<SparseMatrix> {
+    check_radius(radius)?;
+    let n = records.len();
+    let rows = install(pool, || {
+        (0..n)
+            .into_par_iter()
+            .map(|i| {
+                let jaccard = Jaccard::new(&records[i].set, radius, FILTER_CONFIG);
+                let mut row = Vec::new();
+                for (j, record) in records.iter().enumerate().skip(i + 1) {
+                    if let Evaluation::Accepted(dist) = jaccard.evaluate(&record.set) {
+                        row.push((i as u32, j as u32, dist));
+                    }
+                }
+                row
+            })
+            .collect::<Vec<_>>()
+    });
+    let entries = rows.into_iter().flatten().collect();
+    Ok(SparseMatrix { n, entries })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use approx::assert_abs_diff_eq;
+
+    use crate::OrderedSet;
+
+    fn records() -> Vec<Record<u32>> {
+        let a = OrderedSet::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::from_sorted([2, 3, 4, 5]).unwrap();
+        let c = OrderedSet::from_sorted([3, 4, 5, 6, 7]).unwrap();
+        vec![
+            Record { id: 0, set: a },
+            Record { id: 1, set: b },
+            Record { id: 2, set: c },
+        ]
+    }
+
+    #[test]
+    fn test_pairwise_distances() {
+        let matrix = pairwise_distances(&records(), None);
+        let pool = crate::build_thread_pool(2).unwrap();
+        assert_eq!(
+            pairwise_distances(&records(), Some(&pool)).as_slice(),
+            matrix.as_slice()
+        );
+        assert_eq!(matrix.n(), 3);
+        assert_eq!(matrix.as_slice().len(), 3);
+        assert_abs_diff_eq!(matrix.get(0, 1), 1. - 2. / 5.);
+        assert_abs_diff_eq!(matrix.get(2, 0), 1. - 1. / 7.);
+        assert_abs_diff_eq!(matrix.get(1, 2), 1. - 3. / 6.);
+        assert_abs_diff_eq!(matrix.get(1, 1), 0.);
+    }
+
+    #[test]
+    fn test_pairwise_distances_within() {
+        let matrix = pairwise_distances_within(&records(), 0.6, None).unwrap();
+        let pool = crate::build_thread_pool(2).unwrap();
+        let pooled = pairwise_distances_within(&records(), 0.6, Some(&pool)).unwrap();
+        assert_eq!(pooled.entries(), matrix.entries());
+        assert_eq!(matrix.n(), 3);
+        let pairs = matrix
+            .entries()
+            .iter()
+            .map(|&(i, j, _)| (i, j))
+            .collect::<Vec<_>>();
+        assert_eq!(pairs, vec![(0, 1), (1, 2)]);
+    }
+}
@@ -0,0 +1,183 @@
+use std::ops::{Range, RangeInclusive};
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use hashbrown::{HashMap, HashSet};
+
+use crate::metric::{Evaluation, FilterBounds, FilterConfig, Jaccard};
+use crate::{
+    check_radius, id_offsets, Answer, FrozenRecord, Mapping, OrderedSet, RangeSearch, Record,
+};
+
+const FILTER_CONFIG: FilterConfig = FilterConfig::new(true, true);
+
+// Record offset and the position of the element in the record.
+#[derive(Debug, Clone, Copy)]
+struct Posting {
+    offset: u32,
+    pos: u32,
+}
+
+// Inverted index whose radius is chosen per query, e.g., to sweep radii without rebuilding.
+// All the elements are indexed with their positions in the records, and the postings
+// beyond the prefix of a record for the query radius are skipped on probing,
+// at the cost of the postings of all the elements with twice the bytes per posting.
+pub struct PositionalIndex {
+    mapping: Arc<Mapping>,
+    records: Vec<FrozenRecord>,
+    index: HashMap<u32, Vec<Posting>>,
+}
+
+impl PositionalIndex {
+    pub fn from_records(records: &[Record<u32>], universe: u32) -> Result<Self> {
+        let mapping = Mapping::from_records(records, universe)?;
+        Self::from_records_with_mapping(records, Arc::new(mapping))
+    }
+
+    pub fn from_records_with_mapping(
+        records: &[Record<u32>],
+        mapping: Arc<Mapping>,
+    ) -> Result<Self> {
+        if !mapping.covers(records) {
+            return Err(anyhow!("The mapping does not cover the records."));
+        }
+        id_offsets(records)?;
+        let mut records = records
+            .iter()
+            .map(|record| {
+                Record {
+                    id: record.id,
+                    set: mapping.apply(&record.set),
+                }
+                .freeze()
+            })
+            .collect::<Vec<_>>();
+        // Sorted by length as in `InvertedIndex`, so that the length window is a range of each list.
+        records.sort_by_key(|record| record.set.len());
+        let mut index = HashMap::new();
+        for (i, record) in records.iter().enumerate() {
+            for (pos, &elem) in record.set.iter().enumerate() {
+                index.entry(elem).or_insert_with(Vec::new).push(Posting {
+                    offset: i as u32,
+                    pos: pos as u32,
+                });
+            }
+        }
+        Ok(Self {
+            mapping,
+            records,
+            index,
+        })
+    }
+
+    pub fn mapping(&self) -> &Arc<Mapping> {
+        &self.mapping
+    }
+
+    pub fn n_postings(&self) -> usize {
+        self.index.values().map(|list| list.len()).sum()
+    }
+
+    pub fn range_query(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Answer>> {
+        self.range_query_mapped(&self.mapping.apply(query), radius)
+    }
+
+    // The query must be already mapped by `self.mapping()`.
+    pub fn range_query_mapped(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Answer>> {
+        check_radius(radius)?;
+        let threshold = Jaccard::<u32>::threshold(radius);
+        let jaccard = Jaccard::new(query, radius, FILTER_CONFIG);
+        let window = self.length_window(jaccard.length_range());
+
+        // Records are probed by their prefixes for the query radius. The probing prefix
+        // is used on both sides, since the shorter indexing prefix of `InvertedIndex`
+        // can miss records longer than the query.
+        let pfx_len = Jaccard::<u32>::query_prefix_len(query.len(), threshold);
+        let mut visited = HashSet::new();
+        let mut candidates = vec![];
+        for elem in query.iter().take(pfx_len) {
+            let Some(list) = self.index.get(elem) else {
+                continue;
+            };
+            let start = list.partition_point(|p| p.offset < window.start);
+            let end = list.partition_point(|p| p.offset < window.end);
+            for p in &list[start..end] {
+                let record_len = self.records[p.offset as usize].set.len();
+                let record_pfx_len = Jaccard::<u32>::query_prefix_len(record_len, threshold);
+                if (p.pos as usize) < record_pfx_len && visited.insert(p.offset) {
+                    candidates.push(p.offset);
+                }
+            }
+        }
+
+        let mut answers = vec![];
+        for offset in candidates {
+            let record = &self.records[offset as usize];
+            if let Evaluation::Accepted(dist) = jaccard.evaluate(&record.set) {
+                answers.push(Answer {
+                    id: record.id,
+                    dist,
+                });
+            }
+        }
+        answers.sort_unstable();
+        Ok(answers)
+    }
+
+    // Returns the offsets of the records whose lengths are within the bounds.
+    fn length_window(&self, bounds: &RangeInclusive<usize>) -> Range<u32> {
+        let start = self
+            .records
+            .partition_point(|record| record.set.len() < *bounds.start());
+        let end = self
+            .records
+            .partition_point(|record| record.set.len() <= *bounds.end());
+        start as u32..end as u32
+    }
+}
+
+impl RangeSearch for PositionalIndex {
+    fn range_search(&self, query: &OrderedSet<u32>, radius: f32) -> Result<Vec<Answer>> {
+        self.range_query(query, radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng};
+    use rand_xoshiro::Xoshiro256PlusPlus;
+
+    use crate::LinearScan;
+
+    #[test]
+    fn test_range_query() {
+        let mut rng = Xoshiro256PlusPlus::seed_from_u64(7);
+        let records = (0..300)
+            .map(|id| {
+                let n = rng.gen_range(1..12);
+                let set = (0..n).map(|_| rng.gen_range(0..30)).collect::<Vec<_>>();
+                Record {
+                    id,
+                    set: OrderedSet::from_unsorted(set),
+                }
+            })
+            .collect::<Vec<_>>();
+        let linear_scan = LinearScan::from_records(&records, 30).unwrap();
+
+        // One build answers every radius as the linear scan does.
+        let index = PositionalIndex::from_records(&records, 30).unwrap();
+        for radius in [0.0, 0.2, 0.4, 0.5, 0.7] {
+            for record in records.iter().take(60) {
+                assert_eq!(
+                    index.range_query(&record.set, radius).unwrap(),
+                    linear_scan.range_query(&record.set, radius).unwrap(),
+                    "radius={radius}"
+                );
+            }
+        }
+        assert!(index.range_query(&records[0].set, f32::NAN).is_err());
+    }
+}
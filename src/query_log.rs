@@ -0,0 +1,272 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::index_file::{Decoder, Encoder};
+use crate::text::FeatureExtractor;
+use crate::{Mapping, OrderedSet};
+
+// Layout of a query log, which is append-only and little-endian like `index_file`:
+//
+//   magic: [u8; 8]
+//   version: u32
+//   extractor_hash: u8 flag followed by u64 if set
+//   mapping: u32s, to map the logged queries back for indexes with other mappings
+//   entries until the end of the file:
+//     kind: u8, followed by radius: f32 for range queries or k: u64 for top-k queries
+//     elapsed_ns: u64
+//     n_answers: u64
+//     query: u32s, mapped by the mapping
+//
+// A truncated last entry, e.g., by a crash while appending, is ignored on reading,
+// i.e., reading ends at the first entry hitting the end of the file.
+pub const LOG_MAGIC: [u8; 8] = *b"SSEQRLOG";
+pub const LOG_VERSION: u32 = 1;
+
+const KIND_RANGE: u8 = 0;
+const KIND_TOPK: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoggedQuery {
+    Range { radius: f32 },
+    Topk { k: usize },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub query: OrderedSet<u32>,
+    pub kind: LoggedQuery,
+    pub elapsed: Duration,
+    pub n_answers: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogHeader {
+    pub version: u32,
+    // `FeatureExtractor::config_hash` of the extractor of the queries, if given on creation.
+    pub extractor_hash: Option<u64>,
+    pub mapping: Mapping,
+}
+
+impl LogHeader {
+    // Fails if the queries were extracted by another extractor than `extractor`.
+    pub fn check_extractor(&self, extractor: &FeatureExtractor) -> Result<()> {
+        match self.extractor_hash {
+            Some(found) if found != extractor.config_hash() => Err(anyhow!(
+                "The queries were extracted by another extractor: {:016x} != {:016x}",
+                found,
+                extractor.config_hash()
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Appends mapped queries with their parameters and timings, e.g., to replay production
+// traffic against a new build by `read_query_log`. Appending is serialized for threads.
+pub struct QueryLog<W>
+where
+    W: Write,
+{
+    enc: Mutex<Encoder<W>>,
+}
+
+impl QueryLog<BufWriter<File>> {
+    // Creates the log file, truncating the existing one.
+    pub fn create<P>(
+        path: P,
+        mapping: &Mapping,
+        extractor: Option<&FeatureExtractor>,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::new(BufWriter::new(File::create(path)?), mapping, extractor)
+    }
+
+    // Appends to the existing log file, whose header must have the same mapping.
+    pub fn open_append<P>(path: P, mapping: &Mapping) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let header = read_header(&mut Decoder::new(BufReader::new(File::open(&path)?)))?;
+        if &header.mapping != mapping {
+            return Err(anyhow!("The log was written with another mapping."));
+        }
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Self {
+            enc: Mutex::new(Encoder::new(BufWriter::new(file))),
+        })
+    }
+}
+
+impl<W> QueryLog<W>
+where
+    W: Write,
+{
+    pub fn new(writer: W, mapping: &Mapping, extractor: Option<&FeatureExtractor>) -> Result<Self> {
+        let mut enc = Encoder::new(writer);
+        for b in LOG_MAGIC {
+            enc.u8(b)?;
+        }
+        enc.u32(LOG_VERSION)?;
+        match extractor {
+            Some(extractor) => {
+                enc.u8(1)?;
+                enc.u64(extractor.config_hash())?;
+            }
+            None => enc.u8(0)?,
+        }
+        enc.u32s(mapping.as_slice())?;
+        Ok(Self {
+            enc: Mutex::new(enc),
+        })
+    }
+
+    // The query must be mapped by the mapping of the log.
+    pub fn record(
+        &self,
+        query: &OrderedSet<u32>,
+        kind: LoggedQuery,
+        elapsed: Duration,
+        n_answers: usize,
+    ) -> Result<()> {
+        let mut enc = self.enc.lock().unwrap();
+        match kind {
+            LoggedQuery::Range { radius } => {
+                enc.u8(KIND_RANGE)?;
+                enc.f32(radius)?;
+            }
+            LoggedQuery::Topk { k } => {
+                enc.u8(KIND_TOPK)?;
+                enc.len(k)?;
+            }
+        }
+        enc.u64(elapsed.as_nanos().min(u64::MAX as u128) as u64)?;
+        enc.len(n_answers)?;
+        enc.u32s(query.as_slice())
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.enc.lock().unwrap().flush()
+    }
+}
+
+pub fn read_query_log<R>(reader: R) -> Result<(LogHeader, Vec<LogEntry>)>
+where
+    R: Read,
+{
+    let mut dec = Decoder::new(reader);
+    let header = read_header(&mut dec)?;
+    let mut entries = vec![];
+    loop {
+        match read_entry(&mut dec) {
+            Ok(entry) => entries.push(entry),
+            Err(e) if is_eof(&e) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((header, entries))
+}
+
+fn read_header<R>(dec: &mut Decoder<R>) -> Result<LogHeader>
+where
+    R: Read,
+{
+    let mut magic = [0; 8];
+    for b in magic.iter_mut() {
+        *b = dec.u8()?;
+    }
+    if magic != LOG_MAGIC {
+        return Err(anyhow!("Not a query log."));
+    }
+    let version = dec.u32()?;
+    if version != LOG_VERSION {
+        return Err(anyhow!("Unsupported version of the query log: {}", version));
+    }
+    let extractor_hash = match dec.u8()? {
+        0 => None,
+        _ => Some(dec.u64()?),
+    };
+    let mapping = Mapping::from_slice(&dec.u32s()?);
+    Ok(LogHeader {
+        version,
+        extractor_hash,
+        mapping,
+    })
+}
+
+fn read_entry<R>(dec: &mut Decoder<R>) -> Result<LogEntry>
+where
+    R: Read,
+{
+    let kind = match dec.u8()? {
+        KIND_RANGE => LoggedQuery::Range { radius: dec.f32()? },
+        KIND_TOPK => LoggedQuery::Topk { k: dec.len()? },
+        kind => return Err(anyhow!("Unknown kind of the query: {}", kind)),
+    };
+    let elapsed = Duration::from_nanos(dec.u64()?);
+    let n_answers = dec.len()?;
+    let query = OrderedSet::from_sorted(dec.u32s()?)?;
+    Ok(LogEntry {
+        query,
+        kind,
+        elapsed,
+        n_answers,
+    })
+}
+
+fn is_eof(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_log() {
+        let mapping = Mapping::from_slice(&[2, 0, 1, 3]);
+        let extractor = FeatureExtractor::seeded(1..=1, 4, 42).unwrap();
+        let entries = vec![
+            LogEntry {
+                query: OrderedSet::from_sorted([0, 2]).unwrap(),
+                kind: LoggedQuery::Range { radius: 0.5 },
+                elapsed: Duration::from_micros(12),
+                n_answers: 3,
+            },
+            LogEntry {
+                query: OrderedSet::new(),
+                kind: LoggedQuery::Topk { k: 10 },
+                elapsed: Duration::from_nanos(7),
+                n_answers: 0,
+            },
+        ];
+        let mut buf = vec![];
+        {
+            let log = QueryLog::new(&mut buf, &mapping, Some(&extractor)).unwrap();
+            for entry in &entries {
+                log.record(&entry.query, entry.kind, entry.elapsed, entry.n_answers)
+                    .unwrap();
+            }
+        }
+
+        let (header, read) = read_query_log(buf.as_slice()).unwrap();
+        assert_eq!(header.mapping, mapping);
+        header.check_extractor(&extractor).unwrap();
+        let other = FeatureExtractor::seeded(1..=1, 4, 7).unwrap();
+        assert!(header.check_extractor(&other).is_err());
+        assert_eq!(read, entries);
+
+        // The truncated last entry is dropped.
+        let (_, read) = read_query_log(&buf[..buf.len() - 3]).unwrap();
+        assert_eq!(read, entries[..1]);
+        assert!(read_query_log(&buf[..5]).is_err());
+    }
+}
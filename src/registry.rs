@@ -0,0 +1,179 @@
+use std::sync::{Arc, OnceLock};
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::mapping::config_hash;
+use crate::text::FeatureExtractor;
+use crate::{InvertedIndex, LinearScan, MappedQuery, Mapping, OrderedSet, Record};
+
+// Owns the extractor and the mapping shared by the indexes and the queries of a setup,
+// so that all of them hash and map elements consistently. Clones share the instances,
+// and a registry can be shared by threads.
+#[derive(Clone)]
+pub struct Registry {
+    extractor: Arc<FeatureExtractor>,
+    mapping: Arc<Mapping>,
+    config_hash: u64,
+}
+
+static GLOBAL: OnceLock<Registry> = OnceLock::new();
+
+impl Registry {
+    // Builds the mapping from the records extracted by `extractor`.
+    pub fn from_records(extractor: FeatureExtractor, records: &[Record<u32>]) -> Result<Self> {
+        let mapping = Mapping::from_records(records, extractor.universe())?;
+        Self::with_mapping(extractor, Arc::new(mapping))
+    }
+
+    pub fn with_mapping(extractor: FeatureExtractor, mapping: Arc<Mapping>) -> Result<Self> {
+        if mapping.universe() != extractor.universe() {
+            return Err(anyhow!(
+                "The universe of the mapping {} differs from that of the extractor {}.",
+                mapping.universe(),
+                extractor.universe()
+            ));
+        }
+        let config_hash = config_hash(extractor.config_hash(), &mapping);
+        Ok(Self {
+            extractor: Arc::new(extractor),
+            mapping,
+            config_hash,
+        })
+    }
+
+    // Installs the registry of the process, which every component can get by `global`.
+    // Installing another configuration fails, while installing the same one is a no-op.
+    pub fn install(self) -> Result<&'static Self> {
+        let installed = GLOBAL.get_or_init(|| self.clone());
+        if !installed.is_compatible(&self) {
+            return Err(anyhow!("Another registry is already installed."));
+        }
+        Ok(installed)
+    }
+
+    pub fn global() -> Option<&'static Self> {
+        GLOBAL.get()
+    }
+
+    pub fn extractor(&self) -> &Arc<FeatureExtractor> {
+        &self.extractor
+    }
+
+    pub fn mapping(&self) -> &Arc<Mapping> {
+        &self.mapping
+    }
+
+    // Hash of the extractor and the mapping, which the indexes built by the registry share.
+    pub const fn config_hash(&self) -> u64 {
+        self.config_hash
+    }
+
+    // Whether the registries extract and map elements in the same way.
+    pub fn is_compatible(&self, other: &Self) -> bool {
+        self.config_hash == other.config_hash && self.check_mapping(&other.mapping).is_ok()
+    }
+
+    pub fn extract<S>(&self, tokens: &[S]) -> OrderedSet<u32>
+    where
+        S: AsRef<str>,
+    {
+        self.extractor.extract(tokens)
+    }
+
+    // Extracts and maps the tokens for the `*_prepared` queries of the indexes.
+    pub fn extract_mapped<S>(&self, tokens: &[S]) -> MappedQuery
+    where
+        S: AsRef<str>,
+    {
+        let set = self.mapping.apply(&self.extractor.extract(tokens));
+        MappedQuery::with_config_hash(set, Some(self.config_hash))
+    }
+
+    pub fn linear_scan(&self, records: &[Record<u32>]) -> Result<LinearScan> {
+        Ok(
+            LinearScan::from_records_with_mapping(records, self.mapping.clone())?
+                .extractor(&self.extractor),
+        )
+    }
+
+    pub fn inverted_index(&self, records: &[Record<u32>], radius: f32) -> Result<InvertedIndex> {
+        Ok(
+            InvertedIndex::from_records_with_mapping(records, self.mapping.clone(), radius)?
+                .extractor(&self.extractor),
+        )
+    }
+
+    // Fails if the mapping of a component, e.g., `LinearScan::mapping`, is not this one.
+    pub fn check_mapping(&self, mapping: &Mapping) -> Result<()> {
+        if mapping.universe() != self.mapping.universe() {
+            return Err(anyhow!(
+                "The universe {} differs from that of the registry {}.",
+                mapping.universe(),
+                self.mapping.universe()
+            ));
+        }
+        if std::ptr::eq(mapping, self.mapping.as_ref()) || mapping == self.mapping.as_ref() {
+            Ok(())
+        } else {
+            Err(anyhow!("The mapping differs from that of the registry."))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(extractor: &FeatureExtractor) -> Vec<Record<u32>> {
+        ["a b c d", "a b c e", "b c d e", "x y z"]
+            .iter()
+            .enumerate()
+            .map(|(id, text)| Record {
+                id: id as u32,
+                set: extractor.extract(&text.split_whitespace().collect::<Vec<_>>()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_registry() {
+        let extractor = FeatureExtractor::seeded(1..=2, 1 << 10, 42).unwrap();
+        let records = records(&extractor);
+        let registry = Registry::from_records(extractor, &records).unwrap();
+
+        let linear_scan = registry.linear_scan(&records).unwrap();
+        let inverted_index = registry.inverted_index(&records, 0.5).unwrap();
+        assert!(Arc::ptr_eq(linear_scan.mapping(), registry.mapping()));
+        registry.check_mapping(inverted_index.mapping()).unwrap();
+
+        let query = registry.extract(&["a", "b", "c", "d"]);
+        let mapped = registry.extract_mapped(&["a", "b", "c", "d"]);
+        assert_eq!(linear_scan.config_hash(), Some(registry.config_hash()));
+        assert_eq!(
+            linear_scan.range_query_prepared(&mapped, 0.5).unwrap(),
+            linear_scan.range_query(&query, 0.5).unwrap()
+        );
+        assert_eq!(
+            inverted_index.range_query_prepared(&mapped).unwrap(),
+            inverted_index.range_query(&query)
+        );
+
+        // Indexes built with other universes or mappings are rejected.
+        let other = LinearScan::from_records(&records, 1 << 11).unwrap();
+        assert!(registry.check_mapping(other.mapping()).is_err());
+        let reversed = records.iter().rev().cloned().collect::<Vec<_>>();
+        let other = Mapping::from_records(&reversed[..1], 1 << 10).unwrap();
+        assert!(registry.check_mapping(&other).is_err());
+        let extractor = FeatureExtractor::seeded(1..=2, 1 << 11, 42).unwrap();
+        assert!(Registry::with_mapping(extractor, registry.mapping().clone()).is_err());
+
+        let installed = registry.clone().install().unwrap();
+        assert!(registry.is_compatible(Registry::global().unwrap()));
+        assert!(installed.clone().install().is_ok());
+        let extractor = FeatureExtractor::seeded(1..=2, 1 << 10, 7).unwrap();
+        let other = Registry::with_mapping(extractor, registry.mapping().clone()).unwrap();
+        assert!(!registry.is_compatible(&other));
+        assert!(other.install().is_err());
+    }
+}
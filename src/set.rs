@@ -1,17 +1,43 @@
+use std::marker::PhantomData;
+
 use anyhow::anyhow;
 use anyhow::Result;
+use serde::{Deserialize, Serialize, Serializer};
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub struct OrderedSet<T> {
-    elems: Vec<T>,
+// Serialized as a plain sequence, which is validated on deserialization.
+// The elements are stored in `S`, which is `Box<[T]>` for the sets stored in indexes,
+// i.e., `FrozenSet`, since they are never modified after the construction.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(
+    try_from = "Vec<T>",
+    bound(deserialize = "T: Deserialize<'de> + Ord + Copy, S: From<Vec<T>>")
+)]
+pub struct OrderedSet<T, S = Vec<T>> {
+    elems: S,
+    marker: PhantomData<T>,
 }
 
+// Immutable set without the capacity, which saves a word per set.
+pub type FrozenSet<T> = OrderedSet<T, Box<[T]>>;
+
 impl<T> OrderedSet<T>
 where
     T: Ord + Copy,
 {
     pub fn new() -> Self {
-        Self { elems: vec![] }
+        Self::from_vec(vec![])
+    }
+
+    // Empty set for `insert`ing up to `capacity` elements without reallocation.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::from_vec(Vec::with_capacity(capacity))
+    }
+
+    const fn from_vec(elems: Vec<T>) -> Self {
+        Self {
+            elems,
+            marker: PhantomData,
+        }
     }
 
     pub fn from_sorted<I>(sorted: I) -> Result<Self>
@@ -30,25 +56,214 @@ where
             }
             elems.push(elem);
         }
-        Ok(Self { elems })
+        if elems.capacity() > elems.len() {
+            elems.shrink_to_fit();
+        }
+        Ok(Self::from_vec(elems))
     }
 
+    // The capacity left by removing duplicates is released.
     pub fn from_unsorted<I>(unsorted: I) -> Self
     where
         I: IntoIterator<Item = T>,
     {
-        let mut elems = unsorted.into_iter().collect::<Vec<_>>();
-        elems.sort_unstable_by(|a, b| a.cmp(b));
+        Self::from_unsorted_vec(unsorted.into_iter().collect())
+    }
+
+    pub(crate) fn from_unsorted_vec(mut elems: Vec<T>) -> Self {
+        elems.sort_unstable();
         elems.dedup();
-        OrderedSet { elems }
+        if elems.capacity() > elems.len() {
+            elems.shrink_to_fit();
+        }
+        Self::from_vec(elems)
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<T> {
+        self.elems
+    }
+
+    // Drops the capacity, e.g., to store the set in an index.
+    pub fn freeze(self) -> FrozenSet<T> {
+        OrderedSet {
+            elems: self.elems.into_boxed_slice(),
+            marker: PhantomData,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.elems.capacity()
+    }
+
+    // Reserves the capacity for at least `additional` more elements to `insert`.
+    pub fn reserve(&mut self, additional: usize) {
+        self.elems.reserve(additional);
+    }
+
+    // Releases the excess capacity, e.g., after `retain` or `remove`, or before storing
+    // many sets built by `insert`.
+    pub fn shrink_to_fit(&mut self) {
+        self.elems.shrink_to_fit();
     }
 
+    // Keeps the elements satisfying the predicate, e.g., to drop stop-features of a query.
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.elems.retain(f);
+    }
+
+    // Returns false if the element is already in the set.
+    pub fn insert(&mut self, elem: T) -> bool {
+        match self.elems.binary_search(&elem) {
+            Ok(_) => false,
+            Err(i) => {
+                self.elems.insert(i, elem);
+                true
+            }
+        }
+    }
+
+    // Returns false if the element is not in the set.
+    pub fn remove(&mut self, elem: &T) -> bool {
+        match self.elems.binary_search(elem) {
+            Ok(i) => {
+                self.elems.remove(i);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl<T, S> OrderedSet<T, S>
+where
+    T: Ord + Copy,
+    S: AsRef<[T]>,
+{
     pub fn get(&self, index: usize) -> Option<&T> {
-        self.elems.get(index)
+        self.as_slice().get(index)
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &T> {
-        self.elems.iter()
+        self.as_slice().iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.as_slice().is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        self.elems.as_ref()
+    }
+
+    // Copies the elements into a modifiable set.
+    pub fn to_set(&self) -> OrderedSet<T> {
+        OrderedSet::from_vec(self.as_slice().to_vec())
+    }
+
+    // Number of the elements less than `elem`, i.e., the position of `elem` if in the set.
+    pub fn rank(&self, elem: &T) -> usize {
+        self.as_slice().partition_point(|e| e < elem)
+    }
+
+    // The `i`-th smallest element, from zero.
+    pub fn select(&self, i: usize) -> Option<T> {
+        self.as_slice().get(i).copied()
+    }
+
+    pub(crate) fn is_strictly_sorted(&self) -> bool {
+        self.as_slice().windows(2).all(|w| w[0] < w[1])
+    }
+}
+
+impl<T, S> TryFrom<Vec<T>> for OrderedSet<T, S>
+where
+    T: Ord + Copy,
+    S: From<Vec<T>>,
+{
+    type Error = anyhow::Error;
+
+    fn try_from(sorted: Vec<T>) -> Result<Self> {
+        let set = OrderedSet::from_sorted(sorted)?;
+        Ok(Self {
+            elems: S::from(set.elems),
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<T, S> Serialize for OrderedSet<T, S>
+where
+    T: Serialize,
+    S: AsRef<[T]>,
+{
+    fn serialize<Ser>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error>
+    where
+        Ser: Serializer,
+    {
+        self.elems.as_ref().serialize(serializer)
+    }
+}
+
+impl<T> From<OrderedSet<T>> for Vec<T> {
+    fn from(set: OrderedSet<T>) -> Self {
+        set.elems
+    }
+}
+
+impl<T, S> Default for OrderedSet<T, S>
+where
+    S: Default,
+{
+    fn default() -> Self {
+        Self {
+            elems: S::default(),
+            marker: PhantomData,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightedSet<T> {
+    elems: Vec<T>,
+    weights: Vec<f32>,
+}
+
+impl<T> WeightedSet<T>
+where
+    T: Ord + Copy,
+{
+    // Unions the sets, weighting each element by the maximum weight of the sets containing it.
+    pub fn from_weighted_sets<'a, I>(sets: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (&'a OrderedSet<T>, f32)>,
+        T: 'a,
+    {
+        let mut pairs = vec![];
+        for (set, weight) in sets {
+            if !(weight >= 0.0 && weight.is_finite()) {
+                return Err(anyhow!("Weights must be non-negative and finite."));
+            }
+            pairs.extend(set.iter().map(|&elem| (elem, weight)));
+        }
+        pairs.sort_unstable_by(|(a, wa), (b, wb)| a.cmp(b).then(wb.total_cmp(wa)));
+        pairs.dedup_by_key(|(elem, _)| *elem);
+        let (elems, weights) = pairs.into_iter().unzip();
+        Ok(Self { elems, weights })
+    }
+
+    pub fn get(&self, index: usize) -> Option<(&T, f32)> {
+        Some((self.elems.get(index)?, self.weights[index]))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&T, f32)> {
+        self.elems.iter().zip(self.weights.iter().cloned())
     }
 
     pub fn len(&self) -> usize {
@@ -58,6 +273,10 @@ where
     pub fn is_empty(&self) -> bool {
         self.elems.is_empty()
     }
+
+    pub fn total_weight(&self) -> f32 {
+        self.weights.iter().sum()
+    }
 }
 
 #[cfg(test)]
@@ -93,4 +312,84 @@ mod tests {
         let set = OrderedSet::<u32>::from_unsorted(vec![]);
         assert!(set.is_empty());
     }
+
+    #[test]
+    fn test_edit() {
+        let mut set = OrderedSet::<u32>::from_unsorted(vec![5, 1, 3]);
+        assert!(set.insert(4));
+        assert!(!set.insert(4));
+        assert!(set.insert(0));
+        assert_eq!(set.as_slice(), &[0, 1, 3, 4, 5]);
+        assert!(set.remove(&3));
+        assert!(!set.remove(&3));
+        set.retain(|&elem| elem % 2 == 0);
+        assert_eq!(set.as_slice(), &[0, 4]);
+        assert!(set.is_strictly_sorted());
+    }
+
+    #[test]
+    fn test_capacity() {
+        // The allocator may keep more than the length after shrinking.
+        let unsorted = vec![3, 1, 3, 1, 3, 1, 2, 2];
+        let capacity = unsorted.capacity();
+        let set = OrderedSet::<u32>::from_unsorted(unsorted);
+        assert_eq!(set.as_slice(), &[1, 2, 3]);
+        assert!(set.capacity() >= set.len() && set.capacity() < capacity);
+        let set = OrderedSet::<u32>::from_sorted(0..5).unwrap();
+        assert!(set.capacity() >= set.len());
+
+        let mut set = OrderedSet::<u32>::with_capacity(10);
+        assert!(set.capacity() >= 10);
+        for elem in [4, 2, 9] {
+            set.insert(elem);
+        }
+        set.reserve(20);
+        let capacity = set.capacity();
+        assert!(capacity >= 23);
+        set.shrink_to_fit();
+        assert!(set.capacity() >= set.len() && set.capacity() < capacity);
+        assert_eq!(set.as_slice(), &[2, 4, 9]);
+    }
+
+    #[test]
+    fn test_freeze() {
+        let set = OrderedSet::<u32>::from_sorted([1, 3, 5]).unwrap();
+        let frozen = set.clone().freeze();
+        assert_eq!(frozen.to_set(), set);
+        assert_eq!(frozen.as_slice(), &[1, 3, 5]);
+        let empty = OrderedSet::<u32>::new().freeze();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_rank_select() {
+        let set = OrderedSet::<u32>::from_unsorted(vec![2, 4, 8]);
+        assert_eq!(
+            (0..10).map(|e| set.rank(&e)).collect::<Vec<_>>(),
+            vec![0, 0, 0, 1, 1, 2, 2, 2, 2, 3]
+        );
+        for i in 0..set.len() {
+            assert_eq!(set.rank(&set.select(i).unwrap()), i);
+        }
+        assert_eq!(set.select(3), None);
+    }
+
+    #[test]
+    fn test_weighted_set() {
+        let a = OrderedSet::<u32>::from_sorted([1, 2, 3]).unwrap();
+        let b = OrderedSet::<u32>::from_sorted([2, 4]).unwrap();
+        let set = WeightedSet::from_weighted_sets([(&a, 0.5), (&b, 1.0)]).unwrap();
+        assert_eq!(
+            set.iter().collect::<Vec<_>>(),
+            vec![(&1, 0.5), (&2, 1.0), (&3, 0.5), (&4, 1.0)]
+        );
+        assert_eq!(set.total_weight(), 3.0);
+    }
+
+    #[test]
+    fn test_weighted_set_invalid() {
+        let a = OrderedSet::<u32>::from_sorted([1, 2, 3]).unwrap();
+        assert!(WeightedSet::from_weighted_sets([(&a, -1.0)]).is_err());
+        assert!(WeightedSet::from_weighted_sets([(&a, f32::NAN)]).is_err());
+    }
 }
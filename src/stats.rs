@@ -0,0 +1,245 @@
+use std::ops::RangeInclusive;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::Xoshiro256PlusPlus;
+
+use crate::OrderedSet;
+
+// Accumulates statistics of sets in one pass, so that corpora too large to hold
+// in memory can be processed as streams. The memory is bounded by the universe
+// and the sample size regardless of the number of sets.
+pub struct CorpusStats {
+    universe: u32,
+    n_sets: usize,
+    // Number of sets per length.
+    len_hist: Vec<usize>,
+    // Document frequencies of elements. Features are already hashed into the universe,
+    // so this is the sketch of the n-gram frequencies.
+    dfs: Vec<usize>,
+    n_distinct: usize,
+    // (n_sets, n_distinct) at every power of two sets.
+    growth: Vec<(usize, usize)>,
+    // Uniform sample of the sets by reservoir sampling.
+    sample: Vec<OrderedSet<u32>>,
+    sample_size: usize,
+    rng: Xoshiro256PlusPlus,
+}
+
+impl CorpusStats {
+    pub fn new(universe: u32, sample_size: usize, seed: u64) -> Result<Self> {
+        if universe == 0 {
+            return Err(anyhow!("Invalid universe."));
+        }
+        Ok(Self {
+            universe,
+            n_sets: 0,
+            len_hist: vec![],
+            dfs: vec![0; universe as usize],
+            n_distinct: 0,
+            growth: vec![],
+            sample: vec![],
+            sample_size,
+            rng: Xoshiro256PlusPlus::seed_from_u64(seed),
+        })
+    }
+
+    pub fn push(&mut self, set: &OrderedSet<u32>) -> Result<()> {
+        if set.iter().any(|&elem| elem >= self.universe) {
+            return Err(anyhow!("The set is out of the universe."));
+        }
+        if self.len_hist.len() <= set.len() {
+            self.len_hist.resize(set.len() + 1, 0);
+        }
+        self.len_hist[set.len()] += 1;
+        for &elem in set.iter() {
+            let df = &mut self.dfs[elem as usize];
+            if *df == 0 {
+                self.n_distinct += 1;
+            }
+            *df += 1;
+        }
+
+        // Algorithm R: the i-th set replaces a random sampled one at probability k/i.
+        if self.sample.len() < self.sample_size {
+            self.sample.push(set.clone());
+        } else {
+            let i = self.rng.gen_range(0..=self.n_sets);
+            if i < self.sample_size {
+                self.sample[i] = set.clone();
+            }
+        }
+
+        self.n_sets += 1;
+        if self.n_sets.is_power_of_two() {
+            self.growth.push((self.n_sets, self.n_distinct));
+        }
+        Ok(())
+    }
+
+    pub fn universe(&self) -> u32 {
+        self.universe
+    }
+
+    pub fn n_sets(&self) -> usize {
+        self.n_sets
+    }
+
+    // `len_hist()[l]` is the number of sets of length `l`.
+    pub fn len_hist(&self) -> &[usize] {
+        &self.len_hist
+    }
+
+    // `dfs()[e]` is the number of sets containing element `e`.
+    pub fn dfs(&self) -> &[usize] {
+        &self.dfs
+    }
+
+    pub fn n_distinct(&self) -> usize {
+        self.n_distinct
+    }
+
+    // Returns the numbers of distinct elements against the numbers of sets,
+    // at every power of two sets and at the end.
+    pub fn growth_curve(&self) -> Vec<(usize, usize)> {
+        let mut growth = self.growth.clone();
+        if !self.n_sets.is_power_of_two() {
+            growth.push((self.n_sets, self.n_distinct));
+        }
+        growth
+    }
+
+    pub fn sample(&self) -> &[OrderedSet<u32>] {
+        &self.sample
+    }
+}
+
+// Strata of sets by length quantiles, e.g., deciles, to report metrics per query length,
+// since aggregated means hide effects only on short or long sets.
+// Quantiles sharing a length are merged, so there can be fewer strata than requested.
+pub struct LengthStrata {
+    // Inclusive upper bound of the lengths of every stratum but the last.
+    uppers: Vec<usize>,
+}
+
+impl LengthStrata {
+    pub fn from_lens<I>(lens: I, n_strata: usize) -> Result<Self>
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        if n_strata == 0 {
+            return Err(anyhow!("n_strata must be positive."));
+        }
+        let mut lens = lens.into_iter().collect::<Vec<_>>();
+        if lens.is_empty() {
+            return Err(anyhow!("No lengths to stratify."));
+        }
+        lens.sort_unstable();
+        let max_len = *lens.last().unwrap();
+        let mut uppers = (1..n_strata)
+            .map(|i| lens[(i * lens.len()).div_ceil(n_strata) - 1])
+            .filter(|&upper| upper < max_len)
+            .collect::<Vec<_>>();
+        uppers.dedup();
+        Ok(Self { uppers })
+    }
+
+    pub fn from_sets(sets: &[OrderedSet<u32>], n_strata: usize) -> Result<Self> {
+        Self::from_lens(sets.iter().map(|set| set.len()), n_strata)
+    }
+
+    pub fn n_strata(&self) -> usize {
+        self.uppers.len() + 1
+    }
+
+    pub fn stratum(&self, len: usize) -> usize {
+        self.uppers.partition_point(|&upper| upper < len)
+    }
+
+    // Tags each set with its stratum.
+    pub fn tag(&self, sets: &[OrderedSet<u32>]) -> Vec<usize> {
+        sets.iter().map(|set| self.stratum(set.len())).collect()
+    }
+
+    pub fn bounds(&self, stratum: usize) -> RangeInclusive<usize> {
+        let start = match stratum {
+            0 => 0,
+            _ => self.uppers[stratum - 1] + 1,
+        };
+        let end = self.uppers.get(stratum).copied().unwrap_or(usize::MAX);
+        start..=end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corpus_stats() {
+        let mut stats = CorpusStats::new(10, 3, 334).unwrap();
+        let sets = [
+            vec![0, 1],
+            vec![1, 2, 3],
+            vec![1],
+            vec![4, 5],
+            vec![1, 2],
+            vec![],
+        ];
+        for set in &sets {
+            stats
+                .push(&OrderedSet::from_sorted(set.clone()).unwrap())
+                .unwrap();
+        }
+        assert!(stats.push(&OrderedSet::from_sorted([10]).unwrap()).is_err());
+
+        assert_eq!(stats.n_sets(), 6);
+        assert_eq!(stats.len_hist(), &[1, 1, 3, 1]);
+        assert_eq!(stats.dfs(), &[1, 4, 2, 1, 1, 1, 0, 0, 0, 0]);
+        assert_eq!(stats.n_distinct(), 6);
+        assert_eq!(stats.growth_curve(), vec![(1, 2), (2, 4), (4, 6), (6, 6)]);
+        assert_eq!(stats.sample().len(), 3);
+    }
+
+    #[test]
+    fn test_reservoir_sampling() {
+        // Every set should be sampled at about k/n.
+        let mut counts = [0; 10];
+        for seed in 0..1000 {
+            let mut stats = CorpusStats::new(10, 2, seed).unwrap();
+            for elem in 0..10 {
+                stats
+                    .push(&OrderedSet::from_sorted([elem]).unwrap())
+                    .unwrap();
+            }
+            for set in stats.sample() {
+                counts[*set.get(0).unwrap() as usize] += 1;
+            }
+        }
+        assert!(counts.iter().all(|&count| (120..280).contains(&count)));
+    }
+
+    #[test]
+    fn test_length_strata() {
+        let strata = LengthStrata::from_lens(1..=100, 10).unwrap();
+        assert_eq!(strata.n_strata(), 10);
+        assert_eq!(strata.stratum(1), 0);
+        assert_eq!(strata.stratum(10), 0);
+        assert_eq!(strata.stratum(11), 1);
+        assert_eq!(strata.stratum(100), 9);
+        assert_eq!(strata.stratum(1000), 9);
+        assert_eq!(strata.bounds(0), 0..=10);
+        assert_eq!(strata.bounds(9), 91..=usize::MAX);
+
+        // Ties are merged.
+        let strata = LengthStrata::from_lens([1, 1, 1, 1, 2, 3], 4).unwrap();
+        assert_eq!(strata.n_strata(), 3);
+        assert_eq!(strata.bounds(1), 2..=2);
+        let strata = LengthStrata::from_lens([5; 10], 10).unwrap();
+        assert_eq!(strata.n_strata(), 1);
+
+        assert!(LengthStrata::from_lens([], 10).is_err());
+        assert!(LengthStrata::from_lens([1], 0).is_err());
+    }
+}
@@ -1,28 +1,147 @@
 use std::hash::{BuildHasher, Hash, Hasher};
-use std::ops::RangeInclusive;
+use std::ops::{Range, RangeInclusive};
+use std::sync::{Arc, Mutex};
 
 use ahash::RandomState;
 use anyhow::anyhow;
 use anyhow::Result;
+use hashbrown::{HashMap, HashSet};
 use rand::RngCore;
 use rand::SeedableRng;
 use rand_xoshiro::SplitMix64;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
 
 use crate::OrderedSet;
 
+// How the n-gram order of a feature is encoded in its id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OrderTagging {
+    None,
+    // `id % n_orders` is the order.
+    Interleaved,
+    // Each order has a disjoint contiguous range of ids.
+    Namespaced,
+}
+
+// How n-grams are turned into canonical strings before hashing, e.g., to match
+// features computed externally from the same strings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Canonicalization {
+    // Joins the grams of an n-gram.
+    pub separator: String,
+    // Applies NFKC normalization to the joined string.
+    pub nfkc: bool,
+    pub lowercase: bool,
+}
+
+impl Default for Canonicalization {
+    fn default() -> Self {
+        Self {
+            separator: " ".to_string(),
+            nfkc: false,
+            lowercase: false,
+        }
+    }
+}
+
+impl Canonicalization {
+    pub fn apply<S>(&self, ngram: &[S]) -> String
+    where
+        S: AsRef<str>,
+    {
+        let joined = ngram
+            .iter()
+            .map(|gram| gram.as_ref())
+            .collect::<Vec<_>>()
+            .join(&self.separator);
+        let normalized = if self.nfkc {
+            joined.nfkc().collect::<String>()
+        } else {
+            joined
+        };
+        if self.lowercase {
+            normalized.to_lowercase()
+        } else {
+            normalized
+        }
+    }
+}
+
+// Hash function of n-grams.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HashScheme {
+    AHash,
+    // MurmurHash3 (x86, 32-bit) with seed 0 as in scikit-learn.
+    Murmur3,
+}
+
+// How the hash seed of `FeatureExtractor` is chosen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeedPolicy {
+    Fixed(u64),
+    // Draws a seed from OS entropy, so the features differ between runs.
+    #[cfg(feature = "entropy")]
+    FromEntropy,
+}
+
+impl SeedPolicy {
+    pub fn resolve(self) -> u64 {
+        match self {
+            Self::Fixed(seed) => seed,
+            #[cfg(feature = "entropy")]
+            Self::FromEntropy => rand::thread_rng().next_u64(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct FeatureExtractor {
     ngram_range: RangeInclusive<usize>,
     build_hasher: RandomState,
     universe: u32,
     seed: u64,
+    tagging: OrderTagging,
+    // Hashes the canonical strings of n-grams if set, or the grams one by one otherwise.
+    canonicalization: Option<Canonicalization>,
+    scheme: HashScheme,
+    // Shared by the clones.
+    recorder: Option<Arc<Mutex<NgramRecorder>>>,
+}
+
+// Keeps the first distinct n-grams per id, up to `max_per_id`, for collision diagnostics.
+#[derive(Debug)]
+struct NgramRecorder {
+    max_per_id: usize,
+    ngrams: HashMap<u32, Vec<String>>,
 }
 
 impl FeatureExtractor {
+    // Draws a seed from OS entropy if `seed` is none, which requires the `entropy` feature.
     pub fn new(
         ngram_range: RangeInclusive<usize>,
         universe: u32,
         seed: Option<u64>,
+    ) -> Result<Self> {
+        let policy = match seed {
+            Some(seed) => SeedPolicy::Fixed(seed),
+            #[cfg(feature = "entropy")]
+            None => SeedPolicy::FromEntropy,
+            #[cfg(not(feature = "entropy"))]
+            None => return Err(anyhow!("A seed is required without the entropy feature.")),
+        };
+        Self::with_seed_policy(ngram_range, universe, policy)
+    }
+
+    // Deterministic regardless of the features.
+    pub fn seeded(ngram_range: RangeInclusive<usize>, universe: u32, seed: u64) -> Result<Self> {
+        Self::with_seed_policy(ngram_range, universe, SeedPolicy::Fixed(seed))
+    }
+
+    pub fn with_seed_policy(
+        ngram_range: RangeInclusive<usize>,
+        universe: u32,
+        policy: SeedPolicy,
     ) -> Result<Self> {
         if universe == 0 {
             return Err(anyhow!("Invalid universe."));
@@ -30,7 +149,7 @@ impl FeatureExtractor {
         if ngram_range.start() > ngram_range.end() {
             return Err(anyhow!("Invalid ngram range."));
         }
-        let seed = seed.unwrap_or_else(|| rand::thread_rng().next_u64());
+        let seed = policy.resolve();
         let mut seeder = SplitMix64::seed_from_u64(seed);
         let build_hasher = RandomState::with_seeds(
             seeder.next_u64(),
@@ -43,9 +162,157 @@ impl FeatureExtractor {
             build_hasher,
             universe,
             seed,
+            tagging: OrderTagging::None,
+            canonicalization: None,
+            scheme: HashScheme::AHash,
+            recorder: None,
         })
     }
 
+    // Tags each feature with its n-gram order as `id % n_orders`,
+    // so that the order can be recovered from the id by `order_of`.
+    pub fn tag_orders(self) -> Result<Self> {
+        self.with_tagging(OrderTagging::Interleaved)
+    }
+
+    // Partitions the universe into namespaces of the same size per n-gram order,
+    // from the lowest order, e.g., unigrams get the smallest ids.
+    pub fn namespace_orders(self) -> Result<Self> {
+        self.with_tagging(OrderTagging::Namespaced)
+    }
+
+    // Hashes the canonical strings of n-grams instead of the grams one by one.
+    pub fn canonicalize(mut self, canonicalization: Canonicalization) -> Self {
+        self.canonicalization = Some(canonicalization);
+        self
+    }
+
+    // Hashes n-grams as scikit-learn's HashingVectorizer with `n_features` = universe,
+    // i.e., `abs(murmurhash3_32(" ".join(ngram), seed=0)) % n_features`, so that the sets
+    // are the non-zero columns of the vectorizer with `alternate_sign=False`.
+    // Tokenize and lowercase the text as the vectorizer does, e.g., by its `token_pattern`.
+    // The seed of the extractor is not used.
+    pub fn murmur3(mut self) -> Self {
+        self.scheme = HashScheme::Murmur3;
+        if self.canonicalization.is_none() {
+            self.canonicalization = Some(Canonicalization::default());
+        }
+        self
+    }
+
+    // Returns the string form of the n-gram, which is hashed if canonicalizing.
+    // The grams are joined by spaces otherwise.
+    pub fn canonical_form<S>(&self, ngram: &[S]) -> String
+    where
+        S: AsRef<str>,
+    {
+        match &self.canonicalization {
+            Some(canonicalization) => canonicalization.apply(ngram),
+            None => Canonicalization::default().apply(ngram),
+        }
+    }
+
+    // Returns the feature of an n-gram given by its canonical string form, e.g., computed
+    // externally, where `n` is the order. Requires `canonicalize`.
+    pub fn feature_of(&self, canonical: &str, n: usize) -> Result<u32> {
+        if self.canonicalization.is_none() {
+            return Err(anyhow!("The extractor does not canonicalize n-grams."));
+        }
+        if !self.ngram_range.contains(&n) {
+            return Err(anyhow!("The order is out of the ngram range: {}", n));
+        }
+        Ok(self.feature_id(self.hash_str(canonical) as u32, n))
+    }
+
+    // Records the n-gram strings producing each id in `extract`, up to `max_per_id` per id,
+    // so that `recorded_ngrams` tells which n-grams collide into an id.
+    // Recording serializes the extraction and costs memory, so it is for diagnostics.
+    pub fn record_ngrams(mut self, max_per_id: usize) -> Self {
+        self.recorder = Some(Arc::new(Mutex::new(NgramRecorder {
+            max_per_id,
+            ngrams: HashMap::new(),
+        })));
+        self
+    }
+
+    // Returns the recorded n-grams of the id in the string form of `canonical_form`,
+    // or `None` if not recording.
+    pub fn recorded_ngrams(&self, elem: u32) -> Option<Vec<String>> {
+        let recorder = self.recorder.as_ref()?.lock().unwrap();
+        Some(recorder.ngrams.get(&elem).cloned().unwrap_or_default())
+    }
+
+    // Sets the universe recommended by `recommend_universe`.
+    pub fn auto_universe<D, S>(
+        mut self,
+        corpus_sample: &[D],
+        max_collision_rate: f64,
+    ) -> Result<Self>
+    where
+        D: AsRef<[S]>,
+        S: AsRef<str>,
+    {
+        self.universe = self.recommend_universe(corpus_sample, max_collision_rate)?;
+        Ok(self)
+    }
+
+    // Suggests the smallest universe such that each distinct n-gram in the sample shares
+    // its id with another one at most at `max_collision_rate`, assuming uniform hashing.
+    // The sample should cover the vocabulary of the corpus, or the universe is underestimated.
+    pub fn recommend_universe<D, S>(
+        &self,
+        corpus_sample: &[D],
+        max_collision_rate: f64,
+    ) -> Result<u32>
+    where
+        D: AsRef<[S]>,
+        S: AsRef<str>,
+    {
+        if !(max_collision_rate > 0.0 && max_collision_rate < 1.0) {
+            return Err(anyhow!("Invalid collision rate: {}", max_collision_rate));
+        }
+        let mut distinct = vec![HashSet::new(); self.n_orders()];
+        for tokens in corpus_sample {
+            let tokens = tokens.as_ref();
+            for (distinct, n) in distinct.iter_mut().zip(self.ngram_range.clone()) {
+                if tokens.len() < n {
+                    break;
+                }
+                distinct.extend(tokens.windows(n).map(|ngram| self.hash(ngram)));
+            }
+        }
+
+        // A distinct n-gram collides with none of the other D - 1 ones in W ids
+        // at (1 - 1/W)^(D-1) ~ exp(-(D-1)/W).
+        let width = |n_distinct: usize| {
+            let n_others = n_distinct.saturating_sub(1) as f64;
+            (n_others / -(1.0 - max_collision_rate).ln())
+                .ceil()
+                .max(1.0)
+        };
+        let universe = match self.tagging {
+            // All the orders share the ids.
+            OrderTagging::None => width(distinct.iter().map(|d| d.len()).sum()),
+            // Each order has its own ids of the same number.
+            OrderTagging::Interleaved | OrderTagging::Namespaced => {
+                let max_width = distinct.iter().map(|d| width(d.len())).fold(0.0, f64::max);
+                max_width * self.n_orders() as f64
+            }
+        };
+        if universe > u32::MAX as f64 {
+            return Err(anyhow!("The universe exceeds u32: {}", universe));
+        }
+        Ok(universe as u32)
+    }
+
+    fn with_tagging(mut self, tagging: OrderTagging) -> Result<Self> {
+        if (self.universe as usize) < self.n_orders() {
+            return Err(anyhow!("The universe is too small to tag the orders."));
+        }
+        self.tagging = tagging;
+        Ok(self)
+    }
+
     pub fn extract<S>(&self, tokens: &[S]) -> OrderedSet<u32>
     where
         S: AsRef<str>,
@@ -53,28 +320,128 @@ impl FeatureExtractor {
         if tokens.is_empty() {
             return OrderedSet::new();
         }
+        let mut recorder = self.recorder.as_ref().map(|r| r.lock().unwrap());
         let mut features = Vec::new();
         for n in self.ngram_range.clone() {
             if tokens.len() < n {
                 break;
             }
             for ngram in tokens.windows(n) {
-                let hash = self.hash(ngram);
-                features.push(hash);
+                let hash = self.hash(ngram) as u32;
+                let id = self.feature_id(hash, n);
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.record(id, || self.canonical_form(ngram));
+                }
+                features.push(id);
             }
         }
         OrderedSet::from_unsorted(features)
     }
 
-    fn hash<S>(&self, ngram: &[S]) -> u32
+    // Extracts character n-grams of text without word delimiters, e.g., Japanese.
+    pub fn extract_chars(&self, text: &str) -> OrderedSet<u32> {
+        self.extract(&char_tokens(text))
+    }
+
+    // Returns the features of all the n-gram occurrences with their token spans,
+    // e.g., to highlight the tokens producing some features.
+    pub fn feature_spans<S>(&self, tokens: &[S]) -> Vec<(u32, Range<usize>)>
     where
         S: AsRef<str>,
     {
+        let mut spans = Vec::new();
+        for n in self.ngram_range.clone() {
+            if tokens.len() < n {
+                break;
+            }
+            for (i, ngram) in tokens.windows(n).enumerate() {
+                let hash = self.hash(ngram) as u32;
+                spans.push((self.feature_id(hash, n), i..i + n));
+            }
+        }
+        spans
+    }
+
+    // Same as `extract` but pairs each feature with the token span it came from.
+    // A feature from several spans keeps the first one, i.e., the leftmost and then shortest.
+    // The pairs are in ascending order of the features.
+    pub fn extract_with_offsets<S>(&self, tokens: &[S]) -> Vec<(u32, Range<usize>)>
+    where
+        S: AsRef<str>,
+    {
+        let mut spans = self.feature_spans(tokens);
+        spans.sort_unstable_by_key(|(id, span)| (*id, span.start, span.end));
+        spans.dedup_by_key(|(id, _)| *id);
+        spans
+    }
+
+    fn hash<S>(&self, ngram: &[S]) -> u64
+    where
+        S: AsRef<str>,
+    {
+        if let Some(canonicalization) = &self.canonicalization {
+            return self.hash_str(&canonicalization.apply(ngram));
+        }
         let mut state = self.build_hasher.build_hasher();
         for gram in ngram {
             gram.as_ref().hash(&mut state);
         }
-        state.finish() as u32 % self.universe
+        state.finish()
+    }
+
+    fn hash_str(&self, s: &str) -> u64 {
+        match self.scheme {
+            HashScheme::AHash => self.build_hasher.hash_one(s),
+            // abs() of the signed hash as in scikit-learn, where abs(-2^31) is 2^31.
+            HashScheme::Murmur3 => (murmur3_32(s.as_bytes(), 0) as i32).unsigned_abs() as u64,
+        }
+    }
+
+    fn feature_id(&self, hash: u32, n: usize) -> u32 {
+        let n_orders = self.n_orders() as u32;
+        let tag = (n - self.ngram_range.start()) as u32;
+        match self.tagging {
+            OrderTagging::None => hash % self.universe,
+            OrderTagging::Interleaved => hash % (self.universe / n_orders) * n_orders + tag,
+            OrderTagging::Namespaced => {
+                let width = self.universe / n_orders;
+                tag * width + hash % width
+            }
+        }
+    }
+
+    fn n_orders(&self) -> usize {
+        self.ngram_range.end() - self.ngram_range.start() + 1
+    }
+
+    // Returns the n-gram order of the feature, or `None` if the orders are not tagged.
+    pub fn order_of(&self, elem: u32) -> Option<usize> {
+        let n_orders = self.n_orders() as u32;
+        let tag = match self.tagging {
+            OrderTagging::None => return None,
+            OrderTagging::Interleaved => elem % n_orders,
+            OrderTagging::Namespaced => (elem / (self.universe / n_orders)).min(n_orders - 1),
+        };
+        Some(self.ngram_range.start() + tag as usize)
+    }
+
+    // Returns the ids of the n-gram order, or `None` if the orders are not namespaced.
+    pub fn namespace(&self, n: usize) -> Option<Range<u32>> {
+        if self.tagging != OrderTagging::Namespaced || !self.ngram_range.contains(&n) {
+            return None;
+        }
+        let width = self.universe / self.n_orders() as u32;
+        let tag = (n - self.ngram_range.start()) as u32;
+        Some(tag * width..(tag + 1) * width)
+    }
+
+    // Returns the features of the n-gram order, or `None` if the orders are not tagged.
+    pub fn features_of_order(&self, set: &OrderedSet<u32>, n: usize) -> Option<OrderedSet<u32>> {
+        if self.tagging == OrderTagging::None {
+            return None;
+        }
+        let elems = set.iter().filter(|&&elem| self.order_of(elem) == Some(n));
+        Some(OrderedSet::from_sorted(elems.cloned()).unwrap())
     }
 
     pub const fn universe(&self) -> u32 {
@@ -84,6 +451,151 @@ impl FeatureExtractor {
     pub const fn seed(&self) -> u64 {
         self.seed
     }
+
+    // Hash of the settings determining the features, stable across platforms and releases,
+    // e.g., to check that an index file was built with the same extractor.
+    pub fn config_hash(&self) -> u64 {
+        let mut bytes = vec![];
+        bytes.extend(self.seed.to_le_bytes());
+        bytes.extend((*self.ngram_range.start() as u64).to_le_bytes());
+        bytes.extend((*self.ngram_range.end() as u64).to_le_bytes());
+        bytes.extend(self.universe.to_le_bytes());
+        bytes.push(self.tagging as u8);
+        bytes.push(self.scheme as u8);
+        if let Some(canonicalization) = &self.canonicalization {
+            bytes.push(1);
+            bytes.extend((canonicalization.separator.len() as u64).to_le_bytes());
+            bytes.extend(canonicalization.separator.as_bytes());
+            bytes.push(canonicalization.nfkc as u8);
+            bytes.push(canonicalization.lowercase as u8);
+        }
+        fnv1a_64(&bytes)
+    }
+}
+
+pub(crate) fn fnv1a_64(bytes: &[u8]) -> u64 {
+    let mut h = 0xcbf29ce484222325u64;
+    for &b in bytes {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+impl NgramRecorder {
+    fn record<F>(&mut self, id: u32, ngram: F)
+    where
+        F: FnOnce() -> String,
+    {
+        let ngrams = self.ngrams.entry(id).or_default();
+        if ngrams.len() >= self.max_per_id {
+            return;
+        }
+        let ngram = ngram();
+        if !ngrams.contains(&ngram) {
+            ngrams.push(ngram);
+        }
+    }
+}
+
+// MurmurHash3 (x86, 32-bit).
+fn murmur3_32(bytes: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+    let mix = |mut k: u32| {
+        k = k.wrapping_mul(C1);
+        k = k.rotate_left(15);
+        k.wrapping_mul(C2)
+    };
+
+    let mut h = seed;
+    let mut chunks = bytes.chunks_exact(4);
+    for chunk in chunks.by_ref() {
+        h ^= mix(u32::from_le_bytes(chunk.try_into().unwrap()));
+        h = h.rotate_left(13);
+        h = h.wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let mut k = 0u32;
+        for (i, &b) in tail.iter().enumerate() {
+            k |= (b as u32) << (8 * i);
+        }
+        h ^= mix(k);
+    }
+
+    h ^= bytes.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85ebca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2ae35);
+    h ^= h >> 16;
+    h
+}
+
+// Splits text into characters as tokens, for CJK text where whitespace tokenization
+// gives one token per sentence. Characters are extended grapheme clusters,
+// e.g., a kana with a combining voiced mark is one character. Whitespace is dropped.
+pub fn char_tokens(text: &str) -> Vec<&str> {
+    text.graphemes(true)
+        .filter(|g| !g.chars().all(char::is_whitespace))
+        .collect()
+}
+
+// Assigns distinct ids to distinct n-grams, i.e., without hash collisions.
+#[derive(Clone, Debug)]
+pub struct VocabExtractor {
+    ngram_range: RangeInclusive<usize>,
+    vocab: HashMap<Vec<String>, u32>,
+    // N-grams indexed by their ids.
+    ngrams: Vec<Vec<String>>,
+}
+
+impl VocabExtractor {
+    pub fn new(ngram_range: RangeInclusive<usize>) -> Result<Self> {
+        if ngram_range.start() > ngram_range.end() {
+            return Err(anyhow!("Invalid ngram range."));
+        }
+        Ok(Self {
+            ngram_range,
+            vocab: HashMap::new(),
+            ngrams: vec![],
+        })
+    }
+
+    pub fn extract<S>(&mut self, tokens: &[S]) -> OrderedSet<u32>
+    where
+        S: AsRef<str>,
+    {
+        let mut features = Vec::new();
+        for n in self.ngram_range.clone() {
+            if tokens.len() < n {
+                break;
+            }
+            for ngram in tokens.windows(n) {
+                let ngram = ngram
+                    .iter()
+                    .map(|t| t.as_ref().to_string())
+                    .collect::<Vec<_>>();
+                let next_id = self.vocab.len() as u32;
+                let id = *self.vocab.entry(ngram.clone()).or_insert(next_id);
+                if id == next_id {
+                    self.ngrams.push(ngram);
+                }
+                features.push(id);
+            }
+        }
+        OrderedSet::from_unsorted(features)
+    }
+
+    pub fn vocab_size(&self) -> usize {
+        self.vocab.len()
+    }
+
+    // Returns the n-gram of the id.
+    pub fn ngram(&self, id: u32) -> Option<&[String]> {
+        self.ngrams.get(id as usize).map(|ngram| ngram.as_slice())
+    }
 }
 
 #[cfg(test)]
@@ -98,4 +610,238 @@ mod tests {
         // a, b, c, ab, ba, bc, aba, bab, abc
         assert_eq!(features.len(), 9);
     }
+
+    #[test]
+    fn test_config_hash() {
+        assert_eq!(fnv1a_64(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a_64(b"a"), 0xaf63dc4c8601ec8c);
+
+        let a = FeatureExtractor::seeded(1..=2, 1 << 20, 334).unwrap();
+        assert_eq!(a.config_hash(), a.clone().config_hash());
+        let others = [
+            FeatureExtractor::seeded(1..=2, 1 << 20, 335).unwrap(),
+            FeatureExtractor::seeded(1..=3, 1 << 20, 334).unwrap(),
+            FeatureExtractor::seeded(1..=2, 1 << 19, 334).unwrap(),
+            a.clone().tag_orders().unwrap(),
+            a.clone().murmur3(),
+            a.clone().canonicalize(Canonicalization::default()),
+        ];
+        for other in &others {
+            assert_ne!(a.config_hash(), other.config_hash());
+        }
+    }
+
+    #[test]
+    fn test_seed_policy() {
+        let tokens = vec!["a", "b", "c"];
+        let a = FeatureExtractor::seeded(1..=2, 1 << 20, 334).unwrap();
+        let b = FeatureExtractor::new(1..=2, 1 << 20, Some(334)).unwrap();
+        assert_eq!(a.seed(), 334);
+        assert_eq!(a.extract(&tokens), b.extract(&tokens));
+        let c = FeatureExtractor::seeded(1..=2, 1 << 20, 335).unwrap();
+        assert_ne!(a.extract(&tokens), c.extract(&tokens));
+        #[cfg(not(feature = "entropy"))]
+        assert!(FeatureExtractor::new(1..=2, 1 << 20, None).is_err());
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let canonicalization = Canonicalization {
+            separator: "_".to_string(),
+            nfkc: true,
+            lowercase: true,
+        };
+        let extractor = FeatureExtractor::new(1..=2, 1000, Some(334))
+            .unwrap()
+            .canonicalize(canonicalization);
+        // The full-width letters are normalized by NFKC.
+        assert_eq!(extractor.canonical_form(&["Ｆoo", "Bar"]), "foo_bar");
+        assert_eq!(
+            extractor.extract(&["Ｆoo", "Bar"]),
+            extractor.extract(&["foo", "bar"])
+        );
+
+        let expected = OrderedSet::from_unsorted([
+            extractor.feature_of("foo", 1).unwrap(),
+            extractor.feature_of("bar", 1).unwrap(),
+            extractor.feature_of("foo_bar", 2).unwrap(),
+        ]);
+        assert_eq!(extractor.extract(&["foo", "bar"]), expected);
+        assert!(extractor.feature_of("foo", 3).is_err());
+
+        let extractor = FeatureExtractor::new(1..=2, 1000, Some(334)).unwrap();
+        assert_eq!(extractor.canonical_form(&["Foo", "Bar"]), "Foo Bar");
+        assert!(extractor.feature_of("foo", 1).is_err());
+    }
+
+    #[test]
+    fn test_murmur3() {
+        // The value of sklearn.utils.murmurhash3_32.
+        assert_eq!(murmur3_32(b"foo", 0) as i32, -156908512);
+        // The reference test vectors.
+        assert_eq!(murmur3_32(b"", 0), 0);
+        assert_eq!(murmur3_32(b"", 1), 0x514e28b7);
+        assert_eq!(murmur3_32(b"test", 0), 0xba6bd213);
+        assert_eq!(murmur3_32(b"Hello, world!", 1234), 0xfaf6cdb3);
+
+        let extractor = FeatureExtractor::seeded(1..=2, 1 << 20, 0)
+            .unwrap()
+            .murmur3();
+        let expected = OrderedSet::from_unsorted([
+            156908512 % (1 << 20),
+            extractor.feature_of("bar", 1).unwrap(),
+            extractor.feature_of("foo bar", 2).unwrap(),
+        ]);
+        assert_eq!(extractor.extract(&["foo", "bar"]), expected);
+    }
+
+    #[test]
+    fn test_char_tokens() {
+        // The second "が" is decomposed into "か" and a combining voiced mark.
+        let tokens = char_tokens("甲が 乙か\u{3099}");
+        assert_eq!(tokens, vec!["甲", "が", "乙", "か\u{3099}"]);
+
+        let extractor = FeatureExtractor::new(2..=3, u32::MAX, Some(334)).unwrap();
+        // 甲が, が乙, 乙が, 甲が乙, が乙が
+        let features = extractor.extract_chars("甲が乙が");
+        assert_eq!(features.len(), 5);
+        assert_eq!(features, extractor.extract(&char_tokens("甲が 乙が")));
+    }
+
+    #[test]
+    fn test_feature_spans() {
+        let extractor = FeatureExtractor::new(1..=2, u32::MAX, Some(334)).unwrap();
+        let tokens = ["a", "b", "a"];
+        let spans = extractor.feature_spans(&tokens);
+        let ranges = spans.iter().map(|(_, r)| r.clone()).collect::<Vec<_>>();
+        assert_eq!(ranges, vec![0..1, 1..2, 2..3, 0..2, 1..3]);
+        assert_eq!(spans[0].0, spans[2].0);
+        let features = OrderedSet::from_unsorted(spans.iter().map(|&(id, _)| id));
+        assert_eq!(features, extractor.extract(&tokens));
+    }
+
+    #[test]
+    fn test_extract_with_offsets() {
+        let extractor = FeatureExtractor::new(1..=2, u32::MAX, Some(334)).unwrap();
+        let tokens = ["a", "b", "a", "b"];
+        let features = extractor.extract_with_offsets(&tokens);
+        let ids = features.iter().map(|&(id, _)| id).collect::<Vec<_>>();
+        assert_eq!(ids, extractor.extract(&tokens).into_vec());
+        let mut spans = features.into_iter().map(|(_, s)| s).collect::<Vec<_>>();
+        spans.sort_unstable_by_key(|s| (s.start, s.end));
+        // a, ab, b, ba
+        assert_eq!(spans, vec![0..1, 0..2, 1..2, 1..3]);
+
+        // Every feature collides into 0 and keeps the first span.
+        let extractor = FeatureExtractor::new(1..=2, 1, Some(334)).unwrap();
+        assert_eq!(extractor.extract_with_offsets(&tokens), vec![(0, 0..1)]);
+        assert!(extractor.extract_with_offsets::<&str>(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_record_ngrams() {
+        // Every n-gram collides in the universe of size 1.
+        let extractor = FeatureExtractor::new(1..=2, 1, Some(334))
+            .unwrap()
+            .record_ngrams(3);
+        assert_eq!(extractor.recorded_ngrams(0), Some(vec![]));
+        extractor.extract(&["a", "b", "a"]);
+        let cloned = extractor.clone();
+        cloned.extract(&["c", "d"]);
+        assert_eq!(
+            extractor.recorded_ngrams(0),
+            Some(vec!["a".to_string(), "b".to_string(), "a b".to_string()])
+        );
+
+        let extractor = FeatureExtractor::new(1..=2, 1, Some(334)).unwrap();
+        assert_eq!(extractor.recorded_ngrams(0), None);
+    }
+
+    #[test]
+    fn test_recommend_universe() {
+        let sample = (0..100)
+            .map(|i| vec![format!("w{}", i), format!("w{}", i + 1)])
+            .collect::<Vec<_>>();
+        // 101 unigrams and 100 bigrams.
+        let extractor = FeatureExtractor::new(1..=2, 1, Some(334)).unwrap();
+        let universe = extractor.recommend_universe(&sample, 0.01).unwrap();
+        assert_eq!(universe, (200.0 / -(0.99f64).ln()).ceil() as u32);
+        assert!(extractor.recommend_universe(&sample, 0.0).is_err());
+        assert!(extractor.recommend_universe(&sample, 1.0).is_err());
+
+        let extractor = extractor.auto_universe(&sample, 0.1).unwrap();
+        let features = sample
+            .iter()
+            .flat_map(|tokens| extractor.extract(tokens).into_vec())
+            .collect::<HashSet<_>>();
+        assert!(features.len() as f64 >= 201.0 * 0.8);
+
+        let extractor = FeatureExtractor::new(1..=2, 2, Some(334))
+            .unwrap()
+            .namespace_orders()
+            .unwrap();
+        let universe = extractor.recommend_universe(&sample, 0.01).unwrap();
+        assert_eq!(universe, (100.0 / -(0.99f64).ln()).ceil() as u32 * 2);
+    }
+
+    #[test]
+    fn test_tag_orders() {
+        let extractor = FeatureExtractor::new(1..=3, 1000, Some(334))
+            .unwrap()
+            .tag_orders()
+            .unwrap();
+        let features = extractor.extract(&["a", "b", "c"]);
+        let orders = features
+            .iter()
+            .map(|&elem| extractor.order_of(elem).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(orders.iter().filter(|&&n| n == 1).count(), 3);
+        assert_eq!(orders.iter().filter(|&&n| n == 2).count(), 2);
+        assert_eq!(orders.iter().filter(|&&n| n == 3).count(), 1);
+        assert!(features.iter().all(|&elem| elem < 1000));
+        assert_eq!(extractor.features_of_order(&features, 3).unwrap().len(), 1);
+
+        let extractor = FeatureExtractor::new(1..=3, 1000, Some(334)).unwrap();
+        assert_eq!(extractor.order_of(0), None);
+        assert!(FeatureExtractor::seeded(1..=3, 2, 334)
+            .unwrap()
+            .tag_orders()
+            .is_err());
+    }
+
+    #[test]
+    fn test_namespace_orders() {
+        let extractor = FeatureExtractor::new(1..=3, 1000, Some(334))
+            .unwrap()
+            .namespace_orders()
+            .unwrap();
+        assert_eq!(extractor.namespace(1), Some(0..333));
+        assert_eq!(extractor.namespace(3), Some(666..999));
+        assert_eq!(extractor.namespace(4), None);
+        let features = extractor.extract(&["a", "b", "c"]);
+        for n in 1..=3 {
+            let ns = extractor.namespace(n).unwrap();
+            let of_order = extractor.features_of_order(&features, n).unwrap();
+            assert_eq!(of_order.len(), 4 - n);
+            assert!(of_order.iter().all(|elem| ns.contains(elem)));
+        }
+    }
+
+    #[test]
+    fn test_vocab_extract() {
+        let mut extractor = VocabExtractor::new(1..=2).unwrap();
+        let a = extractor.extract(&["a", "b", "a"]);
+        let b = extractor.extract(&["b", "a", "c"]);
+        // a, b, ab, ba
+        assert_eq!(a.len(), 4);
+        // b, a, c, ba, ac
+        assert_eq!(b.len(), 5);
+        assert_eq!(extractor.vocab_size(), 6);
+        assert_eq!(extractor.ngram(0), Some(&["a".to_string()][..]));
+        assert_eq!(
+            extractor.ngram(5),
+            Some(&["a".to_string(), "c".to_string()][..])
+        );
+        assert_eq!(extractor.ngram(6), None);
+    }
 }
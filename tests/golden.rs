@@ -0,0 +1,119 @@
+// Compares search results on a small bundled corpus against committed golden results.
+// Run with `UPDATE_GOLDEN=1` to regenerate `tests/data/golden.json`
+// after an intended change of the results.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use set_search_experiment::text::VocabExtractor;
+use set_search_experiment::{Answer, FilterConfig, InvertedIndex, LinearScan, OrderedSet, Record};
+
+const RADII: [f32; 5] = [0.0, 0.1, 0.3, 0.5, 0.8];
+const TOPKS: [usize; 2] = [1, 5];
+
+type Golden = BTreeMap<String, Vec<Vec<(u32, f32)>>>;
+
+fn data_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("data")
+        .join(name)
+}
+
+fn load_sets(extractor: &mut VocabExtractor, name: &str) -> Vec<OrderedSet<u32>> {
+    fs::read_to_string(data_path(name))
+        .unwrap()
+        .lines()
+        .map(|line| extractor.extract(&line.split_whitespace().collect::<Vec<_>>()))
+        .collect()
+}
+
+fn run_queries<F>(queries: &[OrderedSet<u32>], search: F) -> Vec<Vec<(u32, f32)>>
+where
+    F: Fn(&OrderedSet<u32>) -> Vec<Answer>,
+{
+    queries
+        .iter()
+        .map(|query| {
+            search(query)
+                .into_iter()
+                .map(|ans| (ans.id, ans.dist))
+                .collect()
+        })
+        .collect()
+}
+
+fn search_all() -> Golden {
+    // Distinct n-grams get distinct ids, so the sets do not depend on hashing.
+    let mut extractor = VocabExtractor::new(1..=2).unwrap();
+    let database = load_sets(&mut extractor, "corpus.txt");
+    let queries = load_sets(&mut extractor, "queries.txt");
+    let universe = extractor.vocab_size() as u32;
+    let records = database
+        .into_iter()
+        .enumerate()
+        .map(|(id, set)| Record { id: id as u32, set })
+        .collect::<Vec<_>>();
+
+    let mut results = Golden::new();
+    let mut linear_scan = LinearScan::from_records(&records, universe).unwrap();
+    for length in [false, true] {
+        for position in [false, true] {
+            linear_scan = linear_scan
+                .filter_config(FilterConfig::new(length, position))
+                .unwrap();
+            let l = usize::from(length);
+            let p = usize::from(position);
+            for radius in RADII {
+                let key = format!("linear_scan/L={l}_P={p}/r={radius}");
+                let answers = run_queries(&queries, |query| {
+                    linear_scan.range_query(query, radius).unwrap()
+                });
+                results.insert(key, answers);
+            }
+            for k in TOPKS {
+                let key = format!("linear_scan/L={l}_P={p}/k={k}");
+                let answers =
+                    run_queries(&queries, |query| linear_scan.topk_query(query, k).unwrap());
+                results.insert(key, answers);
+            }
+        }
+    }
+    for radius in RADII {
+        let index = InvertedIndex::from_records(&records, universe, radius).unwrap();
+        let key = format!("inverted_index/r={radius}");
+        results.insert(key, run_queries(&queries, |query| index.range_query(query)));
+    }
+    results
+}
+
+#[test]
+fn test_golden() {
+    let results = search_all();
+    let path = data_path("golden.json");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, serde_json::to_string_pretty(&results).unwrap()).unwrap();
+        return;
+    }
+    let golden: Golden = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(
+        results.keys().collect::<Vec<_>>(),
+        golden.keys().collect::<Vec<_>>()
+    );
+    for (key, answers) in &results {
+        let expected = &golden[key];
+        assert_eq!(answers.len(), expected.len(), "{key}");
+        for (i, (answers, expected)) in answers.iter().zip(expected).enumerate() {
+            let ids = answers.iter().map(|&(id, _)| id).collect::<Vec<_>>();
+            let expected_ids = expected.iter().map(|&(id, _)| id).collect::<Vec<_>>();
+            assert_eq!(ids, expected_ids, "{key}, query {i}");
+            for (&(_, dist), &(_, expected_dist)) in answers.iter().zip(expected) {
+                assert!(
+                    (dist - expected_dist).abs() <= 1e-6,
+                    "{key}, query {i}: {dist} != {expected_dist}"
+                );
+            }
+        }
+    }
+}
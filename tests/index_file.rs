@@ -0,0 +1,74 @@
+// Checks that the index files are byte-identical across platforms, e.g., little- and
+// big-endian or 32- and 64-bit targets, against files committed from another platform.
+// Run on other targets with, e.g., `cargo test --test index_file --target i686-unknown-linux-gnu`.
+// Run with `UPDATE_GOLDEN=1` to regenerate the files after an intended change of the format.
+
+use std::fs;
+use std::path::PathBuf;
+
+use set_search_experiment::text::VocabExtractor;
+use set_search_experiment::{InvertedIndex, LinearScan, OrderedSet, Record};
+
+const RADIUS: f32 = 0.5;
+
+fn data_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("data")
+        .join(name)
+}
+
+fn load_sets(extractor: &mut VocabExtractor, name: &str) -> Vec<OrderedSet<u32>> {
+    fs::read_to_string(data_path(name))
+        .unwrap()
+        .lines()
+        .map(|line| extractor.extract(&line.split_whitespace().collect::<Vec<_>>()))
+        .collect()
+}
+
+fn check_golden(name: &str, saved: &[u8]) -> Vec<u8> {
+    let path = data_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, saved).unwrap();
+    }
+    let golden = fs::read(&path).unwrap();
+    assert!(saved == golden, "{name} differs from the golden file.");
+    golden
+}
+
+#[test]
+fn test_index_file() {
+    // Distinct n-grams get distinct ids, so the sets do not depend on hashing.
+    let mut extractor = VocabExtractor::new(1..=2).unwrap();
+    let database = load_sets(&mut extractor, "corpus.txt");
+    let queries = load_sets(&mut extractor, "queries.txt");
+    let universe = extractor.vocab_size() as u32;
+    let records = database
+        .into_iter()
+        .enumerate()
+        .map(|(id, set)| Record { id: id as u32, set })
+        .collect::<Vec<_>>();
+
+    let index = InvertedIndex::from_records(&records, universe, RADIUS)
+        .unwrap()
+        .deduplicate();
+    let mut saved = vec![];
+    index.save(&mut saved, None).unwrap();
+    let golden = check_golden("inverted_index.bin", &saved);
+    let loaded = InvertedIndex::load(golden.as_slice(), None).unwrap();
+    for query in &queries {
+        assert_eq!(loaded.range_query(query), index.range_query(query));
+    }
+
+    let index = LinearScan::from_records(&records, universe).unwrap();
+    let mut saved = vec![];
+    index.save(&mut saved, None).unwrap();
+    let golden = check_golden("linear_scan.bin", &saved);
+    let loaded = LinearScan::load(golden.as_slice(), None).unwrap();
+    for query in &queries {
+        assert_eq!(
+            loaded.range_query(query, RADIUS).unwrap(),
+            index.range_query(query, RADIUS).unwrap()
+        );
+    }
+}
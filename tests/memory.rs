@@ -0,0 +1,82 @@
+// Measures the heap bytes of the built indexes by counting the live bytes of the allocator,
+// so that a stored set keeping spare capacity or a wider layout is caught.
+// Kept in its own test binary with a single test, since the count is process-global.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+use set_search_experiment::{InvertedIndex, LinearScan, OrderedSet, Record};
+
+struct CountingAllocator;
+
+static LIVE_BYTES: AtomicIsize = AtomicIsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE_BYTES.fetch_add(layout.size() as isize, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.fetch_sub(layout.size() as isize, Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        LIVE_BYTES.fetch_add(
+            new_size as isize - layout.size() as isize,
+            Ordering::Relaxed,
+        );
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Returns the index and the growth of the live heap bytes by building it.
+fn measure<T>(build: impl FnOnce() -> T) -> (T, isize) {
+    let before = LIVE_BYTES.load(Ordering::Relaxed);
+    let index = build();
+    (index, LIVE_BYTES.load(Ordering::Relaxed) - before)
+}
+
+// Records of `len` elements each, built with much spare capacity as by `insert`.
+fn records(n: u32, len: u32) -> Vec<Record<u32>> {
+    (0..n)
+        .map(|id| {
+            let mut set = OrderedSet::with_capacity(64);
+            for i in 0..len {
+                set.insert((id + i * 7) % 1000);
+            }
+            Record { id, set }
+        })
+        .collect()
+}
+
+#[test]
+fn test_index_heap_bytes() {
+    const N: u32 = 2000;
+    const LEN: u32 = 8;
+    let empty = records(N, 0);
+    let full = records(N, LEN);
+    let input_bytes = (N * 64) as isize * 4;
+
+    // The records of the same ids differ only in the stored elements, which take
+    // four bytes each without the spare capacity of the input. The rest of the index
+    // differs by far less than a word per record.
+    let (index, empty_bytes) = measure(|| LinearScan::from_records(&empty, 1000).unwrap());
+    drop(index);
+    let (index, full_bytes) = measure(|| LinearScan::from_records(&full, 1000).unwrap());
+    let elem_bytes = (N * LEN) as isize * 4;
+    assert!((full_bytes - empty_bytes - elem_bytes).abs() < N as isize);
+    assert!(full_bytes < input_bytes);
+    // Per record, 24 bytes of the id and the boxed slice, 4 bytes of the length order,
+    // and about 19 bytes of the id map, while the capacity word of `Vec` would add 8 more.
+    assert!(empty_bytes < N as isize * 52, "{empty_bytes}");
+    drop(index);
+
+    let (index, full_bytes) = measure(|| InvertedIndex::from_records(&full, 1000, 0.5).unwrap());
+    assert!(full_bytes < input_bytes);
+    drop(index);
+}
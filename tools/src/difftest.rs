@@ -0,0 +1,174 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use clap::Parser;
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use set_search_experiment::{dist_eq_with, Answer};
+
+// Subset of the output of the search tool.
+#[derive(Deserialize)]
+struct SearchOutput {
+    answers: Vec<SearchAnswer>,
+}
+
+#[derive(Deserialize)]
+struct SearchAnswer {
+    query: String,
+    founds: Vec<Answer>,
+}
+
+#[derive(Serialize)]
+struct Output {
+    metadata: Metadata,
+    n_differed_queries: usize,
+    diffs: Vec<Diff>,
+}
+
+#[derive(Serialize)]
+struct Metadata {
+    left_file: String,
+    right_file: String,
+    n_queries: usize,
+    epsilon: f32,
+}
+
+#[derive(Serialize)]
+struct Diff {
+    query_id: usize,
+    query: String,
+    // (id, dist) found only in each side.
+    left_only: Vec<(u32, f32)>,
+    right_only: Vec<(u32, f32)>,
+    // (id, left dist, right dist) found in both sides with different distances.
+    dist_mismatches: Vec<(u32, f32, f32)>,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(short = 'a', long)]
+    left_file: PathBuf,
+
+    #[arg(short = 'b', long)]
+    right_file: PathBuf,
+
+    #[arg(short = 'o', long)]
+    output_json: PathBuf,
+
+    #[arg(short = 'e', long, default_value_t = 1e-6)]
+    epsilon: f32,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    let left = load_output(&args.left_file)?;
+    let right = load_output(&args.right_file)?;
+    if left.answers.len() != right.answers.len() {
+        eprintln!(
+            "The numbers of queries differ: {} vs {}",
+            left.answers.len(),
+            right.answers.len()
+        );
+        return Ok(());
+    }
+    let n_queries = left.answers.len();
+    eprintln!("n_queries: {}", n_queries);
+
+    let mut diffs = Vec::new();
+    for (query_id, (left, right)) in left.answers.iter().zip(&right.answers).enumerate() {
+        if left.query != right.query {
+            eprintln!("The queries differ at {}", query_id);
+            return Ok(());
+        }
+        if let Some(diff) = diff_answers(query_id, left, right, args.epsilon) {
+            diffs.push(diff);
+        }
+    }
+
+    let n_left_only = diffs.iter().map(|d| d.left_only.len()).sum::<usize>();
+    let n_right_only = diffs.iter().map(|d| d.right_only.len()).sum::<usize>();
+    let n_mismatches = diffs.iter().map(|d| d.dist_mismatches.len()).sum::<usize>();
+    eprintln!("# of differed queries: {}", diffs.len());
+    eprintln!("# of founds only in left: {}", n_left_only);
+    eprintln!("# of founds only in right: {}", n_right_only);
+    eprintln!("# of distance mismatches: {}", n_mismatches);
+
+    let output = Output {
+        metadata: Metadata {
+            left_file: args.left_file.to_string_lossy().to_string(),
+            right_file: args.right_file.to_string_lossy().to_string(),
+            n_queries,
+            epsilon: args.epsilon,
+        },
+        n_differed_queries: diffs.len(),
+        diffs,
+    };
+    let j = serde_json::to_string_pretty(&output).unwrap();
+
+    let mut file = File::create(args.output_json).unwrap();
+    file.write_all(j.as_bytes()).unwrap();
+
+    Ok(())
+}
+
+fn load_output<P>(path: P) -> Result<SearchOutput, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+{
+    let reader = BufReader::new(File::open(path)?);
+    let output = serde_json::from_reader(reader)?;
+    Ok(output)
+}
+
+fn diff_answers(
+    query_id: usize,
+    left: &SearchAnswer,
+    right: &SearchAnswer,
+    epsilon: f32,
+) -> Option<Diff> {
+    let right_dists = right
+        .founds
+        .iter()
+        .map(|found| (found.id, found.dist))
+        .collect::<HashMap<_, _>>();
+    let left_dists = left
+        .founds
+        .iter()
+        .map(|found| (found.id, found.dist))
+        .collect::<HashMap<_, _>>();
+
+    let mut left_only = Vec::new();
+    let mut dist_mismatches = Vec::new();
+    for found in &left.founds {
+        match right_dists.get(&found.id) {
+            Some(&dist) if !dist_eq_with(dist, found.dist, epsilon) => {
+                dist_mismatches.push((found.id, found.dist, dist));
+            }
+            Some(_) => {}
+            None => left_only.push((found.id, found.dist)),
+        }
+    }
+    let right_only = right
+        .founds
+        .iter()
+        .filter(|found| !left_dists.contains_key(&found.id))
+        .map(|found| (found.id, found.dist))
+        .collect::<Vec<_>>();
+
+    if left_only.is_empty() && right_only.is_empty() && dist_mismatches.is_empty() {
+        return None;
+    }
+    Some(Diff {
+        query_id,
+        query: left.query.clone(),
+        left_only,
+        right_only,
+        dist_mismatches,
+    })
+}
@@ -2,27 +2,54 @@ use std::error::Error;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::BufWriter;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Instant;
 
-use clap::Parser;
+use arrow_array::{ArrayRef, Float32Array, RecordBatch, StringArray, UInt32Array, UInt64Array};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema};
+use clap::{Parser, ValueEnum};
 use serde::Serialize;
+use set_search_experiment::lsh::{MinHash, MinHashIndex};
 use set_search_experiment::metric::Evaluation;
+use set_search_experiment::stats::LengthStrata;
 use set_search_experiment::text::FeatureExtractor;
+use set_search_experiment::Dropout;
 use set_search_experiment::FilterConfig;
+use set_search_experiment::InvertedIndex;
 use set_search_experiment::LinearScan;
 use set_search_experiment::OrderedSet;
 use set_search_experiment::Record;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Json,
+    // Arrow IPC file (Feather v2) with one row per query, and the metadata in the schema.
+    // The accepted distances are written to the file with the extension `distances.arrow`.
+    Arrow,
+}
+
 #[derive(Serialize)]
 struct Output {
     metadata: Metadata,
+    // Lengths of the query sets, to learn the thresholds of `FilterPolicy` per length.
+    query_lens: Vec<usize>,
     no_filter: Vec<Counter>,
     length_filter: Vec<Counter>,
     position_filter: Vec<Counter>,
     all_filters: Vec<Counter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dropout: Option<Vec<DropoutCounter>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lsh: Option<LshReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    accepted_distances: Option<AcceptedDistances>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    strata: Option<Vec<Stratum>>,
 }
 
 #[derive(Serialize)]
@@ -34,6 +61,9 @@ struct Metadata {
     max_n: usize,
     radius: f32,
     seed: Option<u64>,
+    dropout_probes: usize,
+    dropout_rate: f32,
+    dropout_seed: u64,
 }
 
 #[derive(Default, Debug, Serialize)]
@@ -45,6 +75,65 @@ struct Counter {
     accepted: usize,
 }
 
+impl Counter {
+    // In the order of the fields.
+    fn as_array(&self) -> [usize; 5] {
+        [
+            self.length_filtered,
+            self.position_filtered,
+            self.verified,
+            self.undefined,
+            self.accepted,
+        ]
+    }
+}
+
+// Counters summed over the queries of a length stratum.
+#[derive(Debug, Serialize)]
+struct Stratum {
+    min_len: usize,
+    max_len: usize,
+    n_queries: usize,
+    no_filter: Counter,
+    length_filter: Counter,
+    position_filter: Counter,
+    all_filters: Counter,
+}
+
+#[derive(Default, Debug, Serialize)]
+struct DropoutCounter {
+    accepted: usize,
+    expanded: usize,
+}
+
+// Recall and latency of the MinHash LSH index against the exact inverted index.
+#[derive(Debug, Serialize)]
+struct LshReport {
+    n_bands: usize,
+    band_size: usize,
+    recall: f64,
+    lsh_mean_ms: f64,
+    inverted_index_mean_ms: f64,
+    counters: Vec<LshCounter>,
+}
+
+#[derive(Default, Debug, Serialize)]
+struct LshCounter {
+    // Answers of the exact search.
+    expected: usize,
+    found: usize,
+    candidates: usize,
+}
+
+// Accepted (id, distance) pairs per query for each filter configuration.
+#[derive(Debug, Serialize)]
+struct AcceptedDistances {
+    no_filter: Vec<Vec<(u32, f32)>>,
+    length_filter: Vec<Vec<(u32, f32)>>,
+    position_filter: Vec<Vec<(u32, f32)>>,
+    all_filters: Vec<Vec<(u32, f32)>>,
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
@@ -57,6 +146,9 @@ struct Args {
     #[arg(short = 'o', long)]
     output_json: PathBuf,
 
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    format: OutputFormat,
+
     #[arg(short = 'n', long, default_value_t = 1)]
     max_n: usize,
 
@@ -68,6 +160,32 @@ struct Args {
 
     #[arg(long)]
     seed: Option<u64>,
+
+    #[arg(long, default_value_t = 0)]
+    dropout_probes: usize,
+
+    #[arg(long, default_value_t = 0.2)]
+    dropout_rate: f32,
+
+    #[arg(long, default_value_t = 42)]
+    dropout_seed: u64,
+
+    // Number of bands of the MinHash LSH index to compare, or 0 not to.
+    #[arg(long, default_value_t = 0)]
+    lsh_bands: usize,
+
+    #[arg(long, default_value_t = 4)]
+    lsh_band_size: usize,
+
+    #[arg(long, default_value_t = 42)]
+    lsh_seed: u64,
+
+    #[arg(long)]
+    dump_distances: bool,
+
+    // Number of query length quantiles to report the counters per, e.g., 10 for deciles.
+    #[arg(long, default_value_t = 0)]
+    strata: usize,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -82,7 +200,7 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     eprintln!("Indexing...");
     let start_tp = Instant::now();
-    let index = {
+    let (index, records) = {
         let mut records = Vec::with_capacity(database_texts.len());
         for (id, text) in database_texts.iter().enumerate() {
             let tokens = text.split_whitespace().collect::<Vec<_>>();
@@ -90,7 +208,10 @@ fn main() -> Result<(), Box<dyn Error>> {
             let record = Record { id: id as u32, set };
             records.push(record);
         }
-        LinearScan::from_records(&records, extractor.universe())?
+        (
+            LinearScan::from_records(&records, extractor.universe())?,
+            records,
+        )
     };
     let duration = start_tp.elapsed();
     eprintln!("Elapsed: {:.3} sec", duration.as_millis() as f64 / 1000.);
@@ -102,33 +223,75 @@ fn main() -> Result<(), Box<dyn Error>> {
         .map(|tokens| extractor.extract(&tokens))
         .collect::<Vec<_>>();
 
-    eprintln!("Evaluating no filter...");
-    let index = index.filter_config(FilterConfig {
-        length: false,
-        position: false,
-    });
-    let no_filter = evaluate_range_search(&index, &queries, args.radius);
-
-    eprintln!("Evaluating length filter...");
-    let index = index.filter_config(FilterConfig {
-        length: true,
-        position: false,
-    });
-    let length_filter = evaluate_range_search(&index, &queries, args.radius);
-
-    eprintln!("Evaluating position filter...");
-    let index = index.filter_config(FilterConfig {
-        length: false,
-        position: true,
-    });
-    let position_filter = evaluate_range_search(&index, &queries, args.radius);
-
-    eprintln!("Evaluating all filters...");
-    let index = index.filter_config(FilterConfig {
-        length: true,
-        position: true,
-    });
-    let all_filters = evaluate_range_search(&index, &queries, args.radius);
+    eprintln!("Evaluating...");
+    let [no_filter, length_filter, position_filter, all_filters] =
+        evaluate_range_search(&index, &queries, args.radius)?;
+
+    let dropout = if args.dropout_probes != 0 {
+        eprintln!("Evaluating dropout...");
+        let dropout = Dropout::new(args.dropout_probes, args.dropout_rate, args.dropout_seed)?;
+        let counters = evaluate_dropout(&index, &queries, args.radius, &dropout)?;
+        let accepted = counters.iter().map(|c| c.accepted).sum::<usize>();
+        let expanded = counters.iter().map(|c| c.expanded).sum::<usize>();
+        eprintln!(
+            "Recall gain: {:.3}",
+            expanded as f64 / accepted.max(1) as f64 - 1.
+        );
+        Some(counters)
+    } else {
+        None
+    };
+
+    let lsh = if args.lsh_bands != 0 {
+        eprintln!("Evaluating LSH...");
+        let minhash = MinHash::new(args.lsh_bands, args.lsh_band_size, args.lsh_seed)?;
+        let report = evaluate_lsh(
+            &index,
+            &records,
+            &queries,
+            args.radius,
+            extractor.universe(),
+            minhash,
+        )?;
+        eprintln!(
+            "LSH recall: {:.3}, {:.3} ms per query against {:.3} ms of the inverted index",
+            report.recall, report.lsh_mean_ms, report.inverted_index_mean_ms
+        );
+        Some(report)
+    } else {
+        None
+    };
+
+    let accepted_distances = if args.dump_distances {
+        eprintln!("Dumping accepted distances...");
+        let (accepted, n_mismatches) = dump_accepted_distances(&index, &queries, args.radius)?;
+        eprintln!("Queries with mismatched answers: {n_mismatches}");
+        Some(accepted)
+    } else {
+        None
+    };
+
+    let strata = if args.strata != 0 {
+        let strata = LengthStrata::from_sets(&queries, args.strata)?;
+        let strata = summarize_strata(
+            &strata,
+            &queries,
+            [&no_filter, &length_filter, &position_filter, &all_filters],
+        );
+        for (i, stratum) in strata.iter().enumerate() {
+            eprintln!(
+                "Stratum {i} ({}..={}, {} queries): verified {} -> {} by all filters",
+                stratum.min_len,
+                stratum.max_len,
+                stratum.n_queries,
+                stratum.no_filter.verified,
+                stratum.all_filters.verified
+            );
+        }
+        Some(strata)
+    } else {
+        None
+    };
 
     let output = Output {
         metadata: Metadata {
@@ -139,17 +302,160 @@ fn main() -> Result<(), Box<dyn Error>> {
             max_n: args.max_n,
             radius: args.radius,
             seed: args.seed,
+            dropout_probes: args.dropout_probes,
+            dropout_rate: args.dropout_rate,
+            dropout_seed: args.dropout_seed,
         },
+        query_lens: queries.iter().map(|query| query.len()).collect(),
         no_filter,
         length_filter,
         position_filter,
         all_filters,
+        dropout,
+        lsh,
+        accepted_distances,
+        strata,
     };
-    let j = serde_json::to_string_pretty(&output).unwrap();
+    match args.format {
+        OutputFormat::Json => {
+            let j = serde_json::to_string_pretty(&output).unwrap();
+            let mut file = File::create(args.output_json).unwrap();
+            file.write_all(j.as_bytes()).unwrap();
+        }
+        OutputFormat::Arrow => {
+            write_arrow(&args.output_json, &output)?;
+            if let Some(accepted) = &output.accepted_distances {
+                write_distances_arrow(
+                    args.output_json.with_extension("distances.arrow"),
+                    accepted,
+                )?;
+            }
+        }
+    }
 
-    let mut file = File::create(args.output_json).unwrap();
-    file.write_all(j.as_bytes()).unwrap();
+    Ok(())
+}
 
+// Writes one row per query with the counters of each filter configuration as columns,
+// e.g., `no_filter_verified`. The strata can be recomputed from `query_len`.
+fn write_arrow<P>(path: P, output: &Output) -> Result<(), Box<dyn Error>>
+where
+    P: AsRef<Path>,
+{
+    let mut fields = vec![
+        Field::new("query_idx", DataType::UInt32, false),
+        Field::new("query_len", DataType::UInt64, false),
+    ];
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from_iter_values(
+            0..output.query_lens.len() as u32,
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            output.query_lens.iter().map(|&len| len as u64),
+        )),
+    ];
+    let configs = [
+        ("no_filter", &output.no_filter),
+        ("length_filter", &output.length_filter),
+        ("position_filter", &output.position_filter),
+        ("all_filters", &output.all_filters),
+    ];
+    for (name, counters) in configs {
+        let counts = [
+            "length_filtered",
+            "position_filtered",
+            "verified",
+            "undefined",
+            "accepted",
+        ];
+        for (j, count) in counts.into_iter().enumerate() {
+            fields.push(Field::new(
+                format!("{name}_{count}"),
+                DataType::UInt64,
+                false,
+            ));
+            columns.push(Arc::new(UInt64Array::from_iter_values(
+                counters.iter().map(|c| c.as_array()[j] as u64),
+            )));
+        }
+    }
+    if let Some(dropout) = &output.dropout {
+        fields.push(Field::new("dropout_accepted", DataType::UInt64, false));
+        fields.push(Field::new("dropout_expanded", DataType::UInt64, false));
+        columns.push(Arc::new(UInt64Array::from_iter_values(
+            dropout.iter().map(|c| c.accepted as u64),
+        )));
+        columns.push(Arc::new(UInt64Array::from_iter_values(
+            dropout.iter().map(|c| c.expanded as u64),
+        )));
+    }
+    if let Some(lsh) = &output.lsh {
+        let counts = ["expected", "found", "candidates"];
+        for (j, count) in counts.into_iter().enumerate() {
+            fields.push(Field::new(format!("lsh_{count}"), DataType::UInt64, false));
+            columns.push(Arc::new(UInt64Array::from_iter_values(
+                lsh.counters
+                    .iter()
+                    .map(|c| [c.expected, c.found, c.candidates][j] as u64),
+            )));
+        }
+    }
+    let metadata = [(
+        "metadata".to_string(),
+        serde_json::to_string(&output.metadata)?,
+    )];
+    let schema = Schema::new(fields).with_metadata(metadata.into_iter().collect());
+    write_batch(path, RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+// Writes one row per accepted pair of each filter configuration.
+fn write_distances_arrow<P>(path: P, accepted: &AcceptedDistances) -> Result<(), Box<dyn Error>>
+where
+    P: AsRef<Path>,
+{
+    let configs = [
+        ("no_filter", &accepted.no_filter),
+        ("length_filter", &accepted.length_filter),
+        ("position_filter", &accepted.position_filter),
+        ("all_filters", &accepted.all_filters),
+    ];
+    let mut filters = vec![];
+    let mut query_idxs = vec![];
+    let mut ids = vec![];
+    let mut dists = vec![];
+    for (name, per_query) in configs {
+        for (i, answers) in per_query.iter().enumerate() {
+            for &(id, dist) in answers {
+                filters.push(name);
+                query_idxs.push(i as u32);
+                ids.push(id);
+                dists.push(dist);
+            }
+        }
+    }
+    let schema = Schema::new(vec![
+        Field::new("filters", DataType::Utf8, false),
+        Field::new("query_idx", DataType::UInt32, false),
+        Field::new("id", DataType::UInt32, false),
+        Field::new("dist", DataType::Float32, false),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(filters)),
+        Arc::new(UInt32Array::from(query_idxs)),
+        Arc::new(UInt32Array::from(ids)),
+        Arc::new(Float32Array::from(dists)),
+    ];
+    write_batch(path, RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+fn write_batch<P>(path: P, batch: RecordBatch) -> Result<(), Box<dyn Error>>
+where
+    P: AsRef<Path>,
+{
+    let file = BufWriter::new(File::create(path)?);
+    let mut writer = FileWriter::try_new(file, &batch.schema())?;
+    writer.write(&batch)?;
+    writer.finish()?;
     Ok(())
 }
 
@@ -166,21 +472,157 @@ fn evaluate_range_search(
     index: &LinearScan,
     queries: &[OrderedSet<u32>],
     radius: f32,
-) -> Vec<Counter> {
-    let mut counters = Vec::with_capacity(queries.len());
+) -> Result<[Vec<Counter>; 4], Box<dyn Error>> {
+    let mut counters: [Vec<Counter>; 4] = Default::default();
     for query in queries {
-        let evals = index.evaluate(query, radius);
-        let mut counter = Counter::default();
-        for eval in evals {
-            match eval {
-                Evaluation::LengthFiltered => counter.length_filtered += 1,
-                Evaluation::PositionFiltered => counter.position_filtered += 1,
-                Evaluation::Verified => counter.verified += 1,
-                Evaluation::Undefined => counter.undefined += 1,
-                Evaluation::Accepted(_) => counter.accepted += 1,
+        let sweeps = index.evaluate_sweep(query, radius)?;
+        for (counters, cfg) in counters.iter_mut().zip(FilterConfig::PRESETS) {
+            let mut counter = Counter::default();
+            for sweep in &sweeps {
+                match sweep.get(&cfg) {
+                    Evaluation::LengthFiltered => counter.length_filtered += 1,
+                    Evaluation::PositionFiltered => counter.position_filtered += 1,
+                    Evaluation::Verified => counter.verified += 1,
+                    Evaluation::Undefined => counter.undefined += 1,
+                    Evaluation::Accepted(_) => counter.accepted += 1,
+                }
             }
+            counters.push(counter);
         }
-        counters.push(counter);
     }
-    counters
+    Ok(counters)
+}
+
+fn summarize_strata(
+    strata: &LengthStrata,
+    queries: &[OrderedSet<u32>],
+    counters: [&[Counter]; 4],
+) -> Vec<Stratum> {
+    let tags = strata.tag(queries);
+    (0..strata.n_strata())
+        .map(|i| {
+            let sum = |counters: &[Counter]| {
+                let mut sum = Counter::default();
+                for (counter, _) in counters.iter().zip(&tags).filter(|(_, &tag)| tag == i) {
+                    sum.length_filtered += counter.length_filtered;
+                    sum.position_filtered += counter.position_filtered;
+                    sum.verified += counter.verified;
+                    sum.undefined += counter.undefined;
+                    sum.accepted += counter.accepted;
+                }
+                sum
+            };
+            let lens = queries
+                .iter()
+                .zip(&tags)
+                .filter(|(_, &tag)| tag == i)
+                .map(|(query, _)| query.len());
+            Stratum {
+                min_len: lens.clone().min().unwrap_or(0),
+                max_len: lens.clone().max().unwrap_or(0),
+                n_queries: lens.count(),
+                no_filter: sum(counters[0]),
+                length_filter: sum(counters[1]),
+                position_filter: sum(counters[2]),
+                all_filters: sum(counters[3]),
+            }
+        })
+        .collect()
+}
+
+fn evaluate_dropout(
+    index: &LinearScan,
+    queries: &[OrderedSet<u32>],
+    radius: f32,
+    dropout: &Dropout,
+) -> Result<Vec<DropoutCounter>, Box<dyn Error>> {
+    let mut counters = Vec::with_capacity(queries.len());
+    for query in queries {
+        counters.push(DropoutCounter {
+            accepted: index.range_query(query, radius)?.len(),
+            expanded: index.range_query_dropout(query, radius, dropout)?.len(),
+        });
+    }
+    Ok(counters)
+}
+
+fn evaluate_lsh(
+    index: &LinearScan,
+    records: &[Record<u32>],
+    queries: &[OrderedSet<u32>],
+    radius: f32,
+    universe: u32,
+    minhash: MinHash,
+) -> Result<LshReport, Box<dyn Error>> {
+    let (n_bands, band_size) = (minhash.n_bands(), minhash.band_size());
+    let lsh_index = MinHashIndex::from_records(records, universe, radius, minhash)?;
+    let inverted_index = InvertedIndex::from_records(records, universe, radius)?;
+
+    let mut counters = Vec::with_capacity(queries.len());
+    let mut lsh_elapsed = 0.;
+    let mut inverted_index_elapsed = 0.;
+    for query in queries {
+        let expected = index.range_query(query, radius)?;
+
+        let start_tp = Instant::now();
+        let (answers, candidates) = lsh_index.range_query_with_candidates(query);
+        lsh_elapsed += start_tp.elapsed().as_secs_f64();
+
+        let start_tp = Instant::now();
+        inverted_index.range_query(query);
+        inverted_index_elapsed += start_tp.elapsed().as_secs_f64();
+
+        counters.push(LshCounter {
+            expected: expected.len(),
+            found: answers.len(),
+            candidates,
+        });
+    }
+    let expected = counters.iter().map(|c| c.expected).sum::<usize>();
+    let found = counters.iter().map(|c| c.found).sum::<usize>();
+    let n_queries = queries.len().max(1) as f64;
+    Ok(LshReport {
+        n_bands,
+        band_size,
+        recall: if expected == 0 {
+            1.
+        } else {
+            found as f64 / expected as f64
+        },
+        lsh_mean_ms: lsh_elapsed * 1000. / n_queries,
+        inverted_index_mean_ms: inverted_index_elapsed * 1000. / n_queries,
+        counters,
+    })
+}
+
+// Also returns the number of queries whose answers differ between the configurations.
+fn dump_accepted_distances(
+    index: &LinearScan,
+    queries: &[OrderedSet<u32>],
+    radius: f32,
+) -> Result<(AcceptedDistances, usize), Box<dyn Error>> {
+    let mut accepted: [Vec<Vec<(u32, f32)>>; 4] = Default::default();
+    let mut n_mismatches = 0;
+    for query in queries {
+        let mut answers = Vec::with_capacity(FilterConfig::PRESETS.len());
+        for cfg in FilterConfig::PRESETS {
+            answers.push(index.evaluate_accepted(query, radius, cfg)?);
+        }
+        if answers.iter().any(|ans| ans != &answers[0]) {
+            n_mismatches += 1;
+        }
+        for (accepted, answers) in accepted.iter_mut().zip(answers) {
+            accepted.push(answers.into_iter().map(<(u32, f32)>::from).collect());
+        }
+    }
+    let [no_filter, length_filter, position_filter, all_filters] = accepted;
+    Ok((
+        AcceptedDistances {
+            no_filter,
+            length_filter,
+            position_filter,
+            all_filters,
+        },
+        n_mismatches,
+    ))
 }
@@ -0,0 +1,498 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+use set_search_experiment::kernel;
+use set_search_experiment::linear_scan::QueryStats;
+use set_search_experiment::stats::LengthStrata;
+use set_search_experiment::text::FeatureExtractor;
+use set_search_experiment::{
+    AdaptiveIndex, Answer, ExactIndex, FilterConfig, InvertedIndex, LinearScan, OrderedSet, Record,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum IndexType {
+    LinearScan,
+    InvertedIndex,
+    // Inverted index with the prefix lengths chosen per query, against the fixed ones.
+    Adaptive,
+    Exact,
+}
+
+// One row per (index, max_n, filter config, radius, stratum), flat for pandas.
+#[derive(Clone, Serialize)]
+struct Cell {
+    index: IndexType,
+    max_n: usize,
+    // Query length stratum, or none for all the queries.
+    stratum: Option<usize>,
+    length: bool,
+    position: bool,
+    radius: f32,
+    n_database: usize,
+    n_queries: usize,
+    build_ms: f64,
+    // Growth of the resident set size by building the index, if available.
+    rss_delta_kb: Option<u64>,
+    latency_mean_ms: f64,
+    latency_p50_ms: f64,
+    latency_p95_ms: f64,
+    latency_p99_ms: f64,
+    // Latencies right after flushing caches, in the cold mode.
+    cold_latency_mean_ms: Option<f64>,
+    cold_latency_p50_ms: Option<f64>,
+    cold_latency_p95_ms: Option<f64>,
+    cold_latency_p99_ms: Option<f64>,
+    n_answers: usize,
+    // Filter counters, available for the linear scan only.
+    length_filtered: Option<usize>,
+    position_filtered: Option<usize>,
+    verified: Option<usize>,
+    // Heap allocations in the warm runs, with the `count-allocs` feature only.
+    allocs_per_query: Option<f64>,
+    alloc_bytes_per_query: Option<f64>,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(short = 'd', long)]
+    database_file: PathBuf,
+
+    #[arg(short = 'q', long)]
+    query_file: PathBuf,
+
+    // Written in CSV if the extension is `.csv`, or in JSON otherwise.
+    #[arg(short = 'o', long)]
+    output_file: PathBuf,
+
+    #[arg(short = 'u', long, default_value_t = 1 << 20)]
+    universe: u32,
+
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "linear-scan,inverted-index"
+    )]
+    indexes: Vec<IndexType>,
+
+    #[arg(long, value_delimiter = ',', default_value = "0.1,0.3,0.5")]
+    radii: Vec<f32>,
+
+    #[arg(long, value_delimiter = ',', default_value = "1")]
+    max_ns: Vec<usize>,
+
+    #[arg(long)]
+    seed: Option<u64>,
+
+    // Flushes caches before every batch of queries, and measures the batch cold
+    // and then warm.
+    #[arg(long)]
+    cold: bool,
+
+    #[arg(long, default_value_t = 10)]
+    batch_size: usize,
+
+    #[arg(long, default_value_t = 256)]
+    flush_mb: usize,
+
+    // Number of query length quantiles to also report the cells per, e.g., 10 for deciles.
+    #[arg(long, default_value_t = 0)]
+    strata: usize,
+
+    // Uses the scalar intersection kernel instead of the SIMD one detected at runtime.
+    #[arg(long)]
+    force_scalar: bool,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    let database_texts = load_lines(&args.database_file)?;
+    let query_texts = load_lines(&args.query_file)?;
+    eprintln!("n_database: {}", database_texts.len());
+    eprintln!("n_queries: {}", query_texts.len());
+
+    kernel::force_scalar(args.force_scalar);
+    eprintln!("kernel: {:?}", kernel::active());
+
+    let mut flusher = args.cold.then(|| CacheFlusher {
+        buf: vec![0; args.flush_mb << 20],
+        batch_size: args.batch_size.max(1),
+    });
+    let flusher = &mut flusher;
+
+    let mut cells = Vec::new();
+    for &max_n in &args.max_ns {
+        let extractor = FeatureExtractor::new(1..=max_n, args.universe, args.seed)?;
+        let extract =
+            |text: &String| extractor.extract(&text.split_whitespace().collect::<Vec<_>>());
+        let records = database_texts
+            .iter()
+            .enumerate()
+            .map(|(id, text)| Record {
+                id: id as u32,
+                set: extract(text),
+            })
+            .collect::<Vec<_>>();
+        let queries = query_texts.iter().map(extract).collect::<Vec<_>>();
+        let tags = if args.strata != 0 {
+            LengthStrata::from_sets(&queries, args.strata)?.tag(&queries)
+        } else {
+            vec![]
+        };
+        let cell = |index, config: &FilterConfig, radius, build: &Measured| Cell {
+            index,
+            max_n,
+            stratum: None,
+            length: config.length,
+            position: config.position,
+            radius,
+            n_database: records.len(),
+            n_queries: queries.len(),
+            build_ms: build.ms,
+            rss_delta_kb: build.rss_delta_kb,
+            latency_mean_ms: 0.0,
+            latency_p50_ms: 0.0,
+            latency_p95_ms: 0.0,
+            latency_p99_ms: 0.0,
+            cold_latency_mean_ms: None,
+            cold_latency_p50_ms: None,
+            cold_latency_p95_ms: None,
+            cold_latency_p99_ms: None,
+            n_answers: 0,
+            length_filtered: None,
+            position_filtered: None,
+            verified: None,
+            allocs_per_query: None,
+            alloc_bytes_per_query: None,
+        };
+
+        for &index_type in &args.indexes {
+            eprintln!("Measuring {:?} with max_n={}...", index_type, max_n);
+            match index_type {
+                IndexType::LinearScan => {
+                    for config in FilterConfig::PRESETS {
+                        let (index, build) = measure_build(|| {
+                            let index = LinearScan::from_records(&records, extractor.universe())?;
+                            index.filter_config(config.clone())
+                        })?;
+                        for &radius in &args.radii {
+                            let measured = measure_queries(&queries, flusher, |query| {
+                                let (answers, stats) =
+                                    index.range_query_with_stats(query, radius)?;
+                                Ok((answers, Some(stats)))
+                            })?;
+                            let cell = cell(index_type, &config, radius, &build);
+                            cells.extend(stratify(cell, &measured, &tags));
+                        }
+                    }
+                }
+                IndexType::InvertedIndex => {
+                    let config = FilterConfig::ALL;
+                    for &radius in &args.radii {
+                        let (index, build) = measure_build(|| {
+                            InvertedIndex::from_records(&records, extractor.universe(), radius)
+                        })?;
+                        let measured = measure_queries(&queries, flusher, |query| {
+                            Ok((index.range_query(query), None))
+                        })?;
+                        let cell = cell(index_type, &config, radius, &build);
+                        cells.extend(stratify(cell, &measured, &tags));
+                    }
+                }
+                IndexType::Adaptive => {
+                    let config = FilterConfig::ALL;
+                    for &radius in &args.radii {
+                        let (index, build) = measure_build(|| {
+                            AdaptiveIndex::from_records(&records, extractor.universe(), radius)
+                        })?;
+                        let measured = measure_queries(&queries, flusher, |query| {
+                            Ok((index.range_query(query), None))
+                        })?;
+                        let cell = cell(index_type, &config, radius, &build);
+                        cells.extend(stratify(cell, &measured, &tags));
+                    }
+                }
+                IndexType::Exact => {
+                    let (index, build) =
+                        measure_build(|| ExactIndex::from_records(&records, extractor.universe()))?;
+                    let measured = measure_queries(&queries, flusher, |query| {
+                        Ok((index.range_query(query), None))
+                    })?;
+                    let cell = cell(index_type, &FilterConfig::NONE, 0.0, &build);
+                    cells.extend(stratify(cell, &measured, &tags));
+                }
+            }
+        }
+    }
+
+    let mut file = File::create(&args.output_file)?;
+    if args.output_file.extension().is_some_and(|ext| ext == "csv") {
+        file.write_all(to_csv(&cells)?.as_bytes())?;
+    } else {
+        file.write_all(serde_json::to_string_pretty(&cells)?.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+struct Measured {
+    ms: f64,
+    rss_delta_kb: Option<u64>,
+}
+
+fn measure_build<T, F>(build: F) -> Result<(T, Measured), Box<dyn Error>>
+where
+    F: FnOnce() -> anyhow::Result<T>,
+{
+    let rss = rss_kb();
+    let start_tp = Instant::now();
+    let index = build()?;
+    let ms = start_tp.elapsed().as_secs_f64() * 1000.;
+    let rss_delta_kb = rss
+        .zip(rss_kb())
+        .map(|(before, after)| after.saturating_sub(before));
+    Ok((index, Measured { ms, rss_delta_kb }))
+}
+
+// The indexes live in memory, so the caches to flush are those of the CPU,
+// evicted by writing a buffer larger than them.
+struct CacheFlusher {
+    buf: Vec<u8>,
+    batch_size: usize,
+}
+
+impl CacheFlusher {
+    fn flush(&mut self) {
+        for i in (0..self.buf.len()).step_by(64) {
+            self.buf[i] = self.buf[i].wrapping_add(1);
+        }
+        std::hint::black_box(&self.buf);
+    }
+}
+
+struct QueryMeasured {
+    warm_ms: f64,
+    // Latency right after flushing caches, in the cold mode.
+    cold_ms: Option<f64>,
+    n_answers: usize,
+    stats: Option<QueryStats>,
+    // Numbers of the allocations and the allocated bytes in the warm run, if counted.
+    allocs: Option<(u64, u64)>,
+}
+
+fn measure_queries<F>(
+    queries: &[OrderedSet<u32>],
+    flusher: &mut Option<CacheFlusher>,
+    mut search: F,
+) -> Result<Vec<QueryMeasured>, Box<dyn Error>>
+where
+    F: FnMut(&OrderedSet<u32>) -> anyhow::Result<(Vec<Answer>, Option<QueryStats>)>,
+{
+    let mut measured = Vec::with_capacity(queries.len());
+    let batch_size = flusher
+        .as_ref()
+        .map_or(queries.len().max(1), |flusher| flusher.batch_size);
+    for batch in queries.chunks(batch_size) {
+        let mut cold = vec![None; batch.len()];
+        if let Some(flusher) = flusher.as_mut() {
+            flusher.flush();
+            for (query, cold) in batch.iter().zip(&mut cold) {
+                let start_tp = Instant::now();
+                search(query)?;
+                *cold = Some(start_tp.elapsed().as_secs_f64() * 1000.);
+            }
+        }
+        // The answers and counters are taken from the warm runs.
+        for (query, cold_ms) in batch.iter().zip(cold) {
+            let allocs = alloc_counts();
+            let start_tp = Instant::now();
+            let (answers, stats) = search(query)?;
+            let warm_ms = start_tp.elapsed().as_secs_f64() * 1000.;
+            let allocs = allocs
+                .zip(alloc_counts())
+                .map(|(before, after)| (after.0 - before.0, after.1 - before.1));
+            measured.push(QueryMeasured {
+                warm_ms,
+                cold_ms,
+                n_answers: answers.len(),
+                stats,
+                allocs,
+            });
+        }
+    }
+    Ok(measured)
+}
+
+// Returns the cell over all the queries followed by the cells per stratum, if tagged.
+fn stratify(cell: Cell, measured: &[QueryMeasured], tags: &[usize]) -> Vec<Cell> {
+    let n_strata = tags.iter().max().map_or(0, |&max| max + 1);
+    let mut cells = vec![fill_cell(cell.clone(), measured.iter())];
+    for stratum in 0..n_strata {
+        let measured = measured
+            .iter()
+            .zip(tags)
+            .filter(|(_, &tag)| tag == stratum)
+            .map(|(measured, _)| measured);
+        let cell = Cell {
+            stratum: Some(stratum),
+            ..cell.clone()
+        };
+        cells.push(fill_cell(cell, measured));
+    }
+    cells
+}
+
+fn fill_cell<'a, I>(mut cell: Cell, measured: I) -> Cell
+where
+    I: Iterator<Item = &'a QueryMeasured>,
+{
+    let mut warm = vec![];
+    let mut cold = vec![];
+    for measured in measured {
+        warm.push(measured.warm_ms);
+        cold.extend(measured.cold_ms);
+        cell.n_answers += measured.n_answers;
+        if let Some(stats) = &measured.stats {
+            *cell.length_filtered.get_or_insert(0) += stats.length_filtered;
+            *cell.position_filtered.get_or_insert(0) += stats.position_filtered;
+            *cell.verified.get_or_insert(0) += stats.verified;
+        }
+        if let Some((n_allocs, n_bytes)) = measured.allocs {
+            *cell.allocs_per_query.get_or_insert(0.0) += n_allocs as f64;
+            *cell.alloc_bytes_per_query.get_or_insert(0.0) += n_bytes as f64;
+        }
+    }
+    cell.n_queries = warm.len();
+    if cell.n_queries != 0 {
+        let n_queries = cell.n_queries as f64;
+        cell.allocs_per_query = cell.allocs_per_query.map(|n| n / n_queries);
+        cell.alloc_bytes_per_query = cell.alloc_bytes_per_query.map(|n| n / n_queries);
+    }
+    if let Some([mean, p50, p95, p99]) = summarize(warm) {
+        cell.latency_mean_ms = mean;
+        cell.latency_p50_ms = p50;
+        cell.latency_p95_ms = p95;
+        cell.latency_p99_ms = p99;
+    }
+    if let Some([mean, p50, p95, p99]) = summarize(cold) {
+        cell.cold_latency_mean_ms = Some(mean);
+        cell.cold_latency_p50_ms = Some(p50);
+        cell.cold_latency_p95_ms = Some(p95);
+        cell.cold_latency_p99_ms = Some(p99);
+    }
+    cell
+}
+
+// Returns the mean, p50, p95, and p99.
+fn summarize(mut latencies: Vec<f64>) -> Option<[f64; 4]> {
+    if latencies.is_empty() {
+        return None;
+    }
+    latencies.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p).round() as usize];
+    let mean = latencies.iter().sum::<f64>() / latencies.len() as f64;
+    Some([mean, percentile(0.5), percentile(0.95), percentile(0.99)])
+}
+
+// Counts the allocations of the whole process, including the reallocations,
+// which the queries of a single thread are measured by.
+#[cfg(feature = "count-allocs")]
+mod counting {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    pub static N_ALLOCS: AtomicU64 = AtomicU64::new(0);
+    pub static N_BYTES: AtomicU64 = AtomicU64::new(0);
+
+    pub struct CountingAllocator;
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            N_ALLOCS.fetch_add(1, Ordering::Relaxed);
+            N_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            N_ALLOCS.fetch_add(1, Ordering::Relaxed);
+            N_BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+}
+
+// Returns the numbers of the allocations and the allocated bytes so far, if counted.
+#[cfg(feature = "count-allocs")]
+fn alloc_counts() -> Option<(u64, u64)> {
+    use std::sync::atomic::Ordering;
+    Some((
+        counting::N_ALLOCS.load(Ordering::Relaxed),
+        counting::N_BYTES.load(Ordering::Relaxed),
+    ))
+}
+
+#[cfg(not(feature = "count-allocs"))]
+fn alloc_counts() -> Option<(u64, u64)> {
+    None
+}
+
+// Reads VmRSS of /proc/self/status, available on Linux.
+fn rss_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn to_csv<T>(rows: &[T]) -> Result<String, Box<dyn Error>>
+where
+    T: Serialize,
+{
+    let rows = rows
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()?;
+    let Some(first) = rows.first().and_then(|row| row.as_object()) else {
+        return Ok(String::new());
+    };
+    let header = first.keys().cloned().collect::<Vec<_>>();
+    let mut csv = header.join(",") + "\n";
+    for row in &rows {
+        let fields = header
+            .iter()
+            .map(|key| match &row[key] {
+                serde_json::Value::Null => String::new(),
+                serde_json::Value::String(s) => s.clone(),
+                value => value.to_string(),
+            })
+            .collect::<Vec<_>>();
+        csv += &(fields.join(",") + "\n");
+    }
+    Ok(csv)
+}
+
+fn load_lines<P>(path: P) -> Result<Vec<String>, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+{
+    let reader = BufReader::new(File::open(path)?);
+    let lines = reader.lines().collect::<Result<Vec<_>, _>>()?;
+    Ok(lines)
+}
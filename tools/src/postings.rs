@@ -0,0 +1,118 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use clap::Parser;
+use serde::Serialize;
+use set_search_experiment::text::VocabExtractor;
+use set_search_experiment::InvertedIndex;
+use set_search_experiment::Record;
+
+#[derive(Serialize)]
+struct Output {
+    metadata: Metadata,
+    postings: Vec<Posting>,
+}
+
+#[derive(Serialize)]
+struct Metadata {
+    database_file: String,
+    n_database: usize,
+    max_n: usize,
+    radius: f32,
+    n_postings: usize,
+    total_len: usize,
+}
+
+#[derive(Serialize)]
+struct Posting {
+    ngram: String,
+    len: usize,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(short = 'd', long)]
+    database_file: PathBuf,
+
+    #[arg(short = 'o', long)]
+    output_json: PathBuf,
+
+    #[arg(short = 'n', long, default_value_t = 1)]
+    max_n: usize,
+
+    #[arg(short = 'r', long)]
+    radius: f32,
+
+    #[arg(short = 't', long, default_value_t = 20)]
+    top: usize,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    let database_texts = load_lines(&args.database_file)?;
+    eprintln!("n_database: {}", database_texts.len());
+
+    // Distinct n-grams get distinct ids, so the ids can be mapped back to the n-grams.
+    let mut extractor = VocabExtractor::new(1..=args.max_n)?;
+    let mut records = Vec::with_capacity(database_texts.len());
+    for (id, text) in database_texts.iter().enumerate() {
+        let tokens = text.split_whitespace().collect::<Vec<_>>();
+        let set = extractor.extract(&tokens);
+        records.push(Record { id: id as u32, set });
+    }
+    let universe = extractor.vocab_size().max(1) as u32;
+    let index = InvertedIndex::from_records(&records, universe, args.radius)?;
+
+    let mut postings = index.postings().collect::<Vec<_>>();
+    let n_postings = postings.len();
+    let total_len = postings.iter().map(|&(_, len)| len).sum::<usize>();
+    eprintln!("n_postings: {}", n_postings);
+    eprintln!("total_len: {}", total_len);
+
+    postings.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let postings = postings
+        .into_iter()
+        .take(args.top)
+        .map(|(elem, len)| Posting {
+            ngram: extractor.ngram(elem).unwrap().join(" "),
+            len,
+        })
+        .collect::<Vec<_>>();
+    for posting in &postings {
+        eprintln!("{}\t{}", posting.len, posting.ngram);
+    }
+
+    let output = Output {
+        metadata: Metadata {
+            database_file: args.database_file.to_string_lossy().to_string(),
+            n_database: database_texts.len(),
+            max_n: args.max_n,
+            radius: args.radius,
+            n_postings,
+            total_len,
+        },
+        postings,
+    };
+    let j = serde_json::to_string_pretty(&output)?;
+
+    let mut file = File::create(args.output_json)?;
+    file.write_all(j.as_bytes())?;
+
+    Ok(())
+}
+
+fn load_lines<P>(path: P) -> Result<Vec<String>, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+{
+    let reader = BufReader::new(File::open(path)?);
+    let lines = reader.lines().collect::<Result<Vec<_>, _>>()?;
+    Ok(lines)
+}
@@ -0,0 +1,212 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use clap::Parser;
+use serde::Serialize;
+use set_search_experiment::query_log::{read_query_log, LoggedQuery};
+use set_search_experiment::text::{char_tokens, FeatureExtractor};
+use set_search_experiment::FilterConfig;
+use set_search_experiment::LinearScan;
+use set_search_experiment::Record;
+
+#[derive(Serialize)]
+struct Output {
+    metadata: Metadata,
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+struct Metadata {
+    database_file: String,
+    log_file: String,
+    n_database: usize,
+    n_entries: usize,
+    max_n: usize,
+    length: bool,
+    position: bool,
+    cjk: bool,
+    // Entries answered with other numbers of records than logged.
+    n_mismatched: usize,
+    logged_mean_ms: f64,
+    logged_p95_ms: f64,
+    replayed_mean_ms: f64,
+    replayed_p95_ms: f64,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    radius: Option<f32>,
+    topk: Option<usize>,
+    logged_answers: usize,
+    replayed_answers: usize,
+    logged_ms: f64,
+    replayed_ms: f64,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    // Database of the new build, indexed with the extractor of the log.
+    #[arg(short = 'd', long)]
+    database_file: PathBuf,
+
+    // Query log written by search with --query-log.
+    #[arg(short = 'l', long)]
+    log_file: PathBuf,
+
+    #[arg(short = 'o', long)]
+    output_json: PathBuf,
+
+    #[arg(short = 'n', long, default_value_t = 1)]
+    max_n: usize,
+
+    #[arg(short = 'u', long, default_value_t = 1 << 20)]
+    universe: u32,
+
+    // "none", "all", or comma-separated filters, e.g., "length,position".
+    #[arg(short = 'f', long, default_value_t = FilterConfig::NONE)]
+    filters: FilterConfig,
+
+    #[arg(long)]
+    seed: Option<u64>,
+
+    #[arg(long)]
+    cjk: bool,
+
+    // Must be the same as that of the logging run to match its extractor.
+    #[arg(long)]
+    require_max_order: bool,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    let (header, logged) = read_query_log(BufReader::new(File::open(&args.log_file)?))?;
+    eprintln!("n_entries: {}", logged.len());
+    let mut extractor = FeatureExtractor::new(1..=args.max_n, args.universe, args.seed)?;
+    if args.require_max_order {
+        extractor = extractor.tag_orders()?;
+    }
+    header.check_extractor(&extractor)?;
+
+    let database_texts = load_lines(&args.database_file)?;
+    eprintln!("n_database: {}", database_texts.len());
+
+    eprintln!("Indexing...");
+    let records = database_texts
+        .iter()
+        .enumerate()
+        .map(|(id, text)| Record {
+            id: id as u32,
+            set: extractor.extract(&tokenize(text, args.cjk)),
+        })
+        .collect::<Vec<_>>();
+    let index = LinearScan::from_records(&records, extractor.universe())?
+        .filter_config(args.filters.clone())?;
+
+    // Logged queries are mapped by the mapping of the logging build.
+    let unmapping = header.mapping.inverse();
+
+    eprintln!("Replaying...");
+    let mut entries = Vec::with_capacity(logged.len());
+    for (i, entry) in logged.iter().enumerate() {
+        if i % 100 == 0 {
+            eprintln!("{} / {}", i, logged.len());
+        }
+        let query = unmapping.apply(&entry.query);
+        let start_tp = Instant::now();
+        let answers = match entry.kind {
+            LoggedQuery::Range { radius } => index.range_query(&query, radius)?,
+            LoggedQuery::Topk { k } => index.topk_query(&query, k)?,
+        };
+        let elapsed = start_tp.elapsed();
+        let (radius, topk) = match entry.kind {
+            LoggedQuery::Range { radius } => (Some(radius), None),
+            LoggedQuery::Topk { k } => (None, Some(k)),
+        };
+        entries.push(Entry {
+            radius,
+            topk,
+            logged_answers: entry.n_answers,
+            replayed_answers: answers.len(),
+            logged_ms: entry.elapsed.as_secs_f64() * 1000.,
+            replayed_ms: elapsed.as_secs_f64() * 1000.,
+        });
+    }
+
+    let n_mismatched = entries
+        .iter()
+        .filter(|e| e.logged_answers != e.replayed_answers)
+        .count();
+    eprintln!("n_mismatched: {}", n_mismatched);
+    let logged_ms = entries.iter().map(|e| e.logged_ms).collect::<Vec<_>>();
+    let replayed_ms = entries.iter().map(|e| e.replayed_ms).collect::<Vec<_>>();
+    let output = Output {
+        metadata: Metadata {
+            database_file: args.database_file.to_string_lossy().to_string(),
+            log_file: args.log_file.to_string_lossy().to_string(),
+            n_database: database_texts.len(),
+            n_entries: entries.len(),
+            max_n: args.max_n,
+            length: args.filters.length,
+            position: args.filters.position,
+            cjk: args.cjk,
+            n_mismatched,
+            logged_mean_ms: mean(&logged_ms),
+            logged_p95_ms: percentile(logged_ms.clone(), 0.95),
+            replayed_mean_ms: mean(&replayed_ms),
+            replayed_p95_ms: percentile(replayed_ms.clone(), 0.95),
+        },
+        entries,
+    };
+    eprintln!(
+        "Mean latency: {:.3} ms logged, {:.3} ms replayed",
+        output.metadata.logged_mean_ms, output.metadata.replayed_mean_ms
+    );
+    let j = serde_json::to_string_pretty(&output)?;
+    let mut file = File::create(args.output_json)?;
+    file.write_all(j.as_bytes())?;
+
+    Ok(())
+}
+
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+// Nearest-rank percentile.
+fn percentile(mut values: Vec<f64>, p: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_unstable_by(|a, b| a.total_cmp(b));
+    let rank = ((p * values.len() as f64).ceil() as usize).clamp(1, values.len());
+    values[rank - 1]
+}
+
+// Splits text into characters in the CJK mode, or into words otherwise.
+fn tokenize(text: &str, cjk: bool) -> Vec<&str> {
+    if cjk {
+        char_tokens(text)
+    } else {
+        text.split_whitespace().collect()
+    }
+}
+
+fn load_lines<P>(path: P) -> Result<Vec<String>, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+{
+    let reader = BufReader::new(File::open(path)?);
+    let lines = reader.lines().collect::<Result<Vec<_>, _>>()?;
+    Ok(lines)
+}
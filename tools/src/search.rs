@@ -2,18 +2,33 @@ use std::error::Error;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::BufWriter;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Instant;
 
-use clap::Parser;
+use arrow_array::{ArrayRef, Float32Array, RecordBatch, StringArray, UInt32Array};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema};
+use clap::{Parser, ValueEnum};
 use serde::Serialize;
-use set_search_experiment::text::FeatureExtractor;
+use set_search_experiment::metric::Jaccard;
+use set_search_experiment::query_log::{LoggedQuery, QueryLog};
+use set_search_experiment::text::{char_tokens, FeatureExtractor, VocabExtractor};
 use set_search_experiment::FilterConfig;
 use set_search_experiment::LinearScan;
+use set_search_experiment::OrderedSet;
 use set_search_experiment::Record;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Json,
+    // Arrow IPC file (Feather v2) with one row per found, and the metadata in the schema.
+    Arrow,
+}
+
 #[derive(Serialize)]
 struct Output {
     metadata: Metadata,
@@ -31,6 +46,11 @@ struct Metadata {
     topk: Option<usize>,
     length: bool,
     position: bool,
+    exact: bool,
+    drop_collisions: bool,
+    require_max_order: bool,
+    highlight: bool,
+    cjk: bool,
 }
 
 #[derive(Serialize)]
@@ -41,9 +61,14 @@ struct Answer {
 
 #[derive(Serialize)]
 struct Found {
-    id: u32,
-    dist: f32,
+    #[serde(flatten)]
+    answer: set_search_experiment::Answer,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exact_dist: Option<f32>,
     text: String,
+    // Token spans [start, end) of the text producing the features shared with the query.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spans: Option<Vec<(usize, usize)>>,
 }
 
 #[derive(Parser, Debug)]
@@ -58,6 +83,9 @@ struct Args {
     #[arg(short = 'o', long)]
     output_json: PathBuf,
 
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    format: OutputFormat,
+
     #[arg(short = 'n', long, default_value_t = 1)]
     max_n: usize,
 
@@ -70,14 +98,37 @@ struct Args {
     #[arg(short = 'k', long)]
     topk: Option<usize>,
 
-    #[arg(short = 'L', long)]
-    length: bool,
-
-    #[arg(short = 'P', long)]
-    position: bool,
+    // "none", "all", or comma-separated filters, e.g., "length,position".
+    #[arg(short = 'f', long, default_value_t = FilterConfig::NONE)]
+    filters: FilterConfig,
 
     #[arg(long)]
     seed: Option<u64>,
+
+    #[arg(long)]
+    exact: bool,
+
+    #[arg(long)]
+    drop_collisions: bool,
+
+    #[arg(long)]
+    require_max_order: bool,
+
+    #[arg(long)]
+    highlight: bool,
+
+    #[arg(long)]
+    cjk: bool,
+
+    #[arg(long, default_value_t = 0)]
+    min_set_len: usize,
+
+    #[arg(long, default_value_t = usize::MAX)]
+    max_set_len: usize,
+
+    // Records the mapped queries and their timings for the replay tool.
+    #[arg(long)]
+    query_log: Option<PathBuf>,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -86,32 +137,54 @@ fn main() -> Result<(), Box<dyn Error>> {
         eprintln!("Either -r or -k must be specified.");
         return Ok(());
     }
+    if args.drop_collisions && args.radius.is_none() {
+        eprintln!("--drop-collisions requires -r.");
+        return Ok(());
+    }
+    let exact = args.exact || args.drop_collisions;
+    if args.highlight && args.format == OutputFormat::Arrow {
+        eprintln!("--highlight is supported only in the JSON format.");
+        return Ok(());
+    }
 
     let database_texts = load_lines(&args.database_file)?;
     let query_texts = load_lines(&args.query_file)?;
     eprintln!("n_database: {}", database_texts.len());
     eprintln!("n_queries: {}", query_texts.len());
 
-    let extractor = FeatureExtractor::new(1..=args.max_n, args.universe, args.seed)?;
+    let mut extractor = FeatureExtractor::new(1..=args.max_n, args.universe, args.seed)?;
+    if args.require_max_order {
+        extractor = extractor.tag_orders()?;
+    }
 
     eprintln!("Indexing...");
     let start_tp = Instant::now();
     let index = {
         let mut records = Vec::with_capacity(database_texts.len());
         for (id, text) in database_texts.iter().enumerate() {
-            let tokens = text.split_whitespace().collect::<Vec<_>>();
+            let tokens = tokenize(text, args.cjk);
             let set = extractor.extract(&tokens);
             let record = Record { id: id as u32, set };
             records.push(record);
         }
-        LinearScan::from_records(&records, extractor.universe())?.filter_config(FilterConfig {
-            length: args.length,
-            position: args.position,
-        })
+        let (index, skipped) = LinearScan::from_records_within(
+            &records,
+            extractor.universe(),
+            args.min_set_len..=args.max_set_len,
+        )?;
+        eprintln!("# of skipped records: {}", skipped.len());
+        index.filter_config(args.filters.clone())?
     };
     let duration = start_tp.elapsed();
     eprintln!("Elapsed: {:.3} sec", duration.as_millis() as f64 / 1000.);
 
+    let mut vocab_extractor = VocabExtractor::new(1..=args.max_n)?;
+    let mut n_collisions = 0;
+    let query_log = match &args.query_log {
+        Some(path) => Some(QueryLog::create(path, index.mapping(), Some(&extractor))?),
+        None => None,
+    };
+
     eprintln!("Querying...");
     let start_tp = Instant::now();
     let mut answers = Vec::with_capacity(query_texts.len());
@@ -119,21 +192,68 @@ fn main() -> Result<(), Box<dyn Error>> {
         if i % 100 == 0 {
             eprintln!("{} / {}", i, query_texts.len());
         }
-        let tokens = query_text.split_whitespace().collect::<Vec<_>>();
+        let tokens = tokenize(query_text, args.cjk);
         let query = extractor.extract(&tokens);
+        let query_tp = Instant::now();
         let searched = if let Some(radius) = args.radius {
-            index.range_query(&query, radius)
+            index.range_query(&query, radius)?
         } else if let Some(topk) = args.topk {
-            index.topk_query(&query, topk)
+            index.topk_query(&query, topk)?
         } else {
             unreachable!()
         };
+        if let Some(query_log) = &query_log {
+            let kind = match args.radius {
+                Some(radius) => LoggedQuery::Range { radius },
+                None => LoggedQuery::Topk {
+                    k: args.topk.unwrap(),
+                },
+            };
+            let mapped = index.mapping().apply(&query);
+            query_log.record(&mapped, kind, query_tp.elapsed(), searched.len())?;
+        }
+        let searched = if args.require_max_order {
+            let required = extractor.features_of_order(&query, args.max_n).unwrap();
+            index.retain_sharing(searched, &required)
+        } else {
+            searched
+        };
+        let exact_query = if exact {
+            vocab_extractor.extract(&tokens)
+        } else {
+            OrderedSet::new()
+        };
         let mut founds = Vec::with_capacity(searched.len());
         for ans in searched {
+            let text = &database_texts[ans.id as usize];
+            let exact_dist = if exact {
+                let tokens = tokenize(text, args.cjk);
+                let set = vocab_extractor.extract(&tokens);
+                let jaccard = Jaccard::new(&exact_query, 1.0, FilterConfig::default());
+                jaccard.distance(&set)
+            } else {
+                None
+            };
+            if let (Some(radius), Some(exact_dist)) = (args.radius, exact_dist) {
+                if exact_dist > radius {
+                    n_collisions += 1;
+                    if args.drop_collisions {
+                        continue;
+                    }
+                }
+            }
+            let spans = if args.highlight {
+                let explanation = index.explain_pair(&query, ans.id, 1.0)?.unwrap();
+                let tokens = tokenize(text, args.cjk);
+                Some(shared_spans(&extractor, &tokens, &explanation.shared))
+            } else {
+                None
+            };
             founds.push(Found {
-                id: ans.id,
-                dist: ans.dist,
-                text: database_texts[ans.id as usize].clone(),
+                answer: ans,
+                exact_dist,
+                text: text.clone(),
+                spans,
             });
         }
         answers.push(Answer {
@@ -141,6 +261,9 @@ fn main() -> Result<(), Box<dyn Error>> {
             founds,
         });
     }
+    if let Some(query_log) = &query_log {
+        query_log.flush()?;
+    }
     let duration = start_tp.elapsed();
     eprintln!(
         "Elapsed: {:.3} ms per query",
@@ -150,6 +273,12 @@ fn main() -> Result<(), Box<dyn Error>> {
     let avg_founds =
         answers.iter().map(|ans| ans.founds.len()).sum::<usize>() as f64 / answers.len() as f64;
     eprintln!("Average # of founds: {:.3}", avg_founds);
+    if exact && args.radius.is_some() {
+        eprintln!(
+            "# of founds out of radius by exact n-grams: {}",
+            n_collisions
+        );
+    }
 
     let output = Output {
         metadata: Metadata {
@@ -160,19 +289,113 @@ fn main() -> Result<(), Box<dyn Error>> {
             max_n: args.max_n,
             radius: args.radius,
             topk: args.topk,
-            length: args.length,
-            position: args.position,
+            length: args.filters.length,
+            position: args.filters.position,
+            exact,
+            drop_collisions: args.drop_collisions,
+            require_max_order: args.require_max_order,
+            highlight: args.highlight,
+            cjk: args.cjk,
         },
         answers,
     };
-    let j = serde_json::to_string_pretty(&output).unwrap();
+    match args.format {
+        OutputFormat::Json => {
+            let j = serde_json::to_string_pretty(&output).unwrap();
+            let mut file = File::create(args.output_json).unwrap();
+            file.write_all(j.as_bytes()).unwrap();
+        }
+        OutputFormat::Arrow => write_arrow(args.output_json, &output)?,
+    }
 
-    let mut file = File::create(args.output_json).unwrap();
-    file.write_all(j.as_bytes()).unwrap();
+    Ok(())
+}
 
+// Writes one row per found, since parsing the nested JSON of large outputs is slow.
+fn write_arrow<P>(path: P, output: &Output) -> Result<(), Box<dyn Error>>
+where
+    P: AsRef<Path>,
+{
+    let rows = output
+        .answers
+        .iter()
+        .enumerate()
+        .flat_map(|(i, ans)| ans.founds.iter().map(move |found| (i, ans, found)));
+    let mut query_idxs = vec![];
+    let mut queries = vec![];
+    let mut ids = vec![];
+    let mut dists = vec![];
+    let mut exact_dists = vec![];
+    let mut texts = vec![];
+    for (i, ans, found) in rows {
+        query_idxs.push(i as u32);
+        queries.push(ans.query.as_str());
+        ids.push(found.answer.id);
+        dists.push(found.answer.dist);
+        exact_dists.push(found.exact_dist);
+        texts.push(found.text.as_str());
+    }
+    let metadata = [(
+        "metadata".to_string(),
+        serde_json::to_string(&output.metadata)?,
+    )];
+    let schema = Schema::new(vec![
+        Field::new("query_idx", DataType::UInt32, false),
+        Field::new("query", DataType::Utf8, false),
+        Field::new("id", DataType::UInt32, false),
+        Field::new("dist", DataType::Float32, false),
+        Field::new("exact_dist", DataType::Float32, true),
+        Field::new("text", DataType::Utf8, false),
+    ])
+    .with_metadata(metadata.into_iter().collect());
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from(query_idxs)),
+        Arc::new(StringArray::from(queries)),
+        Arc::new(UInt32Array::from(ids)),
+        Arc::new(Float32Array::from(dists)),
+        Arc::new(Float32Array::from(exact_dists)),
+        Arc::new(StringArray::from(texts)),
+    ];
+    let batch = RecordBatch::try_new(Arc::new(schema), columns)?;
+    let file = BufWriter::new(File::create(path)?);
+    let mut writer = FileWriter::try_new(file, &batch.schema())?;
+    writer.write(&batch)?;
+    writer.finish()?;
     Ok(())
 }
 
+// Merges the token spans of the shared features into disjoint ones.
+fn shared_spans(
+    extractor: &FeatureExtractor,
+    tokens: &[&str],
+    shared: &[u32],
+) -> Vec<(usize, usize)> {
+    let mut spans = extractor
+        .feature_spans(tokens)
+        .into_iter()
+        .filter(|(id, _)| shared.binary_search(id).is_ok())
+        .map(|(_, span)| (span.start, span.end))
+        .collect::<Vec<_>>();
+    spans.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(spans.len());
+    for (start, end) in spans {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+// Splits text into characters in the CJK mode, or into words otherwise.
+fn tokenize(text: &str, cjk: bool) -> Vec<&str> {
+    if cjk {
+        char_tokens(text)
+    } else {
+        text.split_whitespace().collect()
+    }
+}
+
 fn load_lines<P>(path: P) -> Result<Vec<String>, Box<dyn Error>>
 where
     P: AsRef<Path>,
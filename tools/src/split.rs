@@ -0,0 +1,247 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use clap::Parser;
+use hashbrown::{HashMap, HashSet};
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_xoshiro::Xoshiro256PlusPlus;
+use serde::Serialize;
+use set_search_experiment::pairwise::pairwise_distances_within;
+use set_search_experiment::text::VocabExtractor;
+use set_search_experiment::Record;
+
+#[derive(Serialize)]
+struct Output {
+    metadata: Metadata,
+    leaks: Vec<Leak>,
+}
+
+#[derive(Serialize)]
+struct Metadata {
+    input_file: String,
+    n_input: usize,
+    n_database: usize,
+    n_queries: usize,
+    query_ratio: f64,
+    key_field: Option<usize>,
+    seed: u64,
+    max_n: usize,
+    radius: f32,
+    n_leaks: usize,
+    n_dropped: usize,
+}
+
+// A pair of near-duplicates across the splits, with line numbers of the input.
+#[derive(Serialize)]
+struct Leak {
+    database_line: usize,
+    query_line: usize,
+    dist: f32,
+    database_text: String,
+    query_text: String,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(short = 'i', long)]
+    input_file: PathBuf,
+
+    #[arg(short = 'd', long)]
+    database_file: PathBuf,
+
+    #[arg(short = 'q', long)]
+    query_file: PathBuf,
+
+    // Report of the leaking pairs.
+    #[arg(short = 'o', long)]
+    output_json: PathBuf,
+
+    // Fraction of the lines, or of the keys with --key-field, to put in the queries.
+    #[arg(long, default_value_t = 0.1)]
+    query_ratio: f64,
+
+    // Splits tab-separated lines by the field of the index, keeping the lines with
+    // the same key, e.g., the same document, in the same split.
+    #[arg(long)]
+    key_field: Option<usize>,
+
+    // Field of the text in tab-separated lines, or the whole line if not given.
+    #[arg(long)]
+    text_field: Option<usize>,
+
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    #[arg(short = 'n', long, default_value_t = 1)]
+    max_n: usize,
+
+    // Pairs across the splits within the radius are reported as leaks.
+    #[arg(short = 'r', long, default_value_t = 0.1)]
+    radius: f32,
+
+    // Drops the leaking queries instead of failing.
+    #[arg(long)]
+    drop_leaks: bool,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    if !(0.0..=1.0).contains(&args.query_ratio) {
+        return Err(format!("Invalid query ratio: {}", args.query_ratio).into());
+    }
+
+    let lines = load_lines(&args.input_file)?;
+    eprintln!("n_input: {}", lines.len());
+    let keys = lines
+        .iter()
+        .map(|line| match args.key_field {
+            Some(field) => field_of(line, field).map(|key| key.to_owned()),
+            None => Ok(String::new()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let texts = lines
+        .iter()
+        .map(|line| match args.text_field {
+            Some(field) => field_of(line, field),
+            None => Ok(line.as_str()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let is_query = split(&keys, args.key_field.is_some(), args.query_ratio, args.seed);
+    eprintln!("n_queries: {}", is_query.iter().filter(|&&q| q).count());
+
+    eprintln!("Joining...");
+    let mut extractor = VocabExtractor::new(1..=args.max_n)?;
+    let records = texts
+        .iter()
+        .enumerate()
+        .map(|(id, text)| Record {
+            id: id as u32,
+            set: extractor.extract(&text.split_whitespace().collect::<Vec<_>>()),
+        })
+        .collect::<Vec<_>>();
+    let pairs = pairwise_distances_within(&records, args.radius, None)?;
+    let mut leaks = Vec::new();
+    for &(i, j, dist) in pairs.entries() {
+        let (i, j) = (i as usize, j as usize);
+        let (database_line, query_line) = match (is_query[i], is_query[j]) {
+            (false, true) => (i, j),
+            (true, false) => (j, i),
+            _ => continue,
+        };
+        leaks.push(Leak {
+            database_line: database_line + 1,
+            query_line: query_line + 1,
+            dist,
+            database_text: texts[database_line].to_owned(),
+            query_text: texts[query_line].to_owned(),
+        });
+    }
+    eprintln!("n_leaks: {}", leaks.len());
+
+    // Leaking queries belong to neither split if dropped.
+    let mut dropped = vec![false; texts.len()];
+    if args.drop_leaks {
+        for leak in &leaks {
+            dropped[leak.query_line - 1] = true;
+        }
+    }
+    let n_dropped = dropped.iter().filter(|&&d| d).count();
+    eprintln!("n_dropped: {}", n_dropped);
+
+    let mut database_file = File::create(&args.database_file)?;
+    let mut query_file = File::create(&args.query_file)?;
+    let mut n_database = 0;
+    let mut n_queries = 0;
+    for (i, text) in texts.iter().enumerate() {
+        if dropped[i] {
+            continue;
+        }
+        if is_query[i] {
+            writeln!(query_file, "{}", text)?;
+            n_queries += 1;
+        } else {
+            writeln!(database_file, "{}", text)?;
+            n_database += 1;
+        }
+    }
+
+    let n_leaks = leaks.len();
+    let output = Output {
+        metadata: Metadata {
+            input_file: args.input_file.to_string_lossy().to_string(),
+            n_input: lines.len(),
+            n_database,
+            n_queries,
+            query_ratio: args.query_ratio,
+            key_field: args.key_field,
+            seed: args.seed,
+            max_n: args.max_n,
+            radius: args.radius,
+            n_leaks,
+            n_dropped,
+        },
+        leaks,
+    };
+    let j = serde_json::to_string_pretty(&output)?;
+    let mut file = File::create(args.output_json)?;
+    file.write_all(j.as_bytes())?;
+
+    if n_leaks != 0 && !args.drop_leaks {
+        return Err(format!("{} pairs leak across the splits.", n_leaks).into());
+    }
+    Ok(())
+}
+
+// Marks the lines put in the queries, shuffling the lines or the distinct keys by the seed.
+fn split(keys: &[String], by_key: bool, query_ratio: f64, seed: u64) -> Vec<bool> {
+    let mut rng = Xoshiro256PlusPlus::seed_from_u64(seed);
+    if !by_key {
+        let mut lines = (0..keys.len()).collect::<Vec<_>>();
+        lines.shuffle(&mut rng);
+        let n_queries = (keys.len() as f64 * query_ratio).round() as usize;
+        let mut is_query = vec![false; keys.len()];
+        for &i in &lines[..n_queries] {
+            is_query[i] = true;
+        }
+        return is_query;
+    }
+    // Keys are shuffled in the order of appearance to be reproducible.
+    let mut first_lines = HashMap::new();
+    for (i, key) in keys.iter().enumerate() {
+        first_lines.entry(key.as_str()).or_insert(i);
+    }
+    let mut distinct = first_lines.into_iter().collect::<Vec<_>>();
+    distinct.sort_unstable_by_key(|&(_, i)| i);
+    distinct.shuffle(&mut rng);
+    let n_queries = (distinct.len() as f64 * query_ratio).round() as usize;
+    let query_keys = distinct[..n_queries]
+        .iter()
+        .map(|&(key, _)| key)
+        .collect::<HashSet<_>>();
+    keys.iter()
+        .map(|key| query_keys.contains(key.as_str()))
+        .collect()
+}
+
+fn field_of(line: &str, field: usize) -> Result<&str, Box<dyn Error>> {
+    line.split('\t')
+        .nth(field)
+        .ok_or_else(|| format!("No field {} in line: {}", field, line).into())
+}
+
+fn load_lines<P>(path: P) -> Result<Vec<String>, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+{
+    let reader = BufReader::new(File::open(path)?);
+    let lines = reader.lines().collect::<Result<Vec<_>, _>>()?;
+    Ok(lines)
+}
@@ -1,4 +1,3 @@
-use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
 use std::io::BufRead;
@@ -7,14 +6,17 @@ use std::io::Write;
 
 use clap::Parser;
 use serde::Serialize;
+use set_search_experiment::stats::CorpusStats;
 use set_search_experiment::text::FeatureExtractor;
-use set_search_experiment::OrderedSet;
 
 #[derive(Serialize)]
 struct Output {
     metadata: Metadata,
-    lengths: Vec<usize>,
+    // `length_hist[l]` is the number of sets of length `l`.
+    length_hist: Vec<usize>,
     elem_freqs: Vec<usize>,
+    // (# of sets, # of distinct elements) at every power of two sets.
+    growth_curve: Vec<(usize, usize)>,
 }
 
 #[derive(Serialize)]
@@ -47,31 +49,38 @@ struct Args {
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
 
-    let input_texts = load_lines(&args.input_txt)?;
-    eprintln!("n_input: {}", input_texts.len());
-
     let max_n = args.max_n;
     let extractor = FeatureExtractor::new(1..=max_n, args.universe, args.seed)?;
-    let mut sets = Vec::with_capacity(input_texts.len());
-
-    for text in &input_texts {
-        let tokens = text.split_whitespace().collect::<Vec<_>>();
-        sets.push(extractor.extract(&tokens));
+    let mut stats = CorpusStats::new(args.universe, 0, extractor.seed())?;
+
+    // Streams the lines without holding the sets.
+    let reader = BufReader::new(File::open(&args.input_txt)?);
+    for line in reader.lines() {
+        let line = line?;
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        stats.push(&extractor.extract(&tokens))?;
     }
-
-    let lengths = lengths(&sets);
-    let elem_freqs = elem_freqs(&sets);
+    eprintln!("n_input: {}", stats.n_sets());
+
+    let mut elem_freqs = stats
+        .dfs()
+        .iter()
+        .cloned()
+        .filter(|&df| df != 0)
+        .collect::<Vec<_>>();
+    elem_freqs.sort_unstable_by(|a, b| b.cmp(a));
     eprintln!("n_elems: {}", elem_freqs.len());
 
     let output = Output {
         metadata: Metadata {
             input_txt: args.input_txt,
             max_n,
-            n_input: input_texts.len(),
+            n_input: stats.n_sets(),
             n_elems: elem_freqs.len(),
         },
-        lengths,
+        length_hist: stats.len_hist().to_vec(),
         elem_freqs,
+        growth_curve: stats.growth_curve(),
     };
 
     let mut writer = File::create(&args.output_json)?;
@@ -79,25 +88,3 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
-
-fn load_lines(path: &str) -> Result<Vec<String>, Box<dyn Error>> {
-    let reader = BufReader::new(File::open(path)?);
-    let lines = reader.lines().collect::<Result<Vec<_>, _>>()?;
-    Ok(lines)
-}
-
-fn lengths(sets: &[OrderedSet<u32>]) -> Vec<usize> {
-    sets.iter().map(|set| set.len()).collect::<Vec<_>>()
-}
-
-fn elem_freqs(sets: &[OrderedSet<u32>]) -> Vec<usize> {
-    let mut elem_freqs = HashMap::new();
-    for set in sets {
-        for elem in set.iter().cloned() {
-            *elem_freqs.entry(elem).or_insert(0) += 1;
-        }
-    }
-    let mut elem_freqs = elem_freqs.into_iter().map(|(_, c)| c).collect::<Vec<_>>();
-    elem_freqs.sort_unstable_by(|a, b| b.cmp(a));
-    elem_freqs
-}
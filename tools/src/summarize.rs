@@ -0,0 +1,300 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use set_search_experiment::FilterConfig;
+
+// One row per (file, method, config, radius, max_n, stratum), flat for plotting.
+// Counters are averaged per query, and the columns not given by the tool are empty.
+#[derive(Serialize)]
+struct Row {
+    file: String,
+    tool: &'static str,
+    method: String,
+    config: String,
+    radius: Option<f32>,
+    topk: Option<usize>,
+    max_n: usize,
+    // Query length stratum, or none for all the queries.
+    stratum: Option<usize>,
+    n_queries: usize,
+    mean_answers: f64,
+    mean_length_filtered: Option<f64>,
+    mean_position_filtered: Option<f64>,
+    mean_verified: Option<f64>,
+    build_ms: Option<f64>,
+    latency_mean_ms: Option<f64>,
+    latency_p50_ms: Option<f64>,
+    latency_p95_ms: Option<f64>,
+    latency_p99_ms: Option<f64>,
+}
+
+// The fields used of the outputs of the tools, which are told apart by their fields.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Input {
+    Measure(Vec<MeasureCell>),
+    Evaluate(EvaluateOutput),
+    Search(SearchOutput),
+}
+
+#[derive(Deserialize)]
+struct MeasureCell {
+    index: String,
+    max_n: usize,
+    stratum: Option<usize>,
+    length: bool,
+    position: bool,
+    radius: f32,
+    n_queries: usize,
+    build_ms: f64,
+    latency_mean_ms: f64,
+    latency_p50_ms: f64,
+    latency_p95_ms: f64,
+    latency_p99_ms: f64,
+    n_answers: usize,
+    length_filtered: Option<usize>,
+    position_filtered: Option<usize>,
+    verified: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct EvaluateOutput {
+    metadata: EvaluateMetadata,
+    no_filter: Vec<Counter>,
+    length_filter: Vec<Counter>,
+    position_filter: Vec<Counter>,
+    all_filters: Vec<Counter>,
+    strata: Option<Vec<Stratum>>,
+}
+
+#[derive(Deserialize)]
+struct EvaluateMetadata {
+    n_queries: usize,
+    max_n: usize,
+    radius: f32,
+}
+
+#[derive(Deserialize, Default, Clone)]
+struct Counter {
+    length_filtered: usize,
+    position_filtered: usize,
+    verified: usize,
+    undefined: usize,
+    accepted: usize,
+}
+
+#[derive(Deserialize)]
+struct Stratum {
+    n_queries: usize,
+    no_filter: Counter,
+    length_filter: Counter,
+    position_filter: Counter,
+    all_filters: Counter,
+}
+
+#[derive(Deserialize)]
+struct SearchOutput {
+    metadata: SearchMetadata,
+    answers: Vec<SearchAnswer>,
+}
+
+#[derive(Deserialize)]
+struct SearchMetadata {
+    n_queries: usize,
+    max_n: usize,
+    radius: Option<f32>,
+    topk: Option<usize>,
+    length: bool,
+    position: bool,
+}
+
+#[derive(Deserialize)]
+struct SearchAnswer {
+    founds: Vec<serde_json::Value>,
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    // JSON outputs of evaluate, search, and measure, in any mix.
+    #[arg(required = true)]
+    input_files: Vec<PathBuf>,
+
+    #[arg(short = 'o', long)]
+    output_csv: PathBuf,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    let mut rows = Vec::new();
+    for path in &args.input_files {
+        let file = path.to_string_lossy().to_string();
+        let input: Input = serde_json::from_reader(std::io::BufReader::new(File::open(path)?))
+            .map_err(|e| format!("{}: not an output of the tools: {}", file, e))?;
+        let n_rows = rows.len();
+        match input {
+            Input::Measure(cells) => summarize_measure(&file, cells, &mut rows),
+            Input::Evaluate(output) => summarize_evaluate(&file, output, &mut rows),
+            Input::Search(output) => summarize_search(&file, output, &mut rows),
+        }
+        eprintln!("{}: {} rows", file, rows.len() - n_rows);
+    }
+
+    let mut file = File::create(args.output_csv)?;
+    file.write_all(to_csv(&rows)?.as_bytes())?;
+
+    Ok(())
+}
+
+fn summarize_measure(file: &str, cells: Vec<MeasureCell>, rows: &mut Vec<Row>) {
+    for cell in cells {
+        let n = cell.n_queries;
+        rows.push(Row {
+            file: file.to_owned(),
+            tool: "measure",
+            method: cell.index,
+            config: FilterConfig::new(cell.length, cell.position).to_string(),
+            radius: Some(cell.radius),
+            topk: None,
+            max_n: cell.max_n,
+            stratum: cell.stratum,
+            n_queries: n,
+            mean_answers: mean(cell.n_answers, n),
+            mean_length_filtered: cell.length_filtered.map(|c| mean(c, n)),
+            mean_position_filtered: cell.position_filtered.map(|c| mean(c, n)),
+            mean_verified: cell.verified.map(|c| mean(c, n)),
+            build_ms: Some(cell.build_ms),
+            latency_mean_ms: Some(cell.latency_mean_ms),
+            latency_p50_ms: Some(cell.latency_p50_ms),
+            latency_p95_ms: Some(cell.latency_p95_ms),
+            latency_p99_ms: Some(cell.latency_p99_ms),
+        });
+    }
+}
+
+fn summarize_evaluate(file: &str, output: EvaluateOutput, rows: &mut Vec<Row>) {
+    let metadata = &output.metadata;
+    let row = |config: &FilterConfig, stratum, n_queries, counter: &Counter| Row {
+        file: file.to_owned(),
+        tool: "evaluate",
+        method: "linear_scan".to_owned(),
+        config: config.to_string(),
+        radius: Some(metadata.radius),
+        topk: None,
+        max_n: metadata.max_n,
+        stratum,
+        n_queries,
+        mean_answers: mean(counter.accepted, n_queries),
+        mean_length_filtered: Some(mean(counter.length_filtered, n_queries)),
+        mean_position_filtered: Some(mean(counter.position_filtered, n_queries)),
+        mean_verified: Some(mean(counter.verified + counter.undefined, n_queries)),
+        build_ms: None,
+        latency_mean_ms: None,
+        latency_p50_ms: None,
+        latency_p95_ms: None,
+        latency_p99_ms: None,
+    };
+    // Counters are in the order of `FilterConfig::PRESETS`.
+    let per_query = [
+        &output.no_filter,
+        &output.length_filter,
+        &output.position_filter,
+        &output.all_filters,
+    ];
+    for (counters, config) in per_query.into_iter().zip(&FilterConfig::PRESETS) {
+        let total = counters.iter().fold(Counter::default(), |acc, c| Counter {
+            length_filtered: acc.length_filtered + c.length_filtered,
+            position_filtered: acc.position_filtered + c.position_filtered,
+            verified: acc.verified + c.verified,
+            undefined: acc.undefined + c.undefined,
+            accepted: acc.accepted + c.accepted,
+        });
+        rows.push(row(config, None, metadata.n_queries, &total));
+    }
+    for (i, stratum) in output.strata.iter().flatten().enumerate() {
+        let counters = [
+            &stratum.no_filter,
+            &stratum.length_filter,
+            &stratum.position_filter,
+            &stratum.all_filters,
+        ];
+        for (counter, config) in counters.into_iter().zip(&FilterConfig::PRESETS) {
+            rows.push(row(config, Some(i), stratum.n_queries, counter));
+        }
+    }
+}
+
+fn summarize_search(file: &str, output: SearchOutput, rows: &mut Vec<Row>) {
+    let metadata = &output.metadata;
+    let n_founds = output.answers.iter().map(|ans| ans.founds.len()).sum();
+    rows.push(Row {
+        file: file.to_owned(),
+        tool: "search",
+        method: "linear_scan".to_owned(),
+        config: FilterConfig::new(metadata.length, metadata.position).to_string(),
+        radius: metadata.radius,
+        topk: metadata.topk,
+        max_n: metadata.max_n,
+        stratum: None,
+        n_queries: metadata.n_queries,
+        mean_answers: mean(n_founds, metadata.n_queries),
+        mean_length_filtered: None,
+        mean_position_filtered: None,
+        mean_verified: None,
+        build_ms: None,
+        latency_mean_ms: None,
+        latency_p50_ms: None,
+        latency_p95_ms: None,
+        latency_p99_ms: None,
+    });
+}
+
+fn mean(total: usize, n: usize) -> f64 {
+    if n == 0 {
+        0.0
+    } else {
+        total as f64 / n as f64
+    }
+}
+
+fn to_csv<T>(rows: &[T]) -> Result<String, Box<dyn Error>>
+where
+    T: Serialize,
+{
+    let rows = rows
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()?;
+    let Some(first) = rows.first().and_then(|row| row.as_object()) else {
+        return Ok(String::new());
+    };
+    let header = first.keys().cloned().collect::<Vec<_>>();
+    let names = header.iter().map(|key| csv_field(key)).collect::<Vec<_>>();
+    let mut csv = names.join(",") + "\n";
+    for row in &rows {
+        let fields = header
+            .iter()
+            .map(|key| match &row[key] {
+                serde_json::Value::Null => String::new(),
+                serde_json::Value::String(s) => csv_field(s),
+                value => csv_field(&value.to_string()),
+            })
+            .collect::<Vec<_>>();
+        csv += &(fields.join(",") + "\n");
+    }
+    Ok(csv)
+}
+
+// Quotes the field as RFC 4180 if it contains a comma, a quote, or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}